@@ -590,6 +590,14 @@ impl HubRouter {
                     .ok()
                     .and_then(|payload| String::from_utf8(payload).ok())
                     .unwrap_or_else(|| "stream error".to_string());
+                let (message, request_id) = protocol::split_stream_error(&message);
+                debug!(
+                    proxy_conn_id = proxy_conn_id,
+                    stream_id = header.stream_id,
+                    request_id = request_id.unwrap_or("-"),
+                    error = message,
+                    "proxy stream failed"
+                );
                 self.fail_proxy_stream(proxy_conn_id, header.stream_id, message);
             }
             protocol::HEARTBEAT_DATA => {
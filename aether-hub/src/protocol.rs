@@ -114,6 +114,15 @@ pub fn encode_stream_error(stream_id: u32, msg: &str) -> Vec<u8> {
     encode_frame(stream_id, STREAM_ERROR, 0, msg.as_bytes())
 }
 
+/// Split a STREAM_ERROR message into the error text and the request id the
+/// proxy appends as ` (request_id: <id>)`.
+pub fn split_stream_error(msg: &str) -> (&str, Option<&str>) {
+    msg.strip_suffix(')')
+        .and_then(|rest| rest.rsplit_once(" (request_id: "))
+        .filter(|(_, id)| !id.is_empty() && !id.contains(char::is_whitespace))
+        .map_or((msg, None), |(text, id)| (text, Some(id)))
+}
+
 /// Encode a PING frame (stream_id=0)
 pub fn encode_ping() -> Vec<u8> {
     encode_frame(0, PING, 0, &[])
@@ -174,3 +183,28 @@ fn maybe_recompress_payload(
         Ok((payload.to_vec(), 0))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_error_request_id_is_split_off() {
+        assert_eq!(
+            split_stream_error("upstream timeout (request_id: req-123)"),
+            ("upstream timeout", Some("req-123"))
+        );
+        assert_eq!(
+            split_stream_error("send failed (https://api.example.com/v1) (request_id: a1)"),
+            ("send failed (https://api.example.com/v1)", Some("a1"))
+        );
+        assert_eq!(
+            split_stream_error("upstream timeout"),
+            ("upstream timeout", None)
+        );
+        assert_eq!(
+            split_stream_error("bad (request_id: )"),
+            ("bad (request_id: )", None)
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//! Embed build metadata reported to Aether at registration.
+//!
+//! `AETHER_PROXY_GIT_SHA` may be set explicitly (CI, where `cross` builds
+//! can't see the repository's `.git`); otherwise the revision is read from
+//! git when available.  The build date honours `SOURCE_DATE_EPOCH` for
+//! reproducible builds.
+
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    println!(
+        "cargo:rustc-env=AETHER_PROXY_BUILD_TARGET={}",
+        std::env::var("TARGET").unwrap_or_default()
+    );
+
+    println!("cargo:rerun-if-env-changed=AETHER_PROXY_GIT_SHA");
+    let sha = std::env::var("AETHER_PROXY_GIT_SHA")
+        .ok()
+        .filter(|s| !s.trim().is_empty())
+        .or_else(|| git(&["rev-parse", "--short=12", "HEAD"]))
+        .unwrap_or_default();
+    let sha: String = sha.trim().chars().take(12).collect();
+    println!("cargo:rustc-env=AETHER_PROXY_GIT_SHA={}", sha);
+
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let epoch = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
+    println!(
+        "cargo:rustc-env=AETHER_PROXY_BUILD_DATE={}",
+        format_utc(epoch)
+    );
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .unwrap_or_default();
+    println!(
+        "cargo:rustc-env=AETHER_PROXY_RUSTC_VERSION={}",
+        rustc_version.trim()
+    );
+
+    // Enabled cargo features, as `CARGO_FEATURE_<NAME>` env vars.
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(k, _)| {
+            k.strip_prefix("CARGO_FEATURE_")
+                .map(|f| f.to_ascii_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!(
+        "cargo:rustc-env=AETHER_PROXY_FEATURES={}",
+        features.join(",")
+    );
+
+    // Rebuild when HEAD moves.
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        let git_dir = PathBuf::from(git_dir.trim());
+        let mut watched = vec![git_dir.join("HEAD"), git_dir.join("packed-refs")];
+        if let Some(head_ref) = git(&["symbolic-ref", "-q", "HEAD"]) {
+            watched.push(git_dir.join(head_ref.trim()));
+        }
+        for path in watched.iter().filter(|p| p.exists()) {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
+/// `YYYY-MM-DDTHH:MM:SSZ` for a Unix timestamp (proleptic Gregorian, UTC).
+fn format_utc(epoch: u64) -> String {
+    let (days, secs) = ((epoch / 86_400) as i64, epoch % 86_400);
+    // Howard Hinnant's civil_from_days.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60
+    )
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
@@ -1,44 +1,76 @@
 //! Application lifecycle: initialization, task orchestration, and shutdown.
 
-use std::sync::atomic::AtomicU64;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering};
 use std::sync::{Arc, RwLock};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use arc_swap::ArcSwap;
+use futures_util::future::join_all;
 use tokio::signal;
-use tokio::sync::{watch, Mutex};
-use tracing::{error, info, warn};
+use tokio::sync::{watch, Mutex, Notify};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
 
 use crate::config::{Config, ServerEntry};
+use crate::log_file;
+use crate::memory::{self, BufferBudget, MemoryGuard};
 use crate::net;
-use crate::registration::client::AetherClient;
+use crate::notification;
+use crate::pid_file;
+use crate::registration::client::{jitter_delay, AetherClient};
 use crate::runtime::{self, DynamicConfig};
-use crate::state::{AppState, ProxyMetrics, ServerContext};
-use crate::upstream_client;
-use crate::{hardware, target_filter, tunnel};
+use crate::shutdown::{ServerShutdown, ShutdownReport};
+use crate::state::{
+    AppState, DrainStats, ProxyMetrics, ReconnectTrigger, ServerContext,
+    REMOTE_RECONNECT_MIN_INTERVAL,
+};
+use crate::state_file::StateFile;
+use crate::{build_info, dedup, hardware, rate_limit, target_filter, telemetry, tls, tunnel};
+use crate::{upstream_auth, upstream_client};
+
+/// Tunnel task handles tagged with their server label.
+type TunnelHandles = Arc<Mutex<Vec<(String, JoinHandle<()>)>>>;
 
 /// Run the full application lifecycle after config has been parsed.
-pub async fn run(mut config: Config, servers: Vec<ServerEntry>) -> anyhow::Result<()> {
+///
+/// Returns the process exit code derived from the shutdown report.
+pub async fn run(mut config: Config, servers: Vec<ServerEntry>) -> anyhow::Result<i32> {
     config.validate()?;
-    init_tracing(&config);
+    // Held until `run` returns so the log file writer flushes the last lines
+    // and buffered spans are exported.
+    let _log_guards = init_tracing(&config)?;
+    spawn_log_signal_handlers();
 
     info!(
-        version = env!("CARGO_PKG_VERSION"),
+        version = build_info::VERSION,
+        git_sha = build_info::git_sha().unwrap_or("unknown"),
         node_name = %config.node_name,
         server_count = servers.len(),
         "aether-proxy starting (tunnel mode)"
     );
+    for warning in target_filter::port_warnings(&config.allowed_ports) {
+        warn!("{}", warning);
+    }
 
-    // Resolve public IP (best-effort for region info)
+    // Resolve public IP (best-effort for region info).  With detection
+    // disabled, register with the same placeholder as a failed detection
+    // and let Aether use the observed source address of the registration
+    // request.
     let public_ip = match &config.public_ip {
         Some(ip) => ip.clone(),
+        None if config.disable_ip_detection => {
+            info!("public IP detection disabled, deferring to server-side detection");
+            "0.0.0.0".to_string()
+        }
         None => net::detect_public_ip()
             .await
             .unwrap_or_else(|_| "0.0.0.0".to_string()),
     };
 
     // Auto-detect region if not configured
-    if config.node_region.is_none() {
+    if config.node_region.is_none() && !config.disable_ip_detection {
         if let Some(region) = net::detect_region(&public_ip).await {
             config.node_region = Some(region);
         }
@@ -57,25 +89,81 @@ pub async fn run(mut config: Config, servers: Vec<ServerEntry>) -> anyhow::Resul
         );
     }
 
+    if config.global_buffer_budget_mb.is_none() {
+        let auto = (hw_info.total_memory_mb / 4).clamp(64, 8192);
+        config.global_buffer_budget_mb = Some(auto);
+        info!(
+            global_buffer_budget_mb = auto,
+            "auto-detected global_buffer_budget_mb from hardware"
+        );
+    }
+
     info!(
         max_concurrency = hw_info.estimated_max_concurrency,
         "hardware info collected"
     );
 
-    let dns_cache = Arc::new(target_filter::DnsCache::new(
-        Duration::from_secs(config.dns_cache_ttl_secs),
-        config.dns_cache_capacity,
-    ));
+    let dns_cache = Arc::new(
+        target_filter::DnsCache::new(
+            Duration::from_secs(config.dns_cache_ttl_secs),
+            config.dns_cache_capacity,
+        )
+        .with_max_inflight(config.dns_max_inflight),
+    );
 
     // Build Hyper client for tunnel upstream requests (shared).
     // DNS still flows through validated addresses from DnsCache, while the
     // custom connector exposes per-request connect/TLS timing when available.
-    let upstream_client = upstream_client::build_upstream_client(&config, Arc::clone(&dns_cache));
+    // TLS root stores are loaded once here (the OS store can be slow to read).
+    let tunnel_roots = tls::load_root_store(
+        config.tunnel_tls_roots,
+        config.tunnel_extra_ca_file.as_deref(),
+    )?;
+    let upstream_roots = tls::load_root_store(
+        config.upstream_tls_roots,
+        config.upstream_extra_ca_file.as_deref(),
+    )?;
+    info!(
+        tunnel = %tunnel_roots.description,
+        upstream = %upstream_roots.description,
+        "TLS root certificates loaded"
+    );
+    let tunnel_tls_config = Arc::new(tunnel::client::build_tls_config(tunnel_roots.clone()));
+    let upstream_ws_tls_config =
+        upstream_client::build_websocket_tls_config(upstream_roots.store.clone());
+    let mut bound_upstream_clients = HashMap::new();
+    for addr in servers.iter().filter_map(|s| s.bind_address) {
+        bound_upstream_clients.entry(addr).or_insert_with(|| {
+            upstream_client::build_upstream_client(
+                &config,
+                Arc::clone(&dns_cache),
+                upstream_roots.clone(),
+                Some(addr),
+            )
+        });
+    }
+    let upstream_clients = upstream_client::build_upstream_client(
+        &config,
+        Arc::clone(&dns_cache),
+        upstream_roots,
+        None,
+    );
 
     // Register with each Aether server and build per-server contexts.
     // Wrapped in Arc<Mutex> so retry_failed_registrations can append later.
     let server_contexts: Arc<Mutex<Vec<Arc<ServerContext>>>> = Arc::new(Mutex::new(Vec::new()));
-    let mut failed_entries: Vec<(String, ServerEntry)> = Vec::new();
+    // With the node a crashed instance left behind, if it is still registered.
+    let mut failed_entries: Vec<(
+        String,
+        ServerEntry,
+        Arc<rustls::ClientConfig>,
+        Option<String>,
+    )> = Vec::new();
+    // Lazy registration: contexts whose tunnels start before a node_id is
+    // known, with the stale node (if any) still to unregister.
+    let mut pending_servers: Vec<(Arc<ServerContext>, Option<String>)> = Vec::new();
+    // Nodes a crashed previous instance never unregistered.
+    let stale_nodes = StateFile::take_stale_node_ids();
     for (i, entry) in servers.iter().enumerate() {
         let label = if servers.len() == 1 {
             "server".to_string()
@@ -86,32 +174,69 @@ pub async fn run(mut config: Config, servers: Vec<ServerEntry>) -> anyhow::Resul
             .node_name
             .clone()
             .unwrap_or_else(|| config.node_name.clone());
+        let node_region = entry
+            .node_region
+            .clone()
+            .or_else(|| config.node_region.clone());
+        let tls_config =
+            tunnel::client::server_tls_config(&tunnel_tls_config, &tunnel_roots, entry)
+                .map_err(|e| anyhow::anyhow!("{}: tls_ca_cert: {}", label, e))?;
         let client = Arc::new(AetherClient::new(
             &config,
             &entry.aether_url,
             &entry.management_token,
+            &tls_config,
+            StateFile::client_instance_id(&entry.aether_url, &node_name),
         ));
+        let mut stale_node = stale_nodes.get(client.client_instance_id()).cloned();
+        if let Some(stale) = &stale_node {
+            if unregister_stale_node(&label, &client, stale).await {
+                stale_node = None;
+            }
+        }
         match client
-            .register(&config, &node_name, &public_ip, Some(&hw_info))
+            .register(
+                &config,
+                &node_name,
+                node_region.as_deref(),
+                &config.node_tags_for(entry),
+                &public_ip,
+                Some(&hw_info),
+            )
             .await
         {
             Ok(node_id) => {
                 info!(server = %label, node_id = %node_id, url = %entry.aether_url, node_name = %node_name, "registered");
-                // Initialize dynamic config with per-server node_name (not global),
-                // so that the heartbeat and reconnect use the correct name.
-                let mut dynamic = DynamicConfig::from_config(&config);
-                dynamic.node_name = node_name.clone();
-                server_contexts.lock().await.push(Arc::new(ServerContext {
-                    server_label: label,
-                    aether_url: entry.aether_url.clone(),
-                    management_token: entry.management_token.clone(),
+                server_contexts.lock().await.push(new_server_context(
+                    &config,
+                    label,
+                    entry,
                     node_name,
-                    node_id: Arc::new(RwLock::new(node_id)),
-                    aether_client: client,
-                    dynamic: Arc::new(ArcSwap::from_pointee(dynamic)),
-                    active_connections: Arc::new(AtomicU64::new(0)),
-                    metrics: Arc::new(ProxyMetrics::new()),
-                }));
+                    node_region,
+                    node_id,
+                    client,
+                    tls_config,
+                ));
+            }
+            Err(e) if config.lazy_registration => {
+                warn!(
+                    server = %label,
+                    url = %entry.aether_url,
+                    error = %e,
+                    "registration failed, starting tunnels with registration pending"
+                );
+                let server = new_server_context(
+                    &config,
+                    label,
+                    entry,
+                    node_name,
+                    node_region,
+                    String::new(),
+                    client,
+                    tls_config,
+                );
+                server_contexts.lock().await.push(Arc::clone(&server));
+                pending_servers.push((server, stale_node));
             }
             Err(e) => {
                 warn!(
@@ -120,7 +245,7 @@ pub async fn run(mut config: Config, servers: Vec<ServerEntry>) -> anyhow::Resul
                     error = %e,
                     "registration failed, will retry in background"
                 );
-                failed_entries.push((label, entry.clone()));
+                failed_entries.push((label, entry.clone(), tls_config, stale_node));
             }
         }
     }
@@ -138,18 +263,67 @@ pub async fn run(mut config: Config, servers: Vec<ServerEntry>) -> anyhow::Resul
         }
     }
 
+    let memory = Arc::new(MemoryGuard::new(
+        config.memory_soft_limit_mb,
+        config.memory_hard_limit_mb,
+    ));
+    let buffer_budget = Arc::new(BufferBudget::new(
+        config.global_buffer_budget_mb.unwrap_or_default(),
+    ));
+
+    let has_primary = servers.iter().any(|s| s.role.is_primary());
+    let has_secondary = servers.iter().any(|s| !s.role.is_primary());
+    if has_secondary && !has_primary {
+        warn!("no primary servers configured, secondary servers run unconditionally");
+    }
+
+    let global_rate_limit = (config.global_max_rps > 0)
+        .then(|| rate_limit::TokenBucket::per_second(config.global_max_rps.into()));
+    let bandwidth_limit =
+        |bps: u64| (bps > 0).then(|| Arc::new(rate_limit::TokenBucket::per_second(bps)));
+    let upstream_bandwidth = bandwidth_limit(config.max_upstream_bps);
+    let downstream_bandwidth = bandwidth_limit(config.max_downstream_bps);
+    let stream_affinity = if config.enable_stream_affinity {
+        let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let router = tunnel::affinity::AffinityRouter::new(workers)?;
+        info!(workers = router.workers(), "stream affinity enabled");
+        Some(router)
+    } else {
+        None
+    };
+
+    let notifier = notification::Notifier::from_config(&config)?;
+
     // Build shared application state
-    let tunnel_tls_config = Arc::new(crate::tunnel::client::build_tls_config());
     let state = Arc::new(AppState {
         config: Arc::new(config),
         dns_cache,
-        upstream_client,
-        tunnel_tls_config,
+        upstream_clients,
+        bound_upstream_clients,
+        upstream_ws_tls_config,
+        memory,
+        buffer_budget,
+        failover_enabled: has_primary && has_secondary,
+        recent_fingerprints: Arc::new(dedup::ExpiringSet::new(
+            dedup::FINGERPRINT_TTL,
+            dedup::FINGERPRINT_CAPACITY,
+        )),
+        auth_loops: upstream_auth::AuthLoopDetector::new(
+            upstream_auth::AUTH_LOOP_WINDOW,
+            upstream_auth::AUTH_LOOP_CAPACITY,
+        ),
+        global_rate_limit,
+        upstream_bandwidth,
+        downstream_bandwidth,
+        stream_affinity,
+        notifier,
     });
 
     // Shutdown signal channel
     let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
+    memory::spawn_sampler(Arc::clone(&state.memory), shutdown_rx.clone());
+
     info!(
         active_servers = server_contexts.lock().await.len(),
         "running in tunnel mode"
@@ -157,34 +331,90 @@ pub async fn run(mut config: Config, servers: Vec<ServerEntry>) -> anyhow::Resul
 
     // Spawn tunnel connections per server (pool_size connections each)
     let pool_size = state.config.tunnel_connections.max(1) as usize;
-    let mut tunnel_handles = Vec::new();
+    let tunnel_handles: TunnelHandles = Arc::new(Mutex::new(Vec::new()));
     for server in server_contexts.lock().await.iter() {
+        if !state.starts_active(server) {
+            info!(server = %server.server_label, "secondary server on standby");
+            continue;
+        }
         for conn_idx in 0..pool_size {
             let s = Arc::clone(&state);
             let srv = Arc::clone(server);
             let rx = shutdown_rx.clone();
-            tunnel_handles.push(tokio::spawn(async move {
-                tunnel::run(&s, &srv, conn_idx, rx).await;
-            }));
+            tunnel_handles.lock().await.push((
+                server.server_label.clone(),
+                tokio::spawn(async move {
+                    tunnel::run(&s, &srv, conn_idx, rx).await;
+                }),
+            ));
         }
     }
 
+    // Grow/shrink each server's tunnel pool based on health and load
+    {
+        let scaler_state = Arc::clone(&state);
+        let scaler_contexts = Arc::clone(&server_contexts);
+        let scaler_handles = Arc::clone(&tunnel_handles);
+        let scaler_shutdown = shutdown_rx.clone();
+        tokio::spawn(async move {
+            scale_tunnel_pools(
+                scaler_state,
+                scaler_contexts,
+                scaler_handles,
+                scaler_shutdown,
+            )
+            .await;
+        });
+    }
+
+    // Bring secondary servers up while every primary is unhealthy
+    if state.failover_enabled {
+        tokio::spawn(run_failover(
+            Arc::clone(&state),
+            Arc::clone(&server_contexts),
+            Arc::clone(&tunnel_handles),
+            shutdown_rx.clone(),
+        ));
+    }
+
+    // Recover node records that Aether lost while tunnels stay up
+    for server in server_contexts.lock().await.iter() {
+        tokio::spawn(keep_registered(
+            Arc::clone(&state),
+            Arc::clone(server),
+            public_ip.clone(),
+            hw_info.clone(),
+            shutdown_rx.clone(),
+        ));
+    }
+
+    // Keep registering pending servers while their tunnels try to connect
+    for (server, stale_node) in pending_servers {
+        let s = Arc::clone(&state);
+        let public_ip = public_ip.clone();
+        let hw_info = hw_info.clone();
+        let rx = shutdown_rx.clone();
+        tokio::spawn(async move {
+            register_pending(s, server, stale_node, public_ip, hw_info, rx).await;
+        });
+    }
+
     // Spawn background retry for failed server registrations
     if !failed_entries.is_empty() {
         let retry_state = Arc::clone(&state);
         let retry_contexts = Arc::clone(&server_contexts);
+        let retry_handles = Arc::clone(&tunnel_handles);
         let retry_public_ip = public_ip.clone();
         let retry_hw_info = hw_info.clone();
         let retry_shutdown = shutdown_rx.clone();
-        let retry_pool_size = pool_size;
         tokio::spawn(async move {
             retry_failed_registrations(
                 retry_state,
                 retry_contexts,
+                retry_handles,
                 failed_entries,
                 retry_public_ip,
                 retry_hw_info,
-                retry_pool_size,
                 retry_shutdown,
             )
             .await;
@@ -194,30 +424,560 @@ pub async fn run(mut config: Config, servers: Vec<ServerEntry>) -> anyhow::Resul
     // Wait for shutdown signal
     wait_for_shutdown().await;
     info!("shutdown signal received, cleaning up...");
+    let shutdown_started = Instant::now();
     let _ = shutdown_tx.send(true);
 
-    // Graceful unregister from all servers (including retry-registered ones)
-    for server in server_contexts.lock().await.iter() {
+    // Graceful unregister from all servers (including retry-registered ones).
+    // Requests run concurrently under a single deadline so that shutdown time
+    // doesn't grow with the number of servers.
+    let servers: Vec<Arc<ServerContext>> = server_contexts.lock().await.clone();
+    let unregister_timeout_secs = state.config.shutdown_unregister_timeout_secs;
+    let unregister_deadline =
+        tokio::time::Instant::now() + Duration::from_secs(unregister_timeout_secs);
+    let unregister_results: Vec<bool> = join_all(servers.iter().map(|server| async move {
         let node_id = server.node_id.read().unwrap().clone();
-        if let Err(e) = server.aether_client.unregister(&node_id).await {
-            error!(
-                server = %server.server_label,
-                error = %e,
-                "unregister failed during shutdown"
-            );
+        if node_id.is_empty() {
+            // Registration never completed; nothing to unregister.
+            return true;
         }
-    }
+        let result = tokio::time::timeout_at(
+            unregister_deadline,
+            server.aether_client.unregister(&node_id),
+        )
+        .await;
+        match result {
+            Ok(Ok(())) => true,
+            Ok(Err(e)) => {
+                error!(
+                    server = %server.server_label,
+                    error = %e,
+                    "unregister failed during shutdown"
+                );
+                false
+            }
+            Err(_) => {
+                error!(
+                    server = %server.server_label,
+                    timeout_secs = unregister_timeout_secs,
+                    "unregister timed out during shutdown"
+                );
+                false
+            }
+        }
+    }))
+    .await;
 
-    // Wait for all tunnel tasks
-    for h in tunnel_handles {
+    // Wait for all tunnel tasks (each drains its in-flight streams first)
+    let mut handles = std::mem::take(&mut *tunnel_handles.lock().await);
+    let mut summaries = Vec::with_capacity(servers.len());
+    for (server, unregister_ok) in servers.iter().zip(unregister_results) {
+        let (own, rest): (Vec<_>, Vec<_>) = handles
+            .into_iter()
+            .partition(|(label, _)| *label == server.server_label);
+        handles = rest;
+        for (_, h) in own {
+            let _ = h.await;
+        }
+        summaries.push(ServerShutdown {
+            server: server.server_label.clone(),
+            streams_drained: server.drain.streams_drained.load(Ordering::Relaxed),
+            streams_aborted: server.drain.streams_aborted.load(Ordering::Relaxed),
+            unregister_ok,
+            duration_ms: shutdown_started.elapsed().as_millis() as u64,
+        });
+    }
+    for (_, h) in handles {
         let _ = h.await;
     }
 
+    let report = ShutdownReport::new(summaries, shutdown_started.elapsed().as_millis() as u64);
+    report.log();
+    let unregistered: Vec<String> = servers
+        .iter()
+        .zip(&report.servers)
+        .filter(|(_, summary)| summary.unregister_ok)
+        .map(|(server, _)| server.node_id.read().unwrap().clone())
+        .filter(|node_id| !node_id.is_empty())
+        .collect();
+    if let Err(e) = StateFile::update(|s| {
+        s.last_shutdown = Some(report.clone());
+        s.clean_shutdown = Some(unregistered);
+    }) {
+        warn!(error = %e, "failed to persist shutdown report");
+    }
+    if let Some(path) = &state.config.pid_file {
+        pid_file::remove_pid_file(std::path::Path::new(path));
+    }
+
     info!("aether-proxy stopped");
-    Ok(())
+    Ok(report.exit_code)
+}
+
+/// Upper bound for unregistering a node left behind by a crashed instance.
+const STALE_UNREGISTER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Best-effort unregister of `node_id`, which a previous instance left
+/// registered because it crashed or was killed.  Returns whether it worked.
+async fn unregister_stale_node(label: &str, client: &AetherClient, node_id: &str) -> bool {
+    warn!(
+        server = %label,
+        node_id = %node_id,
+        "previous instance did not shut down cleanly, unregistering its node"
+    );
+    match tokio::time::timeout(STALE_UNREGISTER_TIMEOUT, client.unregister(node_id)).await {
+        Ok(Ok(())) => return true,
+        Ok(Err(e)) => warn!(server = %label, error = %e, "stale node unregister failed"),
+        Err(_) => warn!(
+            server = %label,
+            timeout_secs = STALE_UNREGISTER_TIMEOUT.as_secs(),
+            "stale node unregister timed out"
+        ),
+    }
+    false
+}
+
+/// How often the pool scaler re-evaluates server health.
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Add connections only when the server is this healthy.
+const SCALE_UP_MIN_HEALTH: u8 = 90;
+/// Drain extra connections once health falls below this.
+const SCALE_DOWN_BELOW_HEALTH: u8 = 50;
+
+/// Background task that adapts each server's tunnel pool.
+///
+/// Connections beyond `tunnel_connections` are added one per check while the
+/// server is healthy and loaded (up to `max_tunnel_connections`), and all of
+/// them are drained when health drops.  Extra connections get their own
+/// shutdown channel so they can be stopped individually.
+async fn scale_tunnel_pools(
+    state: Arc<AppState>,
+    server_contexts: Arc<Mutex<Vec<Arc<ServerContext>>>>,
+    tunnel_handles: TunnelHandles,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let base = state.config.tunnel_connections.max(1) as usize;
+    let max = (state.config.max_tunnel_connections as usize).max(base);
+    let target_streams = state.config.target_streams_per_connection as u64;
+    let mut extras: HashMap<String, Vec<watch::Sender<bool>>> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(HEALTH_CHECK_INTERVAL) => {}
+            _ = shutdown.changed() => break,
+        }
+
+        let servers: Vec<Arc<ServerContext>> = server_contexts.lock().await.clone();
+        // Failover secondaries keep a fixed pool so draining them is complete.
+        for server in servers.into_iter().filter(|s| state.starts_active(s)) {
+            let score = server.refresh_health_score();
+            let server_extras = extras.entry(server.server_label.clone()).or_default();
+            let current = base + server_extras.len();
+            let active = server.active_connections.load(Ordering::Acquire);
+
+            if score >= SCALE_UP_MIN_HEALTH
+                && active > target_streams * current as u64
+                && current < max
+            {
+                let (conn_tx, conn_rx) = watch::channel(false);
+                let s = Arc::clone(&state);
+                let srv = Arc::clone(&server);
+                let conn_idx = current;
+                tunnel_handles.lock().await.push((
+                    server.server_label.clone(),
+                    tokio::spawn(async move {
+                        tunnel::run(&s, &srv, conn_idx, conn_rx).await;
+                    }),
+                ));
+                server_extras.push(conn_tx);
+                info!(
+                    server = %server.server_label,
+                    health = score,
+                    active_streams = active,
+                    connections = current + 1,
+                    "added tunnel connection"
+                );
+            } else if score < SCALE_DOWN_BELOW_HEALTH && !server_extras.is_empty() {
+                info!(
+                    server = %server.server_label,
+                    health = score,
+                    drained = server_extras.len(),
+                    connections = base,
+                    "draining extra tunnel connections"
+                );
+                for conn_tx in server_extras.drain(..) {
+                    let _ = conn_tx.send(true);
+                }
+            }
+        }
+    }
+
+    // Forward process shutdown to the extra connections.
+    for conn_tx in extras.into_values().flatten() {
+        let _ = conn_tx.send(true);
+    }
 }
 
-/// Retry interval for failed server registrations (5 minutes).
+/// How often the failover task checks primary health.
+const FAILOVER_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Background task that runs secondary servers' tunnels during failover.
+///
+/// Secondaries are activated once the healthiest primary falls below
+/// `failover_threshold` and drained (in-flight streams finish) once a
+/// primary is back at `failover_recovery_threshold`.  Secondaries that
+/// register late join an active failover on the next check.
+async fn run_failover(
+    state: Arc<AppState>,
+    server_contexts: Arc<Mutex<Vec<Arc<ServerContext>>>>,
+    tunnel_handles: TunnelHandles,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let pool_size = state.config.tunnel_connections.max(1) as usize;
+    let mut active = false;
+    // Per secondary: one shutdown sender for all of its connections.
+    let mut secondaries: HashMap<String, watch::Sender<bool>> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(FAILOVER_CHECK_INTERVAL) => {}
+            _ = shutdown.changed() => break,
+        }
+
+        let servers: Vec<Arc<ServerContext>> = server_contexts.lock().await.clone();
+        let best_primary = servers
+            .iter()
+            .filter(|s| s.role.is_primary())
+            .map(|s| s.refresh_health_score())
+            .max();
+        let next = failover_active(
+            active,
+            best_primary,
+            state.config.failover_threshold,
+            state.config.failover_recovery_threshold,
+        );
+        if next != active {
+            if next {
+                warn!(
+                    primary_health = best_primary.unwrap_or(0),
+                    "all primary servers unhealthy, activating secondary servers"
+                );
+            } else {
+                info!(
+                    primary_health = best_primary.unwrap_or(0),
+                    drained = secondaries.len(),
+                    "primary server recovered, draining secondary servers"
+                );
+                for (_, conn_tx) in secondaries.drain() {
+                    let _ = conn_tx.send(true);
+                }
+            }
+            active = next;
+        }
+        if !active {
+            continue;
+        }
+
+        for server in servers.iter().filter(|s| !s.role.is_primary()) {
+            if secondaries.contains_key(&server.server_label) {
+                continue;
+            }
+            let (conn_tx, conn_rx) = watch::channel(false);
+            for conn_idx in 0..pool_size {
+                let s = Arc::clone(&state);
+                let srv = Arc::clone(server);
+                let rx = conn_rx.clone();
+                tunnel_handles.lock().await.push((
+                    server.server_label.clone(),
+                    tokio::spawn(async move {
+                        tunnel::run(&s, &srv, conn_idx, rx).await;
+                    }),
+                ));
+            }
+            info!(server = %server.server_label, connections = pool_size, "secondary server activated");
+            secondaries.insert(server.server_label.clone(), conn_tx);
+        }
+    }
+
+    // Forward process shutdown to active secondaries.
+    for conn_tx in secondaries.into_values() {
+        let _ = conn_tx.send(true);
+    }
+}
+
+/// Failover state after a health check: enter below `threshold`, leave at
+/// or above `recovery`.  `best_primary` is `None` if no primary registered.
+fn failover_active(active: bool, best_primary: Option<u8>, threshold: u8, recovery: u8) -> bool {
+    let score = best_primary.unwrap_or(0);
+    if active {
+        score < recovery
+    } else {
+        score < threshold
+    }
+}
+
+/// Build a server context.  `node_id` is empty while registration is pending.
+#[allow(clippy::too_many_arguments)]
+fn new_server_context(
+    config: &Config,
+    label: String,
+    entry: &ServerEntry,
+    node_name: String,
+    node_region: Option<String>,
+    node_id: String,
+    client: Arc<AetherClient>,
+    tls_config: Arc<rustls::ClientConfig>,
+) -> Arc<ServerContext> {
+    // Initialize dynamic config with per-server node_name (not global),
+    // so that the heartbeat and reconnect use the correct name.
+    let mut dynamic = DynamicConfig::from_config(config);
+    dynamic.node_name = node_name.clone();
+    if !node_id.is_empty() {
+        dynamic.config_version_floor = StateFile::config_version_floor(&node_id);
+    }
+    Arc::new(ServerContext {
+        server_label: label,
+        aether_url: entry.aether_url.clone(),
+        management_token: entry.management_token.clone(),
+        node_name,
+        node_region,
+        node_tags: config.node_tags_for(entry),
+        role: entry.role,
+        tls_sni_override: entry.tls_sni_override.clone(),
+        bind_address: entry.bind_address,
+        tls_config,
+        node_id: Arc::new(RwLock::new(node_id)),
+        aether_client: client,
+        dynamic: Arc::new(ArcSwap::from_pointee(dynamic)),
+        active_connections: Arc::new(AtomicU64::new(0)),
+        metrics: Arc::new(ProxyMetrics::new()),
+        drain: Arc::new(DrainStats::default()),
+        health_score: Arc::new(AtomicU8::new(100)),
+        heartbeat_failures: Arc::new(AtomicU32::new(0)),
+        circuit_open: Arc::new(AtomicBool::new(false)),
+        reregister: Arc::new(Notify::new()),
+        node_id_changed: watch::Sender::new(()),
+        remote_reconnect: ReconnectTrigger::new(REMOTE_RECONNECT_MIN_INTERVAL),
+        connections: Arc::new(tunnel::routing::ConnectionRegistry::default()),
+    })
+}
+
+/// Application state and one registered server context built from
+/// command-line `args`, for tests driving tunnel code without Aether.
+#[cfg(test)]
+pub(crate) fn test_context(args: &[&str]) -> (Arc<AppState>, Arc<ServerContext>) {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let config = crate::config::test_config(args);
+    let roots = || tls::RootStore {
+        store: rustls::RootCertStore::empty(),
+        description: String::new(),
+    };
+    let dns_cache = Arc::new(target_filter::DnsCache::new(
+        Duration::from_secs(config.dns_cache_ttl_secs),
+        config.dns_cache_capacity,
+    ));
+    let tls_config = Arc::new(tunnel::client::build_tls_config(roots()));
+    let entry = config.servers(None).remove(0);
+    let client = Arc::new(AetherClient::new(
+        &config,
+        &entry.aether_url,
+        &entry.management_token,
+        &tls_config,
+        "test-instance".to_string(),
+    ));
+    let server = new_server_context(
+        &config,
+        "server".to_string(),
+        &entry,
+        config.node_name.clone(),
+        config.node_region.clone(),
+        "node-test".to_string(),
+        client,
+        tls_config,
+    );
+    let state = Arc::new(AppState {
+        dns_cache: Arc::clone(&dns_cache),
+        upstream_clients: upstream_client::build_upstream_client(&config, dns_cache, roots(), None),
+        bound_upstream_clients: HashMap::new(),
+        upstream_ws_tls_config: upstream_client::build_websocket_tls_config(roots().store),
+        memory: Arc::new(MemoryGuard::new(
+            config.memory_soft_limit_mb,
+            config.memory_hard_limit_mb,
+        )),
+        buffer_budget: Arc::new(BufferBudget::new(0)),
+        failover_enabled: false,
+        recent_fingerprints: Arc::new(dedup::ExpiringSet::new(
+            dedup::FINGERPRINT_TTL,
+            dedup::FINGERPRINT_CAPACITY,
+        )),
+        auth_loops: upstream_auth::AuthLoopDetector::new(
+            upstream_auth::AUTH_LOOP_WINDOW,
+            upstream_auth::AUTH_LOOP_CAPACITY,
+        ),
+        global_rate_limit: None,
+        upstream_bandwidth: None,
+        downstream_bandwidth: None,
+        stream_affinity: None,
+        notifier: None,
+        config: Arc::new(config),
+    });
+    (state, server)
+}
+
+/// First retry delay for lazy registration; doubles up to the max.
+const LAZY_REGISTRATION_RETRY_MIN: Duration = Duration::from_secs(2);
+/// Upper bound on the lazy registration retry delay.
+const LAZY_REGISTRATION_RETRY_MAX: Duration = Duration::from_secs(60);
+
+/// Register a server whose tunnels are already running (lazy registration).
+///
+/// Retries with exponential backoff until it succeeds or shutdown begins,
+/// then publishes the node_id so heartbeats and reconnects pick it up.
+/// A `stale_node` left by a crashed instance is unregistered first, retried
+/// along with the registration.
+async fn register_pending(
+    state: Arc<AppState>,
+    server: Arc<ServerContext>,
+    mut stale_node: Option<String>,
+    public_ip: String,
+    hw_info: crate::hardware::HardwareInfo,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let label = &server.server_label;
+    let mut delay = LAZY_REGISTRATION_RETRY_MIN;
+    let mut attempt = 0u32;
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.changed() => {
+                info!(server = %label, "shutdown during pending registration");
+                return;
+            }
+        }
+        attempt += 1;
+
+        if let Some(stale) = &stale_node {
+            if unregister_stale_node(label, &server.aether_client, stale).await {
+                stale_node = None;
+            }
+        }
+
+        let node_name = server.dynamic.load().node_name.clone();
+        match server
+            .aether_client
+            .register(
+                &state.config,
+                &node_name,
+                server.node_region.as_deref(),
+                &server.node_tags,
+                &public_ip,
+                Some(&hw_info),
+            )
+            .await
+        {
+            Ok(node_id) => {
+                info!(server = %label, node_id = %node_id, attempt, "pending registration succeeded");
+                publish_node_id(&server, node_id);
+                // Releases the tunnel connections held back until now.
+                server.node_id_changed.send_replace(());
+                return;
+            }
+            Err(e) => {
+                delay = (delay * 2).min(LAZY_REGISTRATION_RETRY_MAX);
+                warn!(
+                    server = %label,
+                    attempt,
+                    retry_in_secs = delay.as_secs(),
+                    error = %e,
+                    "pending registration failed"
+                );
+            }
+        }
+    }
+}
+
+/// Make a (new) node_id visible to heartbeats and future reconnects.
+fn publish_node_id(server: &ServerContext, node_id: String) {
+    let floor = StateFile::config_version_floor(&node_id);
+    server.dynamic.rcu(|current| {
+        let mut next = DynamicConfig::clone(current);
+        next.config_version_floor = next.config_version_floor.max(floor);
+        next
+    });
+    *server.node_id.write().unwrap() = node_id;
+}
+
+/// Keep a registered server's node record alive in Aether.
+///
+/// Re-registers (an idempotent upsert) every `reregister_interval_secs`, if
+/// set, and whenever a heartbeat ACK reports the node missing.  Heartbeats
+/// pick up a changed node_id at once; tunnel connections drain and reconnect
+/// so their handshake carries it.
+async fn keep_registered(
+    state: Arc<AppState>,
+    server: Arc<ServerContext>,
+    public_ip: String,
+    hw_info: crate::hardware::HardwareInfo,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let label = &server.server_label;
+    let interval = Duration::from_secs(state.config.reregister_interval_secs);
+    loop {
+        let periodic = async {
+            if interval.is_zero() {
+                std::future::pending::<()>().await;
+            }
+            tokio::time::sleep(interval).await;
+        };
+        tokio::select! {
+            _ = periodic => {}
+            _ = server.reregister.notified() => {
+                info!(server = %label, "node reported missing, re-registering");
+            }
+            _ = shutdown.changed() => return,
+        }
+        // Pending registrations are handled by `register_pending`.
+        if !server.is_registered() {
+            continue;
+        }
+
+        let node_name = server.dynamic.load().node_name.clone();
+        match server
+            .aether_client
+            .register(
+                &state.config,
+                &node_name,
+                server.node_region.as_deref(),
+                &server.node_tags,
+                &public_ip,
+                Some(&hw_info),
+            )
+            .await
+        {
+            Ok(node_id) => {
+                let previous = server.node_id.read().unwrap().clone();
+                if node_id == previous {
+                    debug!(server = %label, node_id = %node_id, "re-registered");
+                } else {
+                    warn!(
+                        server = %label,
+                        old_node_id = %previous,
+                        node_id = %node_id,
+                        "re-registered with a new node_id"
+                    );
+                    publish_node_id(&server, node_id);
+                    server.node_id_changed.send_replace(());
+                }
+            }
+            Err(e) => {
+                warn!(server = %label, error = %e, "re-registration failed");
+            }
+        }
+    }
+}
+
+/// First retry delay for failed server registrations; doubles per attempt.
+const REGISTRATION_RETRY_BASE: Duration = Duration::from_secs(5);
+/// Upper bound on the registration retry delay (5 minutes).
 const REGISTRATION_RETRY_INTERVAL: Duration = Duration::from_secs(300);
 /// Max registration retry attempts before giving up.
 const REGISTRATION_RETRY_MAX: u32 = 12;
@@ -226,37 +986,64 @@ const REGISTRATION_RETRY_MAX: u32 = 12;
 async fn retry_failed_registrations(
     state: Arc<AppState>,
     server_contexts: Arc<Mutex<Vec<Arc<ServerContext>>>>,
-    failed: Vec<(String, ServerEntry)>,
+    tunnel_handles: TunnelHandles,
+    failed: Vec<(
+        String,
+        ServerEntry,
+        Arc<rustls::ClientConfig>,
+        Option<String>,
+    )>,
     public_ip: String,
     hw_info: crate::hardware::HardwareInfo,
-    pool_size: usize,
     mut shutdown: watch::Receiver<bool>,
 ) {
-    for (label, entry) in &failed {
+    let pool_size = state.config.tunnel_connections.max(1) as usize;
+    for (label, entry, tls_config, stale_node) in &failed {
         let node_name = entry
             .node_name
             .clone()
             .unwrap_or_else(|| state.config.node_name.clone());
+        let node_region = entry
+            .node_region
+            .clone()
+            .or_else(|| state.config.node_region.clone());
         let client = Arc::new(AetherClient::new(
             &state.config,
             &entry.aether_url,
             &entry.management_token,
+            tls_config,
+            StateFile::client_instance_id(&entry.aether_url, &node_name),
         ));
 
+        let mut stale_node = stale_node.clone();
         let mut attempt = 0u32;
         let node_id = loop {
             attempt += 1;
 
             tokio::select! {
-                _ = tokio::time::sleep(REGISTRATION_RETRY_INTERVAL) => {}
+                _ = tokio::time::sleep(jitter_delay(registration_retry_delay(attempt))) => {}
                 _ = shutdown.changed() => {
                     info!(server = %label, "shutdown during registration retry");
                     return;
                 }
             }
 
+            // Clear the crashed instance's node before registering anew.
+            if let Some(stale) = &stale_node {
+                if unregister_stale_node(label, &client, stale).await {
+                    stale_node = None;
+                }
+            }
+
             match client
-                .register(&state.config, &node_name, &public_ip, Some(&hw_info))
+                .register(
+                    &state.config,
+                    &node_name,
+                    node_region.as_deref(),
+                    &state.config.node_tags_for(entry),
+                    &public_ip,
+                    Some(&hw_info),
+                )
                 .await
             {
                 Ok(id) => {
@@ -280,35 +1067,65 @@ async fn retry_failed_registrations(
         };
 
         // Build server context and spawn tunnels
-        let mut dynamic = DynamicConfig::from_config(&state.config);
-        dynamic.node_name = node_name.clone();
-        let server = Arc::new(ServerContext {
-            server_label: label.clone(),
-            aether_url: entry.aether_url.clone(),
-            management_token: entry.management_token.clone(),
+        let server = new_server_context(
+            &state.config,
+            label.clone(),
+            entry,
             node_name,
-            node_id: Arc::new(RwLock::new(node_id)),
-            aether_client: client,
-            dynamic: Arc::new(ArcSwap::from_pointee(dynamic)),
-            active_connections: Arc::new(AtomicU64::new(0)),
-            metrics: Arc::new(ProxyMetrics::new()),
-        });
+            node_region,
+            node_id,
+            client,
+            Arc::clone(tls_config),
+        );
 
         // Add to shared list so shutdown can unregister this server
         server_contexts.lock().await.push(Arc::clone(&server));
 
-        for conn_idx in 0..pool_size {
+        // Standby secondaries are started by the failover task.
+        let conns = if state.starts_active(&server) {
+            pool_size
+        } else {
+            0
+        };
+        for conn_idx in 0..conns {
             let s = Arc::clone(&state);
             let srv = Arc::clone(&server);
             let rx = shutdown.clone();
-            tokio::spawn(async move {
-                tunnel::run(&s, &srv, conn_idx, rx).await;
-            });
+            tunnel_handles.lock().await.push((
+                label.clone(),
+                tokio::spawn(async move {
+                    tunnel::run(&s, &srv, conn_idx, rx).await;
+                }),
+            ));
         }
+        tokio::spawn(keep_registered(
+            Arc::clone(&state),
+            server,
+            public_ip.clone(),
+            hw_info.clone(),
+            shutdown.clone(),
+        ));
     }
 }
 
-fn init_tracing(config: &Config) {
+/// Exponential backoff for registration retry `attempt` (1-based), capped at
+/// `REGISTRATION_RETRY_INTERVAL`.
+fn registration_retry_delay(attempt: u32) -> Duration {
+    let shift = attempt.saturating_sub(1).min(16);
+    REGISTRATION_RETRY_BASE
+        .saturating_mul(1 << shift)
+        .min(REGISTRATION_RETRY_INTERVAL)
+}
+
+/// Install the global subscriber.  Returns the log file writer's and the
+/// span exporter's guards, which flush pending lines and spans when dropped.
+fn init_tracing(
+    config: &Config,
+) -> anyhow::Result<(
+    Option<tracing_appender::non_blocking::WorkerGuard>,
+    Option<telemetry::TelemetryGuard>,
+)> {
+    use tracing_subscriber::filter::dynamic_filter_fn;
     use tracing_subscriber::prelude::*;
     use tracing_subscriber::{reload, EnvFilter};
 
@@ -316,25 +1133,100 @@ fn init_tracing(config: &Config) {
 
     let (filter_layer, reload_handle) = reload::Layer::new(filter);
 
-    runtime::set_log_reloader(Box::new(move |level: &str| {
-        if let Ok(new_filter) = EnvFilter::try_new(level) {
-            let _ = reload_handle.modify(|f| *f = new_filter);
+    runtime::set_log_reloader(
+        Box::new(move |level: &str| {
+            if let Ok(new_filter) = EnvFilter::try_new(level) {
+                let _ = reload_handle.modify(|f| *f = new_filter);
+            }
+        }),
+        &config.log_level,
+        config.log_json,
+    );
+
+    let (file_writer, guard) = match &config.log_file {
+        Some(path) => {
+            let rotation = log_file::LogRotation::parse(&config.log_rotation)?;
+            let file = log_file::RotatingFile::open(Path::new(path), rotation)
+                .map_err(|e| anyhow::anyhow!("failed to open log file {}: {}", path, e))?;
+            let (writer, guard) = tracing_appender::non_blocking(file);
+            (Some(writer), Some(guard))
         }
-    }));
+        None => (None, None),
+    };
+    let telemetry = config
+        .otel_endpoint
+        .as_deref()
+        .map(telemetry::init)
+        .transpose()?;
 
-    if config.log_json {
-        tracing_subscriber::registry()
-            .with(filter_layer)
-            .with(tracing_subscriber::fmt::layer().json())
-            .init();
-    } else {
-        tracing_subscriber::registry()
-            .with(filter_layer)
-            .with(tracing_subscriber::fmt::layer())
-            .init();
-    }
+    // Both formatters are installed per destination; a per-event check of
+    // the runtime flag picks one, so SIGUSR2 can switch without rebuilding
+    // the subscriber.
+    let stderr = config.log_stderr;
+    tracing_subscriber::registry()
+        .with(filter_layer)
+        .with(stderr.then(|| {
+            tracing_subscriber::fmt::layer()
+                .with_filter(dynamic_filter_fn(|_, _| !runtime::log_json()))
+        }))
+        .with(stderr.then(|| {
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_filter(dynamic_filter_fn(|_, _| runtime::log_json()))
+        }))
+        .with(file_writer.clone().map(|writer| {
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(writer)
+                .with_filter(dynamic_filter_fn(|_, _| !runtime::log_json()))
+        }))
+        .with(file_writer.map(|writer| {
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_writer(writer)
+                .with_filter(dynamic_filter_fn(|_, _| runtime::log_json()))
+        }))
+        .with(telemetry.as_ref().map(telemetry::layer))
+        .init();
+    Ok((guard, telemetry))
 }
 
+/// `SIGUSR1` raises log verbosity one level (wrapping to `error` after
+/// `trace`); `SIGUSR2` toggles JSON output.
+#[cfg(unix)]
+fn spawn_log_signal_handlers() {
+    use signal::unix::{signal, SignalKind};
+
+    let (mut usr1, mut usr2) = match (
+        signal(SignalKind::user_defined1()),
+        signal(SignalKind::user_defined2()),
+    ) {
+        (Ok(usr1), Ok(usr2)) => (usr1, usr2),
+        (Err(e), _) | (_, Err(e)) => {
+            warn!(error = %e, "failed to install log signal handlers");
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                Some(()) = usr1.recv() => {
+                    let level = runtime::cycle_log_level();
+                    warn!(level, "log level changed (SIGUSR1)");
+                }
+                Some(()) = usr2.recv() => {
+                    let json = runtime::toggle_log_json();
+                    warn!(format = if json { "json" } else { "pretty" }, "log format changed (SIGUSR2)");
+                }
+                else => break,
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn spawn_log_signal_handlers() {}
+
 async fn wait_for_shutdown() {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -358,3 +1250,32 @@ async fn wait_for_shutdown() {
         _ = terminate => {},
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failover_enters_and_leaves_with_hysteresis() {
+        assert!(!failover_active(false, Some(100), 20, 80));
+        assert!(!failover_active(false, Some(20), 20, 80));
+        assert!(failover_active(false, Some(10), 20, 80));
+        // No registered primary counts as failed.
+        assert!(failover_active(false, None, 20, 80));
+        // Stays active until a primary reaches the recovery threshold.
+        assert!(failover_active(true, Some(50), 20, 80));
+        assert!(!failover_active(true, Some(80), 20, 80));
+    }
+
+    #[test]
+    fn registration_retry_delay_doubles_up_to_cap() {
+        assert_eq!(registration_retry_delay(1), Duration::from_secs(5));
+        assert_eq!(registration_retry_delay(2), Duration::from_secs(10));
+        assert_eq!(registration_retry_delay(4), Duration::from_secs(40));
+        assert_eq!(registration_retry_delay(7), REGISTRATION_RETRY_INTERVAL);
+        assert_eq!(
+            registration_retry_delay(u32::MAX),
+            REGISTRATION_RETRY_INTERVAL
+        );
+    }
+}
@@ -0,0 +1,72 @@
+//! Version and build metadata of this binary.
+
+use std::fmt::Write as _;
+
+use serde::Serialize;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Target triple the binary was compiled for (e.g. `x86_64-unknown-linux-musl`).
+pub const BUILD_TARGET: &str = env!("AETHER_PROXY_BUILD_TARGET");
+
+/// Build time as `YYYY-MM-DDTHH:MM:SSZ` (`SOURCE_DATE_EPOCH` when set).
+pub const BUILD_DATE: &str = env!("AETHER_PROXY_BUILD_DATE");
+
+/// `rustc --version` of the compiler that built the binary.
+pub const RUSTC_VERSION: &str = env!("AETHER_PROXY_RUSTC_VERSION");
+
+/// Short git revision, if the build had one.
+pub fn git_sha() -> Option<&'static str> {
+    Some(env!("AETHER_PROXY_GIT_SHA")).filter(|s| !s.is_empty())
+}
+
+/// Enabled cargo features, sorted.
+pub fn features() -> Vec<&'static str> {
+    env!("AETHER_PROXY_FEATURES")
+        .split(',')
+        .filter(|f| !f.is_empty())
+        .collect()
+}
+
+/// Structured build info, reported to Aether in `hardware_info.build`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: Option<&'static str>,
+    pub build_date: &'static str,
+    pub rustc: &'static str,
+    pub target: &'static str,
+    pub features: Vec<&'static str>,
+}
+
+pub fn info() -> BuildInfo {
+    BuildInfo {
+        version: VERSION,
+        git_sha: git_sha(),
+        build_date: BUILD_DATE,
+        rustc: RUSTC_VERSION,
+        target: BUILD_TARGET,
+        features: features(),
+    }
+}
+
+/// Multi-line version block for `aether-proxy version --verbose`.
+pub fn verbose_version() -> String {
+    let info = info();
+    let mut out = format!("aether-proxy {}\n", info.version);
+    let features = if info.features.is_empty() {
+        "(none)".to_string()
+    } else {
+        info.features.join(", ")
+    };
+    for (label, value) in [
+        ("commit", info.git_sha.unwrap_or("unknown")),
+        ("build date", info.build_date),
+        ("rustc", info.rustc),
+        ("target", info.target),
+        ("features", &features),
+    ] {
+        let _ = writeln!(out, "  {:<11} {}", format!("{label}:"), value);
+    }
+    out
+}
@@ -1,8 +1,125 @@
-use std::path::Path;
+use std::collections::{BTreeMap, HashSet};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
 use clap::Parser;
 use serde::{Deserialize, Serialize};
 
+use crate::header_rules::HeaderRules;
+use crate::setup::service::ServiceConfig;
+use crate::target_filter::PortRange;
+use crate::tls::TlsRoots;
+use crate::tunnel::compression::ContentTypeRules;
+use crate::tunnel::heartbeat::REPORTABLE_FIELDS;
+use crate::tunnel::writer::WRITER_CHANNEL_CAPACITY;
+use crate::upstream_auth::UpstreamAuth;
+use crate::upstream_client::UpstreamPoolOverrides;
+
+/// Default config file name.
+pub const DEFAULT_CONFIG: &str = "aether-proxy.toml";
+
+/// Built-in config profiles, selectable with `--profile <name>`.  A profile
+/// of the same name under `[profiles.<name>]` in the config file replaces
+/// the built-in one.
+pub const DEFAULT_PROFILES: &str = r#"
+[profiles.development]
+log_level = "debug"
+tunnel_connections = 1
+
+# Production uses the regular defaults.
+[profiles.production]
+"#;
+
+/// Credential headers masked in logs unless `log_redact_headers` is set.
+const DEFAULT_LOG_REDACT_HEADERS: [&str; 7] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+    "api-key",
+    "x-goog-api-key",
+];
+
+/// Keys a profile may not override.
+const PROFILE_EXCLUDED_KEYS: &[&str] = &["include", "profiles", "servers", "service"];
+
+/// `--strict-config` / `AETHER_PROXY_STRICT_CONFIG`, read like
+/// [`selected_profile`] because it governs how the file itself is loaded.
+fn strict_config_requested() -> bool {
+    let mut args = std::env::args().skip(1);
+    let value = loop {
+        match args.next() {
+            Some(arg) if arg == "--strict-config" => break args.next(),
+            Some(arg) => {
+                if let Some(value) = arg.strip_prefix("--strict-config=") {
+                    break Some(value.to_string());
+                }
+            }
+            None => break std::env::var("AETHER_PROXY_STRICT_CONFIG").ok(),
+        }
+    };
+    value.is_some_and(|v| v.parse().unwrap_or(false))
+}
+
+/// Profile selected via `--profile` or `AETHER_PROXY_PROFILE`.
+///
+/// Read straight from argv because the config file is loaded (and injected
+/// as env defaults) before clap parses the command line.
+pub fn selected_profile() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--profile" {
+            return args.next();
+        }
+        if let Some(name) = arg.strip_prefix("--profile=") {
+            return Some(name.to_string());
+        }
+    }
+    std::env::var("AETHER_PROXY_PROFILE")
+        .ok()
+        .filter(|v| !v.is_empty())
+}
+
+/// Path of the active config file (`AETHER_PROXY_CONFIG` or the default).
+pub fn config_file_path() -> PathBuf {
+    std::env::var("AETHER_PROXY_CONFIG")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(DEFAULT_CONFIG))
+}
+
+/// Mask a secret for display, keeping a short prefix for identification
+/// (e.g. `ae_1234...` -> `ae_1***`).
+pub fn redact_secret(secret: &str) -> String {
+    if secret.is_empty() {
+        return String::new();
+    }
+    let prefix: String = secret.chars().take(4).collect();
+    if prefix.len() == secret.len() {
+        "***".to_string()
+    } else {
+        format!("{}***", prefix)
+    }
+}
+
+/// Whether an `AETHER_PROXY_*` env var / config key holds a secret.
+pub fn is_secret_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    key.contains("token") || key.contains("secret")
+}
+
+/// Parse a `key=value` node tag.
+fn parse_node_tag(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected key=value, got '{}'", s))?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(format!("empty tag key in '{}'", s));
+    }
+    Ok((key.to_string(), value.trim().to_string()))
+}
+
 /// Fields that existed in 0.1.x but were removed in 0.2.0.
 const LEGACY_ONLY_KEYS: &[&str] = &[
     "hmac_key",
@@ -53,6 +170,21 @@ pub struct Config {
     #[arg(long, env = "AETHER_PROXY_PUBLIC_IP")]
     pub public_ip: Option<String>,
 
+    /// Write the process id to this file while running (lets `status`
+    /// work without systemd)
+    #[arg(long, env = "AETHER_PROXY_PID_FILE")]
+    pub pid_file: Option<String>,
+
+    /// POST tunnel state changes (connected / disconnected / reconnecting)
+    /// as JSON to this URL
+    #[arg(long, env = "AETHER_PROXY_NOTIFICATION_WEBHOOK_URL")]
+    pub notification_webhook_url: Option<String>,
+
+    /// Sign webhook bodies with HMAC-SHA256 of this secret
+    /// (`X-Aether-Signature: sha256=<hex>`)
+    #[arg(long, env = "AETHER_PROXY_NOTIFICATION_WEBHOOK_SECRET")]
+    pub notification_webhook_secret: Option<String>,
+
     /// Human-readable node name
     #[arg(long, env = "AETHER_PROXY_NODE_NAME", default_value = "proxy-01")]
     pub node_name: String,
@@ -61,18 +193,57 @@ pub struct Config {
     #[arg(long, env = "AETHER_PROXY_NODE_REGION")]
     pub node_region: Option<String>,
 
+    /// Node tags reported to Aether (key=value, comma-separated)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_NODE_TAGS",
+        value_delimiter = ',',
+        value_parser = parse_node_tag
+    )]
+    pub node_tags: Vec<(String, String)>,
+
+    /// Skip public IP / region auto-detection via third-party services
+    /// (the Aether server uses the observed source address instead)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_DISABLE_IP_DETECTION",
+        default_value_t = false
+    )]
+    pub disable_ip_detection: bool,
+
     /// Heartbeat interval in seconds
     #[arg(long, env = "AETHER_PROXY_HEARTBEAT_INTERVAL", default_value_t = 30)]
     pub heartbeat_interval: u64,
 
-    /// Allowed destination ports (default: 80,443,8080,8443)
+    /// Metrics to include in heartbeats (comma-separated, e.g.
+    /// total_requests,failed_requests); empty reports all.  node_id and the
+    /// heartbeat ids are always sent.
+    #[arg(
+        long,
+        env = "AETHER_PROXY_HEARTBEAT_REPORT_FIELDS",
+        value_delimiter = ','
+    )]
+    pub heartbeat_report_fields: Vec<String>,
+
+    /// Request headers whose values are masked when logged (comma-separated,
+    /// case-insensitive)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_LOG_REDACT_HEADERS",
+        value_delimiter = ',',
+        default_values_t = DEFAULT_LOG_REDACT_HEADERS.map(String::from)
+    )]
+    pub log_redact_headers: Vec<String>,
+
+    /// Allowed destination ports and port ranges, e.g. 443,8000-8999
+    /// (default: 80,443,8080,8443)
     #[arg(
         long,
         env = "AETHER_PROXY_ALLOWED_PORTS",
         value_delimiter = ',',
-        default_values_t = vec![80, 443, 8080, 8443]
+        default_values_t = [80, 443, 8080, 8443].map(PortRange::from)
     )]
-    pub allowed_ports: Vec<u16>,
+    pub allowed_ports: Vec<PortRange>,
 
     /// Aether API request timeout in seconds
     #[arg(
@@ -118,6 +289,14 @@ pub struct Config {
     #[arg(long, env = "AETHER_PROXY_AETHER_HTTP2", default_value_t = true)]
     pub aether_http2: bool,
 
+    /// Overall timeout for unregistering from all servers at shutdown, in seconds
+    #[arg(
+        long,
+        env = "AETHER_PROXY_SHUTDOWN_UNREGISTER_TIMEOUT",
+        default_value_t = 5
+    )]
+    pub shutdown_unregister_timeout_secs: u64,
+
     /// Aether API retry attempts (including initial)
     #[arg(
         long,
@@ -154,6 +333,11 @@ pub struct Config {
     #[arg(long, env = "AETHER_PROXY_DNS_CACHE_CAPACITY", default_value_t = 1024)]
     pub dns_cache_capacity: usize,
 
+    /// Most DNS lookups in flight at once (concurrent misses for the same
+    /// host share one lookup)
+    #[arg(long, env = "AETHER_PROXY_DNS_MAX_INFLIGHT", default_value_t = 32)]
+    pub dns_max_inflight: usize,
+
     /// Upstream HTTP client connect timeout in seconds
     #[arg(
         long,
@@ -178,6 +362,16 @@ pub struct Config {
     )]
     pub upstream_pool_idle_timeout_secs: u64,
 
+    /// Per-host pool idle timeouts for hot hosts (JSON object of host ->
+    /// {idle_timeout_secs}; `[upstream_pool_overrides]` table in the config
+    /// file)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_POOL_OVERRIDES",
+        default_value = "{}"
+    )]
+    pub upstream_pool_overrides: UpstreamPoolOverrides,
+
     /// Upstream TCP keepalive in seconds (0 disables)
     #[arg(
         long,
@@ -194,6 +388,126 @@ pub struct Config {
     )]
     pub upstream_tcp_nodelay: bool,
 
+    /// Header used to forward the stream's request id upstream (empty disables)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_REQUEST_ID_HEADER",
+        default_value = "x-request-id"
+    )]
+    pub upstream_request_id_header: String,
+
+    /// Retry GET/HEAD/PUT/DELETE on connect errors before any response
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_RETRY_IDEMPOTENT",
+        default_value_t = false
+    )]
+    pub upstream_retry_idempotent: bool,
+
+    /// Stream request bodies with the Content-Length Aether declares instead
+    /// of chunked; a body that turns out longer or shorter fails the stream
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_FORWARD_CONTENT_LENGTH",
+        default_value_t = false
+    )]
+    pub upstream_forward_content_length: bool,
+
+    /// Gunzip `Content-Encoding: gzip` upstream responses while streaming
+    /// them back (the encoding and length headers are dropped)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_DECOMPRESS_RESPONSES",
+        default_value_t = false
+    )]
+    pub upstream_decompress_responses: bool,
+
+    /// Response body compression per content type (JSON object of
+    /// type/subtype or type/* -> "on" / "off" / minimum size in bytes;
+    /// `[compression.content_types]` table in the config file)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_COMPRESSION_CONTENT_TYPES",
+        default_value = "{}"
+    )]
+    pub compression_content_types: ContentTypeRules,
+
+    /// Replace the User-Agent supplied by Aether on upstream requests
+    #[arg(long, env = "AETHER_PROXY_UPSTREAM_USER_AGENT_OVERRIDE")]
+    pub upstream_user_agent_override: Option<String>,
+
+    /// Append `Via: 1.1 aether-proxy/<version>` to upstream requests
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_APPEND_VIA",
+        default_value_t = false
+    )]
+    pub upstream_append_via: bool,
+
+    /// Only allow https (and wss) target URLs
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_REQUIRE_HTTPS",
+        default_value_t = false,
+        action = clap::ArgAction::Set
+    )]
+    pub upstream_require_https: bool,
+
+    /// Headers set on every upstream request (name=value, comma-separated);
+    /// values may contain {node_name}, {node_id} and {version}
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_IDENTITY_HEADERS",
+        value_delimiter = ',',
+        value_parser = parse_node_tag
+    )]
+    pub upstream_identity_headers: Vec<(String, String)>,
+
+    /// Header set/remove/rename rules applied to upstream requests (JSON
+    /// list; `[[upstream_header_rules]]` tables in the config file)
+    #[arg(long, env = "AETHER_PROXY_UPSTREAM_HEADER_RULES", default_value = "[]")]
+    pub upstream_header_rules: HeaderRules,
+
+    /// Header set/remove/rename rules applied to upstream responses before
+    /// they are sent back through the tunnel (JSON list;
+    /// `[[response_header_rules]]` tables in the config file)
+    #[arg(long, env = "AETHER_PROXY_RESPONSE_HEADER_RULES", default_value = "[]")]
+    pub response_header_rules: HeaderRules,
+
+    /// Static upstream credentials by host, injected when the request has
+    /// no Authorization of its own (JSON object of host -> {header, value};
+    /// `[upstream_auth]` table in the config file)
+    #[arg(long, env = "AETHER_PROXY_UPSTREAM_AUTH", default_value = "{}")]
+    pub upstream_auth: UpstreamAuth,
+
+    /// Root certificates trusted for upstream HTTPS (webpki, native, both)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_TLS_ROOTS",
+        value_enum,
+        default_value_t = TlsRoots::Webpki
+    )]
+    pub upstream_tls_roots: TlsRoots,
+
+    /// Extra PEM CA bundle appended to the upstream root store
+    #[arg(long, env = "AETHER_PROXY_UPSTREAM_EXTRA_CA_FILE")]
+    pub upstream_extra_ca_file: Option<String>,
+
+    /// Cap on upstream requests per second across all streams and servers
+    /// (0 = unlimited)
+    #[arg(long, env = "AETHER_PROXY_GLOBAL_MAX_RPS", default_value_t = 0)]
+    pub global_max_rps: u32,
+
+    /// Cap on request body bytes per second sent upstream, across all
+    /// streams (0 = unlimited)
+    #[arg(long, env = "AETHER_PROXY_MAX_UPSTREAM_BPS", default_value_t = 0)]
+    pub max_upstream_bps: u64,
+
+    /// Cap on response body bytes per second relayed back through the
+    /// tunnels, across all streams (0 = unlimited)
+    #[arg(long, env = "AETHER_PROXY_MAX_DOWNSTREAM_BPS", default_value_t = 0)]
+    pub max_downstream_bps: u64,
+
     /// Log level (trace, debug, info, warn, error)
     #[arg(long, env = "AETHER_PROXY_LOG_LEVEL", default_value = "info")]
     pub log_level: String,
@@ -202,6 +516,30 @@ pub struct Config {
     #[arg(long, env = "AETHER_PROXY_LOG_JSON", default_value_t = false)]
     pub log_json: bool,
 
+    /// Also write logs to this file (same format as stderr, no colors)
+    #[arg(long, env = "AETHER_PROXY_LOG_FILE")]
+    pub log_file: Option<String>,
+
+    /// Log file rotation: `<size>:<keep>` (e.g. `100M:5` rotates at 100 MiB
+    /// and keeps 5 old files) or `never`
+    #[arg(long, env = "AETHER_PROXY_LOG_ROTATION", default_value = "100M:5")]
+    pub log_rotation: String,
+
+    /// Write logs to stderr (disable to log only to `log_file`)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_LOG_STDERR",
+        default_value_t = true,
+        action = clap::ArgAction::Set
+    )]
+    pub log_stderr: bool,
+
+    /// Export tracing spans to this OTLP/HTTP traces endpoint (e.g.
+    /// `http://collector:4318/v1/traces`) and continue Aether's
+    /// `traceparent` upstream
+    #[arg(long, env = "AETHER_PROXY_OTEL_ENDPOINT")]
+    pub otel_endpoint: Option<String>,
+
     /// Tunnel reconnect base delay in milliseconds (used by exponential backoff)
     #[arg(
         long,
@@ -242,13 +580,234 @@ pub struct Config {
     #[arg(long, env = "AETHER_PROXY_TUNNEL_TCP_NODELAY", default_value_t = true)]
     pub tunnel_tcp_nodelay: bool,
 
-    /// Tunnel connection staleness timeout in seconds (triggers reconnect if no data received)
+    /// SO_RCVLOWAT for the tunnel socket: minimum bytes buffered before a
+    /// read wakes up (advisory on Linux, enforced on macOS/BSD; Unix only)
+    #[arg(long, env = "AETHER_PROXY_TUNNEL_TCP_RECV_LOWAT")]
+    pub tunnel_tcp_recv_lowat: Option<u32>,
+
+    /// SO_SNDLOWAT for the tunnel socket: minimum free send buffer space
+    /// before a write wakes up (not settable on Linux; Unix only)
+    #[arg(long, env = "AETHER_PROXY_TUNNEL_TCP_SEND_LOWAT")]
+    pub tunnel_tcp_send_lowat: Option<u32>,
+
+    /// Idle seconds before a tunnel connection is probed; it is reconnected
+    /// only if the probe also goes unanswered
     #[arg(long, env = "AETHER_PROXY_TUNNEL_STALE_TIMEOUT", default_value_t = 45)]
     pub tunnel_stale_timeout_secs: u64,
 
+    /// Offer cross-connection responses in the tunnel handshake: when
+    /// Aether accepts, a stream whose connection is lost or congested
+    /// finishes over the healthiest other connection to the same server
+    #[arg(
+        long,
+        env = "AETHER_PROXY_TUNNEL_RESPONSE_REROUTING",
+        default_value_t = true,
+        action = clap::ArgAction::Set
+    )]
+    pub tunnel_response_rerouting: bool,
+
+    /// Seconds in-flight streams may keep running after the proxy rotates a
+    /// tunnel connection (node_id change, drain, shutdown) before they are
+    /// ended with `connection_rotating`
+    #[arg(
+        long,
+        env = "AETHER_PROXY_CONNECTION_ROTATION_GRACE",
+        default_value_t = 30
+    )]
+    pub connection_rotation_grace_secs: u64,
+
     /// Number of parallel WebSocket tunnel connections per server (connection pool)
     #[arg(long, env = "AETHER_PROXY_TUNNEL_CONNECTIONS", default_value_t = 3)]
     pub tunnel_connections: u32,
+
+    /// Upper bound for tunnel connections per server when the pool grows under load
+    #[arg(
+        long,
+        env = "AETHER_PROXY_MAX_TUNNEL_CONNECTIONS",
+        default_value_t = 10
+    )]
+    pub max_tunnel_connections: u32,
+
+    /// Active streams per connection above which a healthy server gets another connection
+    #[arg(
+        long,
+        env = "AETHER_PROXY_TARGET_STREAMS_PER_CONNECTION",
+        default_value_t = 64
+    )]
+    pub target_streams_per_connection: u32,
+
+    /// Per-stream request body channel depth (frames buffered per stream)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_STREAM_BODY_CHANNEL_DEPTH",
+        default_value_t = 64
+    )]
+    pub stream_body_channel_depth: usize,
+
+    /// Offer to number response body frames per stream and reorder
+    /// sequenced request body frames from Aether; used only on connections
+    /// where Aether accepts it in the handshake (needs an Aether that
+    /// understands the SEQUENCED flag)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_ENABLE_FRAME_SEQUENCING",
+        default_value_t = false
+    )]
+    pub enable_frame_sequencing: bool,
+
+    /// Run each stream handler on a per-core single-threaded runtime chosen
+    /// by stream id, instead of the shared multi-threaded runtime
+    #[arg(
+        long,
+        env = "AETHER_PROXY_ENABLE_STREAM_AFFINITY",
+        default_value_t = false
+    )]
+    pub enable_stream_affinity: bool,
+
+    /// Root certificates trusted for the tunnel and Aether API (webpki, native, both)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_TUNNEL_TLS_ROOTS",
+        value_enum,
+        default_value_t = TlsRoots::Webpki
+    )]
+    pub tunnel_tls_roots: TlsRoots,
+
+    /// Extra PEM CA bundle appended to the tunnel root store
+    #[arg(long, env = "AETHER_PROXY_TUNNEL_EXTRA_CA_FILE")]
+    pub tunnel_extra_ca_file: Option<String>,
+
+    /// Fragment outgoing WebSocket messages larger than this many bytes (0 = unlimited)
+    #[arg(long, env = "AETHER_PROXY_WS_MAX_MESSAGE_SIZE", default_value_t = 0)]
+    pub ws_max_message_size_bytes: usize,
+
+    /// Largest Ping/Pong/heartbeat frame payload accepted from Aether; bigger
+    /// ones are dropped
+    #[arg(
+        long,
+        env = "AETHER_PROXY_MAX_CONTROL_FRAME_BYTES",
+        default_value_t = 64 * 1024
+    )]
+    pub max_control_frame_bytes: usize,
+
+    /// Write remote config pushed by Aether back into the config file
+    #[arg(
+        long,
+        env = "AETHER_PROXY_PERSIST_REMOTE_CONFIG",
+        default_value_t = false
+    )]
+    pub persist_remote_config: bool,
+
+    /// Writer queue depth at which the dispatcher pauses intake and rejects
+    /// new streams
+    #[arg(
+        long,
+        env = "AETHER_PROXY_WRITER_BACKPRESSURE_HIGH_WATERMARK",
+        default_value_t = 200
+    )]
+    pub writer_backpressure_high_watermark: usize,
+
+    /// Writer queue depth below which new streams are accepted again
+    #[arg(
+        long,
+        env = "AETHER_PROXY_WRITER_BACKPRESSURE_LOW_WATERMARK",
+        default_value_t = 100
+    )]
+    pub writer_backpressure_low_watermark: usize,
+
+    /// Reject new streams once process RSS exceeds this many MiB (0 = off)
+    #[arg(long, env = "AETHER_PROXY_MEMORY_SOFT_LIMIT_MB", default_value_t = 0)]
+    pub memory_soft_limit_mb: u64,
+
+    /// Cancel the oldest large in-flight streams once process RSS exceeds
+    /// this many MiB (0 = off)
+    #[arg(long, env = "AETHER_PROXY_MEMORY_HARD_LIMIT_MB", default_value_t = 0)]
+    pub memory_hard_limit_mb: u64,
+
+    /// Cap on MiB buffered by all streams together (request bodies held for
+    /// integrity checks or retries, response chunks awaiting the writer);
+    /// once spent new streams are rejected and streams pause reading
+    /// upstream (0 = off, auto-detected from hardware memory if omitted)
+    #[arg(long, env = "AETHER_PROXY_GLOBAL_BUFFER_BUDGET_MB")]
+    pub global_buffer_budget_mb: Option<u64>,
+
+    /// Config profile to apply over the config file (built-in: development,
+    /// production)
+    #[arg(long, env = "AETHER_PROXY_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Keep retrying registration in the background instead of exiting when
+    /// Aether is unreachable at startup; tunnels connect once it succeeds
+    #[arg(long, env = "AETHER_PROXY_LAZY_REGISTRATION", default_value_t = false)]
+    pub lazy_registration: bool,
+
+    /// Re-register over HTTP every N seconds so a node whose record was
+    /// purged in Aether recovers without a reconnect (0 = only when a
+    /// heartbeat ACK reports the node missing)
+    #[arg(long, env = "AETHER_PROXY_REREGISTER_INTERVAL", default_value_t = 0)]
+    pub reregister_interval_secs: u64,
+
+    /// Activate `secondary` servers once every primary's health score is
+    /// below this (0-100)
+    #[arg(long, env = "AETHER_PROXY_FAILOVER_THRESHOLD", default_value_t = 20)]
+    pub failover_threshold: u8,
+
+    /// Drain `secondary` servers again once a primary's health score is
+    /// back at or above this (0-100)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_FAILOVER_RECOVERY_THRESHOLD",
+        default_value_t = 80
+    )]
+    pub failover_recovery_threshold: u8,
+
+    /// Also emit the legacy `x-proxy-timing` key names
+    /// (`upstream_processing_ms`, `body_size`); will be removed next release
+    #[arg(
+        long,
+        env = "AETHER_PROXY_PROXY_TIMING_LEGACY_KEYS",
+        default_value_t = true,
+        action = clap::ArgAction::Set
+    )]
+    pub proxy_timing_legacy_keys: bool,
+
+    /// Add the `x-proxy-timing` header to tunnelled responses; disable to
+    /// keep internal timings from reaching clients
+    #[arg(
+        long,
+        env = "AETHER_PROXY_INJECT_TIMING_HEADER",
+        default_value_t = true,
+        action = clap::ArgAction::Set
+    )]
+    pub inject_timing_header: bool,
+
+    /// Send an ErrorReport frame to Aether for each failed stream (URL
+    /// without query string, error kind, duration)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_ERROR_REPORTING_ENABLED",
+        default_value_t = true,
+        action = clap::ArgAction::Set
+    )]
+    pub error_reporting_enabled: bool,
+
+    /// Fraction of failed streams reported (0.0-1.0)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_ERROR_REPORT_SAMPLE_RATE",
+        default_value_t = 1.0
+    )]
+    pub error_report_sample_rate: f64,
+
+    /// Fail on any invalid config file entry instead of skipping bad
+    /// `[[servers]]` entries and keys with a warning
+    #[arg(
+        long,
+        env = "AETHER_PROXY_STRICT_CONFIG",
+        default_value_t = false,
+        action = clap::ArgAction::Set
+    )]
+    pub strict_config: bool,
 }
 
 impl Config {
@@ -261,14 +820,42 @@ impl Config {
         if self.heartbeat_interval > 3600 {
             anyhow::bail!("heartbeat_interval must be <= 3600");
         }
-        if self.allowed_ports.is_empty() {
-            anyhow::bail!("allowed_ports must not be empty");
+        for field in &self.heartbeat_report_fields {
+            if !REPORTABLE_FIELDS.contains(&field.as_str()) {
+                anyhow::bail!(
+                    "heartbeat_report_fields: unknown field '{}' (available: {})",
+                    field,
+                    REPORTABLE_FIELDS.join(", ")
+                );
+            }
+        }
+        if !(0.0..=1.0).contains(&self.error_report_sample_rate) {
+            anyhow::bail!("error_report_sample_rate must be between 0.0 and 1.0");
+        }
+        crate::log_file::LogRotation::parse(&self.log_rotation)?;
+        if !self.log_stderr && self.log_file.is_none() {
+            anyhow::bail!("log_stderr = false requires log_file");
         }
-        for &port in &self.allowed_ports {
-            if port == 0 {
-                anyhow::bail!("allowed_ports: port 0 is not valid");
+        if let Some(url) = &self.notification_webhook_url {
+            match url::Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+                _ => anyhow::bail!("notification_webhook_url must be an http(s) URL: {}", url),
             }
         }
+        if let Some(url) = &self.otel_endpoint {
+            match url::Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+                _ => anyhow::bail!("otel_endpoint must be an http(s) URL: {}", url),
+            }
+        }
+        if self.allowed_ports.is_empty() {
+            anyhow::bail!(
+                "allowed_ports must not be empty: every upstream target would be rejected"
+            );
+        }
+        if self.dns_max_inflight == 0 {
+            anyhow::bail!("dns_max_inflight must be > 0");
+        }
         if self.tunnel_connect_timeout_secs == 0 {
             anyhow::bail!("tunnel_connect_timeout_secs must be > 0");
         }
@@ -285,14 +872,105 @@ impl Config {
         if self.tunnel_connections == 0 {
             anyhow::bail!("tunnel_connections must be > 0");
         }
+        if self.target_streams_per_connection == 0 {
+            anyhow::bail!("target_streams_per_connection must be > 0");
+        }
+        if self.stream_body_channel_depth == 0 {
+            anyhow::bail!("stream_body_channel_depth must be > 0");
+        }
+        if self.tunnel_tcp_recv_lowat == Some(0) || self.tunnel_tcp_send_lowat == Some(0) {
+            anyhow::bail!("tunnel_tcp_recv_lowat / tunnel_tcp_send_lowat must be > 0");
+        }
+        if self.max_control_frame_bytes == 0 {
+            anyhow::bail!("max_control_frame_bytes must be > 0");
+        }
+        if self.writer_backpressure_high_watermark == 0
+            || self.writer_backpressure_high_watermark > WRITER_CHANNEL_CAPACITY
+        {
+            anyhow::bail!(
+                "writer_backpressure_high_watermark must be in 1..={}",
+                WRITER_CHANNEL_CAPACITY
+            );
+        }
+        if self.writer_backpressure_low_watermark >= self.writer_backpressure_high_watermark {
+            anyhow::bail!(
+                "writer_backpressure_low_watermark must be < writer_backpressure_high_watermark"
+            );
+        }
+        if self.memory_soft_limit_mb > 0
+            && self.memory_hard_limit_mb > 0
+            && self.memory_hard_limit_mb < self.memory_soft_limit_mb
+        {
+            anyhow::bail!("memory_hard_limit_mb must be >= memory_soft_limit_mb");
+        }
+        if self.shutdown_unregister_timeout_secs == 0 {
+            anyhow::bail!("shutdown_unregister_timeout_secs must be > 0");
+        }
         if self.aether_retry_max_attempts == 0 {
             anyhow::bail!("aether_retry_max_attempts must be >= 1");
         }
         if self.upstream_connect_timeout_secs == 0 {
             anyhow::bail!("upstream_connect_timeout_secs must be > 0");
         }
+        if self.failover_recovery_threshold > 100
+            || self.failover_threshold >= self.failover_recovery_threshold
+        {
+            anyhow::bail!(
+                "failover_threshold ({}) must be < failover_recovery_threshold ({}) <= 100",
+                self.failover_threshold,
+                self.failover_recovery_threshold
+            );
+        }
+        if !self.upstream_request_id_header.is_empty()
+            && hyper::header::HeaderName::from_bytes(self.upstream_request_id_header.as_bytes())
+                .is_err()
+        {
+            anyhow::bail!(
+                "upstream_request_id_header is not a valid header name: {}",
+                self.upstream_request_id_header
+            );
+        }
+        if let Some(ua) = &self.upstream_user_agent_override {
+            if hyper::header::HeaderValue::from_str(ua).is_err() {
+                anyhow::bail!("upstream_user_agent_override is not a valid header value: {ua}");
+            }
+        }
+        for (name, _) in &self.upstream_identity_headers {
+            if hyper::header::HeaderName::from_bytes(name.as_bytes()).is_err() {
+                anyhow::bail!("upstream_identity_headers: invalid header name: {name}");
+            }
+        }
         Ok(())
     }
+
+    /// Servers to connect to: `[[servers]]` (or the top-level server) from
+    /// `file`, else the single server given on the command line / env.
+    pub fn servers(&self, file: Option<&ConfigFile>) -> Vec<ServerEntry> {
+        file.map(ConfigFile::effective_servers)
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| {
+                vec![ServerEntry {
+                    aether_url: self.aether_url.clone(),
+                    management_token: self.management_token.clone(),
+                    node_name: Some(self.node_name.clone()),
+                    node_region: None,
+                    node_tags: BTreeMap::new(),
+                    role: ServerRole::Primary,
+                    tls_sni_override: None,
+                    tls_ca_cert: None,
+                    tls_ca_only_custom: false,
+                    bind_address: None,
+                }]
+            })
+    }
+
+    /// Tags to register with `entry`: the global `node_tags` overlaid with
+    /// the server's own.
+    pub fn node_tags_for(&self, entry: &ServerEntry) -> BTreeMap<String, String> {
+        let mut tags: BTreeMap<String, String> = self.node_tags.iter().cloned().collect();
+        tags.extend(entry.node_tags.clone());
+        tags
+    }
 }
 
 /// Per-server connection config (used in multi-server TOML `[[servers]]`).
@@ -302,6 +980,46 @@ pub struct ServerEntry {
     pub management_token: String,
     /// Per-server node name override. Falls back to the global `node_name`.
     pub node_name: Option<String>,
+    /// Per-server region override. Falls back to the global `node_region`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_region: Option<String>,
+    /// Per-server tags, merged over the global `node_tags`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub node_tags: BTreeMap<String, String>,
+    /// `secondary` servers only get tunnels while every primary is unhealthy.
+    #[serde(default, skip_serializing_if = "ServerRole::is_primary")]
+    pub role: ServerRole,
+    /// TLS SNI (and certificate name) for the tunnel handshake, when
+    /// `aether_url` is an IP or a CDN name the certificate doesn't cover.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_sni_override: Option<String>,
+    /// PEM CA certificate(s) trusted for this server, on top of the tunnel
+    /// root store (for Aether instances behind an internal CA).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_ca_cert: Option<String>,
+    /// Trust only `tls_ca_cert` for this server, not the tunnel root store.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub tls_ca_only_custom: bool,
+    /// Local address for this server's tunnel connections and the upstream
+    /// requests they carry, on hosts with several public IPs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_address: Option<IpAddr>,
+}
+
+/// Failover role of a `[[servers]]` entry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerRole {
+    #[default]
+    Primary,
+    /// Disaster-recovery server, activated when all primaries fail.
+    Secondary,
+}
+
+impl ServerRole {
+    pub fn is_primary(&self) -> bool {
+        *self == Self::Primary
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -312,6 +1030,10 @@ pub struct ServerEntry {
 /// All fields are optional -- only populated values are written.
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct ConfigFile {
+    /// Additional config files (paths or globs, relative to this file's
+    /// directory) merged on top of this file in order, last-write-wins.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub aether_url: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -319,13 +1041,27 @@ pub struct ConfigFile {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub public_ip: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification_webhook_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification_webhook_secret: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub node_name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub node_region: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub node_tags: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable_ip_detection: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub heartbeat_interval: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub allowed_ports: Option<Vec<u16>>,
+    pub heartbeat_report_fields: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_redact_headers: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_ports: Option<Vec<PortRange>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub aether_request_timeout_secs: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -341,6 +1077,8 @@ pub struct ConfigFile {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub aether_http2: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub shutdown_unregister_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub aether_retry_max_attempts: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub aether_retry_base_delay_ms: Option<u64>,
@@ -353,23 +1091,67 @@ pub struct ConfigFile {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub dns_cache_capacity: Option<usize>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_max_inflight: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub upstream_connect_timeout_secs: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub upstream_pool_max_idle_per_host: Option<usize>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub upstream_pool_idle_timeout_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "UpstreamPoolOverrides::is_empty")]
+    pub upstream_pool_overrides: UpstreamPoolOverrides,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub upstream_tcp_keepalive_secs: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub upstream_tcp_nodelay: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub log_level: Option<String>,
+    pub upstream_request_id_header: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub log_json: Option<bool>,
+    pub upstream_retry_idempotent: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub tunnel_reconnect_base_ms: Option<u64>,
+    pub upstream_forward_content_length: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub tunnel_reconnect_max_ms: Option<u64>,
+    pub upstream_decompress_responses: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_user_agent_override: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_append_via: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_require_https: Option<bool>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub upstream_identity_headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "HeaderRules::is_empty")]
+    pub upstream_header_rules: HeaderRules,
+    #[serde(default, skip_serializing_if = "HeaderRules::is_empty")]
+    pub response_header_rules: HeaderRules,
+    #[serde(default, skip_serializing_if = "UpstreamAuth::is_empty")]
+    pub upstream_auth: UpstreamAuth,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_tls_roots: Option<TlsRoots>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_extra_ca_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub global_max_rps: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_upstream_bps: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_downstream_bps: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_json: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_rotation: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_stderr: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub otel_endpoint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel_reconnect_base_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel_reconnect_max_ms: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tunnel_ping_interval_secs: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -381,29 +1163,300 @@ pub struct ConfigFile {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tunnel_tcp_nodelay: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel_tcp_recv_lowat: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel_tcp_send_lowat: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tunnel_stale_timeout_secs: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel_response_rerouting: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection_rotation_grace_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tunnel_connections: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tunnel_connections: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_streams_per_connection: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_body_channel_depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_frame_sequencing: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_stream_affinity: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel_tls_roots: Option<TlsRoots>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel_extra_ca_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ws_max_message_size_bytes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_control_frame_bytes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub persist_remote_config: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lazy_registration: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reregister_interval_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failover_threshold: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failover_recovery_threshold: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub writer_backpressure_high_watermark: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub writer_backpressure_low_watermark: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_soft_limit_mb: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_hard_limit_mb: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub global_buffer_budget_mb: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy_timing_legacy_keys: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inject_timing_header: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_reporting_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_report_sample_rate: Option<f64>,
 
     /// Multi-server config: each entry connects to a separate Aether instance.
     /// When present, top-level aether_url/management_token are ignored for
     /// tunnel connections (but still injected as env for clap compatibility).
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub servers: Vec<ServerEntry>,
+
+    /// systemd unit settings used by `setup` when installing the service.
+    #[serde(default, skip_serializing_if = "ServiceConfig::is_empty")]
+    pub service: ServiceConfig,
+
+    /// `[compression]` table.
+    #[serde(default, skip_serializing_if = "CompressionSection::is_empty")]
+    pub compression: CompressionSection,
+
+    /// Named overrides of the flat fields above, applied with `--profile`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, toml::Table>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict_config: Option<bool>,
+
+    /// Entries skipped by a lenient load (see [`ConfigFile::load_startup`]),
+    /// e.g. `servers[1]: missing field `management_token``.
+    #[serde(skip)]
+    pub load_problems: Vec<String>,
+
+    /// Keys the file sets that no field reads, e.g.
+    /// `servers[0].managment_token (did you mean 'management_token'?)`.
+    #[serde(skip)]
+    pub unknown_keys: Vec<String>,
 }
 
 impl ConfigFile {
-    /// Load from a TOML file.
+    /// Load from a TOML file, resolving `include` entries and applying the
+    /// selected profile (see [`selected_profile`]).
     pub fn load(path: &Path) -> anyhow::Result<Self> {
-        let content = std::fs::read_to_string(path)?;
-        Ok(toml::from_str(&content)?)
+        Self::load_profile(path, selected_profile().as_deref())
+    }
+
+    /// Load from a TOML file, resolving `include` entries and applying
+    /// `profile` if given.
+    pub fn load_profile(path: &Path, profile: Option<&str>) -> anyhow::Result<Self> {
+        Self::load_with(path, profile, false)
+    }
+
+    /// Load only the keys set in `path` itself: `include` entries are kept
+    /// but not resolved, and no profile is applied.  For editors that write
+    /// the file back, so values from included files aren't copied into it.
+    pub fn load_own(path: &Path) -> anyhow::Result<Self> {
+        Self::from_table(read_table(path)?, false)
+    }
+
+    /// With `lenient`, invalid `[[servers]]` entries and top-level keys are
+    /// dropped and recorded in `load_problems` instead of failing the load.
+    fn load_with(path: &Path, profile: Option<&str>, lenient: bool) -> anyhow::Result<Self> {
+        let mut stack = HashSet::new();
+        let table = load_table_with_includes(path, &mut stack)?;
+        let file = Self::from_table(table, lenient)?;
+        match profile {
+            Some(name) => {
+                let problems = file.load_problems.clone();
+                let unknown_keys = file.unknown_keys.clone();
+                let mut file = file.with_profile(name)?;
+                file.load_problems = problems;
+                file.unknown_keys = unknown_keys;
+                Ok(file)
+            }
+            None => Ok(file),
+        }
+    }
+
+    /// Deserialize a config table, checking each `[[servers]]` entry on its
+    /// own so one typo doesn't hide the others.  Every problem is reported
+    /// with its entry index and key; unless `lenient`, any problem fails.
+    fn from_table(mut table: toml::Table, lenient: bool) -> anyhow::Result<Self> {
+        let unknown_keys = find_unknown_keys(&table);
+        check_allowed_ports_not_empty(&table)?;
+        let mut problems = Vec::new();
+        let servers = match table.remove("servers") {
+            None => Vec::new(),
+            Some(toml::Value::Array(entries)) => entries
+                .into_iter()
+                .enumerate()
+                .filter_map(|(i, entry)| {
+                    serde_path_to_error::deserialize::<_, ServerEntry>(entry)
+                        .map_err(|e| problems.push(describe_problem(&format!("servers[{i}]"), e)))
+                        .ok()
+                })
+                .collect(),
+            Some(other) => {
+                problems.push(format!(
+                    "servers: expected an array of tables, found {}",
+                    other.type_str()
+                ));
+                Vec::new()
+            }
+        };
+
+        // A bad top-level key is dropped (with everything under it) and the
+        // rest is tried again.
+        let mut file = loop {
+            let e = match serde_path_to_error::deserialize::<_, Self>(toml::Value::Table(
+                table.clone(),
+            )) {
+                Ok(file) => break file,
+                Err(e) => e,
+            };
+            let key = match e.path().iter().next() {
+                Some(serde_path_to_error::Segment::Map { key }) if table.contains_key(key) => {
+                    key.clone()
+                }
+                _ => anyhow::bail!("{}", describe_problem("", e)),
+            };
+            table.remove(&key);
+            problems.push(describe_problem("", e));
+        };
+        file.servers = servers;
+
+        if !problems.is_empty() && !lenient {
+            anyhow::bail!("invalid config:\n  {}", problems.join("\n  "));
+        }
+        file.load_problems = problems;
+        file.unknown_keys = unknown_keys;
+        Ok(file)
+    }
+
+    /// Overlay profile `name` (from the file, else built-in) onto the flat fields.
+    pub fn with_profile(self, name: &str) -> anyhow::Result<Self> {
+        let overrides = match self.profiles.get(name) {
+            Some(profile) => profile.clone(),
+            None => {
+                let builtin: Self = toml::from_str(DEFAULT_PROFILES)?;
+                match builtin.profiles.get(name) {
+                    Some(profile) => profile.clone(),
+                    None => {
+                        let mut available: Vec<&String> = builtin
+                            .profiles
+                            .keys()
+                            .chain(self.profiles.keys())
+                            .collect();
+                        available.sort();
+                        available.dedup();
+                        let available: Vec<&str> = available.iter().map(|s| s.as_str()).collect();
+                        anyhow::bail!(
+                            "unknown profile '{}' (available: {})",
+                            name,
+                            available.join(", ")
+                        );
+                    }
+                }
+            }
+        };
+
+        let mut table = toml::Table::try_from(&self)?;
+        for (key, value) in overrides {
+            if PROFILE_EXCLUDED_KEYS.contains(&key.as_str()) {
+                anyhow::bail!("profile '{}' cannot set '{}'", name, key);
+            }
+            table.insert(key, value);
+        }
+        Ok(toml::Value::Table(table).try_into()?)
     }
 
-    /// Save to a TOML file.
+    /// Save to a TOML file (atomically, see [`write_atomic`]).
+    ///
+    /// Callers doing a read-modify-write cycle hold [`lock_config`] around it.
     pub fn save(&self, path: &Path) -> anyhow::Result<()> {
         let content = toml::to_string_pretty(self)?;
-        std::fs::write(path, content)?;
-        Ok(())
+        write_atomic(path, content.as_bytes())
+    }
+
+    /// Find a replacement for a config file left unparseable by an
+    /// interrupted write.
+    ///
+    /// If `path` no longer parses as TOML but `<name>.tmp` (an unfinished
+    /// atomic write) does and is newer than it, returns the temp file.
+    /// Nothing is restored automatically: the operator decides whether the
+    /// temp file is what they want.
+    pub fn recovery_candidate(path: &Path) -> anyhow::Result<Option<PathBuf>> {
+        let _lock = lock_config(path)?;
+        if !path.exists() || parses_as_toml(path) {
+            return Ok(None);
+        }
+        let tmp = sibling_path(path, "tmp");
+        let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+        let newer = match (modified(&tmp), modified(path)) {
+            (Some(tmp_time), Some(config_time)) => tmp_time > config_time,
+            _ => false,
+        };
+        Ok((newer && parses_as_toml(&tmp)).then_some(tmp))
+    }
+
+    /// Recover, migrate and load the config file at startup.  This is the
+    /// only read of the file before the proxy runs; later steps use the
+    /// returned struct rather than loading it again.
+    pub fn load_startup(path: &Path) -> anyhow::Result<Self> {
+        match Self::recovery_candidate(path) {
+            Ok(Some(tmp)) => eprintln!(
+                "  WARNING: {} could not be parsed; {} is a newer copy left by an interrupted write. If it is correct, restore it with: mv {} {}",
+                path.display(),
+                tmp.display(),
+                tmp.display(),
+                path.display()
+            ),
+            Ok(None) => {}
+            Err(e) => eprintln!("  WARNING: config recovery check failed: {}", e),
+        }
+        // Migrate legacy 0.1.x config to 0.2.0 format if needed
+        if let Err(e) = Self::migrate_legacy(path) {
+            eprintln!("  WARNING: config migration failed: {}", e);
+        }
+        let file = Self::load_with(path, selected_profile().as_deref(), true)?;
+        let unknown = file
+            .unknown_keys
+            .iter()
+            .map(|key| format!("unknown key {key}"));
+        let problems: Vec<String> = file.load_problems.iter().cloned().chain(unknown).collect();
+        if !problems.is_empty() && (file.strict_config == Some(true) || strict_config_requested()) {
+            anyhow::bail!(
+                "invalid config (strict_config is set):\n  {}",
+                problems.join("\n  ")
+            );
+        }
+        for problem in &file.load_problems {
+            eprintln!("  WARNING: config entry ignored: {}", problem);
+        }
+        for key in &file.unknown_keys {
+            eprintln!("  WARNING: unknown config key ignored: {}", key);
+        }
+        for (i, j) in file.duplicate_servers() {
+            eprintln!(
+                "  WARNING: servers[{}] duplicates servers[{}] (same aether_url and node_name); both register the same node",
+                j, i
+            );
+        }
+        Ok(file)
     }
 
     /// Detect and migrate a 0.1.x config file to 0.2.0 format in-place.
@@ -411,11 +1464,40 @@ impl ConfigFile {
     /// Returns `true` if migration was performed, `false` if already current.
     /// The original file is backed up as `<name>.v1.bak` before rewriting.
     pub fn migrate_legacy(path: &Path) -> anyhow::Result<bool> {
+        let _lock = lock_config(path)?;
         let content = match std::fs::read_to_string(path) {
             Ok(c) => c,
             Err(_) => return Ok(false),
         };
-        let mut table: toml::map::Map<String, toml::Value> = toml::from_str(&content)?;
+        let Some(new_content) = Self::migrated_legacy(&content)? else {
+            return Ok(false);
+        };
+
+        // Backup original file (abort migration if backup fails)
+        let backup_path = path.with_extension("v1.bak");
+        std::fs::copy(path, &backup_path).map_err(|e| {
+            anyhow::anyhow!(
+                "failed to backup config before migration: {} -> {}: {}",
+                path.display(),
+                backup_path.display(),
+                e
+            )
+        })?;
+
+        // Write migrated config
+        write_atomic(path, new_content.as_bytes())?;
+
+        eprintln!("  Config migrated from 0.1.x to 0.2.0 format.");
+        eprintln!("  Backup saved: {}", backup_path.display());
+
+        Ok(true)
+    }
+
+    /// The 0.2.0 form of a 0.1.x config file's `content`, or `None` if it is
+    /// already current.  Writes nothing; [`migrate_legacy`](Self::migrate_legacy)
+    /// applies it in place.
+    pub fn migrated_legacy(content: &str) -> anyhow::Result<Option<String>> {
+        let mut table: toml::map::Map<String, toml::Value> = toml::from_str(content)?;
 
         // Detect legacy format: presence of any 0.1.x-only key.
         let is_legacy = LEGACY_ONLY_KEYS.iter().any(|k| table.contains_key(*k))
@@ -424,7 +1506,7 @@ impl ConfigFile {
                 .any(|(old, _)| table.contains_key(*old));
 
         if !is_legacy {
-            return Ok(false);
+            return Ok(None);
         }
 
         // 1. Rename delegate_* -> upstream_* (carry over user-customized values)
@@ -463,25 +1545,23 @@ impl ConfigFile {
             table.remove(key);
         }
 
-        // 4. Backup original file (abort migration if backup fails)
-        let backup_path = path.with_extension("v1.bak");
-        std::fs::copy(path, &backup_path).map_err(|e| {
-            anyhow::anyhow!(
-                "failed to backup config before migration: {} -> {}: {}",
-                path.display(),
-                backup_path.display(),
-                e
-            )
-        })?;
-
-        // 5. Write migrated config
-        let new_content = toml::to_string_pretty(&table)?;
-        std::fs::write(path, &new_content)?;
-
-        eprintln!("  Config migrated from 0.1.x to 0.2.0 format.");
-        eprintln!("  Backup saved: {}", backup_path.display());
+        Ok(Some(toml::to_string_pretty(&table)?))
+    }
 
-        Ok(true)
+    /// Mask management tokens and secrets so the config can be shown or
+    /// shared.
+    pub fn redacted(mut self) -> Self {
+        if let Some(ref token) = self.management_token {
+            self.management_token = Some(redact_secret(token));
+        }
+        if let Some(ref secret) = self.notification_webhook_secret {
+            self.notification_webhook_secret = Some(redact_secret(secret));
+        }
+        for server in &mut self.servers {
+            server.management_token = redact_secret(&server.management_token);
+        }
+        self.upstream_auth = self.upstream_auth.redacted();
+        self
     }
 
     /// Resolve the effective server list.
@@ -496,12 +1576,44 @@ impl ConfigFile {
             (Some(url), Some(token)) => vec![ServerEntry {
                 aether_url: url.clone(),
                 management_token: token.clone(),
-                node_name: None,
+                node_name: self.node_name.clone(),
+                node_region: None,
+                node_tags: BTreeMap::new(),
+                role: ServerRole::Primary,
+                tls_sni_override: None,
+                tls_ca_cert: None,
+                tls_ca_only_custom: false,
+                bind_address: None,
             }],
             _ => vec![],
         }
     }
 
+    /// Index pairs of `[[servers]]` entries that would register the same
+    /// node and fight over it: the same `aether_url` (ignoring case and a
+    /// trailing `/`) with the same node name (falling back to the global
+    /// `node_name`).
+    pub fn duplicate_servers(&self) -> Vec<(usize, usize)> {
+        let key = |entry: &ServerEntry| {
+            (
+                entry.aether_url.trim().trim_end_matches('/').to_lowercase(),
+                entry
+                    .node_name
+                    .clone()
+                    .or_else(|| self.node_name.clone())
+                    .unwrap_or_default(),
+            )
+        };
+        let keys: Vec<_> = self.servers.iter().map(key).collect();
+        let mut pairs = Vec::new();
+        for (j, later) in keys.iter().enumerate() {
+            if let Some(i) = keys[..j].iter().position(|earlier| earlier == later) {
+                pairs.push((i, j));
+            }
+        }
+        pairs
+    }
+
     /// Inject values as environment variables so clap picks them up.
     ///
     /// Only sets variables that are **not** already present in the
@@ -548,8 +1660,22 @@ impl ConfigFile {
         set!("AETHER_PROXY_AETHER_URL", aether_url);
         set!("AETHER_PROXY_MANAGEMENT_TOKEN", management_token);
         set!("AETHER_PROXY_PUBLIC_IP", self.public_ip);
+        set!("AETHER_PROXY_PID_FILE", self.pid_file);
+        set!(
+            "AETHER_PROXY_NOTIFICATION_WEBHOOK_URL",
+            self.notification_webhook_url
+        );
+        set!(
+            "AETHER_PROXY_NOTIFICATION_WEBHOOK_SECRET",
+            self.notification_webhook_secret
+        );
+        set!("AETHER_PROXY_STRICT_CONFIG", self.strict_config);
         set!("AETHER_PROXY_NODE_NAME", node_name);
         set!("AETHER_PROXY_NODE_REGION", self.node_region);
+        set!(
+            "AETHER_PROXY_DISABLE_IP_DETECTION",
+            self.disable_ip_detection
+        );
         set!("AETHER_PROXY_HEARTBEAT_INTERVAL", self.heartbeat_interval);
         set!(
             "AETHER_PROXY_AETHER_REQUEST_TIMEOUT",
@@ -573,6 +1699,10 @@ impl ConfigFile {
         );
         set!("AETHER_PROXY_AETHER_TCP_NODELAY", self.aether_tcp_nodelay);
         set!("AETHER_PROXY_AETHER_HTTP2", self.aether_http2);
+        set!(
+            "AETHER_PROXY_SHUTDOWN_UNREGISTER_TIMEOUT",
+            self.shutdown_unregister_timeout_secs
+        );
         set!(
             "AETHER_PROXY_AETHER_RETRY_MAX_ATTEMPTS",
             self.aether_retry_max_attempts
@@ -591,6 +1721,7 @@ impl ConfigFile {
         );
         set!("AETHER_PROXY_DNS_CACHE_TTL", self.dns_cache_ttl_secs);
         set!("AETHER_PROXY_DNS_CACHE_CAPACITY", self.dns_cache_capacity);
+        set!("AETHER_PROXY_DNS_MAX_INFLIGHT", self.dns_max_inflight);
         set!(
             "AETHER_PROXY_UPSTREAM_CONNECT_TIMEOUT",
             self.upstream_connect_timeout_secs
@@ -611,8 +1742,45 @@ impl ConfigFile {
             "AETHER_PROXY_UPSTREAM_TCP_NODELAY",
             self.upstream_tcp_nodelay
         );
+        set!(
+            "AETHER_PROXY_UPSTREAM_REQUEST_ID_HEADER",
+            self.upstream_request_id_header
+        );
+        set!(
+            "AETHER_PROXY_UPSTREAM_RETRY_IDEMPOTENT",
+            self.upstream_retry_idempotent
+        );
+        set!(
+            "AETHER_PROXY_UPSTREAM_FORWARD_CONTENT_LENGTH",
+            self.upstream_forward_content_length
+        );
+        set!(
+            "AETHER_PROXY_UPSTREAM_DECOMPRESS_RESPONSES",
+            self.upstream_decompress_responses
+        );
+        set!(
+            "AETHER_PROXY_UPSTREAM_USER_AGENT_OVERRIDE",
+            self.upstream_user_agent_override
+        );
+        set!("AETHER_PROXY_UPSTREAM_APPEND_VIA", self.upstream_append_via);
+        set!(
+            "AETHER_PROXY_UPSTREAM_REQUIRE_HTTPS",
+            self.upstream_require_https
+        );
+        set!("AETHER_PROXY_UPSTREAM_TLS_ROOTS", self.upstream_tls_roots);
+        set!(
+            "AETHER_PROXY_UPSTREAM_EXTRA_CA_FILE",
+            self.upstream_extra_ca_file
+        );
+        set!("AETHER_PROXY_GLOBAL_MAX_RPS", self.global_max_rps);
+        set!("AETHER_PROXY_MAX_UPSTREAM_BPS", self.max_upstream_bps);
+        set!("AETHER_PROXY_MAX_DOWNSTREAM_BPS", self.max_downstream_bps);
         set!("AETHER_PROXY_LOG_LEVEL", self.log_level);
         set!("AETHER_PROXY_LOG_JSON", self.log_json);
+        set!("AETHER_PROXY_LOG_FILE", self.log_file);
+        set!("AETHER_PROXY_LOG_ROTATION", self.log_rotation);
+        set!("AETHER_PROXY_LOG_STDERR", self.log_stderr);
+        set!("AETHER_PROXY_OTEL_ENDPOINT", self.otel_endpoint);
         set!(
             "AETHER_PROXY_TUNNEL_RECONNECT_BASE_MS",
             self.tunnel_reconnect_base_ms
@@ -635,11 +1803,110 @@ impl ConfigFile {
             self.tunnel_tcp_keepalive_secs
         );
         set!("AETHER_PROXY_TUNNEL_TCP_NODELAY", self.tunnel_tcp_nodelay);
+        set!(
+            "AETHER_PROXY_TUNNEL_TCP_RECV_LOWAT",
+            self.tunnel_tcp_recv_lowat
+        );
+        set!(
+            "AETHER_PROXY_TUNNEL_TCP_SEND_LOWAT",
+            self.tunnel_tcp_send_lowat
+        );
         set!(
             "AETHER_PROXY_TUNNEL_STALE_TIMEOUT",
             self.tunnel_stale_timeout_secs
         );
+        set!(
+            "AETHER_PROXY_TUNNEL_RESPONSE_REROUTING",
+            self.tunnel_response_rerouting
+        );
+        set!(
+            "AETHER_PROXY_CONNECTION_ROTATION_GRACE",
+            self.connection_rotation_grace_secs
+        );
         set!("AETHER_PROXY_TUNNEL_CONNECTIONS", self.tunnel_connections);
+        set!(
+            "AETHER_PROXY_MAX_TUNNEL_CONNECTIONS",
+            self.max_tunnel_connections
+        );
+        set!(
+            "AETHER_PROXY_TARGET_STREAMS_PER_CONNECTION",
+            self.target_streams_per_connection
+        );
+        set!(
+            "AETHER_PROXY_STREAM_BODY_CHANNEL_DEPTH",
+            self.stream_body_channel_depth
+        );
+        set!(
+            "AETHER_PROXY_ENABLE_FRAME_SEQUENCING",
+            self.enable_frame_sequencing
+        );
+        set!(
+            "AETHER_PROXY_ENABLE_STREAM_AFFINITY",
+            self.enable_stream_affinity
+        );
+        set!("AETHER_PROXY_TUNNEL_TLS_ROOTS", self.tunnel_tls_roots);
+        set!(
+            "AETHER_PROXY_TUNNEL_EXTRA_CA_FILE",
+            self.tunnel_extra_ca_file
+        );
+        set!(
+            "AETHER_PROXY_WS_MAX_MESSAGE_SIZE",
+            self.ws_max_message_size_bytes
+        );
+        set!(
+            "AETHER_PROXY_MAX_CONTROL_FRAME_BYTES",
+            self.max_control_frame_bytes
+        );
+        set!(
+            "AETHER_PROXY_PERSIST_REMOTE_CONFIG",
+            self.persist_remote_config
+        );
+        set!("AETHER_PROXY_LAZY_REGISTRATION", self.lazy_registration);
+        set!(
+            "AETHER_PROXY_REREGISTER_INTERVAL",
+            self.reregister_interval_secs
+        );
+        set!("AETHER_PROXY_FAILOVER_THRESHOLD", self.failover_threshold);
+        set!(
+            "AETHER_PROXY_FAILOVER_RECOVERY_THRESHOLD",
+            self.failover_recovery_threshold
+        );
+        set!(
+            "AETHER_PROXY_WRITER_BACKPRESSURE_HIGH_WATERMARK",
+            self.writer_backpressure_high_watermark
+        );
+        set!(
+            "AETHER_PROXY_WRITER_BACKPRESSURE_LOW_WATERMARK",
+            self.writer_backpressure_low_watermark
+        );
+        set!(
+            "AETHER_PROXY_MEMORY_SOFT_LIMIT_MB",
+            self.memory_soft_limit_mb
+        );
+        set!(
+            "AETHER_PROXY_MEMORY_HARD_LIMIT_MB",
+            self.memory_hard_limit_mb
+        );
+        set!(
+            "AETHER_PROXY_GLOBAL_BUFFER_BUDGET_MB",
+            self.global_buffer_budget_mb
+        );
+        set!(
+            "AETHER_PROXY_PROXY_TIMING_LEGACY_KEYS",
+            self.proxy_timing_legacy_keys
+        );
+        set!(
+            "AETHER_PROXY_INJECT_TIMING_HEADER",
+            self.inject_timing_header
+        );
+        set!(
+            "AETHER_PROXY_ERROR_REPORTING_ENABLED",
+            self.error_reporting_enabled
+        );
+        set!(
+            "AETHER_PROXY_ERROR_REPORT_SAMPLE_RATE",
+            self.error_report_sample_rate
+        );
 
         // allowed_ports needs special handling (comma-separated)
         if let Some(ref ports) = self.allowed_ports {
@@ -652,5 +1919,862 @@ impl ConfigFile {
                 std::env::set_var("AETHER_PROXY_ALLOWED_PORTS", s);
             }
         }
+        if let Some(ref fields) = self.heartbeat_report_fields {
+            if force || std::env::var("AETHER_PROXY_HEARTBEAT_REPORT_FIELDS").is_err() {
+                std::env::set_var("AETHER_PROXY_HEARTBEAT_REPORT_FIELDS", fields.join(","));
+            }
+        }
+        if let Some(ref headers) = self.log_redact_headers {
+            if force || std::env::var("AETHER_PROXY_LOG_REDACT_HEADERS").is_err() {
+                std::env::set_var("AETHER_PROXY_LOG_REDACT_HEADERS", headers.join(","));
+            }
+        }
+        if !self.node_tags.is_empty() && (force || std::env::var("AETHER_PROXY_NODE_TAGS").is_err())
+        {
+            let s: String = self
+                .node_tags
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>()
+                .join(",");
+            std::env::set_var("AETHER_PROXY_NODE_TAGS", s);
+        }
+        if !self.upstream_identity_headers.is_empty()
+            && (force || std::env::var("AETHER_PROXY_UPSTREAM_IDENTITY_HEADERS").is_err())
+        {
+            let s: String = self
+                .upstream_identity_headers
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>()
+                .join(",");
+            std::env::set_var("AETHER_PROXY_UPSTREAM_IDENTITY_HEADERS", s);
+        }
+        if !self.upstream_header_rules.is_empty()
+            && (force || std::env::var("AETHER_PROXY_UPSTREAM_HEADER_RULES").is_err())
+        {
+            std::env::set_var(
+                "AETHER_PROXY_UPSTREAM_HEADER_RULES",
+                self.upstream_header_rules.to_string(),
+            );
+        }
+        if !self.response_header_rules.is_empty()
+            && (force || std::env::var("AETHER_PROXY_RESPONSE_HEADER_RULES").is_err())
+        {
+            std::env::set_var(
+                "AETHER_PROXY_RESPONSE_HEADER_RULES",
+                self.response_header_rules.to_string(),
+            );
+        }
+        if !self.upstream_auth.is_empty()
+            && (force || std::env::var("AETHER_PROXY_UPSTREAM_AUTH").is_err())
+        {
+            std::env::set_var("AETHER_PROXY_UPSTREAM_AUTH", self.upstream_auth.to_string());
+        }
+        if !self.upstream_pool_overrides.is_empty()
+            && (force || std::env::var("AETHER_PROXY_UPSTREAM_POOL_OVERRIDES").is_err())
+        {
+            std::env::set_var(
+                "AETHER_PROXY_UPSTREAM_POOL_OVERRIDES",
+                self.upstream_pool_overrides.to_string(),
+            );
+        }
+        if !self.compression.content_types.is_empty()
+            && (force || std::env::var("AETHER_PROXY_COMPRESSION_CONTENT_TYPES").is_err())
+        {
+            std::env::set_var(
+                "AETHER_PROXY_COMPRESSION_CONTENT_TYPES",
+                self.compression.content_types.to_string(),
+            );
+        }
+    }
+}
+
+/// `[compression]` table of the config file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompressionSection {
+    /// Per content type overrides of response body compression.
+    #[serde(default, skip_serializing_if = "ContentTypeRules::is_empty")]
+    pub content_types: ContentTypeRules,
+}
+
+impl CompressionSection {
+    pub fn is_empty(&self) -> bool {
+        self.content_types.is_empty()
+    }
+}
+
+/// `<file name>.<suffix>` next to `path`.
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Reject `allowed_ports = []` (top level or in a profile) with a clear
+/// message; passed on to clap it only reads as an invalid port.
+fn check_allowed_ports_not_empty(table: &toml::Table) -> anyhow::Result<()> {
+    let profiles = table
+        .get("profiles")
+        .and_then(toml::Value::as_table)
+        .into_iter()
+        .flatten()
+        .filter_map(|(name, p)| Some((format!("profiles.{name}."), p.as_table()?)));
+    for (prefix, section) in std::iter::once((String::new(), table)).chain(profiles) {
+        let ports = section.get("allowed_ports").and_then(toml::Value::as_array);
+        if ports.is_some_and(|p| p.is_empty()) {
+            anyhow::bail!(
+                "{prefix}allowed_ports must not be empty: every upstream target would be \
+                 rejected (remove the key for the default 80,443,8080,8443)"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Keys of a config table that [`ConfigFile`] doesn't read, checked down
+/// into `[[servers]]`, `[service]`, `[compression]` and each profile.
+///
+/// 0.1.x keys are reported with what replaced them: `migrate_legacy` only
+/// rewrites the main file, not its includes.
+fn find_unknown_keys(table: &toml::Table) -> Vec<String> {
+    let mut unknown = Vec::new();
+    unknown_keys_at(table, "", &mut unknown);
+    unknown
+}
+
+/// [`find_unknown_keys`] for the main table (`prefix` empty) or a profile
+/// (`profiles.<name>.`), which is checked like the main table except for
+/// the keys a profile cannot set.
+fn unknown_keys_at(table: &toml::Table, prefix: &str, unknown: &mut Vec<String>) {
+    let top = field_names::<ConfigFile>();
+    let in_profile = !prefix.is_empty();
+    for (key, value) in table {
+        match key.as_str() {
+            key if in_profile && PROFILE_EXCLUDED_KEYS.contains(&key) => {
+                unknown.push(format!("{prefix}{key} (not allowed in a profile)"));
+            }
+            "servers" => {
+                for (i, entry) in value.as_array().into_iter().flatten().enumerate() {
+                    let prefix = format!("servers[{i}].");
+                    unknown_in(entry, field_names::<ServerEntry>(), &prefix, unknown);
+                }
+            }
+            "service" => unknown_in(value, field_names::<ServiceConfig>(), "service.", unknown),
+            "compression" => unknown_in(
+                value,
+                field_names::<CompressionSection>(),
+                &format!("{prefix}compression."),
+                unknown,
+            ),
+            "profiles" => {
+                let profiles = value.as_table().into_iter().flatten();
+                for (name, profile) in profiles.filter_map(|(n, p)| Some((n, p.as_table()?))) {
+                    unknown_keys_at(profile, &format!("profiles.{name}."), unknown);
+                }
+            }
+            key if top.contains(&key) => {}
+            key if LEGACY_ONLY_KEYS.contains(&key) => {
+                unknown.push(format!("{prefix}{key} (removed in 0.2.0)"));
+            }
+            key => match DELEGATE_TO_UPSTREAM.iter().find(|(old, _)| *old == key) {
+                Some((_, new)) => {
+                    unknown.push(format!("{prefix}{key} (renamed to '{new}' in 0.2.0)"))
+                }
+                None => unknown.push(describe_unknown(prefix, key, top)),
+            },
+        }
+    }
+}
+
+/// The keys of `value` (if a table) not in `fields`.
+fn unknown_in(value: &toml::Value, fields: &[&str], prefix: &str, unknown: &mut Vec<String>) {
+    let keys = value.as_table().into_iter().flat_map(|table| table.keys());
+    for key in keys.filter(|key| !fields.contains(&key.as_str())) {
+        unknown.push(describe_unknown(prefix, key, fields));
+    }
+}
+
+/// `<prefix><key>`, with the closest of `fields` if one looks like a typo.
+fn describe_unknown(prefix: &str, key: &str, fields: &[&str]) -> String {
+    let closest = fields
+        .iter()
+        .map(|field| (strsim::jaro_winkler(key, field), field))
+        .filter(|(score, _)| *score > 0.85)
+        .max_by(|a, b| a.0.total_cmp(&b.0));
+    match closest {
+        Some((_, field)) => format!("{prefix}{key} (did you mean '{field}'?)"),
+        None => format!("{prefix}{key}"),
+    }
+}
+
+/// Field names `T`'s derived `Deserialize` accepts, captured from the
+/// `deserialize_struct` call it makes.
+fn field_names<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    use serde::de::{self, Visitor};
+
+    struct Capture<'a>(&'a std::cell::Cell<&'static [&'static str]>);
+
+    impl<'de> de::Deserializer<'de> for Capture<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            self.0.set(fields);
+            Err(de::Error::custom("fields captured"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let fields = std::cell::Cell::new(&[][..]);
+    let _ = T::deserialize(Capture(&fields));
+    fields.get()
+}
+
+/// `<prefix>.<path>: <message>` for a deserialization error.
+fn describe_problem(prefix: &str, e: serde_path_to_error::Error<toml::de::Error>) -> String {
+    let path = e.path().to_string();
+    let location = match (prefix, path.as_str()) {
+        (prefix, ".") => prefix.to_string(),
+        ("", path) => path.to_string(),
+        (prefix, path) => format!("{prefix}.{path}"),
+    };
+    let message = e.into_inner().message().trim().to_string();
+    if location.is_empty() {
+        message
+    } else {
+        format!("{location}: {message}")
+    }
+}
+
+fn parses_as_toml(path: &Path) -> bool {
+    std::fs::read_to_string(path)
+        .ok()
+        .is_some_and(|content| toml::from_str::<toml::Table>(&content).is_ok())
+}
+
+/// Replace `path` with `content` without ever exposing a partial file.
+///
+/// The content is written and fsynced to `<name>.tmp` in the same directory,
+/// then renamed over `path`.  An existing file's permissions are kept (the
+/// config holds management tokens).
+pub fn write_atomic(path: &Path, content: &[u8]) -> anyhow::Result<()> {
+    use std::io::Write;
+
+    let tmp_path = sibling_path(path, "tmp");
+    let write_tmp = || -> std::io::Result<()> {
+        let mut file = std::fs::File::create(&tmp_path)?;
+        if let Ok(meta) = std::fs::metadata(path) {
+            file.set_permissions(meta.permissions())?;
+        }
+        file.write_all(content)?;
+        file.sync_all()
+    };
+    write_tmp()
+        .and_then(|()| std::fs::rename(&tmp_path, path))
+        .map_err(|e| anyhow::anyhow!("failed to write {}: {}", path.display(), e))?;
+
+    // Persist the rename itself; best-effort, the data is already on disk.
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        if let Ok(dir) = std::fs::File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    Ok(())
+}
+
+/// Advisory lock on a config file, released on drop.
+pub struct ConfigLock {
+    _file: std::fs::File,
+}
+
+/// Take an exclusive advisory lock for a read-modify-write of `path`,
+/// blocking while another process (e.g. `setup` next to the running
+/// service) holds it.
+///
+/// The lock is held on `<name>.lock` rather than the config itself, since
+/// [`write_atomic`] replaces the config's inode.  Locks are per open file, so
+/// a process must not take it twice.
+pub fn lock_config(path: &Path) -> anyhow::Result<ConfigLock> {
+    let lock_path = sibling_path(path, "lock");
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(|e| anyhow::anyhow!("failed to open {}: {}", lock_path.display(), e))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::AsRawFd;
+        loop {
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+                break;
+            }
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                anyhow::bail!("failed to lock {}: {}", lock_path.display(), err);
+            }
+        }
+    }
+
+    Ok(ConfigLock { _file: file })
+}
+
+/// Parse a TOML file and overlay its includes (recursively, in order).
+///
+/// Top-level keys present in an included file replace those of the parent.
+/// `stack` holds the files currently being loaded to detect include cycles.
+fn load_table_with_includes(
+    path: &Path,
+    stack: &mut HashSet<PathBuf>,
+) -> anyhow::Result<toml::map::Map<String, toml::Value>> {
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if !stack.insert(canonical.clone()) {
+        anyhow::bail!("circular config include: {}", path.display());
+    }
+
+    let mut table = read_table(path)?;
+    let patterns: Vec<String> = match table.get("include") {
+        Some(toml::Value::Array(items)) => items
+            .iter()
+            .map(|v| {
+                v.as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| anyhow::anyhow!("include entries must be strings"))
+            })
+            .collect::<anyhow::Result<_>>()?,
+        Some(toml::Value::String(single)) => vec![single.clone()],
+        Some(_) => anyhow::bail!("include must be a string or an array of strings"),
+        None => Vec::new(),
+    };
+
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    for pattern in &patterns {
+        for include_path in expand_include(base_dir, pattern)? {
+            let included = load_table_with_includes(&include_path, stack)?;
+            for (key, value) in included {
+                if key != "include" {
+                    table.insert(key, value);
+                }
+            }
+        }
+    }
+
+    stack.remove(&canonical);
+    Ok(table)
+}
+
+/// Parse a single TOML file, without resolving its includes.
+fn read_table(path: &Path) -> anyhow::Result<toml::map::Map<String, toml::Value>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+    toml::from_str(&content)
+        .map_err(|e| anyhow::anyhow!("failed to parse {}: {}", path.display(), e))
+}
+
+/// Resolve an include pattern relative to `base_dir`.
+///
+/// Glob patterns expand to their matches in sorted order (an empty match is
+/// fine, e.g. an empty `conf.d/`); plain paths must exist.
+fn expand_include(base_dir: &Path, pattern: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let joined = base_dir.join(pattern);
+    if !pattern.contains(['*', '?', '[']) {
+        if !joined.exists() {
+            anyhow::bail!("included config not found: {}", joined.display());
+        }
+        return Ok(vec![joined]);
+    }
+
+    let joined_str = joined
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("include path contains invalid UTF-8"))?;
+    let mut paths: Vec<PathBuf> = glob::glob(joined_str)
+        .map_err(|e| anyhow::anyhow!("invalid include pattern '{}': {}", pattern, e))?
+        .filter_map(Result::ok)
+        .filter(|p| p.is_file())
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+/// Config parsed from command-line `args`, with an Aether URL and token
+/// filled in, for unit tests.
+#[cfg(test)]
+pub(crate) fn test_config(args: &[&str]) -> Config {
+    use clap::Parser;
+
+    Config::try_parse_from(
+        [
+            "aether-proxy",
+            "--aether-url=https://aether.example.com",
+            "--management-token=ae_test",
+        ]
+        .iter()
+        .chain(args),
+    )
+    .expect("valid test arguments")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacted_masks_all_tokens() {
+        let file: ConfigFile = toml::from_str(
+            r#"
+            management_token = "ae_top_secret"
+            notification_webhook_secret = "hook-key-123"
+
+            [[servers]]
+            aether_url = "https://a.example.com"
+            management_token = "ae_server_secret"
+            "#,
+        )
+        .unwrap();
+        let shown = toml::to_string(&file.redacted()).unwrap();
+        assert!(!shown.contains("_secret\""));
+        assert!(!shown.contains("key-123"));
+        assert!(shown.contains("hook***"));
+        assert!(shown.contains("ae_t***"));
+        assert!(shown.contains("ae_s***"));
+        assert_eq!(redact_secret("abc"), "***");
+    }
+
+    #[test]
+    fn duplicate_servers_match_on_url_and_effective_node_name() {
+        let file: ConfigFile = toml::from_str(
+            r#"
+            node_name = "edge"
+
+            [[servers]]
+            aether_url = "https://a.example.com"
+            management_token = "ae_1"
+
+            [[servers]]
+            aether_url = "https://a.example.com"
+            management_token = "ae_2"
+            node_name = "edge-2"
+
+            [[servers]]
+            aether_url = "https://A.example.com/"
+            management_token = "ae_3"
+            node_name = "edge"
+
+            [[servers]]
+            aether_url = "https://b.example.com"
+            management_token = "ae_4"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(file.duplicate_servers(), vec![(0, 2)]);
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "aether-proxy-config-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("conf.d")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn includes_override_parent_in_order() {
+        let dir = temp_dir("include");
+        std::fs::write(
+            dir.join("aether-proxy.toml"),
+            "include = [\"conf.d/*.toml\"]\nlog_level = \"info\"\nheartbeat_interval = 30\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("conf.d/10-env.toml"), "log_level = \"debug\"\n").unwrap();
+        std::fs::write(dir.join("conf.d/20-env.toml"), "log_level = \"warn\"\n").unwrap();
+
+        let cfg = ConfigFile::load(&dir.join("aether-proxy.toml")).unwrap();
+        assert_eq!(cfg.log_level.as_deref(), Some("warn"));
+        assert_eq!(cfg.heartbeat_interval, Some(30));
+        assert_eq!(cfg.include, vec!["conf.d/*.toml".to_string()]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn load_own_skips_includes() {
+        let dir = temp_dir("include-own");
+        let path = dir.join("aether-proxy.toml");
+        std::fs::write(
+            &path,
+            "include = [\"conf.d/*.toml\"]\nlog_level = \"info\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("conf.d/10-env.toml"),
+            "log_level = \"debug\"\nheartbeat_interval = 5\n",
+        )
+        .unwrap();
+
+        let own = ConfigFile::load_own(&path).unwrap();
+        assert_eq!(own.log_level.as_deref(), Some("info"));
+        assert_eq!(own.heartbeat_interval, None);
+
+        // Writing it back leaves the included values in their own file.
+        own.save(&path).unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(!saved.contains("heartbeat_interval"), "{saved}");
+        let cfg = ConfigFile::load_profile(&path, None).unwrap();
+        assert_eq!(cfg.log_level.as_deref(), Some("debug"));
+        assert_eq!(cfg.heartbeat_interval, Some(5));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn profiles_override_flat_fields() {
+        let base: ConfigFile = toml::from_str(
+            r#"
+            log_level = "info"
+            heartbeat_interval = 30
+
+            [profiles.development]
+            log_level = "trace"
+            "#,
+        )
+        .unwrap();
+
+        let dev = base.with_profile("development").unwrap();
+        // File profile replaces the built-in one of the same name.
+        assert_eq!(dev.log_level.as_deref(), Some("trace"));
+        assert_eq!(dev.tunnel_connections, None);
+        assert_eq!(dev.heartbeat_interval, Some(30));
+
+        let prod = ConfigFile::default().with_profile("production").unwrap();
+        assert_eq!(prod.log_level, None);
+        let builtin_dev = ConfigFile::default().with_profile("development").unwrap();
+        assert_eq!(builtin_dev.log_level.as_deref(), Some("debug"));
+        assert_eq!(builtin_dev.tunnel_connections, Some(1));
+
+        let err = ConfigFile::default().with_profile("staging").unwrap_err();
+        assert!(err.to_string().contains("unknown profile"));
+    }
+
+    #[test]
+    fn node_tags_merge_per_server_over_global() {
+        let file: ConfigFile = toml::from_str(
+            r#"
+            [node_tags]
+            env = "prod"
+            owner = "infra"
+
+            [[servers]]
+            aether_url = "https://a.example.com"
+            management_token = "ae_x"
+            node_tags = { env = "staging", dc = "fra1" }
+            "#,
+        )
+        .unwrap();
+        // Tables survive a save/load round trip.
+        let reparsed: ConfigFile = toml::from_str(&toml::to_string(&file).unwrap()).unwrap();
+        assert_eq!(reparsed.node_tags, file.node_tags);
+        assert_eq!(reparsed.servers[0].node_tags, file.servers[0].node_tags);
+
+        let config = Config::try_parse_from([
+            "aether-proxy",
+            "--aether-url=https://a.example.com",
+            "--management-token=ae_x",
+            "--node-tags=env=prod,owner=infra",
+        ])
+        .unwrap();
+        let tags = config.node_tags_for(&file.servers[0]);
+        assert_eq!(
+            tags.into_iter().collect::<Vec<_>>(),
+            [("dc", "fra1"), ("env", "staging"), ("owner", "infra")]
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+        );
+
+        assert!(parse_node_tag("novalue").is_err());
+        assert!(parse_node_tag(" =x").is_err());
+        assert_eq!(parse_node_tag("k=").unwrap(), ("k".into(), String::new()));
+    }
+
+    #[test]
+    fn server_fallbacks_keep_node_name() {
+        let config = Config::try_parse_from([
+            "aether-proxy",
+            "--aether-url=https://cli.example.com",
+            "--management-token=ae_x",
+            "--node-name=cli-node",
+        ])
+        .unwrap();
+        let servers = config.servers(None);
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].aether_url, "https://cli.example.com");
+        assert_eq!(servers[0].node_name.as_deref(), Some("cli-node"));
+
+        let top_level: ConfigFile = toml::from_str(
+            r#"
+            aether_url = "https://a.example.com"
+            management_token = "ae_a"
+            node_name = "file-node"
+            "#,
+        )
+        .unwrap();
+        let servers = config.servers(Some(&top_level));
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].aether_url, "https://a.example.com");
+        assert_eq!(servers[0].node_name.as_deref(), Some("file-node"));
+
+        // [[servers]] are used as-is, without an extra CLI entry.
+        let multi: ConfigFile = toml::from_str(
+            r#"
+            [[servers]]
+            aether_url = "https://a.example.com"
+            management_token = "ae_a"
+            node_name = "a-node"
+
+            [[servers]]
+            aether_url = "https://b.example.com"
+            management_token = "ae_b"
+            "#,
+        )
+        .unwrap();
+        let servers = config.servers(Some(&multi));
+        assert_eq!(servers.len(), 2);
+        assert_eq!(servers[0].node_name.as_deref(), Some("a-node"));
+        assert_eq!(servers[1].node_name, None);
+    }
+
+    #[test]
+    fn bad_server_entries_are_skipped_with_their_location() {
+        let dir = temp_dir("lenient");
+        let path = dir.join("aether-proxy.toml");
+        std::fs::write(
+            &path,
+            r#"
+            log_level = "debug"
+            heartbeat_interval = "often"
+
+            [[servers]]
+            aether_url = "https://a.example.com"
+            management_token = "ae_a"
+
+            [[servers]]
+            aether_url = "https://b.example.com"
+            managment_token = "ae_b"
+
+            [[servers]]
+            aether_url = "https://c.example.com"
+            management_token = "ae_c"
+            tls_ca_only_custom = "yes"
+
+            [[servers]]
+            aether_url = "https://d.example.com"
+            management_token = "ae_d"
+            "#,
+        )
+        .unwrap();
+
+        let file = ConfigFile::load_startup(&path).unwrap();
+        let urls: Vec<&str> = file.servers.iter().map(|s| s.aether_url.as_str()).collect();
+        assert_eq!(urls, ["https://a.example.com", "https://d.example.com"]);
+        assert_eq!(file.log_level.as_deref(), Some("debug"));
+        assert_eq!(file.heartbeat_interval, None);
+        assert_eq!(file.load_problems.len(), 3, "{:?}", file.load_problems);
+        assert!(file.load_problems[0].starts_with("servers[1]: missing field `management_token`"));
+        assert!(file.load_problems[1].starts_with("servers[2].tls_ca_only_custom: "));
+        assert!(file.load_problems[2].starts_with("heartbeat_interval: "));
+
+        // Other loaders (setup, convert) must not silently drop entries.
+        let err = ConfigFile::load(&path).unwrap_err().to_string();
+        assert!(
+            err.contains("servers[1]") && err.contains("servers[2]"),
+            "{err}"
+        );
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, format!("strict_config = true\n{content}")).unwrap();
+        assert!(ConfigFile::load_startup(&path).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn empty_allowed_ports_is_a_load_error() {
+        let load = |content: &str| ConfigFile::from_table(toml::from_str(content).unwrap(), true);
+        let err = load("allowed_ports = []").unwrap_err().to_string();
+        assert!(err.starts_with("allowed_ports must not be empty"), "{err}");
+        let err = load("[profiles.edge]\nallowed_ports = []")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("profiles.edge.allowed_ports must not be empty"),
+            "{err}"
+        );
+        assert!(load("allowed_ports = [443]").is_ok());
+    }
+
+    #[test]
+    fn unknown_keys_are_reported_not_fatal() {
+        let dir = temp_dir("unknown-keys");
+        let path = dir.join("aether-proxy.toml");
+        std::fs::write(
+            &path,
+            r#"
+            heartbeat_intervl = 15
+            log_level = "debug"
+            delegate_tcp_nodelay = true
+            include = []
+
+            [[servers]]
+            aether_url = "https://a.example.com"
+            management_token = "ae_a"
+            node_region = "eu"
+            colour = "blue"
+
+            [service]
+            hardening = true
+
+            [profiles.edge]
+            tunnel_conections = 4
+            delegate_tcp_nodelay = true
+            servers = []
+
+            [profiles.edge.compression]
+            content_type = []
+            "#,
+        )
+        .unwrap();
+
+        let file = ConfigFile::load(&path).unwrap();
+        assert_eq!(file.log_level.as_deref(), Some("debug"));
+        assert_eq!(
+            file.unknown_keys,
+            [
+                "delegate_tcp_nodelay (renamed to 'upstream_tcp_nodelay' in 0.2.0)",
+                "heartbeat_intervl (did you mean 'heartbeat_interval'?)",
+                "profiles.edge.compression.content_type (did you mean 'content_types'?)",
+                "profiles.edge.delegate_tcp_nodelay (renamed to 'upstream_tcp_nodelay' in 0.2.0)",
+                "profiles.edge.servers (not allowed in a profile)",
+                "profiles.edge.tunnel_conections (did you mean 'tunnel_connections'?)",
+                "servers[0].colour",
+            ]
+        );
+        assert!(file.load_problems.is_empty());
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, format!("strict_config = true\n{content}")).unwrap();
+        let err = ConfigFile::load_startup(&path).unwrap_err().to_string();
+        assert!(err.contains("unknown key heartbeat_intervl"), "{err}");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn startup_load_sees_the_migrated_file() {
+        let dir = temp_dir("migrate");
+        let path = dir.join("aether-proxy.toml");
+        std::fs::write(
+            &path,
+            "aether_url = \"https://a.example.com\"\n\
+             management_token = \"ae_a\"\n\
+             node_name = \"legacy-node\"\n\
+             hmac_key = \"old\"\n",
+        )
+        .unwrap();
+
+        let file = ConfigFile::load_startup(&path).unwrap();
+        assert!(path.with_extension("v1.bak").exists());
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("hmac_key"));
+        // The migrated [[servers]] entry, not the legacy top-level fields.
+        assert_eq!(file.aether_url, None);
+        let servers = file.effective_servers();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].aether_url, "https://a.example.com");
+        assert_eq!(servers[0].node_name.as_deref(), Some("legacy-node"));
+
+        // A file that no longer parses is an error, not an empty config.
+        std::fs::write(&path, "[[servers]\n").unwrap();
+        std::fs::remove_file(path.with_extension("v1.bak")).unwrap();
+        assert!(ConfigFile::load_startup(&path).is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn circular_include_is_rejected() {
+        let dir = temp_dir("cycle");
+        std::fs::write(dir.join("a.toml"), "include = [\"b.toml\"]\n").unwrap();
+        std::fs::write(dir.join("b.toml"), "include = [\"a.toml\"]\n").unwrap();
+
+        let err = ConfigFile::load(&dir.join("a.toml")).unwrap_err();
+        assert!(err.to_string().contains("circular"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn torn_write_offers_a_newer_temp_file() {
+        let dir = temp_dir("recover");
+        let path = dir.join("aether-proxy.toml");
+        let tmp = sibling_path(&path, "tmp");
+        let cfg = ConfigFile {
+            log_level: Some("debug".into()),
+            ..Default::default()
+        };
+        cfg.save(&path).unwrap();
+        assert!(!tmp.exists());
+        assert_eq!(ConfigFile::recovery_candidate(&path).unwrap(), None);
+
+        // Crash mid-write: the config is truncated, the finished temp file
+        // was never renamed into place.  It is reported, not restored.
+        let torn = "log_level = \"deb";
+        std::fs::write(&path, torn).unwrap();
+        std::fs::write(&tmp, toml::to_string_pretty(&cfg).unwrap()).unwrap();
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        assert_eq!(
+            ConfigFile::recovery_candidate(&path).unwrap(),
+            Some(tmp.clone())
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), torn);
+
+        // A temp file older than the config is stale, a torn one useless;
+        // the migration backup is never offered.
+        std::fs::File::options()
+            .write(true)
+            .open(&tmp)
+            .unwrap()
+            .set_modified(old - std::time::Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(ConfigFile::recovery_candidate(&path).unwrap(), None);
+        std::fs::write(&tmp, "[[servers]\n").unwrap();
+        std::fs::write(path.with_extension("v1.bak"), "log_level = \"warn\"\n").unwrap();
+        assert_eq!(ConfigFile::recovery_candidate(&path).unwrap(), None);
+
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
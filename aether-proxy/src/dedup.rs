@@ -0,0 +1,124 @@
+//! Short-lived set for recognising requests Aether sends more than once.
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a request fingerprint is remembered.
+pub const FINGERPRINT_TTL: Duration = Duration::from_secs(30);
+/// Maximum number of fingerprints remembered at once.
+pub const FINGERPRINT_CAPACITY: usize = 10_000;
+
+/// Set whose members expire after a fixed TTL, bounded by capacity
+/// (the oldest member is evicted first).
+pub struct ExpiringSet<T> {
+    ttl: Duration,
+    capacity: usize,
+    inner: Mutex<Entries<T>>,
+}
+
+struct Entries<T> {
+    /// Member -> insertion time.
+    members: HashMap<T, Instant>,
+    /// Insertions, oldest first.  An entry whose time no longer matches
+    /// `members` (removed, or inserted again later) is skipped.
+    order: VecDeque<(T, Instant)>,
+}
+
+impl<T: Eq + Hash + Clone> Entries<T> {
+    /// Drop the oldest insertion, and its member if still current.
+    fn pop_oldest(&mut self) -> bool {
+        let Some((value, inserted)) = self.order.pop_front() else {
+            return false;
+        };
+        if self.members.get(&value) == Some(&inserted) {
+            self.members.remove(&value);
+        }
+        true
+    }
+}
+
+impl<T: Eq + Hash + Clone> ExpiringSet<T> {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            inner: Mutex::new(Entries {
+                members: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Add `value`, returning `false` if it was already present and unexpired.
+    pub fn insert(&self, value: T) -> bool {
+        self.insert_at(value, Instant::now())
+    }
+
+    /// Forget `value`, so inserting it again succeeds.
+    pub fn remove(&self, value: &T) {
+        self.inner.lock().unwrap().members.remove(value);
+    }
+
+    fn insert_at(&self, value: T, now: Instant) -> bool {
+        if self.capacity == 0 || self.ttl.is_zero() {
+            return true;
+        }
+        let mut entries = self.inner.lock().unwrap();
+        if entries
+            .members
+            .get(&value)
+            .is_some_and(|inserted| now.duration_since(*inserted) < self.ttl)
+        {
+            return false;
+        }
+        while entries
+            .order
+            .front()
+            .is_some_and(|(_, inserted)| now.duration_since(*inserted) >= self.ttl)
+        {
+            entries.pop_oldest();
+        }
+        while entries.members.len() >= self.capacity && entries.pop_oldest() {}
+        entries.members.insert(value.clone(), now);
+        entries.order.push_back((value, now));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn members_expire_and_oldest_is_evicted_at_capacity() {
+        let set = ExpiringSet::new(Duration::from_secs(30), 2);
+        let start = Instant::now();
+        assert!(set.insert_at("a", start));
+        assert!(!set.insert_at("a", start + Duration::from_secs(29)));
+        assert!(set.insert_at("a", start + Duration::from_secs(30)));
+
+        let later = start + Duration::from_secs(31);
+        assert!(set.insert_at("b", later));
+        assert!(set.insert_at("c", later + Duration::from_secs(1)));
+        // "a" (oldest) made room for "c"; "b" is still remembered.
+        assert!(!set.insert_at("b", later + Duration::from_secs(2)));
+        assert!(set.insert_at("a", later + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn removed_members_can_be_inserted_again() {
+        let set = ExpiringSet::new(Duration::from_secs(30), 2);
+        let start = Instant::now();
+        assert!(set.insert_at("a", start));
+        set.remove(&"a");
+        assert!(set.insert_at("b", start + Duration::from_secs(1)));
+        assert!(set.insert_at("a", start + Duration::from_secs(2)));
+        assert!(!set.insert_at("a", start + Duration::from_secs(3)));
+        // The stale first insertion of "a" doesn't evict the current one.
+        assert!(set.insert_at("c", start + Duration::from_secs(4)));
+        assert!(!set.insert_at("a", start + Duration::from_secs(5)));
+        assert!(set.insert_at("b", start + Duration::from_secs(5)));
+    }
+}
@@ -2,6 +2,8 @@ use serde::Serialize;
 use sysinfo::System;
 use tracing::info;
 
+use crate::build_info;
+
 /// Hardware information collected at startup.
 ///
 /// The struct is `Serialize`-able so it can be sent directly as the
@@ -13,6 +15,8 @@ pub struct HardwareInfo {
     pub total_memory_mb: u64,
     pub os_info: String,
     pub fd_limit: u64,
+    /// Build of this binary (version, commit, rustc, target, features).
+    pub build: build_info::BuildInfo,
     #[serde(skip)]
     pub estimated_max_concurrency: u64,
 }
@@ -57,6 +61,7 @@ pub fn collect() -> HardwareInfo {
         total_memory_mb,
         os_info,
         fd_limit,
+        build: build_info::info(),
         estimated_max_concurrency,
     }
 }
@@ -0,0 +1,199 @@
+//! Declarative header rewriting for upstream requests and responses.
+//!
+//! `upstream_header_rules` is a list of set/remove/rename actions, each
+//! optionally limited to one host, applied in order to every upstream
+//! request (HTTP and WebSocket) after the proxy has added its own headers.
+//! `response_header_rules` does the same to upstream response headers
+//! before they go back through the tunnel.
+
+use std::fmt;
+use std::str::FromStr;
+
+use hyper::header::{Entry, HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+/// One rewrite rule.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeaderRule {
+    /// Only apply to requests for this host (case-insensitive); a leading
+    /// `*.` matches any subdomain.  Applies to every host when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(flatten)]
+    pub action: HeaderAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum HeaderAction {
+    /// Set `name` to `value`, replacing any existing values.
+    Set { name: String, value: String },
+    /// Drop `name`.
+    Remove { name: String },
+    /// Move the values of `name` to `to` (no-op if `name` is absent).
+    Rename { name: String, to: String },
+}
+
+impl HeaderRule {
+    fn matches_host(&self, host: &str) -> bool {
+        match self.host.as_deref() {
+            None => true,
+            Some(pattern) => match pattern.strip_prefix("*.") {
+                Some(suffix) => host.len().checked_sub(suffix.len() + 1).is_some_and(|dot| {
+                    host.as_bytes()[dot] == b'.' && host[dot + 1..].eq_ignore_ascii_case(suffix)
+                }),
+                None => host.eq_ignore_ascii_case(pattern),
+            },
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let name = |n: &str| {
+            HeaderName::from_bytes(n.as_bytes())
+                .map(|_| ())
+                .map_err(|_| format!("invalid header name: {n}"))
+        };
+        match &self.action {
+            HeaderAction::Set { name: n, value } => {
+                name(n)?;
+                HeaderValue::from_str(value)
+                    .map(|_| ())
+                    .map_err(|_| format!("invalid value for header {n}"))
+            }
+            HeaderAction::Remove { name: n } => name(n),
+            HeaderAction::Rename { name: n, to } => name(n).and_then(|_| name(to)),
+        }
+    }
+}
+
+/// The configured rule list.  Parsed from JSON on the command line and in
+/// the environment; `[[upstream_header_rules]]` tables in the config file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct HeaderRules(pub Vec<HeaderRule>);
+
+impl HeaderRules {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Apply the rules matching `host` to `headers`, in order.
+    pub fn apply(&self, host: &str, headers: &mut HeaderMap) {
+        for rule in self.0.iter().filter(|r| r.matches_host(host)) {
+            // Names and values were checked when the rules were parsed.
+            match &rule.action {
+                HeaderAction::Set { name, value } => {
+                    if let (Ok(name), Ok(value)) = (
+                        HeaderName::from_bytes(name.as_bytes()),
+                        HeaderValue::from_str(value),
+                    ) {
+                        headers.insert(name, value);
+                    }
+                }
+                HeaderAction::Remove { name } => {
+                    headers.remove(name.as_str());
+                }
+                HeaderAction::Rename { name, to } => {
+                    let (Ok(from), Ok(to)) = (
+                        HeaderName::from_bytes(name.as_bytes()),
+                        HeaderName::from_bytes(to.as_bytes()),
+                    ) else {
+                        continue;
+                    };
+                    let Entry::Occupied(entry) = headers.entry(from) else {
+                        continue;
+                    };
+                    let values: Vec<HeaderValue> = entry.remove_entry_mult().1.collect();
+                    headers.remove(&to);
+                    for value in values {
+                        headers.append(to.clone(), value);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for HeaderRules {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rules: Self = serde_json::from_str(s).map_err(|e| e.to_string())?;
+        for rule in &rules.0 {
+            rule.validate()?;
+        }
+        Ok(rules)
+    }
+}
+
+impl fmt::Display for HeaderRules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&serde_json::to_string(self).map_err(|_| fmt::Error)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_apply_in_order_and_respect_host() {
+        let rules: HeaderRules = r#"[
+            {"action": "set", "name": "x-region", "value": "jp"},
+            {"action": "remove", "name": "x-client-fingerprint", "host": "*.example.com"},
+            {"action": "rename", "name": "x-api-key", "to": "authorization", "host": "api.example.com"}
+        ]"#
+        .parse()
+        .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-client-fingerprint", HeaderValue::from_static("abc"));
+        headers.insert("x-api-key", HeaderValue::from_static("secret"));
+        headers.insert("authorization", HeaderValue::from_static("stale"));
+        rules.apply("API.example.com", &mut headers);
+        assert_eq!(headers["x-region"], "jp");
+        assert!(!headers.contains_key("x-client-fingerprint"));
+        assert!(!headers.contains_key("x-api-key"));
+        assert_eq!(headers.get_all("authorization").iter().count(), 1);
+        assert_eq!(headers["authorization"], "secret");
+
+        // Neither the wildcard nor the exact host matches the bare domain.
+        let mut headers = HeaderMap::new();
+        headers.insert("x-client-fingerprint", HeaderValue::from_static("abc"));
+        headers.insert("x-api-key", HeaderValue::from_static("secret"));
+        rules.apply("example.com", &mut headers);
+        assert_eq!(headers["x-client-fingerprint"], "abc");
+        assert_eq!(headers["x-api-key"], "secret");
+        assert_eq!(headers["x-region"], "jp");
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        assert!(r#"[{"action": "set", "name": "bad name", "value": "x"}]"#
+            .parse::<HeaderRules>()
+            .is_err());
+        assert!(r#"[{"action": "rename", "name": "a"}]"#.parse::<HeaderRules>().is_err());
+        assert!(r#"[{"action": "drop", "name": "a"}]"#.parse::<HeaderRules>().is_err());
+    }
+
+    #[test]
+    fn toml_tables_round_trip_through_the_env_format() {
+        #[derive(Deserialize)]
+        struct File {
+            upstream_header_rules: HeaderRules,
+        }
+        let file: File = toml::from_str(
+            r#"
+            [[upstream_header_rules]]
+            action = "set"
+            name = "x-region"
+            value = "jp"
+            host = "api.example.com"
+            "#,
+        )
+        .unwrap();
+        let reparsed: HeaderRules = file.upstream_header_rules.to_string().parse().unwrap();
+        assert_eq!(reparsed, file.upstream_header_rules);
+        assert_eq!(reparsed.0[0].host.as_deref(), Some("api.example.com"));
+    }
+}
@@ -0,0 +1,171 @@
+//! Log file output with size-based rotation.
+//!
+//! With `log_file` set, log lines are also written to that file through
+//! tracing-appender's non-blocking writer, so a slow disk never stalls a
+//! tunnel task.  Once the file reaches the `log_rotation` size it is
+//! renamed to `<file>.1` (older copies shift to `.2`, `.3`, ...) and a new
+//! file is started; only the newest `keep` rotated files are kept.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Parsed `log_rotation`: `<size>:<keep>` (size with an optional K/M/G
+/// suffix, e.g. `100M:5`) or `never`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogRotation {
+    /// Rotate once the file reaches this many bytes (`None` = never).
+    pub max_bytes: Option<u64>,
+    /// Rotated files to keep.
+    pub keep: usize,
+}
+
+impl LogRotation {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("never") {
+            return Ok(Self {
+                max_bytes: None,
+                keep: 0,
+            });
+        }
+        let Some((size, keep)) = s.split_once(':') else {
+            anyhow::bail!("log_rotation must be <size>:<keep> or never, got '{}'", s);
+        };
+        let size = size.trim();
+        let (digits, unit) = match size.char_indices().last() {
+            Some((i, c)) if c.is_ascii_alphabetic() => (&size[..i], c.to_ascii_uppercase()),
+            _ => (size, 'B'),
+        };
+        let multiplier: u64 = match unit {
+            'B' => 1,
+            'K' => 1 << 10,
+            'M' => 1 << 20,
+            'G' => 1 << 30,
+            _ => anyhow::bail!("log_rotation: unknown size unit '{}'", unit),
+        };
+        let max_bytes = digits
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .and_then(|n| n.checked_mul(multiplier))
+            .filter(|&n| n > 0)
+            .ok_or_else(|| anyhow::anyhow!("log_rotation: invalid size '{}'", size))?;
+        let keep = keep
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| anyhow::anyhow!("log_rotation: invalid keep count '{}'", keep))?;
+        Ok(Self {
+            max_bytes: Some(max_bytes),
+            keep,
+        })
+    }
+}
+
+/// Appends to `path`, rotating it per [`LogRotation`].
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: LogRotation,
+    file: File,
+    written: u64,
+}
+
+impl RotatingFile {
+    /// Open (or create) `path` for appending, creating its directory.
+    pub fn open(path: &Path, rotation: LogRotation) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = open_append(path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            rotation,
+            file,
+            written,
+        })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.rotation.keep == 0 {
+            // Nothing to keep: start over in place.
+            self.file.set_len(0)?;
+        } else {
+            let _ = fs::remove_file(self.rotated(self.rotation.keep));
+            for n in (1..self.rotation.keep).rev() {
+                let _ = fs::rename(self.rotated(n), self.rotated(n + 1));
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+            self.file = open_append(&self.path)?;
+        }
+        self.written = 0;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl Write for RotatingFile {
+    /// The non-blocking worker hands over one formatted event per call, so
+    /// rotating between calls never splits a line.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(max) = self.rotation.max_bytes {
+            if self.written > 0 && self.written + buf.len() as u64 > max {
+                self.rotate()?;
+            }
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation_keeps_the_newest_files() {
+        assert_eq!(
+            LogRotation::parse("100M:5").unwrap(),
+            LogRotation {
+                max_bytes: Some(100 << 20),
+                keep: 5
+            }
+        );
+        assert_eq!(LogRotation::parse("never").unwrap().max_bytes, None);
+        assert_eq!(LogRotation::parse("512:0").unwrap().max_bytes, Some(512));
+        for bad in ["100M", "0:3", "10X:1", "1M:-1", ""] {
+            assert!(LogRotation::parse(bad).is_err(), "{bad}");
+        }
+
+        let dir = std::env::temp_dir().join(format!("aether-log-file-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("logs/proxy.log");
+        let mut file = RotatingFile::open(&path, LogRotation::parse("10:2").unwrap()).unwrap();
+        for line in ["one-line\n", "two-line\n", "three-li\n", "four-lin\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        let read = |p: PathBuf| fs::read_to_string(p).unwrap();
+        assert_eq!(read(path.clone()), "four-lin\n");
+        assert_eq!(read(file.rotated(1)), "three-li\n");
+        assert_eq!(read(file.rotated(2)), "two-line\n");
+        assert!(!file.rotated(3).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,23 +1,34 @@
 mod app;
+mod build_info;
 mod config;
+mod dedup;
 mod hardware;
+mod header_rules;
+mod log_file;
+mod memory;
 mod net;
+mod notification;
+mod pid_file;
+mod rate_limit;
 mod registration;
 mod runtime;
 mod setup;
+mod shutdown;
 mod state;
+mod state_file;
 mod target_filter;
+mod telemetry;
+mod timing;
+mod tls;
 mod tunnel;
+mod upstream_auth;
 mod upstream_client;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use clap::{CommandFactory, FromArgMatches, Parser};
 
-use config::Config;
-
-/// Default config file name.
-const DEFAULT_CONFIG: &str = "aether-proxy.toml";
+use config::{Config, DEFAULT_CONFIG};
 
 /// Build the full clap command: Config args + discoverable subcommands.
 ///
@@ -25,6 +36,7 @@ const DEFAULT_CONFIG: &str = "aether-proxy.toml";
 /// flags so that e.g. `aether-proxy setup` doesn't demand `--aether-url`.
 fn build_command() -> clap::Command {
     Config::command()
+        .after_help(shutdown::EXIT_CODES_HELP)
         .subcommand(
             clap::Command::new("setup")
                 .about("Interactive setup wizard (TUI)")
@@ -34,6 +46,179 @@ fn build_command() -> clap::Command {
                         .default_value(DEFAULT_CONFIG),
                 ),
         )
+        .subcommand(
+            clap::Command::new("check")
+                .about("Validate config and TLS setup without starting")
+                .arg(
+                    clap::Arg::new("once")
+                        .long("once")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Also register with the first server, then unregister and exit"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("doctor")
+                .about("Collect a redacted diagnostic bundle (tar.gz)")
+                .arg(
+                    clap::Arg::new("output")
+                        .long("output")
+                        .value_name("DIR")
+                        .help("Directory to write the bundle to")
+                        .default_value("."),
+                )
+                .arg(
+                    clap::Arg::new("no_logs")
+                        .long("no-logs")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Don't include service logs"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("convert-config")
+                .about("Convert a config file to TOML, YAML or env var exports")
+                .arg(
+                    clap::Arg::new("input")
+                        .long("input")
+                        .value_name("FILE")
+                        .required(true)
+                        .help("Config file to read (.toml, or .yaml/.yml)"),
+                )
+                .arg(
+                    clap::Arg::new("output_format")
+                        .long("output-format")
+                        .value_parser(setup::convert::OUTPUT_FORMATS)
+                        .default_value("toml")
+                        .help("Output format"),
+                )
+                .arg(
+                    clap::Arg::new("show_secrets")
+                        .long("show-secrets")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Print management tokens unmasked"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("migrate")
+                .about("Migrate a 0.1.x config file to the current format")
+                .arg(
+                    clap::Arg::new("path")
+                        .value_name("PATH")
+                        .help("Config file (defaults to AETHER_PROXY_CONFIG or aether-proxy.toml)"),
+                )
+                .arg(
+                    clap::Arg::new("dry_run")
+                        .long("dry-run")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Print the diff the migration would make without writing anything"),
+                )
+                .arg(
+                    clap::Arg::new("show_secrets")
+                        .long("show-secrets")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Print management tokens unmasked in the diff"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("ping")
+                .about("Measure WebSocket round-trip latency to the Aether server(s)")
+                .arg(
+                    clap::Arg::new("count")
+                        .long("count")
+                        .short('c')
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .default_value("5")
+                        .help("Number of pings to send"),
+                )
+                .arg(
+                    clap::Arg::new("interval")
+                        .long("interval")
+                        .short('i')
+                        .value_parser(clap::value_parser!(f64))
+                        .default_value("1")
+                        .help("Seconds between pings"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("servers")
+                .about("List, add or remove [[servers]] entries in the config file")
+                .subcommand_required(true)
+                .subcommand(clap::Command::new("list").about("List configured servers (tokens redacted)"))
+                .subcommand(
+                    clap::Command::new("add")
+                        .about("Add a [[servers]] entry")
+                        .arg(
+                            clap::Arg::new("url")
+                                .long("url")
+                                .required(true)
+                                .help("Aether server URL"),
+                        )
+                        .arg(
+                            clap::Arg::new("token")
+                                .long("token")
+                                .required(true)
+                                .help("Management token (ae_...)"),
+                        )
+                        .arg(
+                            clap::Arg::new("name")
+                                .long("name")
+                                .help("Node name for this server (defaults to the global node_name)"),
+                        )
+                        .arg(
+                            clap::Arg::new("region")
+                                .long("region")
+                                .help("Node region for this server"),
+                        )
+                        .arg(
+                            clap::Arg::new("secondary")
+                                .long("secondary")
+                                .action(clap::ArgAction::SetTrue)
+                                .help("Only use this server while every primary is unhealthy"),
+                        ),
+                )
+                .subcommand(
+                    clap::Command::new("remove")
+                        .about("Remove a [[servers]] entry by node name")
+                        .arg(
+                            clap::Arg::new("name")
+                                .long("name")
+                                .required(true)
+                                .help("Node name of the entry to remove"),
+                        )
+                        .arg(
+                            clap::Arg::new("url")
+                                .long("url")
+                                .help("Pick among entries sharing the name"),
+                        )
+                        .arg(
+                            clap::Arg::new("force")
+                                .long("force")
+                                .action(clap::ArgAction::SetTrue)
+                                .help("Allow removing the last server"),
+                        ),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("schema")
+                .about("Print the config file schema (keys, types, defaults, env vars)")
+                .arg(
+                    clap::Arg::new("format")
+                        .long("format")
+                        .value_parser(setup::schema::SCHEMA_FORMATS)
+                        .default_value("json")
+                        .help("json (JSON Schema) or markdown (a table)"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("version")
+                .about("Print the version (with --verbose: commit, build date, rustc, target, features)")
+                .arg(
+                    clap::Arg::new("verbose")
+                        .long("verbose")
+                        .short('v')
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Print the full build info"),
+                ),
+        )
         .subcommand(clap::Command::new("start").about("Start the systemd service"))
         .subcommand(clap::Command::new("status").about("Show service status"))
         .subcommand(clap::Command::new("logs").about("Tail service logs"))
@@ -54,17 +239,30 @@ async fn main() -> anyhow::Result<()> {
         .install_default()
         .map_err(|_| anyhow::anyhow!("Failed to install rustls CryptoProvider"))?;
 
-    // Load config file as env-var defaults (before clap parsing)
-    let config_file_path =
-        std::env::var("AETHER_PROXY_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG.to_string());
-    let config_path = std::path::Path::new(&config_file_path);
-    if config_path.exists() {
-        // Migrate legacy 0.1.x config to 0.2.0 format if needed
-        if let Err(e) = config::ConfigFile::migrate_legacy(config_path) {
-            eprintln!("  WARNING: config migration failed: {}", e);
+    // Load config file as env-var defaults (before clap parsing).  The file
+    // is read exactly once; the proxy uses this result for its server list.
+    let config_file_path = config::config_file_path();
+    let config_path = config_file_path.as_path();
+    let mut file_cfg = None;
+    // `migrate` reads the file itself; loading it here would migrate it
+    // before a `--dry-run` could preview the change.
+    let migrating = std::env::args().nth(1).as_deref() == Some("migrate");
+    if config_path.exists() && !migrating {
+        match config::ConfigFile::load_startup(config_path) {
+            Ok(loaded) => {
+                loaded.inject_env();
+                file_cfg = Some(Ok(loaded));
+            }
+            Err(e) => {
+                eprintln!("  WARNING: failed to load config file: {}", e);
+                file_cfg = Some(Err(e));
+            }
         }
-        if let Ok(file_cfg) = config::ConfigFile::load(config_path) {
-            file_cfg.inject_env();
+    } else if let Some(profile) = config::selected_profile() {
+        // Built-in profiles apply even without a config file.
+        match config::ConfigFile::default().with_profile(&profile) {
+            Ok(file_cfg) => file_cfg.inject_env(),
+            Err(e) => eprintln!("  WARNING: {}", e),
         }
     }
 
@@ -78,9 +276,102 @@ async fn main() -> anyhow::Result<()> {
                     .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG));
                 handle_setup_result(setup::run(path)?).await
             }
+            Some(("check", sub_m)) => {
+                let file = file_cfg
+                    .as_ref()
+                    .map(|f| {
+                        f.as_ref()
+                            .map_err(|e| anyhow::anyhow!("failed to load config file: {:#}", e))
+                    })
+                    .transpose()?;
+                cmd_check(sub_m.get_flag("once"), file).await
+            }
+            Some(("doctor", sub_m)) => {
+                let output = sub_m
+                    .get_one::<String>("output")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| PathBuf::from("."));
+                setup::doctor::cmd_doctor(output, !sub_m.get_flag("no_logs")).await
+            }
+            Some(("convert-config", sub_m)) => {
+                let input = sub_m
+                    .get_one::<String>("input")
+                    .map(PathBuf::from)
+                    .unwrap_or_default();
+                let format = sub_m
+                    .get_one::<String>("output_format")
+                    .map(String::as_str)
+                    .unwrap_or("toml");
+                setup::convert::cmd_convert_config(&input, format, sub_m.get_flag("show_secrets"))
+            }
+            Some(("migrate", sub_m)) => {
+                let path = sub_m
+                    .get_one::<String>("path")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(config::config_file_path);
+                setup::migrate::cmd_migrate(
+                    &path,
+                    sub_m.get_flag("dry_run"),
+                    sub_m.get_flag("show_secrets"),
+                )
+            }
+            Some(("ping", sub_m)) => {
+                let count = sub_m.get_one::<u32>("count").copied().unwrap_or(5);
+                let interval = sub_m.get_one::<f64>("interval").copied().unwrap_or(1.0);
+                let interval = std::time::Duration::try_from_secs_f64(interval)
+                    .map_err(|_| anyhow::anyhow!("invalid --interval: {}", interval))?;
+                setup::ping::cmd_ping(count, interval).await
+            }
+            Some(("servers", sub_m)) => {
+                let path = config::config_file_path();
+                let arg = |m: &clap::ArgMatches, id: &str| m.get_one::<String>(id).cloned();
+                match sub_m.subcommand() {
+                    Some(("add", m)) => setup::servers::cmd_add(
+                        &path,
+                        &setup::servers::NewServer {
+                            url: arg(m, "url").unwrap_or_default(),
+                            token: arg(m, "token").unwrap_or_default(),
+                            name: arg(m, "name"),
+                            region: arg(m, "region"),
+                            secondary: m.get_flag("secondary"),
+                        },
+                    ),
+                    Some(("remove", m)) => setup::servers::cmd_remove(
+                        &path,
+                        &arg(m, "name").unwrap_or_default(),
+                        arg(m, "url").as_deref(),
+                        m.get_flag("force"),
+                    ),
+                    _ => setup::servers::cmd_list(&path),
+                }
+            }
+            Some(("schema", sub_m)) => {
+                let format = sub_m
+                    .get_one::<String>("format")
+                    .map(String::as_str)
+                    .unwrap_or("json");
+                setup::schema::cmd_schema(format)
+            }
+            Some(("version", sub_m)) => {
+                if sub_m.get_flag("verbose") {
+                    print!("{}", build_info::verbose_version());
+                } else {
+                    println!("aether-proxy {}", build_info::VERSION);
+                }
+                Ok(())
+            }
             Some(("start", _)) => setup::service::cmd_start(),
+            Some(("status", _)) if !setup::service::is_systemd_available() => {
+                let pid_file = std::env::var_os("AETHER_PROXY_PID_FILE").map(PathBuf::from);
+                setup::service::cmd_status_pid_file(pid_file.as_deref())
+            }
             Some(("status", _)) => setup::service::cmd_status(),
-            Some(("logs", _)) => setup::service::cmd_logs(),
+            Some(("logs", _)) => {
+                let log_file = std::env::var_os("AETHER_PROXY_LOG_FILE").map(PathBuf::from);
+                let log_stderr = std::env::var("AETHER_PROXY_LOG_STDERR")
+                    .map_or(true, |v| v.parse().unwrap_or(true));
+                setup::service::cmd_logs(log_file.as_deref(), log_stderr)
+            }
             Some(("restart", _)) => setup::service::cmd_restart(),
             Some(("stop", _)) => setup::service::cmd_stop(),
             Some(("uninstall", _)) => setup::service::cmd_uninstall(),
@@ -92,13 +383,13 @@ async fn main() -> anyhow::Result<()> {
             None => {
                 // No subcommand — run the proxy with parsed config.
                 let config = Config::from_arg_matches(&matches)?;
-                run_proxy(config).await
+                run_proxy(config, file_cfg).await
             }
         },
         Err(e) => {
             if e.kind() == clap::error::ErrorKind::MissingRequiredArgument {
                 eprintln!("Missing required config, launching setup wizard...\n");
-                handle_setup_result(setup::run(PathBuf::from(&config_file_path))?).await
+                handle_setup_result(setup::run(config_file_path.clone())?).await
             } else {
                 e.exit();
             }
@@ -113,15 +404,16 @@ async fn handle_setup_result(outcome: setup::SetupOutcome) -> anyhow::Result<()>
         setup::SetupOutcome::ReadyToRun(config_path) => {
             // Reload config from the file that setup just wrote, overriding
             // any stale env vars from a previous config.
-            match config::ConfigFile::load(&config_path) {
-                Ok(file_cfg) => file_cfg.inject_env_override(),
+            let file_cfg = match config::ConfigFile::load(&config_path) {
+                Ok(file_cfg) => file_cfg,
                 Err(e) => anyhow::bail!("failed to reload config after setup: {}", e),
-            }
+            };
+            file_cfg.inject_env_override();
             // Parse from env-only (argv may still contain "setup" etc.)
             let config = Config::try_parse_from(["aether-proxy"])
                 .map_err(|e| anyhow::anyhow!("config invalid after setup: {}", e))?;
             eprintln!("  Starting proxy...\n");
-            run_proxy(config).await
+            run_proxy(config, Some(Ok(file_cfg))).await
         }
         setup::SetupOutcome::Cancelled => {
             eprintln!("  Setup cancelled.");
@@ -130,8 +422,147 @@ async fn handle_setup_result(outcome: setup::SetupOutcome) -> anyhow::Result<()>
     }
 }
 
+/// `aether-proxy check` -- validate the effective config and report which
+/// TLS root certificates would be used, without registering or connecting.
+async fn cmd_check(once: bool, file: Option<&config::ConfigFile>) -> anyhow::Result<()> {
+    let config = Config::try_parse_from(["aether-proxy"])
+        .map_err(|e| anyhow::anyhow!("config invalid: {}", e))?;
+    config.validate()?;
+
+    let tunnel = tls::load_root_store(
+        config.tunnel_tls_roots,
+        config.tunnel_extra_ca_file.as_deref(),
+    )?;
+    let upstream = tls::load_root_store(
+        config.upstream_tls_roots,
+        config.upstream_extra_ca_file.as_deref(),
+    )?;
+
+    eprintln!();
+    eprintln!(
+        "  Config file:        {}",
+        config::config_file_path().display()
+    );
+    eprintln!(
+        "  Tunnel TLS roots:   {} [{}]",
+        config.tunnel_tls_roots, tunnel.description
+    );
+    eprintln!(
+        "  Upstream TLS roots: {} [{}]",
+        config.upstream_tls_roots, upstream.description
+    );
+    eprintln!();
+    let port_warnings = target_filter::port_warnings(&config.allowed_ports);
+    if !port_warnings.is_empty() {
+        eprintln!("  Allowed ports:");
+        for warning in &port_warnings {
+            eprintln!("    - WARNING: {}", warning);
+        }
+    }
+    if let Some(keys) = file.map(|f| &f.unknown_keys).filter(|k| !k.is_empty()) {
+        eprintln!("  Unknown config keys (ignored):");
+        for key in keys {
+            eprintln!("    - {}", key);
+        }
+    }
+    if let Some(problems) = file.map(|f| &f.load_problems).filter(|p| !p.is_empty()) {
+        eprintln!("  Config file problems (these entries are ignored):");
+        for problem in problems {
+            eprintln!("    - {}", problem);
+        }
+        anyhow::bail!("{} config file problem(s)", problems.len());
+    }
+    eprintln!("  Config OK.");
+
+    if once {
+        check_registration(&config, file, tunnel).await?;
+    }
+    Ok(())
+}
+
+/// `check --once`: register with the first server and unregister again, to
+/// separate token/URL problems from tunnel problems.
+async fn check_registration(
+    config: &Config,
+    file: Option<&config::ConfigFile>,
+    roots: tls::RootStore,
+) -> anyhow::Result<()> {
+    // Registration is an upsert keyed by the node's address, so this would
+    // take over (and then remove) the running service's node.
+    if setup::service::is_service_active() {
+        anyhow::bail!("the service is running; stop it before `check --once`");
+    }
+
+    let servers = config.servers(file);
+    let server = servers
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("no servers configured"))?;
+    let node_name = server
+        .node_name
+        .clone()
+        .unwrap_or_else(|| config.node_name.clone());
+    let node_region = server
+        .node_region
+        .clone()
+        .or_else(|| config.node_region.clone());
+    let public_ip = match &config.public_ip {
+        Some(ip) => ip.clone(),
+        None if config.disable_ip_detection => String::new(),
+        None => net::detect_public_ip()
+            .await
+            .unwrap_or_else(|_| "0.0.0.0".to_string()),
+    };
+
+    let shared = std::sync::Arc::new(tunnel::client::build_tls_config(roots.clone()));
+    let tls_config = tunnel::client::server_tls_config(&shared, &roots, server)?;
+    let client = registration::client::AetherClient::new(
+        config,
+        &server.aether_url,
+        &server.management_token,
+        &tls_config,
+        state_file::StateFile::client_instance_id(&server.aether_url, &node_name),
+    );
+
+    eprintln!();
+    eprintln!(
+        "  Registering with {} as {}...",
+        server.aether_url, node_name
+    );
+    let node_id = client
+        .register(
+            config,
+            &node_name,
+            node_region.as_deref(),
+            &config.node_tags_for(server),
+            &public_ip,
+            Some(&hardware::collect()),
+        )
+        .await
+        .map_err(|e| anyhow::anyhow!("registration failed: {:#}", e))?;
+    eprintln!("  Registered:   node_id={}", node_id);
+
+    client
+        .unregister(&node_id)
+        .await
+        .map_err(|e| anyhow::anyhow!("unregister failed for node_id={}: {:#}", node_id, e))?;
+    eprintln!("  Unregistered.");
+    eprintln!();
+    eprintln!("  Registration OK.");
+    Ok(())
+}
+
 /// Start the proxy server, checking for systemd conflicts first.
-async fn run_proxy(config: Config) -> anyhow::Result<()> {
+///
+/// `file_cfg` is the config file as loaded at startup (`None` without one);
+/// a file that failed to load is fatal here rather than silently ignored.
+async fn run_proxy(
+    config: Config,
+    file_cfg: Option<anyhow::Result<config::ConfigFile>>,
+) -> anyhow::Result<()> {
+    let file_cfg = file_cfg
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("failed to load config file: {:#}", e))?;
+
     // Warn if systemd service is already running (would cause port conflict).
     // Skip this check when we ARE the systemd service (INVOCATION_ID is set by systemd).
     if std::env::var_os("INVOCATION_ID").is_none() && setup::service::is_service_active() {
@@ -141,28 +572,16 @@ async fn run_proxy(config: Config) -> anyhow::Result<()> {
         std::process::exit(1);
     }
 
+    if let Some(path) = &config.pid_file {
+        pid_file::write_pid_file(Path::new(path))?;
+    }
+
     // Resolve server list: prefer [[servers]] from TOML, fall back to CLI/env single server.
-    let config_path =
-        std::env::var("AETHER_PROXY_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG.to_string());
-    let servers = if std::path::Path::new(&config_path).exists() {
-        config::ConfigFile::load(std::path::Path::new(&config_path))
-            .ok()
-            .map(|f| f.effective_servers())
-            .filter(|s| !s.is_empty())
-            .unwrap_or_else(|| {
-                vec![config::ServerEntry {
-                    aether_url: config.aether_url.clone(),
-                    management_token: config.management_token.clone(),
-                    node_name: None,
-                }]
-            })
-    } else {
-        vec![config::ServerEntry {
-            aether_url: config.aether_url.clone(),
-            management_token: config.management_token.clone(),
-            node_name: None,
-        }]
-    };
+    let servers = config.servers(file_cfg.as_ref());
 
-    app::run(config, servers).await
+    let exit_code = app::run(config, servers).await?;
+    if exit_code != shutdown::EXIT_CLEAN {
+        std::process::exit(exit_code);
+    }
+    Ok(())
 }
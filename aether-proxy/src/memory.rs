@@ -0,0 +1,539 @@
+//! Process memory guard: shed load before the OOM killer does.
+//!
+//! A sampler reads process RSS every few seconds.  Above the soft limit new
+//! streams are rejected with `memory_pressure`; above the hard limit the
+//! oldest in-flight streams with large request bodies are cancelled until
+//! enough memory should be freed.
+//!
+//! Independently of RSS, [`BufferBudget`] caps the bytes streams hold in
+//! memory at once (buffered request bodies, response chunks on their way
+//! to the writer).  Once it is spent new streams are rejected the same way
+//! and streams pause reading upstream until buffered bytes are released.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tokio::sync::{watch, Notify};
+use tracing::{error, info, warn};
+
+/// RSS sampling interval.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(2);
+/// Streams with less request body than this are never cancelled.
+const STREAM_ABORT_MIN_BYTES: u64 = 1024 * 1024;
+/// Pressure is only released once RSS falls this far (percent) below the limit,
+/// so the guard doesn't flap around the threshold.
+const RELEASE_PERCENT: u64 = 90;
+
+/// Current memory pressure level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MemoryPressure {
+    Normal = 0,
+    /// Above the soft limit: reject new streams.
+    Soft = 1,
+    /// Above the hard limit: also cancel large in-flight streams.
+    Hard = 2,
+}
+
+impl MemoryPressure {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            2 => Self::Hard,
+            1 => Self::Soft,
+            _ => Self::Normal,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Soft => "soft",
+            Self::Hard => "hard",
+        }
+    }
+}
+
+struct TrackedEntry {
+    started: Instant,
+    body_bytes: Arc<AtomicUsize>,
+    cancel: Arc<Notify>,
+}
+
+/// Shared memory guard (one per process).
+pub struct MemoryGuard {
+    soft_limit: u64,
+    hard_limit: u64,
+    level: AtomicU8,
+    rss: AtomicU64,
+    next_key: AtomicU64,
+    streams: Mutex<HashMap<u64, TrackedEntry>>,
+}
+
+impl MemoryGuard {
+    /// Limits are in MiB; 0 disables that limit.
+    pub fn new(soft_limit_mb: u64, hard_limit_mb: u64) -> Self {
+        Self {
+            soft_limit: soft_limit_mb.saturating_mul(1024 * 1024),
+            hard_limit: hard_limit_mb.saturating_mul(1024 * 1024),
+            level: AtomicU8::new(MemoryPressure::Normal as u8),
+            rss: AtomicU64::new(0),
+            next_key: AtomicU64::new(0),
+            streams: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.soft_limit > 0 || self.hard_limit > 0
+    }
+
+    pub fn pressure(&self) -> MemoryPressure {
+        MemoryPressure::from_u8(self.level.load(Ordering::Acquire))
+    }
+
+    /// Last sampled RSS in bytes (0 until the first sample).
+    pub fn rss_bytes(&self) -> u64 {
+        self.rss.load(Ordering::Acquire)
+    }
+
+    /// Register an in-flight stream so it can be cancelled under hard pressure.
+    pub fn track(self: &Arc<Self>) -> TrackedStream {
+        let key = self.next_key.fetch_add(1, Ordering::Relaxed);
+        let body_bytes = Arc::new(AtomicUsize::new(0));
+        let cancel = Arc::new(Notify::new());
+        self.streams.lock().unwrap().insert(
+            key,
+            TrackedEntry {
+                started: Instant::now(),
+                body_bytes: Arc::clone(&body_bytes),
+                cancel: Arc::clone(&cancel),
+            },
+        );
+        TrackedStream {
+            key,
+            guard: Arc::clone(self),
+            body_bytes,
+            response_bytes: Arc::new(AtomicU64::new(0)),
+            cancel,
+        }
+    }
+
+    /// Apply an RSS reading: update the pressure level (logging transitions)
+    /// and, at the hard limit, cancel streams.  Returns the new level.
+    pub fn observe(&self, rss: u64) -> MemoryPressure {
+        self.rss.store(rss, Ordering::Release);
+        let previous = self.pressure();
+        let level = classify(rss, self.soft_limit, self.hard_limit, previous);
+        if level != previous {
+            self.level.store(level as u8, Ordering::Release);
+            let rss_mb = rss / (1024 * 1024);
+            if level > previous {
+                warn!(
+                    rss_mb,
+                    from = previous.as_str(),
+                    to = level.as_str(),
+                    "memory pressure increased"
+                );
+            } else {
+                info!(
+                    rss_mb,
+                    from = previous.as_str(),
+                    to = level.as_str(),
+                    "memory pressure eased"
+                );
+            }
+        }
+
+        if level == MemoryPressure::Hard {
+            let excess = rss.saturating_sub(self.hard_limit);
+            let streams = self.streams.lock().unwrap();
+            let candidates: Vec<(u64, Instant, u64)> = streams
+                .iter()
+                .map(|(key, e)| (*key, e.started, e.body_bytes.load(Ordering::Relaxed) as u64))
+                .collect();
+            let victims = select_victims(&candidates, STREAM_ABORT_MIN_BYTES, excess);
+            if !victims.is_empty() {
+                warn!(
+                    count = victims.len(),
+                    excess_mb = excess / (1024 * 1024),
+                    "cancelling large streams over memory hard limit"
+                );
+            }
+            for key in victims {
+                if let Some(entry) = streams.get(&key) {
+                    entry.cancel.notify_one();
+                }
+            }
+        }
+        level
+    }
+}
+
+/// Handle for an in-flight stream; unregisters itself on drop.
+pub struct TrackedStream {
+    key: u64,
+    guard: Arc<MemoryGuard>,
+    /// Request body bytes forwarded upstream so far.
+    pub body_bytes: Arc<AtomicUsize>,
+    /// Response header and body bytes sent back to Aether so far.
+    pub response_bytes: Arc<AtomicU64>,
+    cancel: Arc<Notify>,
+}
+
+impl TrackedStream {
+    /// Resolves when the guard cancels this stream.
+    pub async fn cancelled(&self) {
+        self.cancel.notified().await;
+    }
+}
+
+impl Drop for TrackedStream {
+    fn drop(&mut self) {
+        self.guard.streams.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// Proxy-wide budget of bytes buffered by streams.
+pub struct BufferBudget {
+    /// 0 = unlimited (bytes are still counted).
+    limit: u64,
+    used: AtomicU64,
+    room: Notify,
+}
+
+impl BufferBudget {
+    /// `limit_mb` in MiB; 0 disables the limit.
+    pub fn new(limit_mb: u64) -> Self {
+        Self {
+            limit: limit_mb.saturating_mul(1024 * 1024),
+            used: AtomicU64::new(0),
+            room: Notify::new(),
+        }
+    }
+
+    /// Bytes currently buffered.
+    pub fn used_bytes(&self) -> u64 {
+        self.used.load(Ordering::Acquire)
+    }
+
+    /// Whether the buffered bytes reached the limit.
+    pub fn exhausted(&self) -> bool {
+        self.limit > 0 && self.used_bytes() >= self.limit
+    }
+
+    /// Count `bytes` as buffered until the reservation is dropped.  Never
+    /// blocks: callers check [`exhausted`](Self::exhausted) or wait in
+    /// [`wait_for_room`](Self::wait_for_room) before taking on more.
+    pub fn reserve(self: &Arc<Self>, bytes: usize) -> BufferReservation {
+        let mut reservation = BufferReservation {
+            budget: Arc::clone(self),
+            bytes: 0,
+        };
+        reservation.grow(bytes);
+        reservation
+    }
+
+    /// `payload`, counted as buffered until its last clone is dropped (e.g.
+    /// once the writer has sent the frame carrying it).
+    pub fn hold(self: &Arc<Self>, payload: Bytes) -> Bytes {
+        let reservation = self.reserve(payload.len());
+        Bytes::from_owner(HeldPayload {
+            payload,
+            _reservation: reservation,
+        })
+    }
+
+    /// Resolves once the buffered bytes are below the limit.
+    pub async fn wait_for_room(&self) {
+        loop {
+            let notified = self.room.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if !self.exhausted() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    fn release(&self, bytes: u64) {
+        if bytes == 0 {
+            return;
+        }
+        let previous = self
+            .used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                Some(used.saturating_sub(bytes))
+            })
+            .unwrap_or_default();
+        if previous < bytes {
+            error!(
+                used = previous,
+                released = bytes,
+                "buffer budget underflow, counter reset to 0"
+            );
+        }
+        if self.limit > 0 && previous.saturating_sub(bytes) < self.limit {
+            self.room.notify_waiters();
+        }
+    }
+}
+
+/// Bytes counted against a [`BufferBudget`]; released on drop, so every
+/// exit path of the holder gives them back.
+pub struct BufferReservation {
+    budget: Arc<BufferBudget>,
+    bytes: u64,
+}
+
+impl BufferReservation {
+    /// Count `bytes` more.
+    pub fn grow(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+        self.budget.used.fetch_add(bytes as u64, Ordering::AcqRel);
+    }
+}
+
+impl Drop for BufferReservation {
+    fn drop(&mut self) {
+        self.budget.release(self.bytes);
+    }
+}
+
+/// Owner of a [`BufferBudget::hold`] payload.
+struct HeldPayload {
+    payload: Bytes,
+    _reservation: BufferReservation,
+}
+
+impl AsRef<[u8]> for HeldPayload {
+    fn as_ref(&self) -> &[u8] {
+        &self.payload
+    }
+}
+
+/// Pressure level for `rss` given the limits (0 = disabled) and the current
+/// level.  Leaving a level requires dropping below `RELEASE_PERCENT` of its
+/// limit.
+pub fn classify(rss: u64, soft: u64, hard: u64, current: MemoryPressure) -> MemoryPressure {
+    let release = |limit: u64| limit / 100 * RELEASE_PERCENT;
+    if hard > 0 && rss >= hard {
+        return MemoryPressure::Hard;
+    }
+    if current == MemoryPressure::Hard && hard > 0 && rss >= release(hard) {
+        return MemoryPressure::Hard;
+    }
+    if soft > 0 && rss >= soft {
+        return MemoryPressure::Soft;
+    }
+    // Without a soft limit the hard limit's release band acts as the soft level.
+    let soft_release = if soft > 0 {
+        release(soft)
+    } else {
+        release(hard)
+    };
+    if current != MemoryPressure::Normal && rss >= soft_release {
+        return MemoryPressure::Soft;
+    }
+    MemoryPressure::Normal
+}
+
+/// Streams to cancel, oldest first, among `(key, started, body_bytes)` with
+/// at least `min_bytes`, until their bodies add up to `excess` bytes.  At
+/// least one stream is chosen when any qualifies.
+pub fn select_victims(streams: &[(u64, Instant, u64)], min_bytes: u64, excess: u64) -> Vec<u64> {
+    let mut eligible: Vec<&(u64, Instant, u64)> =
+        streams.iter().filter(|(_, _, b)| *b >= min_bytes).collect();
+    eligible.sort_by_key(|(_, started, _)| *started);
+
+    let mut victims = Vec::new();
+    let mut freed = 0u64;
+    for (key, _, bytes) in eligible {
+        victims.push(*key);
+        freed = freed.saturating_add(*bytes);
+        if freed >= excess {
+            break;
+        }
+    }
+    victims
+}
+
+/// Sample RSS periodically until shutdown.  No-op if no limit is configured.
+pub fn spawn_sampler(guard: Arc<MemoryGuard>, mut shutdown: watch::Receiver<bool>) {
+    if !guard.enabled() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+        let mut warned = false;
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.changed() => return,
+            }
+            match read_rss() {
+                Some(rss) => {
+                    guard.observe(rss);
+                }
+                None if !warned => {
+                    warn!("process RSS unavailable, memory limits not enforced");
+                    warned = true;
+                }
+                None => {}
+            }
+        }
+    });
+}
+
+/// Resident set size of this process in bytes.
+#[cfg(target_os = "linux")]
+fn read_rss() -> Option<u64> {
+    // statm: size resident shared text lib data dt (in pages)
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    (page_size > 0).then(|| pages * page_size as u64)
+}
+
+/// Resident set size of this process in bytes.
+#[cfg(not(target_os = "linux"))]
+fn read_rss() -> Option<u64> {
+    use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System};
+
+    let pid = sysinfo::get_current_pid().ok()?;
+    let mut sys = System::new();
+    sys.refresh_processes_specifics(
+        ProcessesToUpdate::Some(&[pid]),
+        false,
+        ProcessRefreshKind::new().with_memory(),
+    );
+    sys.process(pid).map(|p| p.memory())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    #[test]
+    fn classify_applies_limits_with_hysteresis() {
+        use MemoryPressure::*;
+        let (soft, hard) = (300 * MB, 400 * MB);
+        assert_eq!(classify(100 * MB, soft, hard, Normal), Normal);
+        assert_eq!(classify(300 * MB, soft, hard, Normal), Soft);
+        assert_eq!(classify(450 * MB, soft, hard, Soft), Hard);
+        // Just under the hard limit: stays hard until the release band.
+        assert_eq!(classify(390 * MB, soft, hard, Hard), Hard);
+        assert_eq!(classify(350 * MB, soft, hard, Hard), Soft);
+        assert_eq!(classify(280 * MB, soft, hard, Soft), Soft);
+        assert_eq!(classify(260 * MB, soft, hard, Soft), Normal);
+        // Disabled limits never trigger.
+        assert_eq!(classify(u64::MAX, 0, 0, Normal), Normal);
+    }
+
+    #[test]
+    fn victims_are_large_streams_oldest_first() {
+        let t0 = Instant::now();
+        let at = |ms| t0 + Duration::from_millis(ms);
+        let streams = [
+            (1, at(30), 8 * MB),
+            (2, at(10), 4 * MB),
+            (3, at(0), 100), // oldest but tiny
+            (4, at(20), 2 * MB),
+        ];
+        assert_eq!(select_victims(&streams, MB, 5 * MB), vec![2, 4]);
+        assert_eq!(select_victims(&streams, MB, 0), vec![2]);
+        assert!(select_victims(&streams, 16 * MB, MB).is_empty());
+    }
+
+    #[tokio::test]
+    async fn hard_limit_cancels_tracked_streams() {
+        let guard = Arc::new(MemoryGuard::new(100, 200));
+        let small = guard.track();
+        let large = guard.track();
+        large.body_bytes.store(64 * MB as usize, Ordering::Relaxed);
+
+        assert_eq!(guard.observe(150 * MB), MemoryPressure::Soft);
+        assert_eq!(guard.observe(250 * MB), MemoryPressure::Hard);
+        tokio::time::timeout(Duration::from_millis(100), large.cancelled())
+            .await
+            .expect("large stream cancelled");
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), small.cancelled())
+                .await
+                .is_err()
+        );
+
+        drop(large);
+        assert_eq!(guard.streams.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn buffer_reservations_balance_across_tasks() {
+        let budget = Arc::new(BufferBudget::new(1));
+        let tasks: Vec<_> = (0..32)
+            .map(|i| {
+                let budget = Arc::clone(&budget);
+                tokio::spawn(async move {
+                    for n in 0..100 {
+                        let mut reservation = budget.reserve(n * 10);
+                        reservation.grow(i);
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(budget.used_bytes(), 0);
+    }
+
+    #[tokio::test]
+    async fn exhausted_budget_pauses_until_released() {
+        let budget = Arc::new(BufferBudget::new(1));
+        let held = budget.reserve(MB as usize);
+        assert!(budget.exhausted());
+
+        let waiter = tokio::spawn({
+            let budget = Arc::clone(&budget);
+            async move { budget.wait_for_room().await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+
+        drop(held);
+        tokio::time::timeout(Duration::from_millis(100), waiter)
+            .await
+            .expect("resumed once released")
+            .unwrap();
+        assert!(!budget.exhausted());
+    }
+
+    #[test]
+    fn unlimited_budget_still_counts() {
+        let budget = Arc::new(BufferBudget::new(0));
+        let held = budget.reserve(usize::MAX / 2);
+        assert_eq!(budget.used_bytes(), (usize::MAX / 2) as u64);
+        assert!(!budget.exhausted());
+        drop(held);
+        budget.release(1);
+        assert_eq!(budget.used_bytes(), 0);
+    }
+
+    #[tokio::test]
+    async fn held_payload_counts_until_the_last_copy_is_sent() {
+        let budget = Arc::new(BufferBudget::new(0));
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<Bytes>(2);
+        let payload = budget.hold(Bytes::from_static(b"chunk"));
+        tx.send(payload.clone()).await.unwrap();
+        drop(payload);
+        // Still queued for the writer.
+        assert_eq!(budget.used_bytes(), 5);
+
+        assert_eq!(rx.recv().await.unwrap(), Bytes::from_static(b"chunk"));
+        assert_eq!(budget.used_bytes(), 0);
+    }
+}
@@ -1,11 +1,26 @@
-//! Network utility functions (public IP detection, region detection).
+//! Network utility functions (public IP detection, region detection,
+//! outbound connections from a chosen local address).
 //!
 //! These are standalone helpers not tied to any specific client or service.
 
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use futures_util::future::{select_ok, BoxFuture};
+use futures_util::FutureExt;
 use reqwest::Client;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpSocket, TcpStream};
 use tracing::{debug, info};
 
+/// Per-request timeout for third-party detection services.  Kept short so a
+/// blocked egress path doesn't stall startup.
+const DETECT_TIMEOUT: Duration = Duration::from_secs(3);
+
 /// Auto-detect public IP by querying external services.
+///
+/// All endpoints are queried concurrently; the first successful answer wins.
 pub async fn detect_public_ip() -> anyhow::Result<String> {
     let endpoints = [
         "https://api.ipify.org",
@@ -13,74 +28,149 @@ pub async fn detect_public_ip() -> anyhow::Result<String> {
         "https://icanhazip.com",
     ];
 
-    let client = Client::builder()
-        .timeout(std::time::Duration::from_secs(5))
-        .build()?;
-
-    for endpoint in &endpoints {
-        match client.get(*endpoint).send().await {
-            Ok(resp) if resp.status().is_success() => {
-                let ip = resp.text().await?.trim().to_string();
-                if !ip.is_empty() {
-                    info!(ip = %ip, source = %endpoint, "detected public IP");
-                    return Ok(ip);
+    let client = Client::builder().timeout(DETECT_TIMEOUT).build()?;
+
+    let probes: Vec<BoxFuture<'static, anyhow::Result<(String, &str)>>> = endpoints
+        .iter()
+        .map(|&endpoint| {
+            let client = client.clone();
+            async move {
+                let result = async {
+                    let resp = client.get(endpoint).send().await?;
+                    if !resp.status().is_success() {
+                        anyhow::bail!("HTTP {}", resp.status());
+                    }
+                    let ip = resp.text().await?.trim().to_string();
+                    if ip.is_empty() {
+                        anyhow::bail!("empty response");
+                    }
+                    Ok((ip, endpoint))
                 }
+                .await;
+                if let Err(ref e) = result {
+                    debug!(endpoint = %endpoint, error = %e, "IP detection failed");
+                }
+                result
             }
-            Ok(resp) => {
-                debug!(endpoint = %endpoint, status = %resp.status(), "IP detection failed");
-            }
-            Err(e) => {
-                debug!(endpoint = %endpoint, error = %e, "IP detection failed");
-            }
+            .boxed()
+        })
+        .collect();
+
+    match select_ok(probes).await {
+        Ok(((ip, source), _)) => {
+            info!(ip = %ip, source = %source, "detected public IP");
+            Ok(ip)
         }
+        Err(_) => anyhow::bail!("failed to detect public IP from any source; use --public-ip"),
     }
-
-    anyhow::bail!("failed to detect public IP from any source; use --public-ip")
 }
 
 /// Auto-detect geographic region from a public IP address.
 ///
-/// Uses multiple providers with HTTPS preferred.  Falls back to ip-api.com
-/// over plain HTTP (their free tier doesn't support HTTPS).
-/// This is best-effort and non-sensitive -- region detection should never
-/// block startup.
+/// Queries multiple providers concurrently, preferring the HTTPS answer.
+/// ip-api.com is only reachable over plain HTTP (their free tier doesn't
+/// support HTTPS).  This is best-effort and non-sensitive -- region
+/// detection should never block startup.
 pub async fn detect_region(ip: &str) -> Option<String> {
-    // Try HTTPS provider first
-    let https_url = format!("https://ipinfo.io/{}/country", ip);
-
-    let client = Client::builder()
-        .timeout(std::time::Duration::from_secs(5))
-        .build()
-        .ok()?;
-
-    // Try ipinfo.io (HTTPS, returns plain text country code)
-    if let Ok(resp) = client.get(&https_url).send().await {
-        if resp.status().is_success() {
-            if let Ok(text) = resp.text().await {
-                let code = text.trim();
-                if !code.is_empty() && code.len() <= 3 {
-                    info!(region = %code, ip = %ip, source = "ipinfo.io", "detected region");
-                    return Some(code.to_string());
-                }
-            }
-        }
+    let client = Client::builder().timeout(DETECT_TIMEOUT).build().ok()?;
+
+    let (ipinfo, ip_api) = tokio::join!(
+        region_from_ipinfo(&client, ip),
+        region_from_ip_api(&client, ip)
+    );
+
+    if let Some(code) = ipinfo {
+        info!(region = %code, ip = %ip, source = "ipinfo.io", "detected region");
+        return Some(code);
+    }
+    if let Some(code) = ip_api {
+        info!(region = %code, ip = %ip, source = "ip-api.com", "detected region");
+        return Some(code);
     }
+    debug!(ip = %ip, "region detection failed");
+    None
+}
 
-    // Fallback: ip-api.com (HTTP only on free tier, non-sensitive data)
-    let http_url = format!("http://ip-api.com/json/{}?fields=countryCode", ip);
-    match client.get(&http_url).send().await {
-        Ok(resp) if resp.status().is_success() => {
-            let body: serde_json::Value = resp.json().await.ok()?;
-            let code = body.get("countryCode")?.as_str()?;
-            if code.is_empty() {
-                return None;
-            }
-            info!(region = %code, ip = %ip, source = "ip-api.com", "detected region");
-            Some(code.to_string())
-        }
-        _ => {
-            debug!(ip = %ip, "region detection failed");
-            None
+/// ipinfo.io (HTTPS, returns plain text country code).
+async fn region_from_ipinfo(client: &Client, ip: &str) -> Option<String> {
+    let url = format!("https://ipinfo.io/{}/country", ip);
+    let resp = client.get(&url).send().await.ok()?;
+    if !resp.status().is_success() {
+        return None;
+    }
+    let text = resp.text().await.ok()?;
+    let code = text.trim();
+    if code.is_empty() || code.len() > 3 {
+        return None;
+    }
+    Some(code.to_string())
+}
+
+/// ip-api.com (HTTP only on free tier, non-sensitive data).
+async fn region_from_ip_api(client: &Client, ip: &str) -> Option<String> {
+    let url = format!("http://ip-api.com/json/{}?fields=countryCode", ip);
+    let resp = client.get(&url).send().await.ok()?;
+    if !resp.status().is_success() {
+        return None;
+    }
+    let body: serde_json::Value = resp.json().await.ok()?;
+    let code = body.get("countryCode")?.as_str()?;
+    if code.is_empty() {
+        return None;
+    }
+    Some(code.to_string())
+}
+
+/// Connect to the first reachable address in `addrs`, like
+/// `TcpStream::connect`, but from `local` when given.  Addresses of the
+/// other IP family are skipped since the socket can't reach them.
+pub async fn connect_tcp(addrs: &[SocketAddr], local: Option<IpAddr>) -> io::Result<TcpStream> {
+    let Some(local) = local else {
+        return TcpStream::connect(addrs).await;
+    };
+    let mut last_err = None;
+    for &addr in addrs.iter().filter(|a| a.is_ipv4() == local.is_ipv4()) {
+        match connect_from(addr, local).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_err = Some(e),
         }
     }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            format!("no address of the same IP family as bind address {local}"),
+        )
+    }))
+}
+
+async fn connect_from(addr: SocketAddr, local: IpAddr) -> io::Result<TcpStream> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.bind(&SocketAddr::new(local, 0).into())?;
+    socket.set_nonblocking(true)?;
+    TcpSocket::from_std_stream(socket.into())
+        .connect(addr)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Linux routes all of 127.0.0.0/8 to loopback; other systems only 127.0.0.1.
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn connections_leave_from_the_bind_address() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let local: IpAddr = "127.0.0.2".parse().unwrap();
+
+        let stream = connect_tcp(&[addr], Some(local)).await.unwrap();
+        let (_, peer) = listener.accept().await.unwrap();
+        assert_eq!(stream.local_addr().unwrap().ip(), local);
+        assert_eq!(peer.ip(), local);
+
+        let v6: IpAddr = "::1".parse().unwrap();
+        let err = connect_tcp(&[addr], Some(v6)).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+    }
 }
@@ -0,0 +1,167 @@
+//! Webhook notifications for tunnel state changes.
+//!
+//! With `notification_webhook_url` set, every tunnel connection posts a
+//! small JSON event when it connects, disconnects, or schedules a
+//! reconnect, so operators hear about a flapping tunnel without reading
+//! logs.  Delivery is best-effort: one attempt per event, off the tunnel's
+//! task, and failures are only logged.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use sha2::Sha256;
+use tracing::{debug, warn};
+
+use crate::config::Config;
+
+/// Upper bound for one webhook delivery.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Signature header, `sha256=<hex HMAC of the body>`.
+pub const SIGNATURE_HEADER: &str = "X-Aether-Signature";
+
+/// Tunnel state change reported to the webhook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunnelEvent {
+    Connected,
+    Disconnected,
+    Reconnecting,
+}
+
+impl TunnelEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Connected => "connected",
+            Self::Disconnected => "disconnected",
+            Self::Reconnecting => "reconnecting",
+        }
+    }
+}
+
+/// Posts tunnel events to the configured webhook.
+pub struct Notifier {
+    client: Client,
+    url: String,
+    secret: Option<String>,
+    node_name: String,
+}
+
+impl Notifier {
+    /// `None` unless `notification_webhook_url` is set.
+    pub fn from_config(config: &Config) -> anyhow::Result<Option<Self>> {
+        let Some(url) = config.notification_webhook_url.clone() else {
+            return Ok(None);
+        };
+        let client = Client::builder().timeout(WEBHOOK_TIMEOUT).build()?;
+        Ok(Some(Self {
+            client,
+            url,
+            secret: config
+                .notification_webhook_secret
+                .clone()
+                .filter(|s| !s.is_empty()),
+            node_name: config.node_name.clone(),
+        }))
+    }
+
+    /// Queue `event` for connection `conn_idx` of `server`.  Returns
+    /// immediately; the POST runs on its own task.
+    pub fn tunnel_event(
+        &self,
+        event: TunnelEvent,
+        server: &str,
+        conn_idx: usize,
+        reason: Option<&str>,
+    ) {
+        let body = payload(event, &self.node_name, server, conn_idx, reason);
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header("Content-Type", "application/json");
+        if let Some(secret) = &self.secret {
+            request = request.header(SIGNATURE_HEADER, signature(secret, &body));
+        }
+        let request = request.body(body);
+        let server = server.to_string();
+        tokio::spawn(async move {
+            match request.send().await {
+                Ok(resp) if resp.status().is_success() => {
+                    debug!(server = %server, event = event.as_str(), "webhook notified");
+                }
+                Ok(resp) => warn!(
+                    server = %server,
+                    event = event.as_str(),
+                    status = resp.status().as_u16(),
+                    "notification webhook rejected the event"
+                ),
+                Err(e) => warn!(
+                    server = %server,
+                    event = event.as_str(),
+                    error = %e,
+                    "notification webhook unreachable"
+                ),
+            }
+        });
+    }
+}
+
+fn payload(
+    event: TunnelEvent,
+    node_name: &str,
+    server: &str,
+    conn_idx: usize,
+    reason: Option<&str>,
+) -> Vec<u8> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let json = serde_json::json!({
+        "event": event.as_str(),
+        "node_name": node_name,
+        "server": server,
+        "conn_idx": conn_idx,
+        "timestamp": timestamp,
+        "reason": reason,
+    });
+    serde_json::to_vec(&json).unwrap_or_default()
+}
+
+/// `sha256=<hex>` HMAC-SHA256 of `body` keyed with `secret`.
+fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_and_signature_match_the_documented_format() {
+        let body = payload(
+            TunnelEvent::Reconnecting,
+            "proxy-01",
+            "server-0",
+            2,
+            Some("connection lost"),
+        );
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["event"], "reconnecting");
+        assert_eq!(json["server"], "server-0");
+        assert_eq!(json["conn_idx"], 2);
+        assert_eq!(json["reason"], "connection lost");
+        assert!(json["timestamp"].as_u64().unwrap() > 0);
+        let json: serde_json::Value =
+            serde_json::from_slice(&payload(TunnelEvent::Connected, "n", "s", 0, None)).unwrap();
+        assert!(json["reason"].is_null());
+
+        // RFC 4231 test case 2.
+        assert_eq!(
+            signature("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
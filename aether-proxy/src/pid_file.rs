@@ -0,0 +1,90 @@
+//! PID file for running without systemd (`pid_file`).
+//!
+//! Written when the proxy starts and removed on shutdown, so
+//! `aether-proxy status` can tell whether the proxy is running where
+//! `systemctl` isn't available.
+
+use std::path::Path;
+
+/// Record this process in `path`.  Fails if the file names another live
+/// process; a stale file (process gone) is replaced.
+pub fn write_pid_file(path: &Path) -> anyhow::Result<()> {
+    if let Some(pid) = running_pid(path) {
+        if pid != std::process::id() {
+            anyhow::bail!(
+                "aether-proxy is already running (PID {}, pid file {})",
+                pid,
+                path.display()
+            );
+        }
+    }
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, format!("{}\n", std::process::id()))
+        .map_err(|e| anyhow::anyhow!("failed to write pid file {}: {}", path.display(), e))
+}
+
+/// Remove `path` if it still names this process.
+pub fn remove_pid_file(path: &Path) {
+    if read_pid(path) == Some(std::process::id()) {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// PID recorded in `path`, if that process is still alive.
+pub fn running_pid(path: &Path) -> Option<u32> {
+    read_pid(path).filter(|&pid| is_alive(pid))
+}
+
+fn read_pid(path: &Path) -> Option<u32> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn is_alive(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        let Ok(pid) = libc::pid_t::try_from(pid) else {
+            return false;
+        };
+        // SAFETY: signal 0 only checks that the process exists.
+        let ret = unsafe { libc::kill(pid, 0) };
+        // EPERM: the process exists but belongs to another user.
+        ret == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = pid;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pid_file_tracks_this_process_and_ignores_stale_entries() {
+        let dir = std::env::temp_dir().join(format!("aether-proxy-pid-{}", std::process::id()));
+        let path = dir.join("run/aether-proxy.pid");
+
+        write_pid_file(&path).unwrap();
+        assert_eq!(running_pid(&path), Some(std::process::id()));
+        // Rewriting our own file is fine.
+        write_pid_file(&path).unwrap();
+        remove_pid_file(&path);
+        assert!(!path.exists());
+
+        // A PID that can't be alive counts as stopped and is replaced.
+        std::fs::write(&path, "999999999\n").unwrap();
+        assert_eq!(running_pid(&path), None);
+        write_pid_file(&path).unwrap();
+        assert_eq!(running_pid(&path), Some(std::process::id()));
+
+        // Someone else's file is left alone.
+        std::fs::write(&path, "1\n").unwrap();
+        remove_pid_file(&path);
+        assert!(path.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,127 @@
+//! Token buckets for the proxy-wide request and bandwidth budgets.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Token bucket refilled continuously at a fixed rate, holding at most one
+/// second's worth of tokens.
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// Bucket refilled with `rate` tokens per second (starts full).
+    pub fn per_second(rate: u64) -> Self {
+        let rate = rate.max(1) as f64;
+        Self {
+            rate,
+            burst: rate,
+            state: Mutex::new(BucketState {
+                tokens: rate,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take one token, or return how long until one becomes available.
+    pub fn try_acquire(&self) -> Result<(), Duration> {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&self, now: Instant) -> Result<(), Duration> {
+        let mut state = self.refill(now);
+        if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - state.tokens) / self.rate))
+        }
+    }
+
+    /// Take `n` tokens unconditionally, going into debt if needed, and
+    /// return how long the caller should wait before using them.  Later
+    /// callers queue behind the debt, so the long-run rate stays capped.
+    pub fn reserve(&self, n: u64) -> Duration {
+        self.reserve_at(n, Instant::now())
+    }
+
+    fn reserve_at(&self, n: u64, now: Instant) -> Duration {
+        let mut state = self.refill(now);
+        state.tokens -= n as f64;
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.tokens / self.rate)
+        }
+    }
+
+    fn refill(&self, now: Instant) -> std::sync::MutexGuard<'_, BucketState> {
+        let mut state = self.state.lock().unwrap();
+        let elapsed = now.saturating_duration_since(state.refilled_at);
+        state.tokens = (state.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        state.refilled_at = now;
+        state
+    }
+}
+
+/// Wait until `n` bytes fit within `limit`'s budget (no-op without a limit).
+pub async fn throttle(limit: Option<&TokenBucket>, n: usize) {
+    if let Some(limit) = limit {
+        let wait = limit.reserve(n as u64);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_refills_at_rate_up_to_burst() {
+        let bucket = TokenBucket::per_second(2);
+        let start = Instant::now();
+        assert!(bucket.try_acquire_at(start).is_ok());
+        assert!(bucket.try_acquire_at(start).is_ok());
+        assert_eq!(
+            bucket.try_acquire_at(start),
+            Err(Duration::from_millis(500))
+        );
+
+        assert!(bucket
+            .try_acquire_at(start + Duration::from_millis(500))
+            .is_ok());
+        assert!(bucket
+            .try_acquire_at(start + Duration::from_millis(500))
+            .is_err());
+
+        // A long idle period refills only up to the burst size.
+        let later = start + Duration::from_secs(60);
+        assert!(bucket.try_acquire_at(later).is_ok());
+        assert!(bucket.try_acquire_at(later).is_ok());
+        assert!(bucket.try_acquire_at(later).is_err());
+    }
+
+    #[test]
+    fn reservations_queue_behind_debt() {
+        let bucket = TokenBucket::per_second(1000);
+        let start = Instant::now();
+        // The initial burst covers one second's worth.
+        assert_eq!(bucket.reserve_at(1000, start), Duration::ZERO);
+        assert_eq!(bucket.reserve_at(500, start), Duration::from_millis(500));
+        // The next caller waits for the previous debt too.
+        assert_eq!(bucket.reserve_at(500, start), Duration::from_secs(1));
+        assert_eq!(
+            bucket.reserve_at(500, start + Duration::from_secs(2)),
+            Duration::ZERO
+        );
+    }
+}
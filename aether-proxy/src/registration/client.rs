@@ -1,20 +1,30 @@
+use std::collections::BTreeMap;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 use tokio::time::sleep;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
+use crate::build_info;
 use crate::config::Config;
 use crate::hardware::HardwareInfo;
+use crate::state_file::StateFile;
+use crate::target_filter::PortRange;
 
 #[derive(Debug, Serialize)]
 struct RegisterRequest {
     name: String,
+    /// Stable per-entry id (see `StateFile::client_instance_id`), so a
+    /// restarted node is matched to its previous registration.
+    client_instance_id: String,
     ip: String,
     port: u16,
     #[serde(skip_serializing_if = "Option::is_none")]
     region: Option<String>,
+    /// Free-form key/value labels for grouping nodes in Aether.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    tags: BTreeMap<String, String>,
     heartbeat_interval: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     hardware_info: Option<serde_json::Value>,
@@ -22,7 +32,14 @@ struct RegisterRequest {
     estimated_max_concurrency: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     proxy_metadata: Option<serde_json::Value>,
+    agent_version: &'static str,
+    build_target: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    git_sha: Option<&'static str>,
     tunnel_mode: bool,
+    /// Ask Aether to use the request's observed source address as the node IP.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    detect_ip_server_side: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -31,12 +48,29 @@ pub struct RegisterResponse {
 }
 
 /// Remote configuration pushed by the Aether management backend.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct RemoteConfig {
     pub node_name: Option<String>,
-    pub allowed_ports: Option<Vec<u16>>,
+    pub allowed_ports: Option<Vec<PortRange>>,
     pub log_level: Option<String>,
     pub heartbeat_interval: Option<u64>,
+    /// Per-connection concurrent stream limit (throttles the node remotely).
+    pub tunnel_max_streams: Option<u32>,
+    /// Enable/disable tunnel-level gzip compression of response frames.
+    pub compression_enabled: Option<bool>,
+    /// Minimum payload size (bytes) before compression is attempted.
+    pub compression_min_size: Option<usize>,
+    /// Stop accepting new streams (maintenance) while staying registered.
+    pub paused: Option<bool>,
+    /// User-Agent for upstream requests (empty string removes the override).
+    pub upstream_user_agent_override: Option<String>,
+    pub upstream_append_via: Option<bool>,
+    /// Replaces the configured identity headers as a whole.
+    pub upstream_identity_headers: Option<BTreeMap<String, String>>,
+    /// Rotate this server's tunnel connections once the update is applied,
+    /// so a new node_name reaches the handshake at once.
+    #[serde(default)]
+    pub reconnect: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -52,11 +86,27 @@ pub struct AetherClient {
     retry_max_attempts: u32,
     retry_base_delay: Duration,
     retry_max_delay: Duration,
+    client_instance_id: String,
 }
 
 impl AetherClient {
-    pub fn new(config: &Config, aether_url: &str, management_token: &str) -> Self {
+    /// `tls_config` is the tunnel TLS config, so the API and the tunnel
+    /// trust the same roots.
+    pub fn new(
+        config: &Config,
+        aether_url: &str,
+        management_token: &str,
+        tls_config: &rustls::ClientConfig,
+        client_instance_id: String,
+    ) -> Self {
+        let mut tls_config = tls_config.clone();
+        tls_config.alpn_protocols = if config.aether_http2 {
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        } else {
+            vec![b"http/1.1".to_vec()]
+        };
         let mut builder = Client::builder()
+            .use_preconfigured_tls(tls_config)
             .timeout(Duration::from_secs(config.aether_request_timeout_secs))
             .connect_timeout(Duration::from_secs(config.aether_connect_timeout_secs))
             .pool_max_idle_per_host(config.aether_pool_max_idle_per_host)
@@ -87,9 +137,15 @@ impl AetherClient {
             retry_max_attempts: config.aether_retry_max_attempts.max(1),
             retry_base_delay,
             retry_max_delay,
+            client_instance_id,
         }
     }
 
+    /// Idempotency key sent on register and heartbeat.
+    pub fn client_instance_id(&self) -> &str {
+        &self.client_instance_id
+    }
+
     /// Register this node with Aether (idempotent upsert by ip:port).
     ///
     /// Returns the stable node_id assigned by Aether.
@@ -97,22 +153,30 @@ impl AetherClient {
         &self,
         config: &Config,
         node_name: &str,
+        node_region: Option<&str>,
+        node_tags: &BTreeMap<String, String>,
         public_ip: &str,
         hw: Option<&HardwareInfo>,
     ) -> anyhow::Result<String> {
         let url = format!("{}/api/admin/proxy-nodes/register", self.base_url);
         let body = RegisterRequest {
             name: node_name.to_string(),
+            client_instance_id: self.client_instance_id.clone(),
             ip: public_ip.to_string(),
             port: 0,
-            region: config.node_region.clone(),
+            region: node_region.map(str::to_string),
+            tags: node_tags.clone(),
             heartbeat_interval: config.heartbeat_interval,
             hardware_info: hw.and_then(|h| serde_json::to_value(h).ok()),
             estimated_max_concurrency: hw.map(|h| h.estimated_max_concurrency),
             proxy_metadata: Some(serde_json::json!({
-                "version": env!("CARGO_PKG_VERSION"),
+                "version": build_info::VERSION,
             })),
+            agent_version: build_info::VERSION,
+            build_target: build_info::BUILD_TARGET,
+            git_sha: build_info::git_sha(),
             tunnel_mode: true,
+            detect_ip_server_side: config.disable_ip_detection && config.public_ip.is_none(),
         };
 
         info!(
@@ -142,6 +206,15 @@ impl AetherClient {
 
         let data: RegisterResponse = resp.json().await?;
         info!(node_id = %data.node_id, "registered successfully");
+        // Aether's answer is authoritative; remember it for the next run.
+        if let Some(previous) = StateFile::record_node_id(&self.client_instance_id, &data.node_id) {
+            warn!(
+                previous = %previous,
+                node_id = %data.node_id,
+                client_instance_id = %self.client_instance_id,
+                "Aether assigned a different node_id than last time"
+            );
+        }
         Ok(data.node_id)
     }
 
@@ -244,7 +317,7 @@ fn should_retry_status(status: StatusCode) -> bool {
         || status == StatusCode::REQUEST_TIMEOUT
 }
 
-fn jitter_delay(base: Duration) -> Duration {
+pub(crate) fn jitter_delay(base: Duration) -> Duration {
     if base.is_zero() {
         return base;
     }
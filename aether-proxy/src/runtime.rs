@@ -4,24 +4,53 @@
 //! [`Config`](crate::config::Config) and may be overridden by the Aether
 //! management backend through the heartbeat response.
 
-use std::collections::HashSet;
-use std::sync::{Arc, OnceLock};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 
 use arc_swap::ArcSwap;
+use sha2::{Digest, Sha256};
+use toml_edit::{Array, DocumentMut, Item, TableLike, Value};
 use tracing::info;
 
-use crate::config::Config;
+use crate::config::{self, Config, ConfigFile};
+use crate::registration::client::RemoteConfig;
+use crate::target_filter::PortSet;
+use crate::tunnel::protocol::COMPRESS_MIN_SIZE;
 
 /// Configuration that can be changed at runtime without restart.
 #[derive(Debug, Clone)]
 pub struct DynamicConfig {
     pub node_name: String,
-    pub allowed_ports: Arc<HashSet<u16>>,
+    pub allowed_ports: Arc<PortSet>,
     pub log_level: String,
     pub heartbeat_interval: u64,
+    /// Per-connection concurrent stream limit, read by the dispatcher for
+    /// every new stream.
+    pub max_streams: usize,
+    /// Tunnel-level gzip compression of response frames.
+    pub compression_enabled: bool,
+    pub compression_min_size: usize,
+    /// Set remotely for maintenance: new streams are rejected while the
+    /// tunnel, heartbeats and in-flight streams carry on.
+    pub paused: bool,
+    /// Node identification applied to every upstream request, overriding
+    /// what Aether supplied.
+    pub upstream_user_agent: Option<String>,
+    pub upstream_append_via: bool,
+    /// Header values may contain `{node_name}`, `{node_id}` and `{version}`.
+    pub upstream_identity_headers: Arc<Vec<(String, String)>>,
     /// Monotonically increasing version from the backend.
     /// `0` means no remote config has ever been applied.
     pub config_version: u64,
+    /// Highest version applied by any previous session for this node.
+    /// Updates older than this are rejected; an equal version is re-applied
+    /// since in-memory state starts from the static config after a restart.
+    pub config_version_floor: u64,
+    /// Etag of the last remote config received, echoed in heartbeats so
+    /// Aether can leave an unchanged config out of the ACK.  Empty until
+    /// the first remote config arrives.
+    pub config_etag: String,
 }
 
 impl DynamicConfig {
@@ -32,9 +61,27 @@ impl DynamicConfig {
             allowed_ports: Arc::new(config.allowed_ports.iter().copied().collect()),
             log_level: config.log_level.clone(),
             heartbeat_interval: config.heartbeat_interval,
+            max_streams: config.tunnel_max_streams.unwrap_or(128) as usize,
+            compression_enabled: true,
+            compression_min_size: COMPRESS_MIN_SIZE,
+            paused: false,
+            upstream_user_agent: config
+                .upstream_user_agent_override
+                .clone()
+                .filter(|ua| !ua.is_empty()),
+            upstream_append_via: config.upstream_append_via,
+            upstream_identity_headers: Arc::new(config.upstream_identity_headers.clone()),
             config_version: 0,
+            config_version_floor: 0,
+            config_etag: String::new(),
         }
     }
+
+    /// Minimum size for frame compression, or `None` when disabled.
+    pub fn compression_min_size(&self) -> Option<usize> {
+        self.compression_enabled
+            .then_some(self.compression_min_size)
+    }
 }
 
 /// Shared dynamic config handle (lock-free reads via ArcSwap).
@@ -46,10 +93,61 @@ pub type SharedDynamicConfig = Arc<ArcSwap<DynamicConfig>>;
 type LogReloader = Box<dyn Fn(&str) + Send + Sync>;
 
 static LOG_RELOADER: OnceLock<LogReloader> = OnceLock::new();
+/// Filter currently applied (as last set, not necessarily a plain level).
+static LOG_LEVEL: Mutex<String> = Mutex::new(String::new());
+/// Whether log output is JSON; checked by the formatting layers per event.
+static LOG_JSON: AtomicBool = AtomicBool::new(false);
 
-/// Register the log-level reload function (called once from `init_tracing`).
-pub fn set_log_reloader(f: LogReloader) {
+/// Levels cycled through by `SIGUSR1`, least verbose first.
+const LOG_LEVELS: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
+
+/// Register the log-level reload function and the initial log settings
+/// (called once from `init_tracing`).
+pub fn set_log_reloader(f: LogReloader, level: &str, json: bool) {
     let _ = LOG_RELOADER.set(f);
+    *LOG_LEVEL.lock().unwrap() = level.to_string();
+    LOG_JSON.store(json, Ordering::Release);
+}
+
+/// Apply a new log filter (e.g. `debug` or `info,hyper=warn`).
+pub fn reload_log_level(level: &str) {
+    if let Some(reloader) = LOG_RELOADER.get() {
+        reloader(level);
+    }
+    *LOG_LEVEL.lock().unwrap() = level.to_string();
+}
+
+pub fn log_level() -> String {
+    LOG_LEVEL.lock().unwrap().clone()
+}
+
+/// Switch to the next more verbose level, wrapping from `trace` to `error`.
+pub fn cycle_log_level() -> &'static str {
+    let next = next_log_level(&log_level());
+    reload_log_level(next);
+    next
+}
+
+fn next_log_level(current: &str) -> &'static str {
+    // A compound filter counts as its default level.
+    let base = current
+        .split(',')
+        .find(|d| !d.contains('='))
+        .unwrap_or("info")
+        .trim();
+    match LOG_LEVELS.iter().position(|l| l.eq_ignore_ascii_case(base)) {
+        Some(i) => LOG_LEVELS[(i + 1) % LOG_LEVELS.len()],
+        None => "debug",
+    }
+}
+
+pub fn log_json() -> bool {
+    LOG_JSON.load(Ordering::Acquire)
+}
+
+/// Flip between pretty and JSON output.  Returns the new setting.
+pub fn toggle_log_json() -> bool {
+    !LOG_JSON.fetch_xor(true, Ordering::AcqRel)
 }
 
 /// Apply a remote config update to the dynamic config.
@@ -60,12 +158,12 @@ pub fn set_log_reloader(f: LogReloader) {
 /// Returns `true` if the config was actually changed.
 pub fn apply_remote_config(
     dynamic: &SharedDynamicConfig,
-    remote: &crate::registration::client::RemoteConfig,
+    remote: &RemoteConfig,
     version: u64,
 ) -> bool {
     let current = dynamic.load();
 
-    if version <= current.config_version {
+    if version <= current.config_version || version < current.config_version_floor {
         return false;
     }
 
@@ -79,10 +177,11 @@ pub fn apply_remote_config(
         }
     }
 
-    if let Some(ref ports) = remote.allowed_ports {
-        let new_set: HashSet<u16> = ports.iter().copied().collect();
+    // An empty list would reject every target.
+    if let Some(ports) = remote.allowed_ports.as_ref().filter(|p| !p.is_empty()) {
+        let new_set: PortSet = ports.iter().copied().collect();
         if new_set != *new_cfg.allowed_ports {
-            changed.push(format!("allowed_ports -> {:?}", ports));
+            changed.push(format!("allowed_ports -> {}", new_set));
             new_cfg.allowed_ports = Arc::new(new_set);
         }
     }
@@ -94,17 +193,80 @@ pub fn apply_remote_config(
         }
     }
 
+    if let Some(max_streams) = remote.tunnel_max_streams.filter(|&n| n > 0) {
+        let max_streams = max_streams as usize;
+        if max_streams != new_cfg.max_streams {
+            changed.push(format!("max_streams -> {}", max_streams));
+            new_cfg.max_streams = max_streams;
+        }
+    }
+
+    if let Some(enabled) = remote.compression_enabled {
+        if enabled != new_cfg.compression_enabled {
+            changed.push(format!("compression_enabled -> {}", enabled));
+            new_cfg.compression_enabled = enabled;
+        }
+    }
+
+    if let Some(min_size) = remote.compression_min_size {
+        if min_size != new_cfg.compression_min_size {
+            changed.push(format!("compression_min_size -> {}", min_size));
+            new_cfg.compression_min_size = min_size;
+        }
+    }
+
+    if let Some(paused) = remote.paused {
+        if paused != new_cfg.paused {
+            changed.push(format!("paused -> {}", paused));
+            new_cfg.paused = paused;
+        }
+    }
+
+    if let Some(ref ua) = remote.upstream_user_agent_override {
+        let ua = Some(ua.clone()).filter(|ua| !ua.is_empty());
+        if ua != new_cfg.upstream_user_agent {
+            changed.push(format!(
+                "upstream_user_agent_override -> {}",
+                ua.as_deref().unwrap_or("(none)")
+            ));
+            new_cfg.upstream_user_agent = ua;
+        }
+    }
+
+    if let Some(via) = remote.upstream_append_via {
+        if via != new_cfg.upstream_append_via {
+            changed.push(format!("upstream_append_via -> {}", via));
+            new_cfg.upstream_append_via = via;
+        }
+    }
+
+    if let Some(ref headers) = remote.upstream_identity_headers {
+        let headers: Vec<(String, String)> = headers
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        if headers != *new_cfg.upstream_identity_headers {
+            let names: Vec<&str> = headers.iter().map(|(k, _)| k.as_str()).collect();
+            changed.push(format!("upstream_identity_headers -> {:?}", names));
+            new_cfg.upstream_identity_headers = Arc::new(headers);
+        }
+    }
+
     if let Some(ref level) = remote.log_level {
         if *level != new_cfg.log_level {
             changed.push(format!("log_level -> {}", level));
             new_cfg.log_level = level.clone();
             // Hot-reload tracing filter
-            if let Some(reloader) = LOG_RELOADER.get() {
-                reloader(level);
-            }
+            reload_log_level(level);
         }
     }
 
+    if remote.reconnect {
+        // Counts as a change so the version is recorded and the request is
+        // acted on once.
+        changed.push("reconnect requested".to_string());
+    }
+
     let has_changes = !changed.is_empty();
 
     if has_changes {
@@ -119,3 +281,319 @@ pub fn apply_remote_config(
 
     has_changes
 }
+
+/// Etag of a `remote_config` body: hex SHA-256 of its JSON serialization.
+pub fn config_etag(remote: &serde_json::Value) -> String {
+    hex::encode(Sha256::digest(
+        serde_json::to_vec(remote).unwrap_or_default(),
+    ))
+}
+
+/// Remember `etag` as the config received at `version`, unless that
+/// version is older than what has already been applied.
+pub fn record_config_etag(dynamic: &SharedDynamicConfig, etag: String, version: u64) {
+    let current = dynamic.load();
+    if version < current.config_version
+        || version < current.config_version_floor
+        || current.config_etag == etag
+    {
+        return;
+    }
+    let mut new_cfg = (**current).clone();
+    new_cfg.config_etag = etag;
+    dynamic.store(Arc::new(new_cfg));
+}
+
+/// Write the fields carried by a remote config update back into the TOML
+/// config file so that a restart comes up already converged.
+///
+/// Only the changed keys are edited (through `toml_edit`, so comments and
+/// formatting survive), only in the file itself (includes are left
+/// untouched), and compression settings and `paused` have no static
+/// counterpart so they are not written.  A remote `node_name` is stored on
+/// the `[[servers]]` entry matching `aether_url`, or at the top level for
+/// single-server configs.  Blocks on the config lock; call it from
+/// `spawn_blocking` in async code.
+pub fn persist_remote_config(
+    path: &Path,
+    aether_url: &str,
+    remote: &RemoteConfig,
+) -> anyhow::Result<()> {
+    let _lock = config::lock_config(path)?;
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+    let edited = remote_config_edit(&content, aether_url, remote)?;
+    if edited == content {
+        return Ok(());
+    }
+    toml::from_str::<ConfigFile>(&edited)
+        .map_err(|e| anyhow::anyhow!("edited config would not load: {}", e))?;
+    config::write_atomic(path, edited.as_bytes())
+}
+
+/// `content` with the keys set by `remote` replaced.
+fn remote_config_edit(
+    content: &str,
+    aether_url: &str,
+    remote: &RemoteConfig,
+) -> anyhow::Result<String> {
+    let mut doc: DocumentMut = content.parse()?;
+
+    if let Some(ref name) = remote.node_name {
+        let same_url = |url: Option<&str>| {
+            url.is_some_and(|u| u.trim_end_matches('/') == aether_url.trim_end_matches('/'))
+        };
+        let servers = doc.get_mut("servers");
+        let entry = match servers {
+            Some(Item::ArrayOfTables(tables)) => tables
+                .iter_mut()
+                .find(|t| same_url(t.get("aether_url").and_then(Item::as_str)))
+                .map(|t| t as &mut dyn TableLike),
+            Some(Item::Value(Value::Array(array))) => array
+                .iter_mut()
+                .filter_map(Value::as_inline_table_mut)
+                .find(|t| same_url(t.get("aether_url").and_then(Value::as_str)))
+                .map(|t| t as &mut dyn TableLike),
+            _ => None,
+        };
+        match entry {
+            Some(entry) => set_value(entry, "node_name", name.as_str().into()),
+            None => set_value(doc.as_table_mut(), "node_name", name.as_str().into()),
+        }
+    }
+
+    let root = doc.as_table_mut();
+    if let Some(ports) = remote.allowed_ports.as_ref().filter(|p| !p.is_empty()) {
+        let ports: Array = ports
+            .iter()
+            .map(|range| match range.start == range.end {
+                true => Value::from(i64::from(range.start)),
+                false => Value::from(range.to_string()),
+            })
+            .collect();
+        set_value(root, "allowed_ports", ports.into());
+    }
+    if let Some(ref level) = remote.log_level {
+        set_value(root, "log_level", level.as_str().into());
+    }
+    if let Some(interval) = remote.heartbeat_interval {
+        set_value(root, "heartbeat_interval", (interval as i64).into());
+    }
+    if let Some(max_streams) = remote.tunnel_max_streams.filter(|&n| n > 0) {
+        set_value(root, "tunnel_max_streams", (max_streams as i64).into());
+    }
+    if let Some(ref ua) = remote.upstream_user_agent_override {
+        if ua.is_empty() {
+            root.remove("upstream_user_agent_override");
+        } else {
+            set_value(root, "upstream_user_agent_override", ua.as_str().into());
+        }
+    }
+    if let Some(via) = remote.upstream_append_via {
+        set_value(root, "upstream_append_via", via.into());
+    }
+    if let Some(ref headers) = remote.upstream_identity_headers {
+        if headers.is_empty() {
+            root.remove("upstream_identity_headers");
+        } else {
+            let table = root
+                .entry("upstream_identity_headers")
+                .or_insert_with(toml_edit::table)
+                .as_table_like_mut()
+                .ok_or_else(|| anyhow::anyhow!("upstream_identity_headers must be a table"))?;
+            let stale: Vec<String> = table
+                .iter()
+                .map(|(name, _)| name.to_string())
+                .filter(|name| !headers.contains_key(name))
+                .collect();
+            for name in stale {
+                table.remove(&name);
+            }
+            for (name, value) in headers {
+                set_value(table, name, value.as_str().into());
+            }
+        }
+    }
+
+    Ok(doc.to_string())
+}
+
+/// Set `key` to `value`, keeping the existing value's decoration (e.g. a
+/// trailing comment).  An unchanged value is left exactly as written.
+fn set_value(table: &mut dyn TableLike, key: &str, mut value: Value) {
+    match table.get_mut(key).and_then(Item::as_value_mut) {
+        Some(existing) => {
+            let mut bare = existing.clone();
+            bare.decor_mut().clear();
+            if bare.to_string() != value.to_string() {
+                *value.decor_mut() = existing.decor().clone();
+                *existing = value;
+            }
+        }
+        None => {
+            table.insert(key, Item::Value(value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::target_filter::PortRange;
+
+    #[test]
+    fn log_level_cycles_towards_trace_and_wraps() {
+        assert_eq!(next_log_level("error"), "warn");
+        assert_eq!(next_log_level("INFO"), "debug");
+        assert_eq!(next_log_level("trace"), "error");
+        assert_eq!(next_log_level("warn,hyper=error"), "info");
+        assert_eq!(next_log_level("hyper=error"), "debug");
+    }
+
+    fn dynamic(floor: u64) -> SharedDynamicConfig {
+        let config = crate::config::test_config(&[]);
+        Arc::new(ArcSwap::from_pointee(DynamicConfig {
+            config_version_floor: floor,
+            ..DynamicConfig::from_config(&config)
+        }))
+    }
+
+    fn rename(name: &str) -> RemoteConfig {
+        RemoteConfig {
+            node_name: Some(name.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn version_below_floor_is_rejected() {
+        let dynamic = dynamic(5);
+        assert!(!apply_remote_config(&dynamic, &rename("stale"), 4));
+        assert_eq!(dynamic.load().node_name, "proxy-01");
+    }
+
+    #[test]
+    fn version_at_floor_is_reapplied_after_restart() {
+        let dynamic = dynamic(5);
+        assert!(apply_remote_config(&dynamic, &rename("jp-01"), 5));
+        assert_eq!(dynamic.load().node_name, "jp-01");
+        assert!(!apply_remote_config(&dynamic, &rename("jp-02"), 5));
+    }
+
+    #[test]
+    fn remote_throttle_and_compression_are_applied() {
+        let dynamic = dynamic(0);
+        let remote = RemoteConfig {
+            tunnel_max_streams: Some(8),
+            compression_enabled: Some(false),
+            ..rename("proxy-01")
+        };
+        assert!(apply_remote_config(&dynamic, &remote, 1));
+        let cfg = dynamic.load();
+        assert_eq!(cfg.max_streams, 8);
+        assert_eq!(cfg.compression_min_size(), None);
+
+        // A zero stream limit is ignored rather than blocking all traffic.
+        let zero = RemoteConfig {
+            tunnel_max_streams: Some(0),
+            ..rename("proxy-01")
+        };
+        assert!(!apply_remote_config(&dynamic, &zero, 2));
+        assert_eq!(dynamic.load().max_streams, 8);
+
+        // Likewise an empty port list, which would reject every target.
+        let no_ports = RemoteConfig {
+            allowed_ports: Some(Vec::new()),
+            ..rename("proxy-01")
+        };
+        assert!(!apply_remote_config(&dynamic, &no_ports, 3));
+        assert!(dynamic.load().allowed_ports.contains(443));
+    }
+
+    #[test]
+    fn etag_follows_the_latest_config_version() {
+        let dynamic = dynamic(3);
+        let body = serde_json::json!({"node_name": "jp-01"});
+        let etag = config_etag(&body);
+        assert_eq!(etag.len(), 64);
+        assert_eq!(
+            etag,
+            config_etag(&serde_json::json!({"node_name": "jp-01"}))
+        );
+
+        // Stale versions don't move the etag.
+        record_config_etag(&dynamic, etag.clone(), 2);
+        assert_eq!(dynamic.load().config_etag, "");
+        record_config_etag(&dynamic, etag.clone(), 3);
+        assert_eq!(dynamic.load().config_etag, etag);
+
+        assert!(apply_remote_config(&dynamic, &rename("jp-01"), 7));
+        record_config_etag(&dynamic, "older".into(), 6);
+        assert_eq!(dynamic.load().config_etag, etag);
+    }
+
+    #[test]
+    fn persisting_remote_config_edits_only_its_keys() {
+        let content = "# managed by ansible\n\
+                       log_level = \"info\" # bump when debugging\n\
+                       allowed_ports = [443]\n\
+                       \n\
+                       [upstream_identity_headers]\n\
+                       X-Old = \"1\"\n\
+                       \n\
+                       # primary\n\
+                       [[servers]]\n\
+                       aether_url = \"https://a.example.com\"\n\
+                       management_token = \"ae_a\"\n\
+                       \n\
+                       [[servers]]\n\
+                       aether_url = \"https://b.example.com\"\n\
+                       management_token = \"ae_b\"\n";
+        let remote = RemoteConfig {
+            allowed_ports: Some(vec![PortRange::from(443), "8000-8100".parse().unwrap()]),
+            log_level: Some("debug".into()),
+            upstream_identity_headers: Some([("X-Node".into(), "{node_name}".into())].into()),
+            ..rename("jp-01")
+        };
+        let edited = remote_config_edit(content, "https://b.example.com/", &remote).unwrap();
+        assert_eq!(
+            edited,
+            "# managed by ansible\n\
+             log_level = \"debug\" # bump when debugging\n\
+             allowed_ports = [443, \"8000-8100\"]\n\
+             \n\
+             [upstream_identity_headers]\n\
+             X-Node = \"{node_name}\"\n\
+             \n\
+             # primary\n\
+             [[servers]]\n\
+             aether_url = \"https://a.example.com\"\n\
+             management_token = \"ae_a\"\n\
+             \n\
+             [[servers]]\n\
+             aether_url = \"https://b.example.com\"\n\
+             management_token = \"ae_b\"\n\
+             node_name = \"jp-01\"\n"
+        );
+        // Applying the same update again changes nothing.
+        assert_eq!(
+            remote_config_edit(&edited, "https://b.example.com", &remote).unwrap(),
+            edited
+        );
+    }
+
+    #[test]
+    fn single_server_node_name_is_set_at_the_top_level() {
+        let content = "aether_url = \"https://a.example.com\"\n\
+                       management_token = \"ae_a\"\n\
+                       node_name = \"old\"   # set by setup\n";
+        let edited =
+            remote_config_edit(content, "https://a.example.com", &rename("jp-01")).unwrap();
+        assert_eq!(
+            edited,
+            "aether_url = \"https://a.example.com\"\n\
+             management_token = \"ae_a\"\n\
+             node_name = \"jp-01\"   # set by setup\n"
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! `aether-proxy convert-config` -- convert a config file between formats.
+//!
+//! Reads TOML (includes resolved) or YAML and prints TOML, YAML or shell
+//! `export` statements.  Env var names come from the `env` attribute of
+//! the matching [`Config`] argument, so they stay in sync with the flags.
+
+use std::fmt::Write as _;
+use std::path::Path;
+
+use clap::CommandFactory;
+
+use crate::config::{Config, ConfigFile};
+
+/// Output formats accepted by `--output-format`.
+pub const OUTPUT_FORMATS: [&str; 3] = ["toml", "yaml", "env"];
+
+/// `aether-proxy convert-config --input FILE [--output-format F] [--show-secrets]`
+pub fn cmd_convert_config(input: &Path, format: &str, show_secrets: bool) -> anyhow::Result<()> {
+    let file = read_config(input)?;
+    let file = if show_secrets { file } else { file.redacted() };
+    print!("{}", render(&file, format)?);
+    Ok(())
+}
+
+/// Parse `path` as YAML (`.yaml`/`.yml`) or TOML (anything else).
+fn read_config(path: &Path) -> anyhow::Result<ConfigFile> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("yaml" | "yml") => {
+            let content = std::fs::read_to_string(path)?;
+            Ok(serde_yaml::from_str(&content)?)
+        }
+        _ => ConfigFile::load_profile(path, None),
+    }
+}
+
+fn render(file: &ConfigFile, format: &str) -> anyhow::Result<String> {
+    match format {
+        "toml" => Ok(toml::to_string_pretty(file)?),
+        "yaml" => Ok(serde_yaml::to_string(file)?),
+        "env" => render_env(file),
+        other => anyhow::bail!(
+            "unknown output format '{}' (expected one of: {})",
+            other,
+            OUTPUT_FORMATS.join(", ")
+        ),
+    }
+}
+
+/// One `export` line per set field; fields without an env var (e.g.
+/// `[[servers]]`, `[profiles]`) are listed as comments.
+fn render_env(file: &ConfigFile) -> anyhow::Result<String> {
+    let command = Config::command();
+    let serde_json::Value::Object(fields) = serde_json::to_value(file)? else {
+        anyhow::bail!("config did not serialize to a table");
+    };
+
+    let mut out = String::new();
+    for (key, value) in &fields {
+        let env = command
+            .get_arguments()
+            .find(|arg| arg.get_id().as_str() == key)
+            .and_then(|arg| arg.get_env())
+            .and_then(|env| env.to_str());
+        match (env, env_value(value)) {
+            (Some(env), Some(value)) => {
+                let _ = writeln!(out, "export {}={}", env, shell_quote(&value));
+            }
+            _ => {
+                let _ = writeln!(out, "# {key}: no environment variable equivalent, skipped");
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Flatten a field to the string clap expects: lists comma-joined, maps as
+/// comma-joined `key=value`.  `None` for nested tables.
+fn env_value(value: &serde_json::Value) -> Option<String> {
+    use serde_json::Value;
+
+    fn scalar(value: &Value) -> Option<String> {
+        match value {
+            Value::String(s) => Some(s.clone()),
+            Value::Bool(_) | Value::Number(_) => Some(value.to_string()),
+            _ => None,
+        }
+    }
+
+    match value {
+        Value::Array(items) => items
+            .iter()
+            .map(scalar)
+            .collect::<Option<Vec<_>>>()
+            .map(|items| items.join(",")),
+        Value::Object(map) => map
+            .iter()
+            .map(|(k, v)| scalar(v).map(|v| format!("{k}={v}")))
+            .collect::<Option<Vec<_>>>()
+            .map(|items| items.join(",")),
+        _ => scalar(value),
+    }
+}
+
+/// Single-quote `value` for POSIX shells.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"
+aether_url = "https://a.example.com"
+management_token = "ae_secret_token"
+node_name = "jp-01"
+allowed_ports = [80, 443]
+upstream_append_via = true
+
+[node_tags]
+env = "prod"
+
+[[servers]]
+aether_url = "https://b.example.com"
+management_token = "ae_other"
+"#;
+
+    #[test]
+    fn env_output_uses_config_env_names_and_masks_tokens() {
+        let file: ConfigFile = toml::from_str(SAMPLE).unwrap();
+        let env = render(&file.redacted(), "env").unwrap();
+
+        assert!(env.contains("export AETHER_PROXY_AETHER_URL='https://a.example.com'\n"));
+        assert!(env.contains("export AETHER_PROXY_MANAGEMENT_TOKEN='ae_s***'\n"));
+        assert!(env.contains("export AETHER_PROXY_NODE_NAME='jp-01'\n"));
+        assert!(env.contains("export AETHER_PROXY_ALLOWED_PORTS='80,443'\n"));
+        assert!(env.contains("export AETHER_PROXY_UPSTREAM_APPEND_VIA='true'\n"));
+        assert!(env.contains("export AETHER_PROXY_NODE_TAGS='env=prod'\n"));
+        assert!(env.contains("# servers: no environment variable equivalent"));
+        assert!(!env.contains("ae_secret_token") && !env.contains("ae_other"));
+    }
+
+    #[test]
+    fn yaml_round_trips_to_the_same_toml() {
+        let file: ConfigFile = toml::from_str(SAMPLE).unwrap();
+        let yaml = render(&file, "yaml").unwrap();
+        let reparsed: ConfigFile = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(
+            render(&reparsed, "toml").unwrap(),
+            render(&file, "toml").unwrap()
+        );
+    }
+}
@@ -0,0 +1,327 @@
+//! `aether-proxy doctor` -- collect a redacted diagnostic bundle.
+//!
+//! Gathers config, environment, platform info, recent logs and connectivity
+//! checks into a single tar.gz that can be attached to a support request.
+//! Every text entry is scrubbed of configured tokens before it is written.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::Parser;
+
+use crate::config::{self, redact_secret, Config, ConfigFile, ServerEntry};
+use crate::tls::{self, TlsRoots};
+use crate::{build_info, hardware, net, state_file};
+
+use super::service::{SERVICE_NAME, UNIT_PATH};
+
+/// Timeout for each DNS / TCP / TLS probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Number of journal lines included in the bundle.
+const LOG_LINES: &str = "500";
+
+/// Files collected into the bundle, with secrets scrubbed on insertion.
+struct Bundle {
+    secrets: Vec<String>,
+    entries: Vec<(String, Vec<u8>)>,
+}
+
+impl Bundle {
+    fn add(&mut self, name: &str, content: String) {
+        let mut content = content;
+        for secret in &self.secrets {
+            content = content.replace(secret.as_str(), &redact_secret(secret));
+        }
+        self.entries.push((name.to_string(), content.into_bytes()));
+    }
+}
+
+/// `aether-proxy doctor [--output DIR] [--no-logs]`
+pub async fn cmd_doctor(output_dir: PathBuf, include_logs: bool) -> anyhow::Result<()> {
+    let config_path = config::config_file_path();
+    let config_file = ConfigFile::load(&config_path).ok();
+    // Env already has the config file injected (see main), so this is the
+    // effective config minus CLI flags.
+    let config = Config::try_parse_from(["aether-proxy"]).ok();
+    let servers = collect_servers(config_file.as_ref(), config.as_ref());
+
+    let mut bundle = Bundle {
+        secrets: collect_secrets(&servers),
+        entries: Vec::new(),
+    };
+
+    eprintln!();
+    eprintln!("  Collecting diagnostics...");
+
+    bundle.add("version.txt", version_info());
+    bundle.add("config.toml", redacted_config(&config_path, config_file));
+    bundle.add("env.txt", effective_env());
+    bundle.add(
+        "hardware.json",
+        serde_json::to_string_pretty(&hardware::collect()).unwrap_or_default(),
+    );
+    if include_logs {
+        bundle.add("logs.txt", recent_logs());
+    }
+    bundle.add(
+        "systemd-unit.txt",
+        std::fs::read_to_string(UNIT_PATH)
+            .unwrap_or_else(|e| format!("{} not readable: {}\n", UNIT_PATH, e)),
+    );
+    bundle.add(
+        "network.txt",
+        network_checks(&servers, config.as_ref()).await,
+    );
+    bundle.add(
+        "state.json",
+        std::fs::read_to_string(state_file::StateFile::path())
+            .unwrap_or_else(|_| "no state file\n".to_string()),
+    );
+
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let path = output_dir.join(format!("aether-proxy-doctor-{}.tar.gz", stamp));
+    write_bundle(&path, &bundle.entries)?;
+
+    eprintln!("  Diagnostic bundle written to {}", path.display());
+    eprintln!();
+    Ok(())
+}
+
+fn collect_servers(file: Option<&ConfigFile>, config: Option<&Config>) -> Vec<ServerEntry> {
+    let servers = file.map(|f| f.effective_servers()).unwrap_or_default();
+    if !servers.is_empty() {
+        return servers;
+    }
+    config
+        .map(|c| {
+            vec![ServerEntry {
+                aether_url: c.aether_url.clone(),
+                management_token: c.management_token.clone(),
+                node_name: None,
+                node_region: None,
+                node_tags: Default::default(),
+                role: Default::default(),
+                tls_sni_override: None,
+                tls_ca_cert: None,
+                tls_ca_only_custom: false,
+                bind_address: None,
+            }]
+        })
+        .unwrap_or_default()
+}
+
+fn collect_secrets(servers: &[ServerEntry]) -> Vec<String> {
+    let mut secrets: Vec<String> = servers
+        .iter()
+        .map(|s| s.management_token.clone())
+        .chain(
+            std::env::vars()
+                .filter(|(k, _)| k.starts_with("AETHER_PROXY_") && config::is_secret_key(k))
+                .map(|(_, v)| v),
+        )
+        .filter(|s| !s.is_empty())
+        .collect();
+    secrets.sort();
+    secrets.dedup();
+    secrets
+}
+
+fn version_info() -> String {
+    format!(
+        "aether-proxy {}\ngit: {}\ntarget: {}\nos: {}\narch: {}\n",
+        build_info::VERSION,
+        build_info::git_sha().unwrap_or("unknown"),
+        build_info::BUILD_TARGET,
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )
+}
+
+fn redacted_config(path: &Path, file: Option<ConfigFile>) -> String {
+    match file {
+        Some(file) => {
+            let body = toml::to_string_pretty(&file.redacted()).unwrap_or_default();
+            format!("# {}\n{}", path.display(), body)
+        }
+        None => format!("# {} not found or unreadable\n", path.display()),
+    }
+}
+
+/// `AETHER_PROXY_*` environment (includes values injected from the config file).
+fn effective_env() -> String {
+    let mut vars: Vec<(String, String)> = std::env::vars()
+        .filter(|(k, _)| k.starts_with("AETHER_PROXY_"))
+        .collect();
+    vars.sort();
+    let mut out = String::new();
+    for (k, v) in vars {
+        let v = if config::is_secret_key(&k) {
+            redact_secret(&v)
+        } else {
+            v
+        };
+        let _ = writeln!(out, "{}={}", k, v);
+    }
+    out
+}
+
+fn recent_logs() -> String {
+    let output = std::process::Command::new("journalctl")
+        .args(["-u", SERVICE_NAME, "-n", LOG_LINES, "--no-pager"])
+        .output();
+    match output {
+        Ok(o) if o.status.success() => String::from_utf8_lossy(&o.stdout).into_owned(),
+        Ok(o) => format!(
+            "journalctl failed ({}): {}\n",
+            o.status,
+            String::from_utf8_lossy(&o.stderr).trim()
+        ),
+        Err(e) => format!("journalctl unavailable: {}\n", e),
+    }
+}
+
+/// DNS + TLS handshake per server, plus public IP detection.
+async fn network_checks(servers: &[ServerEntry], config: Option<&Config>) -> String {
+    let (roots, extra_ca) = config
+        .map(|c| (c.tunnel_tls_roots, c.tunnel_extra_ca_file.clone()))
+        .unwrap_or((TlsRoots::Webpki, None));
+    let root_store = tls::load_root_store(roots, extra_ca.as_deref());
+
+    let mut out = String::new();
+    for (i, server) in servers.iter().enumerate() {
+        let _ = writeln!(out, "[server-{}] {}", i, server.aether_url);
+        let url = match url::Url::parse(&server.aether_url) {
+            Ok(u) => u,
+            Err(e) => {
+                let _ = writeln!(out, "  invalid URL: {}\n", e);
+                continue;
+            }
+        };
+        let host = url.host_str().unwrap_or_default().to_string();
+        let port = url.port_or_known_default().unwrap_or(443);
+
+        let addrs = match tokio::time::timeout(
+            PROBE_TIMEOUT,
+            tokio::net::lookup_host((host.as_str(), port)),
+        )
+        .await
+        {
+            Ok(Ok(addrs)) => addrs.collect::<Vec<_>>(),
+            Ok(Err(e)) => {
+                let _ = writeln!(out, "  dns: failed: {}\n", e);
+                continue;
+            }
+            Err(_) => {
+                let _ = writeln!(out, "  dns: timed out\n");
+                continue;
+            }
+        };
+        let listed: Vec<String> = addrs.iter().map(|a| a.ip().to_string()).collect();
+        let _ = writeln!(out, "  dns: {}", listed.join(", "));
+
+        if url.scheme() != "https" {
+            let _ = writeln!(out, "  tls: skipped ({})\n", url.scheme());
+            continue;
+        }
+        let tls_config = root_store
+            .as_ref()
+            .map_err(|e| e.to_string())
+            .and_then(|r| {
+                let r = match server.tls_ca_cert.as_deref().filter(|p| !p.is_empty()) {
+                    Some(path) => r
+                        .with_ca_file(path, server.tls_ca_only_custom)
+                        .map_err(|e| e.to_string())?,
+                    None => r.clone(),
+                };
+                Ok(Arc::new(
+                    rustls::ClientConfig::builder()
+                        .with_root_certificates(r.store)
+                        .with_no_client_auth(),
+                ))
+            });
+        let result = match (&tls_config, addrs.first()) {
+            (Err(e), _) => format!("root store error: {}", e),
+            (_, None) => "no addresses".to_string(),
+            (Ok(tls_config), Some(addr)) => {
+                let sni = server.tls_sni_override.as_deref().unwrap_or(&host);
+                tls_handshake(Arc::clone(tls_config), *addr, sni).await
+            }
+        };
+        let _ = writeln!(out, "  tls: {} [roots: {}]\n", result, roots);
+    }
+
+    let public_ip = match net::detect_public_ip().await {
+        Ok(ip) => ip,
+        Err(e) => format!("failed: {}", e),
+    };
+    let _ = writeln!(out, "public_ip: {}", public_ip);
+    out
+}
+
+async fn tls_handshake(
+    tls_config: Arc<rustls::ClientConfig>,
+    addr: std::net::SocketAddr,
+    host: &str,
+) -> String {
+    let server_name = match rustls::pki_types::ServerName::try_from(host.to_string()) {
+        Ok(name) => name,
+        Err(e) => return format!("invalid server name: {}", e),
+    };
+    let start = Instant::now();
+    let handshake = async {
+        let tcp = tokio::net::TcpStream::connect(addr).await?;
+        let stream = tokio_rustls::TlsConnector::from(tls_config)
+            .connect(server_name, tcp)
+            .await?;
+        let version = stream.get_ref().1.protocol_version();
+        Ok::<_, std::io::Error>(version)
+    };
+    match tokio::time::timeout(PROBE_TIMEOUT, handshake).await {
+        Ok(Ok(version)) => format!(
+            "ok via {} ({:?}, {}ms)",
+            addr,
+            version,
+            start.elapsed().as_millis()
+        ),
+        Ok(Err(e)) => format!("failed via {}: {}", addr, e),
+        Err(_) => format!("timed out via {}", addr),
+    }
+}
+
+fn write_bundle(path: &Path, entries: &[(String, Vec<u8>)]) -> anyhow::Result<()> {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    let file = std::fs::File::create(path)
+        .map_err(|e| anyhow::anyhow!("failed to create {}: {}", path.display(), e))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600));
+    }
+
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    for (name, data) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o600);
+        header.set_mtime(mtime);
+        header.set_cksum();
+        archive.append_data(
+            &mut header,
+            format!("aether-proxy-doctor/{}", name),
+            data.as_slice(),
+        )?;
+    }
+    archive.into_inner()?.finish()?;
+    Ok(())
+}
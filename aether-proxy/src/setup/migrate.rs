@@ -0,0 +1,159 @@
+//! `aether-proxy migrate` -- migrate a 0.1.x config file to the 0.2.0
+//! format, or with `--dry-run` preview the change as a line diff.
+//!
+//! Startup migrates automatically; this lets an operator see what it would
+//! do first.  Management tokens are redacted in the preview unless
+//! `--show-secrets` is given.
+
+use std::path::Path;
+
+use crate::config::{self, ConfigFile};
+
+/// `aether-proxy migrate [--dry-run] [--show-secrets] [PATH]`
+pub fn cmd_migrate(path: &Path, dry_run: bool, show_secrets: bool) -> anyhow::Result<()> {
+    if !dry_run {
+        if !ConfigFile::migrate_legacy(path)? {
+            eprintln!("  {} is already in the current format.", path.display());
+        }
+        return Ok(());
+    }
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+    match ConfigFile::migrated_legacy(&content)? {
+        None => eprintln!("  {} is already in the current format.", path.display()),
+        Some(migrated) => {
+            println!("--- {}", path.display());
+            println!("+++ {} (migrated)", path.display());
+            print!("{}", diff(&content, &migrated, show_secrets));
+            eprintln!();
+            eprintln!(
+                "  Dry run: nothing written. Without --dry-run the original is kept as {}.",
+                path.with_extension("v1.bak").display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Every line of `old` and `new`, prefixed `-` (removed), `+` (added) or
+/// ` ` (unchanged), in the order of a longest-common-subsequence alignment.
+fn diff(old: &str, new: &str, show_secrets: bool) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // common[i][j]: length of the LCS of old[i..] and new[j..].
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::new();
+    let mut push = |marker: char, line: &str| {
+        let line = if show_secrets {
+            line.to_string()
+        } else {
+            redact_line(line)
+        };
+        out.push(marker);
+        out.push_str(&line);
+        out.push('\n');
+    };
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            push(' ', old[i]);
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+            push('-', old[i]);
+            i += 1;
+        } else {
+            push('+', new[j]);
+            j += 1;
+        }
+    }
+    out
+}
+
+/// `key = "secret"` with the value passed through [`config::redact_secret`]
+/// when `key` names a secret; other lines unchanged.
+fn redact_line(line: &str) -> String {
+    let Some((key, value)) = line.split_once('=') else {
+        return line.to_string();
+    };
+    let key = key.trim();
+    if !config::is_secret_key(key) {
+        return line.to_string();
+    }
+    let value = value.split('#').next().unwrap_or_default().trim();
+    let value = value.trim_matches(|c| c == '"' || c == '\'');
+    format!("{} = \"{}\"", key, config::redact_secret(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_marks_removed_added_and_kept_lines() {
+        assert_eq!(diff("a\nb\nc\n", "a\nc\nd\n", true), " a\n-b\n c\n+d\n");
+        assert_eq!(diff("", "a\n", true), "+a\n");
+    }
+
+    #[test]
+    fn dry_run_preview_redacts_tokens() {
+        let legacy = "aether_url = \"https://a.example.com\"\n\
+                      management_token = \"ae_secret123\"\n\
+                      node_name = \"legacy-node\"\n\
+                      hmac_key = \"old\"\n\
+                      log_level = \"info\"\n";
+        let migrated = ConfigFile::migrated_legacy(legacy).unwrap().unwrap();
+        let preview = diff(legacy, &migrated, false);
+
+        assert_eq!(
+            preview,
+            "+log_level = \"info\"\n\
+             +\n\
+             +[[servers]]\n \
+             aether_url = \"https://a.example.com\"\n \
+             management_token = \"ae_s***\"\n \
+             node_name = \"legacy-node\"\n\
+             -hmac_key = \"old\"\n\
+             -log_level = \"info\"\n"
+        );
+        assert!(diff(legacy, &migrated, true).contains("ae_secret123"));
+    }
+
+    #[test]
+    fn dry_run_writes_nothing() {
+        let dir = std::env::temp_dir().join(format!("aether-proxy-migrate-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("aether-proxy.toml");
+        let legacy = "aether_url = \"https://a.example.com\"\n\
+                      management_token = \"ae_a\"\n\
+                      listen_port = 8080\n";
+        std::fs::write(&path, legacy).unwrap();
+
+        cmd_migrate(&path, true, false).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), legacy);
+        assert!(!path.with_extension("v1.bak").exists());
+
+        cmd_migrate(&path, false, false).unwrap();
+        assert!(!std::fs::read_to_string(&path)
+            .unwrap()
+            .contains("listen_port"));
+        assert!(path.with_extension("v1.bak").exists());
+        assert_eq!(
+            ConfigFile::migrated_legacy(&std::fs::read_to_string(&path).unwrap()).unwrap(),
+            None
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
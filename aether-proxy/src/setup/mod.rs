@@ -1,4 +1,11 @@
+pub(crate) mod convert;
+pub(crate) mod doctor;
+pub(crate) mod migrate;
+pub(crate) mod ping;
+pub(crate) mod schema;
+pub(crate) mod servers;
 pub(crate) mod service;
+mod status;
 mod tui;
 pub(crate) mod upgrade;
 
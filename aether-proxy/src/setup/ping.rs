@@ -0,0 +1,143 @@
+//! `aether-proxy ping` -- measure WebSocket round-trip latency to Aether.
+//!
+//! Opens the tunnel endpoint with the configured credentials and the node_id
+//! the proxy last registered under (no node is registered and no requests
+//! are served), sends WebSocket Pings and reports per-ping RTT plus a
+//! summary in the style of Unix `ping`.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+
+use crate::config::{self, Config, ConfigFile, ServerEntry};
+use crate::state_file::StateFile;
+use crate::tls;
+use crate::tunnel::client;
+
+/// How long to wait for each Pong before counting the ping as lost.
+const PONG_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `aether-proxy ping [--count N] [--interval SECS]`
+pub async fn cmd_ping(count: u32, interval: Duration) -> anyhow::Result<()> {
+    let config = Config::try_parse_from(["aether-proxy"])
+        .map_err(|e| anyhow::anyhow!("config invalid: {}", e))?;
+    let roots = tls::load_root_store(
+        config.tunnel_tls_roots,
+        config.tunnel_extra_ca_file.as_deref(),
+    )?;
+    let shared = Arc::new(client::build_tls_config(roots.clone()));
+
+    let mut any_failed = false;
+    for server in configured_servers(&config) {
+        eprintln!();
+        eprintln!("  PING {}", client::tunnel_url(&server.aether_url));
+
+        let tls_config = match client::server_tls_config(&shared, &roots, &server) {
+            Ok(tls_config) => tls_config,
+            Err(e) => {
+                eprintln!("  error: {}", e);
+                any_failed = true;
+                continue;
+            }
+        };
+        let node_name = server.node_name.as_deref().unwrap_or(&config.node_name);
+        let Some(node_id) = StateFile::node_id_for(&server.aether_url, node_name) else {
+            eprintln!(
+                "  error: no node_id recorded for {} yet (Aether only accepts tunnels for a registered node; start aether-proxy once first)",
+                node_name
+            );
+            any_failed = true;
+            continue;
+        };
+        let mut rtts: Vec<Duration> = Vec::new();
+        let result = client::ping(
+            &config,
+            &tls_config,
+            &server,
+            &node_id,
+            count,
+            interval,
+            PONG_TIMEOUT,
+            |seq, rtt| match rtt {
+                Some(rtt) => {
+                    eprintln!("  pong seq={} time={:.1} ms", seq, ms(rtt));
+                    rtts.push(rtt);
+                }
+                None => eprintln!("  timeout seq={}", seq),
+            },
+        )
+        .await;
+
+        if let Err(e) = result {
+            eprintln!("  error: {}", e);
+            any_failed = true;
+            continue;
+        }
+
+        let received = rtts.len() as u32;
+        let loss = (count - received) as f64 * 100.0 / count.max(1) as f64;
+        eprintln!("  --- ping statistics ---");
+        eprintln!("  {} sent, {} received, {:.0}% loss", count, received, loss);
+        if let Some((min, avg, max)) = rtt_summary(&rtts) {
+            eprintln!("  rtt min/avg/max = {:.1}/{:.1}/{:.1} ms", min, avg, max);
+        }
+        any_failed |= received < count;
+    }
+    eprintln!();
+
+    if any_failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// `[[servers]]` from the config file, or the single CLI/env server.
+fn configured_servers(config: &Config) -> Vec<ServerEntry> {
+    ConfigFile::load(&config::config_file_path())
+        .ok()
+        .map(|f| f.effective_servers())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| {
+            vec![ServerEntry {
+                aether_url: config.aether_url.clone(),
+                management_token: config.management_token.clone(),
+                node_name: None,
+                node_region: None,
+                node_tags: Default::default(),
+                role: Default::default(),
+                tls_sni_override: None,
+                tls_ca_cert: None,
+                tls_ca_only_custom: false,
+                bind_address: None,
+            }]
+        })
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+/// (min, avg, max) in milliseconds, or `None` if nothing was received.
+fn rtt_summary(rtts: &[Duration]) -> Option<(f64, f64, f64)> {
+    let min = rtts.iter().min()?;
+    let max = rtts.iter().max()?;
+    let avg = rtts.iter().map(|d| ms(*d)).sum::<f64>() / rtts.len() as f64;
+    Some((ms(*min), avg, ms(*max)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rtt_summary_reports_min_avg_max() {
+        assert_eq!(rtt_summary(&[]), None);
+        let rtts = [
+            Duration::from_millis(10),
+            Duration::from_millis(30),
+            Duration::from_millis(20),
+        ];
+        assert_eq!(rtt_summary(&rtts), Some((10.0, 20.0, 30.0)));
+    }
+}
@@ -0,0 +1,349 @@
+//! `aether-proxy schema` -- describe every config file key.
+//!
+//! Flat keys are read off the [`Config`] arguments (type from the value
+//! parser, default, env var and help text), so the schema cannot drift
+//! from the flags.  Tables that only exist in the file (`[[servers]]`,
+//! `[service]`, `[compression]`, `[profiles]`, `include`) are described by
+//! hand below.
+
+use std::any::TypeId;
+use std::fmt::Write as _;
+
+use clap::{Arg, CommandFactory};
+use serde_json::{json, Map, Value};
+
+use crate::config::Config;
+use crate::header_rules::HeaderRules;
+use crate::target_filter::PortRange;
+use crate::tunnel::compression::ContentTypeRules;
+use crate::upstream_auth::UpstreamAuth;
+use crate::upstream_client::UpstreamPoolOverrides;
+
+/// Output formats accepted by `--format`.
+pub const SCHEMA_FORMATS: [&str; 2] = ["json", "markdown"];
+
+/// Arguments that are not flat config file keys: `profile` selects how the
+/// file is loaded, `compression_content_types` is `[compression]`'s
+/// `content_types`.
+const NOT_FILE_KEYS: &[&str] = &["profile", "compression_content_types", "help", "version"];
+
+/// `aether-proxy schema [--format json|markdown]`
+pub fn cmd_schema(format: &str) -> anyhow::Result<()> {
+    let schema = config_schema();
+    match format {
+        "json" => println!("{}", serde_json::to_string_pretty(&schema)?),
+        "markdown" => print!("{}", render_markdown(&schema)),
+        other => anyhow::bail!(
+            "unknown schema format '{}' (expected one of: {})",
+            other,
+            SCHEMA_FORMATS.join(", ")
+        ),
+    }
+    Ok(())
+}
+
+/// JSON Schema of the config file.  Flat keys carry their env var as
+/// `x-env`.
+pub fn config_schema() -> Value {
+    let mut command = Config::command();
+    command.build();
+
+    let mut properties = Map::new();
+    for arg in command.get_arguments() {
+        let key = arg.get_id().as_str();
+        if arg.is_positional() || NOT_FILE_KEYS.contains(&key) {
+            continue;
+        }
+        properties.insert(key.to_string(), arg_schema(arg));
+    }
+    for (key, schema) in file_only_tables(&command) {
+        properties.insert(key.to_string(), schema);
+    }
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "aether-proxy config file",
+        "type": "object",
+        "properties": properties,
+    })
+}
+
+fn arg_schema(arg: &Arg) -> Value {
+    let mut schema = value_schema(arg);
+    let defaults: Vec<String> = arg
+        .get_default_values()
+        .iter()
+        .map(|v| v.to_string_lossy().into_owned())
+        .collect();
+    let default = if arg.get_value_delimiter().is_some() {
+        // Lists (and key=value tables) default to one value per element.
+        (!defaults.is_empty())
+            .then(|| Value::Array(defaults.iter().map(|d| default_value(arg, d)).collect()))
+    } else {
+        defaults.first().map(|d| default_value(arg, d))
+    };
+    let fields = schema.as_object_mut().expect("schemas are objects");
+    if let Some(help) = arg.get_help() {
+        fields.insert("description".into(), help.to_string().into());
+    }
+    if let Some(default) = default {
+        fields.insert("default".into(), default);
+    }
+    if let Some(env) = arg.get_env().and_then(|e| e.to_str()) {
+        fields.insert("x-env".into(), env.into());
+    }
+    schema
+}
+
+/// Schema of the value(s) `arg` takes, as written in the file.
+fn value_schema(arg: &Arg) -> Value {
+    let parser = arg.get_value_parser();
+    let id = parser.type_id();
+    let is = |t: TypeId| id == t;
+    let item = if is(TypeId::of::<bool>()) {
+        json!({"type": "boolean"})
+    } else if [
+        TypeId::of::<u8>(),
+        TypeId::of::<u16>(),
+        TypeId::of::<u32>(),
+        TypeId::of::<u64>(),
+        TypeId::of::<usize>(),
+    ]
+    .into_iter()
+    .any(is)
+    {
+        json!({"type": "integer", "minimum": 0})
+    } else if is(TypeId::of::<f64>()) {
+        json!({"type": "number"})
+    } else if is(TypeId::of::<PortRange>()) {
+        json!({"oneOf": [
+            {"type": "integer", "minimum": 1, "maximum": 65535},
+            {"type": "string", "pattern": "^[0-9]+-[0-9]+$"},
+        ]})
+    } else if is(TypeId::of::<(String, String)>()) {
+        // `key=value` pairs on the command line, a table in the file.
+        return json!({"type": "object", "additionalProperties": {"type": "string"}});
+    } else if is(TypeId::of::<HeaderRules>()) {
+        return json!({"type": "array", "items": {"type": "object"}});
+    } else if [
+        TypeId::of::<UpstreamAuth>(),
+        TypeId::of::<UpstreamPoolOverrides>(),
+        TypeId::of::<ContentTypeRules>(),
+    ]
+    .into_iter()
+    .any(is)
+    {
+        return json!({"type": "object"});
+    } else if let Some(values) = parser.possible_values() {
+        let values: Vec<String> = values.map(|v| v.get_name().to_string()).collect();
+        json!({"type": "string", "enum": values})
+    } else {
+        json!({"type": "string"})
+    };
+    if arg.get_value_delimiter().is_some() {
+        json!({"type": "array", "items": item})
+    } else {
+        item
+    }
+}
+
+/// A clap default as the JSON value the file would hold.
+fn default_value(arg: &Arg, default: &str) -> Value {
+    let id = arg.get_value_parser().type_id();
+    if id == TypeId::of::<bool>() {
+        default.parse().map(Value::Bool).unwrap_or(Value::Null)
+    } else if id == TypeId::of::<PortRange>() {
+        default
+            .parse::<PortRange>()
+            .ok()
+            .and_then(|r| serde_json::to_value(r).ok())
+            .unwrap_or(Value::Null)
+    } else if id == TypeId::of::<f64>() {
+        default
+            .parse()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map_or(Value::Null, Value::Number)
+    } else if id == TypeId::of::<String>() || id == TypeId::of::<std::path::PathBuf>() {
+        Value::String(default.to_string())
+    } else {
+        // Numbers and the JSON-valued map options.
+        serde_json::from_str(default).unwrap_or_else(|_| Value::String(default.to_string()))
+    }
+}
+
+/// Tables and lists with no command-line flag.
+fn file_only_tables(command: &clap::Command) -> Vec<(&'static str, Value)> {
+    let string = json!({"type": "string"});
+    let content_types = command
+        .get_arguments()
+        .find(|arg| arg.get_id() == "compression_content_types")
+        .map(arg_schema)
+        .unwrap_or_else(|| json!({"type": "object"}));
+    vec![
+        (
+            "include",
+            json!({
+                "description": "Additional config files (paths or globs, relative to this file) merged on top in order",
+                "type": "array",
+                "items": string,
+            }),
+        ),
+        (
+            "servers",
+            json!({
+                "description": "Aether servers to register with; replaces the top-level aether_url/management_token",
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["aether_url", "management_token"],
+                    "properties": {
+                        "aether_url": string,
+                        "management_token": string,
+                        "node_name": {"type": "string", "description": "Overrides the global node_name"},
+                        "node_region": {"type": "string", "description": "Overrides the global node_region"},
+                        "node_tags": {
+                            "type": "object",
+                            "additionalProperties": string,
+                            "description": "Merged over the global node_tags",
+                        },
+                        "role": {"type": "string", "enum": ["primary", "secondary"], "default": "primary"},
+                        "tls_sni_override": {"type": "string", "description": "TLS SNI for the tunnel handshake"},
+                        "tls_ca_cert": {"type": "string", "description": "PEM CA certificate(s) trusted for this server"},
+                        "tls_ca_only_custom": {"type": "boolean", "default": false},
+                        "bind_address": {"type": "string", "description": "Local address for this server's connections"},
+                    },
+                },
+            }),
+        ),
+        (
+            "service",
+            json!({
+                "description": "systemd service settings used by `setup`",
+                "type": "object",
+                "properties": {
+                    "user": string,
+                    "hardening": {"type": "boolean", "default": false},
+                    "memory_max": {"type": "string", "description": "systemd MemoryMax= (e.g. 512M)"},
+                    "cpu_quota": {"type": "string", "description": "systemd CPUQuota= (e.g. 200%)"},
+                    "tasks_max": {"type": "integer", "minimum": 0},
+                },
+            }),
+        ),
+        (
+            "compression",
+            json!({
+                "type": "object",
+                "properties": {"content_types": content_types},
+            }),
+        ),
+        (
+            "profiles",
+            json!({
+                "description": "Named overrides of the flat keys, applied with --profile",
+                "type": "object",
+                "additionalProperties": {"type": "object"},
+            }),
+        ),
+    ]
+}
+
+/// One row per key: type, default, env var and description.
+fn render_markdown(schema: &Value) -> String {
+    let mut out = String::from(
+        "| Key | Type | Default | Env | Description |\n|-----|------|---------|-----|-------------|\n",
+    );
+    let Some(properties) = schema["properties"].as_object() else {
+        return out;
+    };
+    for (key, property) in properties {
+        let kind = match (&property["type"], &property["enum"]) {
+            (_, Value::Array(values)) => values
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join(" \\| "),
+            (Value::String(kind), _) if kind == "array" => {
+                format!("array of {}", type_name(&property["items"]))
+            }
+            _ => type_name(property),
+        };
+        let cell = |value: &Value| match value {
+            Value::Null => String::new(),
+            Value::String(s) => s.replace('|', "\\|"),
+            other => format!("`{}`", other),
+        };
+        let _ = writeln!(
+            out,
+            "| `{}` | {} | {} | {} | {} |",
+            key,
+            kind,
+            cell(&property["default"]),
+            cell(&property["x-env"]),
+            cell(&property["description"]),
+        );
+    }
+    out
+}
+
+fn type_name(schema: &Value) -> String {
+    match &schema["type"] {
+        Value::String(kind) => kind.clone(),
+        _ if schema["oneOf"].is_array() => "integer \\| range".to_string(),
+        _ => "any".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigFile;
+
+    #[test]
+    fn flat_keys_match_config_file_fields() {
+        let schema = config_schema();
+        let properties = schema["properties"].as_object().unwrap();
+        assert_eq!(
+            properties["allowed_ports"]["default"],
+            json!([80, 443, 8080, 8443])
+        );
+        assert_eq!(
+            properties["upstream_tls_roots"]["enum"],
+            json!(["webpki", "native", "both"])
+        );
+        assert_eq!(properties["node_tags"]["type"], "object");
+        assert_eq!(properties["log_stderr"]["default"], true);
+        assert_eq!(
+            properties["heartbeat_interval"]["x-env"],
+            "AETHER_PROXY_HEARTBEAT_INTERVAL"
+        );
+        assert!(!properties.contains_key("profile"));
+
+        // Every default written under its key survives a round trip
+        // through ConfigFile, so the key names are real file keys.
+        let defaults: Map<String, Value> = properties
+            .iter()
+            .filter_map(|(key, p)| Some((key.clone(), p.get("default")?.clone())))
+            .filter(|(_, d)| !d.as_str().is_some_and(str::is_empty))
+            .collect();
+        let file: ConfigFile = serde_json::from_value(Value::Object(defaults.clone())).unwrap();
+        let Value::Object(round_trip) = serde_json::to_value(&file).unwrap() else {
+            panic!("config file is a table");
+        };
+        for (key, default) in &defaults {
+            let empty = match default {
+                Value::Object(map) => map.is_empty(),
+                Value::Array(items) => items.is_empty(),
+                _ => false,
+            };
+            if empty {
+                // Not written back.
+                continue;
+            }
+            assert_eq!(round_trip.get(key), Some(default), "{key}");
+        }
+
+        let markdown = render_markdown(&schema);
+        assert!(markdown.contains("| `allowed_ports` | array of integer \\| range | `[80,443,8080,8443]` | AETHER_PROXY_ALLOWED_PORTS |"));
+    }
+}
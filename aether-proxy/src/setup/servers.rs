@@ -0,0 +1,428 @@
+//! `aether-proxy servers` -- list, add and remove `[[servers]]` entries
+//! without the TUI, for scripted fleet changes.
+//!
+//! Edits go through `toml_edit`, so comments and formatting in the rest of
+//! the file survive.  Only the main file is edited, not its includes.  A
+//! running proxy doesn't pick up the change until it is restarted.
+
+use std::path::{Path, PathBuf};
+
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Table};
+
+use crate::config::{self, ConfigFile, ServerEntry};
+use crate::pid_file;
+
+use super::service;
+
+/// A `[[servers]]` entry to add.
+#[derive(Debug, Default)]
+pub struct NewServer {
+    pub url: String,
+    pub token: String,
+    pub name: Option<String>,
+    pub region: Option<String>,
+    pub secondary: bool,
+}
+
+/// `aether-proxy servers list`
+pub fn cmd_list(path: &Path) -> anyhow::Result<()> {
+    let file = ConfigFile::load_profile(path, None)?;
+    print_servers(&file);
+    Ok(())
+}
+
+/// `aether-proxy servers add --url URL --token TOKEN [--name NAME] ...`
+pub fn cmd_add(path: &Path, server: &NewServer) -> anyhow::Result<()> {
+    edit(path, |content| add_server(content, server))?;
+    eprintln!("  Added {} to {}", server.url, path.display());
+    finish(path)
+}
+
+/// `aether-proxy servers remove --name NAME [--url URL] [--force]`
+pub fn cmd_remove(path: &Path, name: &str, url: Option<&str>, force: bool) -> anyhow::Result<()> {
+    edit(path, |content| remove_server(content, name, url, force))?;
+    eprintln!("  Removed '{}' from {}", name, path.display());
+    finish(path)
+}
+
+/// Read-modify-write `path` under the config lock.  The result must still
+/// load as a config file before it replaces the original.
+fn edit(path: &Path, apply: impl FnOnce(&str) -> anyhow::Result<String>) -> anyhow::Result<()> {
+    let _lock = config::lock_config(path)?;
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+    let edited = apply(&content)?;
+    toml::from_str::<ConfigFile>(&edited)
+        .map_err(|e| anyhow::anyhow!("edited config would not load: {}", e))?;
+    config::write_atomic(path, edited.as_bytes())
+}
+
+/// Print the resulting list and whether a restart is needed.
+fn finish(path: &Path) -> anyhow::Result<()> {
+    let file = ConfigFile::load_profile(path, None)?;
+    print_servers(&file);
+    if is_running(&file) {
+        eprintln!();
+        eprintln!("  aether-proxy is running; restart it to apply the change:");
+        eprintln!("    aether-proxy restart");
+    }
+    Ok(())
+}
+
+/// One line per effective server, tokens redacted.
+fn print_servers(file: &ConfigFile) {
+    let servers = file.effective_servers();
+    if servers.is_empty() {
+        println!("(no servers configured)");
+    }
+    for (i, server) in servers.iter().enumerate() {
+        println!("{}", describe(i, server, file.node_name.as_deref()));
+    }
+}
+
+/// `[i] <name> <url> <token> <role>`, with the name falling back to the
+/// global `node_name`.
+fn describe(i: usize, server: &ServerEntry, default_name: Option<&str>) -> String {
+    let name = server.node_name.as_deref().or(default_name).unwrap_or("-");
+    let mut line = format!(
+        "[{}] {} {} {}",
+        i,
+        name,
+        server.aether_url,
+        config::redact_secret(&server.management_token)
+    );
+    line.push_str(if server.role.is_primary() {
+        " primary"
+    } else {
+        " secondary"
+    });
+    if let Some(region) = &server.node_region {
+        line.push_str(&format!(" region={}", region));
+    }
+    line
+}
+
+/// The service is active, or the pid file names a live process.
+fn is_running(file: &ConfigFile) -> bool {
+    let pid_path = std::env::var_os("AETHER_PROXY_PID_FILE")
+        .map(PathBuf::from)
+        .or_else(|| file.pid_file.as_ref().map(PathBuf::from));
+    service::is_service_active()
+        || pid_path.is_some_and(|path| pid_file::running_pid(&path).is_some())
+}
+
+/// `content` with `server` appended to `[[servers]]`.  A file that
+/// configures its only server with top-level `aether_url` and
+/// `management_token` has that server moved into `[[servers]]` first, since
+/// the top-level one is ignored once the array exists.
+fn add_server(content: &str, server: &NewServer) -> anyhow::Result<String> {
+    check_url(&server.url)?;
+    check_token(&server.token)?;
+    let mut doc: DocumentMut = content.parse()?;
+
+    if !doc.contains_key("servers") {
+        let mut servers = ArrayOfTables::new();
+        if let (Some(url), Some(token)) = (
+            doc.get("aether_url").and_then(Item::as_str),
+            doc.get("management_token").and_then(Item::as_str),
+        ) {
+            let mut existing = Table::new();
+            existing["aether_url"] = toml_edit::value(url);
+            existing["management_token"] = toml_edit::value(token);
+            // Comments above the moved keys move with them.
+            for key in ["aether_url", "management_token"] {
+                let prefix = doc
+                    .as_table()
+                    .key(key)
+                    .and_then(|k| k.leaf_decor().prefix())
+                    .and_then(|p| p.as_str())
+                    .map(str::to_string);
+                if let (Some(prefix), Some(mut moved)) = (prefix, existing.key_mut(key)) {
+                    moved.leaf_decor_mut().set_prefix(prefix);
+                }
+            }
+            servers.push(existing);
+            doc.remove("aether_url");
+            doc.remove("management_token");
+        }
+        doc.insert("servers", Item::ArrayOfTables(servers));
+    }
+    let servers = servers_mut(&mut doc)?;
+
+    let mut entry = Table::new();
+    entry["aether_url"] = toml_edit::value(server.url.trim());
+    entry["management_token"] = toml_edit::value(server.token.trim());
+    if let Some(name) = &server.name {
+        entry["node_name"] = toml_edit::value(name.as_str());
+    }
+    if let Some(region) = &server.region {
+        entry["node_region"] = toml_edit::value(region.as_str());
+    }
+    if server.secondary {
+        entry["role"] = toml_edit::value("secondary");
+    }
+    servers.push(entry);
+    let added = servers.len() - 1;
+
+    let edited = doc.to_string();
+    let file: ConfigFile = toml::from_str(&edited)?;
+    if let Some((i, _)) = file
+        .duplicate_servers()
+        .into_iter()
+        .find(|&(_, j)| j == added)
+    {
+        anyhow::bail!(
+            "servers[{}] already registers {} with the same node name",
+            i,
+            server.url
+        );
+    }
+    Ok(edited)
+}
+
+/// `content` without the `[[servers]]` entry named `name` (and at `url`,
+/// when several share the name).  Removing the last one needs `force`.
+fn remove_server(
+    content: &str,
+    name: &str,
+    url: Option<&str>,
+    force: bool,
+) -> anyhow::Result<String> {
+    let mut doc: DocumentMut = content.parse()?;
+    let default_name = doc
+        .get("node_name")
+        .and_then(Item::as_str)
+        .map(str::to_string);
+    if !doc.contains_key("servers") {
+        anyhow::bail!("no [[servers]] entries (a top-level aether_url is edited with `setup`)");
+    }
+    let servers = servers_mut(&mut doc)?;
+
+    let same_url = |a: &str, b: &str| {
+        a.trim().trim_end_matches('/').to_lowercase()
+            == b.trim().trim_end_matches('/').to_lowercase()
+    };
+    let matches: Vec<usize> = servers
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| {
+            let entry_name = entry
+                .get("node_name")
+                .and_then(Item::as_str)
+                .or(default_name.as_deref());
+            let entry_url = entry.get("aether_url").and_then(Item::as_str);
+            entry_name == Some(name)
+                && url.is_none_or(|url| entry_url.is_some_and(|e| same_url(e, url)))
+        })
+        .map(|(i, _)| i)
+        .collect();
+    let index = match matches[..] {
+        [] => anyhow::bail!("no server named '{}'", name),
+        [index] => index,
+        _ => anyhow::bail!(
+            "{} servers are named '{}'; pass --url to pick one",
+            matches.len(),
+            name
+        ),
+    };
+    if servers.len() == 1 && !force {
+        anyhow::bail!("'{}' is the last server; pass --force to remove it", name);
+    }
+    servers.remove(index);
+    if servers.is_empty() {
+        doc.remove("servers");
+    }
+    Ok(doc.to_string())
+}
+
+fn servers_mut(doc: &mut DocumentMut) -> anyhow::Result<&mut ArrayOfTables> {
+    doc.get_mut("servers")
+        .and_then(Item::as_array_of_tables_mut)
+        .ok_or_else(|| {
+            anyhow::anyhow!("servers must be written as [[servers]] tables to be edited")
+        })
+}
+
+fn check_url(url: &str) -> anyhow::Result<()> {
+    match url::Url::parse(url.trim()) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") && parsed.has_host() => Ok(()),
+        _ => anyhow::bail!("--url must be an http(s) URL: {}", url),
+    }
+}
+
+/// Management tokens are `ae_` followed by letters and digits.
+fn check_token(token: &str) -> anyhow::Result<()> {
+    let valid = token
+        .trim()
+        .strip_prefix("ae_")
+        .is_some_and(|rest| !rest.is_empty() && rest.chars().all(|c| c.is_ascii_alphanumeric()));
+    if !valid {
+        anyhow::bail!("--token must be a management token (ae_ followed by letters and digits)");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMENTED: &str = r#"# Fleet config, managed by ops
+log_level = "info" # keep quiet
+
+# Tokyo
+[[servers]]
+aether_url = "https://a.example.com"
+management_token = "ae_a1"
+node_name = "jp-01"
+
+# Osaka (DR)
+[[servers]]
+aether_url = "https://b.example.com"
+management_token = "ae_b1"
+node_name = "jp-02"
+role = "secondary"
+
+[service]
+# run unprivileged
+user = "aether"
+"#;
+
+    fn server(url: &str, token: &str, name: &str) -> NewServer {
+        NewServer {
+            url: url.into(),
+            token: token.into(),
+            name: Some(name.into()),
+            ..NewServer::default()
+        }
+    }
+
+    #[test]
+    fn add_appends_an_entry_and_keeps_comments() {
+        let added = add_server(
+            COMMENTED,
+            &NewServer {
+                region: Some("kr".into()),
+                ..server("https://c.example.com", "ae_c1", "kr-01")
+            },
+        )
+        .unwrap();
+        for comment in [
+            "# Fleet config, managed by ops",
+            "# keep quiet",
+            "# Tokyo",
+            "# Osaka (DR)",
+            "# run unprivileged",
+        ] {
+            assert!(added.contains(comment), "{comment} lost:\n{added}");
+        }
+        let file: ConfigFile = toml::from_str(&added).unwrap();
+        assert_eq!(file.servers.len(), 3);
+        assert_eq!(file.servers[2].aether_url, "https://c.example.com");
+        assert_eq!(file.servers[2].node_region.as_deref(), Some("kr"));
+        assert_eq!(file.service.user.as_deref(), Some("aether"));
+    }
+
+    #[test]
+    fn add_validates_the_entry() {
+        let bad_url = add_server(COMMENTED, &server("ftp://c.example.com", "ae_c1", "x"));
+        assert!(bad_url.unwrap_err().to_string().contains("--url"));
+        let bad_token = add_server(COMMENTED, &server("https://c.example.com", "c1", "x"));
+        assert!(bad_token.unwrap_err().to_string().contains("--token"));
+        let duplicate = add_server(
+            COMMENTED,
+            &server("https://A.example.com/", "ae_z", "jp-01"),
+        );
+        assert!(duplicate
+            .unwrap_err()
+            .to_string()
+            .starts_with("servers[0] already registers"));
+    }
+
+    #[test]
+    fn add_moves_a_top_level_server_into_the_array() {
+        let content = "# single server\naether_url = \"https://a.example.com\"\nmanagement_token = \"ae_a1\"\nnode_name = \"jp\"\n";
+        let added =
+            add_server(content, &server("https://b.example.com", "ae_b1", "jp-02")).unwrap();
+        assert!(added.contains("# single server"));
+        let file: ConfigFile = toml::from_str(&added).unwrap();
+        assert_eq!(file.aether_url, None);
+        assert_eq!(file.node_name.as_deref(), Some("jp"));
+        let urls: Vec<&str> = file.servers.iter().map(|s| s.aether_url.as_str()).collect();
+        assert_eq!(urls, ["https://a.example.com", "https://b.example.com"]);
+    }
+
+    #[test]
+    fn remove_drops_the_named_entry_and_keeps_comments() {
+        let removed = remove_server(COMMENTED, "jp-02", None, false).unwrap();
+        assert!(!removed.contains("b.example.com"));
+        assert!(removed.contains("# Tokyo") && removed.contains("# run unprivileged"));
+        let file: ConfigFile = toml::from_str(&removed).unwrap();
+        assert_eq!(file.servers.len(), 1);
+
+        let err = remove_server(COMMENTED, "jp-03", None, false).unwrap_err();
+        assert_eq!(err.to_string(), "no server named 'jp-03'");
+    }
+
+    #[test]
+    fn remove_needs_force_for_the_last_entry() {
+        let one = remove_server(COMMENTED, "jp-02", None, false).unwrap();
+        let err = remove_server(&one, "jp-01", None, false).unwrap_err();
+        assert!(err.to_string().contains("--force"), "{err}");
+        let none = remove_server(&one, "jp-01", None, true).unwrap();
+        let file: ConfigFile = toml::from_str(&none).unwrap();
+        assert!(file.servers.is_empty());
+        assert!(none.contains("# Fleet config, managed by ops"));
+    }
+
+    #[test]
+    fn remove_disambiguates_shared_names_by_url() {
+        let content = "node_name = \"edge\"\n\n[[servers]]\naether_url = \"https://a.example.com\"\nmanagement_token = \"ae_a1\"\n\n[[servers]]\naether_url = \"https://b.example.com\"\nmanagement_token = \"ae_b1\"\n";
+        let err = remove_server(content, "edge", None, false).unwrap_err();
+        assert!(err.to_string().contains("--url"), "{err}");
+        let removed =
+            remove_server(content, "edge", Some("https://B.example.com/"), false).unwrap();
+        let file: ConfigFile = toml::from_str(&removed).unwrap();
+        assert_eq!(file.servers.len(), 1);
+        assert_eq!(file.servers[0].aether_url, "https://a.example.com");
+    }
+
+    #[test]
+    fn listed_servers_have_tokens_redacted() {
+        let file: ConfigFile = toml::from_str(COMMENTED).unwrap();
+        let lines: Vec<String> = file
+            .effective_servers()
+            .iter()
+            .enumerate()
+            .map(|(i, s)| describe(i, s, None))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "[0] jp-01 https://a.example.com ae_a*** primary",
+                "[1] jp-02 https://b.example.com ae_b*** secondary",
+            ]
+        );
+    }
+
+    #[test]
+    fn edits_are_written_to_the_file() {
+        let dir = std::env::temp_dir().join(format!("aether-proxy-servers-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("aether-proxy.toml");
+        std::fs::write(&path, COMMENTED).unwrap();
+
+        edit(&path, |content| {
+            add_server(content, &server("https://c.example.com", "ae_c1", "kr-01"))
+        })
+        .unwrap();
+        let file = ConfigFile::load(&path).unwrap();
+        assert_eq!(file.servers.len(), 3);
+
+        // A failed edit leaves the file alone.
+        assert!(edit(&path, |content| remove_server(content, "nope", None, false)).is_err());
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.contains("# Tokyo") && content.contains("kr-01"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -4,33 +4,142 @@
 //! The unit file points to the binary and config at their current
 //! absolute paths -- no files are copied.
 
+use std::fmt::Write as _;
 use std::path::Path;
 use std::process::Command;
 
-const UNIT_PATH: &str = "/etc/systemd/system/aether-proxy.service";
-const SERVICE_NAME: &str = "aether-proxy";
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigFile;
+
+pub(crate) const UNIT_PATH: &str = "/etc/systemd/system/aether-proxy.service";
+pub(crate) const SERVICE_NAME: &str = "aether-proxy";
+
+/// `[service]` table: user, sandboxing and resource limits for the unit
+/// written by [`install_service`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceConfig {
+    /// Run as this user instead of root (created as a system user if missing).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// NoNewPrivileges, PrivateTmp and ProtectSystem=strict with the config
+    /// directory left writable.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hardening: bool,
+    /// systemd `MemoryMax=` (e.g. `512M`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_max: Option<String>,
+    /// systemd `CPUQuota=` (e.g. `200%`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_quota: Option<String>,
+    /// systemd `TasksMax=`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tasks_max: Option<u64>,
+}
+
+impl ServiceConfig {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Reject values systemd would refuse (or that could inject directives).
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(user) = &self.user {
+            let valid = !user.is_empty()
+                && user.len() <= 32
+                && !user.starts_with('-')
+                && user
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+            if !valid {
+                anyhow::bail!("service user is not a valid user name: {}", user);
+            }
+        }
+        if let Some(memory) = &self.memory_max {
+            let digits = memory.trim_end_matches(['K', 'M', 'G', 'T', '%']);
+            let valid = memory == "infinity"
+                || (!digits.is_empty()
+                    && digits.chars().all(|c| c.is_ascii_digit())
+                    && memory.len() - digits.len() <= 1);
+            if !valid {
+                anyhow::bail!("memory_max must be bytes with an optional K/M/G/T suffix, a percentage or 'infinity': {}", memory);
+            }
+        }
+        if let Some(quota) = &self.cpu_quota {
+            let valid = quota
+                .strip_suffix('%')
+                .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+            if !valid {
+                anyhow::bail!("cpu_quota must be a percentage (e.g. 200%): {}", quota);
+            }
+        }
+        if self.tasks_max == Some(0) {
+            anyhow::bail!("tasks_max must be > 0");
+        }
+        Ok(())
+    }
+
+    /// Settings rendered into an existing unit file by [`render_unit`], so a
+    /// reinstall can keep them.
+    pub fn from_unit(unit: &str) -> Self {
+        let mut service = Self::default();
+        for line in unit.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().to_string();
+            match key.trim() {
+                "User" => service.user = Some(value),
+                "ProtectSystem" => service.hardening = value == "strict",
+                "MemoryMax" => service.memory_max = Some(value),
+                "CPUQuota" => service.cpu_quota = Some(value),
+                "TasksMax" => service.tasks_max = value.parse().ok(),
+                _ => {}
+            }
+        }
+        service
+    }
+
+    /// Settings of the installed unit, if any.
+    pub fn installed() -> Option<Self> {
+        std::fs::read_to_string(UNIT_PATH)
+            .ok()
+            .map(|unit| Self::from_unit(&unit))
+    }
+
+    fn runs_as_root(&self) -> bool {
+        self.user.as_deref().is_none_or(|u| u == "root")
+    }
+}
 
 /// Whether systemd service installation is possible (systemd present + root).
 pub fn is_available() -> bool {
     is_systemd_available() && is_root()
 }
 
-/// Install aether-proxy as a systemd service.  Must be run as root.
-pub fn install_service(config_path: &Path) -> anyhow::Result<()> {
-    if !is_systemd_available() {
-        anyhow::bail!("systemd not available");
-    }
-    if !is_root() {
-        anyhow::bail!("root required, use: sudo ./aether-proxy setup");
-    }
+/// What [`install_service`] would write for a config file.
+#[derive(Debug, Clone)]
+pub struct InstallPlan {
+    pub unit_path: &'static str,
+    /// Absolute path of the running binary (`ExecStart=`).
+    pub exe: String,
+    /// Absolute config path (`AETHER_PROXY_CONFIG=`).
+    pub config: String,
+    pub working_dir: String,
+    /// The config file's `[service]` table.
+    pub service: ServiceConfig,
+}
 
+/// Resolve the paths and `[service]` settings for installing with
+/// `config_path`, without touching the system.
+pub fn plan_install(config_path: &Path) -> anyhow::Result<InstallPlan> {
     let exe_path = std::env::current_exe()?.canonicalize()?;
-    let exe_str = exe_path
+    let exe = exe_path
         .to_str()
         .ok_or_else(|| anyhow::anyhow!("binary path contains invalid UTF-8"))?;
 
     let config_abs = std::fs::canonicalize(config_path)?;
-    let config_str = config_abs
+    let config = config_abs
         .to_str()
         .ok_or_else(|| anyhow::anyhow!("config path contains invalid UTF-8"))?;
 
@@ -40,6 +149,44 @@ pub fn install_service(config_path: &Path) -> anyhow::Result<()> {
         .to_str()
         .unwrap_or("/");
 
+    let file = ConfigFile::load_profile(&config_abs, None)?;
+    file.service.validate()?;
+    Ok(InstallPlan {
+        unit_path: UNIT_PATH,
+        exe: exe.to_string(),
+        config: config.to_string(),
+        working_dir: working_dir.to_string(),
+        service: file.service,
+    })
+}
+
+/// Install aether-proxy as a systemd service.  Must be run as root.
+///
+/// The unit's user, hardening and resource limits come from the config
+/// file's `[service]` table.
+pub fn install_service(config_path: &Path) -> anyhow::Result<()> {
+    if !is_systemd_available() {
+        anyhow::bail!("systemd not available");
+    }
+    if !is_root() {
+        anyhow::bail!("root required, use: sudo ./aether-proxy setup");
+    }
+
+    let plan = plan_install(config_path)?;
+    let (exe_str, config_str, working_dir) = (
+        plan.exe.as_str(),
+        plan.config.as_str(),
+        plan.working_dir.as_str(),
+    );
+    let service = &plan.service;
+    if let Some(user) = service.user.as_deref().filter(|_| !service.runs_as_root()) {
+        let (uid, gid) = ensure_user(user)?;
+        let file = ConfigFile::load_profile(Path::new(config_str), None)?;
+        for warning in access_warnings(&file, Path::new(config_str), uid, gid) {
+            eprintln!("  Warning: user '{}' {}", user, warning);
+        }
+    }
+
     // Stop existing service if running (ignore errors)
     if Path::new(UNIT_PATH).exists() {
         eprintln!("  Stopping existing service...");
@@ -53,26 +200,12 @@ pub fn install_service(config_path: &Path) -> anyhow::Result<()> {
     eprintln!("    Binary:  {}", exe_str);
     eprintln!("    Config:  {}", config_str);
     eprintln!("    WorkDir: {}", working_dir);
+    if let Some(user) = &service.user {
+        eprintln!("    User:    {}", user);
+    }
 
-    let unit_content = format!(
-        "[Unit]\n\
-         Description=Aether Proxy\n\
-         After=network.target\n\
-         \n\
-         [Service]\n\
-         Type=simple\n\
-         WorkingDirectory={working_dir}\n\
-         Environment=AETHER_PROXY_CONFIG={config_str}\n\
-         ExecStart={exe_str}\n\
-         Restart=on-failure\n\
-         RestartSec=5\n\
-         LimitNOFILE=65535\n\
-         UMask=0077\n\
-         \n\
-         [Install]\n\
-         WantedBy=multi-user.target\n",
-    );
-    std::fs::write(UNIT_PATH, &unit_content)?;
+    let unit_content = render_unit(exe_str, config_str, working_dir, service);
+    std::fs::write(plan.unit_path, &unit_content)?;
 
     // Reload and enable
     eprintln!("  Enabling and starting service...");
@@ -104,7 +237,163 @@ pub fn install_service(config_path: &Path) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn is_systemd_available() -> bool {
+/// The unit file for `exe` running with `config`.
+pub(crate) fn render_unit(
+    exe: &str,
+    config: &str,
+    working_dir: &str,
+    service: &ServiceConfig,
+) -> String {
+    let mut unit = String::from(
+        "[Unit]\n\
+         Description=Aether Proxy\n\
+         After=network.target\n\
+         \n\
+         [Service]\n\
+         Type=simple\n",
+    );
+    if !service.runs_as_root() {
+        let _ = writeln!(unit, "User={}", service.user.as_deref().unwrap_or_default());
+    }
+    let _ = write!(
+        unit,
+        "WorkingDirectory={working_dir}\n\
+         Environment=AETHER_PROXY_CONFIG={config}\n\
+         ExecStart={exe}\n\
+         Restart=on-failure\n\
+         RestartSec=5\n\
+         LimitNOFILE=65535\n\
+         UMask=0077\n",
+    );
+    if service.hardening {
+        // The config dir holds the state file and persisted remote config.
+        let _ = write!(
+            unit,
+            "NoNewPrivileges=true\n\
+             ProtectSystem=strict\n\
+             ReadWritePaths={working_dir}\n\
+             PrivateTmp=true\n",
+        );
+    }
+    if let Some(memory) = &service.memory_max {
+        let _ = writeln!(unit, "MemoryMax={memory}");
+    }
+    if let Some(quota) = &service.cpu_quota {
+        let _ = writeln!(unit, "CPUQuota={quota}");
+    }
+    if let Some(tasks) = service.tasks_max {
+        let _ = writeln!(unit, "TasksMax={tasks}");
+    }
+    unit.push_str(
+        "\n\
+         [Install]\n\
+         WantedBy=multi-user.target\n",
+    );
+    unit
+}
+
+/// uid/gid of `user`, creating it as a system user if it doesn't exist.
+fn ensure_user(user: &str) -> anyhow::Result<(u32, u32)> {
+    if let Some(ids) = lookup_user(user) {
+        return Ok(ids);
+    }
+    eprintln!("  Creating system user '{}'...", user);
+    run_cmd(
+        "useradd",
+        &[
+            "--system",
+            "--no-create-home",
+            "--shell",
+            "/usr/sbin/nologin",
+            user,
+        ],
+    )?;
+    lookup_user(user).ok_or_else(|| anyhow::anyhow!("user '{}' not found after useradd", user))
+}
+
+fn lookup_user(user: &str) -> Option<(u32, u32)> {
+    #[cfg(unix)]
+    {
+        let name = std::ffi::CString::new(user).ok()?;
+        // SAFETY: `name` is a valid C string; the returned record is only
+        // read before any other getpw* call on this thread.
+        unsafe {
+            let pw = libc::getpwnam(name.as_ptr());
+            (!pw.is_null()).then(|| ((*pw).pw_uid, (*pw).pw_gid))
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = user;
+        None
+    }
+}
+
+/// Paths the service needs that `uid`/`gid` can't reach: the config file,
+/// its (writable) directory and any configured CA files.
+fn access_warnings(file: &ConfigFile, config: &Path, uid: u32, gid: u32) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut readable = vec![config.to_path_buf()];
+    readable.extend(
+        [&file.tunnel_extra_ca_file, &file.upstream_extra_ca_file]
+            .into_iter()
+            .flatten()
+            .chain(file.servers.iter().filter_map(|s| s.tls_ca_cert.as_ref()))
+            .filter(|p| !p.is_empty())
+            .map(std::path::PathBuf::from),
+    );
+    for path in &readable {
+        if !can_access(path, uid, gid, 0o4) {
+            warnings.push(format!("cannot read {}", path.display()));
+        }
+    }
+    if let Some(dir) = config.parent() {
+        if !can_access(dir, uid, gid, 0o2) {
+            warnings.push(format!(
+                "cannot write {} (state file and persisted remote config)",
+                dir.display()
+            ));
+        }
+    }
+    warnings
+}
+
+/// Whether `uid`/`gid` has `want` (an `rwx` bit, `0o4`/`0o2`/`0o1`) on
+/// `path` and search permission on every parent directory.  Supplementary
+/// groups and ACLs are not considered.
+fn can_access(path: &Path, uid: u32, gid: u32, want: u32) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let allowed = |path: &Path, want: u32| match std::fs::metadata(path) {
+            Ok(meta) => {
+                let shift = if meta.uid() == uid {
+                    6
+                } else if meta.gid() == gid {
+                    3
+                } else {
+                    0
+                };
+                (meta.mode() >> shift) & want != 0
+            }
+            Err(_) => false,
+        };
+        uid == 0
+            || (allowed(path, want)
+                && path
+                    .ancestors()
+                    .skip(1)
+                    .filter(|p| !p.as_os_str().is_empty())
+                    .all(|dir| allowed(dir, 0o1)))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, uid, gid, want);
+        true
+    }
+}
+
+pub fn is_systemd_available() -> bool {
     Command::new("systemctl")
         .arg("--version")
         .stdout(std::process::Stdio::null())
@@ -180,7 +469,14 @@ fn ensure_root_and_service() -> anyhow::Result<()> {
 }
 
 /// `aether-proxy status` -- show service status.
+///
+/// Also prints why the previous instance stopped, if a shutdown report
+/// was recorded.
 pub fn cmd_status() -> anyhow::Result<()> {
+    if let Some(report) = crate::state_file::StateFile::load().last_shutdown {
+        eprintln!();
+        report.print();
+    }
     ensure_service_installed()?;
     let status = Command::new("systemctl")
         .args(["status", SERVICE_NAME])
@@ -189,15 +485,58 @@ pub fn cmd_status() -> anyhow::Result<()> {
     std::process::exit(status.code().unwrap_or(1));
 }
 
-/// `aether-proxy logs` -- tail service logs.
-pub fn cmd_logs() -> anyhow::Result<()> {
-    ensure_service_installed()?;
-    let status = Command::new("journalctl")
-        .args(["-u", SERVICE_NAME, "-f", "--no-pager", "-n", "100"])
-        .status()?;
+/// `aether-proxy status` without systemd -- report whether the process in
+/// the PID file (`pid_file`) is alive.
+pub fn cmd_status_pid_file(pid_file: Option<&Path>) -> anyhow::Result<()> {
+    if let Some(report) = crate::state_file::StateFile::load().last_shutdown {
+        eprintln!();
+        report.print();
+    }
+    let Some(path) = pid_file else {
+        anyhow::bail!("systemd is not available; set pid_file to track the proxy process");
+    };
+    match crate::pid_file::running_pid(path) {
+        Some(pid) => {
+            println!("running (PID {pid})");
+            Ok(())
+        }
+        None => {
+            println!("stopped");
+            std::process::exit(3);
+        }
+    }
+}
+
+/// `aether-proxy logs` -- tail service logs from journald, or from
+/// `log_file` when stderr logging is off (journald then only has startup
+/// output) or there is no installed service or no journalctl.
+pub fn cmd_logs(log_file: Option<&Path>, log_stderr: bool) -> anyhow::Result<()> {
+    let journald = log_stderr && is_installed() && is_journald_available();
+    let status = match log_file {
+        Some(path) if !journald => Command::new("tail")
+            .args(["-F", "-n", "100"])
+            .arg(path)
+            .status()?,
+        _ => {
+            ensure_service_installed()?;
+            Command::new("journalctl")
+                .args(["-u", SERVICE_NAME, "-f", "--no-pager", "-n", "100"])
+                .status()?
+        }
+    };
     std::process::exit(status.code().unwrap_or(1));
 }
 
+fn is_journald_available() -> bool {
+    Command::new("journalctl")
+        .arg("--version")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
 /// `aether-proxy start` -- start the service.
 pub fn cmd_start() -> anyhow::Result<()> {
     ensure_root_and_service()?;
@@ -254,3 +593,90 @@ pub(crate) fn run_cmd(program: &str, args: &[&str]) -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(service: &ServiceConfig) -> String {
+        render_unit(
+            "/opt/aether/aether-proxy",
+            "/opt/aether/aether-proxy.toml",
+            "/opt/aether",
+            service,
+        )
+    }
+
+    #[test]
+    fn default_unit_runs_as_root_without_sandboxing() {
+        let unit = render(&ServiceConfig::default());
+        assert!(unit.contains("ExecStart=/opt/aether/aether-proxy\n"));
+        assert!(unit.contains("UMask=0077\n"));
+        assert!(unit.ends_with("[Install]\nWantedBy=multi-user.target\n"));
+        for directive in [
+            "User=",
+            "ProtectSystem=",
+            "MemoryMax=",
+            "CPUQuota=",
+            "TasksMax=",
+        ] {
+            assert!(!unit.contains(directive), "unexpected {directive}");
+        }
+
+        let root = ServiceConfig {
+            user: Some("root".into()),
+            ..ServiceConfig::default()
+        };
+        assert!(!render(&root).contains("User="));
+    }
+
+    #[test]
+    fn unit_renders_user_hardening_and_limits() {
+        let service = ServiceConfig {
+            user: Some("aether".into()),
+            hardening: true,
+            memory_max: Some("512M".into()),
+            cpu_quota: Some("200%".into()),
+            tasks_max: Some(256),
+        };
+        let unit = render(&service);
+        assert!(unit.contains("[Service]\nType=simple\nUser=aether\n"));
+        assert!(unit.contains(
+            "NoNewPrivileges=true\nProtectSystem=strict\nReadWritePaths=/opt/aether\nPrivateTmp=true\n"
+        ));
+        assert!(unit.contains("MemoryMax=512M\nCPUQuota=200%\nTasksMax=256\n"));
+        // Everything stays in [Service].
+        assert!(unit.find("TasksMax").unwrap() < unit.find("[Install]").unwrap());
+
+        // A reinstall reads the same settings back.
+        assert_eq!(ServiceConfig::from_unit(&unit), service);
+    }
+
+    #[test]
+    fn unit_renders_limits_without_hardening() {
+        let service = ServiceConfig {
+            cpu_quota: Some("50%".into()),
+            ..ServiceConfig::default()
+        };
+        let unit = render(&service);
+        assert!(unit.contains("CPUQuota=50%\n"));
+        assert!(!unit.contains("ProtectSystem"));
+        assert_eq!(ServiceConfig::from_unit(&unit), service);
+    }
+
+    #[test]
+    fn invalid_service_values_are_rejected() {
+        let with = |f: fn(&mut ServiceConfig)| {
+            let mut service = ServiceConfig::default();
+            f(&mut service);
+            service.validate()
+        };
+        assert!(with(|s| s.memory_max = Some("1G".into())).is_ok());
+        assert!(with(|s| s.memory_max = Some("infinity".into())).is_ok());
+        assert!(with(|s| s.memory_max = Some("1GB".into())).is_err());
+        assert!(with(|s| s.memory_max = Some("1G\nUser=root".into())).is_err());
+        assert!(with(|s| s.cpu_quota = Some("150".into())).is_err());
+        assert!(with(|s| s.user = Some("bad user".into())).is_err());
+        assert!(with(|s| s.tasks_max = Some(0)).is_err());
+    }
+}
@@ -0,0 +1,287 @@
+//! Post-save status panel for the setup TUI.
+//!
+//! After a save that hands off to `SetupOutcome::ReadyToRun`, the TUI stays
+//! up and registers each configured server, then opens a tunnel to it, so
+//! a bad token or an unreachable tunnel endpoint shows up before the
+//! terminal fills with logs.  Registration is an upsert, so the proxy
+//! started afterwards reuses the node.
+
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::backend::CrosstermBackend;
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::Terminal;
+
+use crate::config::{Config, ConfigFile, ServerEntry};
+use crate::registration::client::AetherClient;
+use crate::state_file::StateFile;
+use crate::tunnel::client;
+use crate::{hardware, net, tls};
+
+/// Upper bound for registering and opening the tunnel to one server.
+const SERVER_TIMEOUT: Duration = Duration::from_secs(20);
+/// How long to wait for the tunnel's Pong.
+const PONG_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the final status stays on screen (any key skips it).
+const HOLD: Duration = Duration::from_secs(4);
+
+/// Where one server's check has got to.
+#[derive(Clone, Debug, PartialEq)]
+enum Stage {
+    Connecting,
+    Registered { node_id: String },
+    TunnelUp { node_id: String, rtt: Duration },
+    Failed(String),
+}
+
+impl Stage {
+    fn is_done(&self) -> bool {
+        matches!(self, Stage::TunnelUp { .. } | Stage::Failed(_))
+    }
+
+    fn line(&self) -> (String, Color) {
+        match self {
+            Stage::Connecting => ("… connecting".into(), Color::Yellow),
+            Stage::Registered { node_id } => (
+                format!("… registered (node_id={}), opening tunnel", node_id),
+                Color::Yellow,
+            ),
+            Stage::TunnelUp { node_id, rtt } => (
+                format!(
+                    "✓ tunnel up (node_id={}, {:.1} ms)",
+                    node_id,
+                    rtt.as_secs_f64() * 1000.0
+                ),
+                Color::Green,
+            ),
+            Stage::Failed(reason) => (format!("✗ {}", reason), Color::Red),
+        }
+    }
+}
+
+struct ServerStatus {
+    url: String,
+    stage: Stage,
+}
+
+type Statuses = Arc<Mutex<Vec<ServerStatus>>>;
+
+/// Register with every server in the saved config and show the progress
+/// until all checks finish plus `HOLD`, or until a key is pressed.  Does
+/// nothing outside a Tokio runtime.
+pub(super) fn show(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    config_path: &Path,
+) -> anyhow::Result<()> {
+    let Ok(handle) = tokio::runtime::Handle::try_current() else {
+        return Ok(());
+    };
+    let (config, servers) = match load(config_path) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            let statuses = Arc::new(Mutex::new(vec![ServerStatus {
+                url: config_path.display().to_string(),
+                stage: Stage::Failed(format!("{:#}", e)),
+            }]));
+            return status_loop(terminal, &statuses);
+        }
+    };
+
+    let statuses: Statuses = Arc::new(Mutex::new(
+        servers
+            .iter()
+            .map(|server| ServerStatus {
+                url: server.aether_url.clone(),
+                stage: Stage::Connecting,
+            })
+            .collect(),
+    ));
+    let task_statuses = Arc::clone(&statuses);
+    let task = handle.spawn(async move {
+        let config = Arc::new(config);
+        let public_ip = Arc::new(public_ip(&config).await);
+        let checks: Vec<_> = servers
+            .into_iter()
+            .enumerate()
+            .map(|(i, server)| {
+                let config = Arc::clone(&config);
+                let public_ip = Arc::clone(&public_ip);
+                let statuses = Arc::clone(&task_statuses);
+                tokio::spawn(async move {
+                    let set = |stage| statuses.lock().unwrap()[i].stage = stage;
+                    let result = tokio::time::timeout(
+                        SERVER_TIMEOUT,
+                        check_server(&config, &server, &public_ip, &set),
+                    )
+                    .await;
+                    match result {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => set(Stage::Failed(format!("{:#}", e))),
+                        Err(_) => set(Stage::Failed(format!(
+                            "timed out after {}s",
+                            SERVER_TIMEOUT.as_secs()
+                        ))),
+                    }
+                })
+            })
+            .collect();
+        for check in checks {
+            let _ = check.await;
+        }
+    });
+
+    let result = status_loop(terminal, &statuses);
+    task.abort();
+    result
+}
+
+/// The effective config as the proxy will see it after the handoff.
+fn load(config_path: &Path) -> anyhow::Result<(Config, Vec<ServerEntry>)> {
+    let file_cfg = ConfigFile::load(config_path)?;
+    file_cfg.inject_env_override();
+    let config = Config::try_parse_from(["aether-proxy"])
+        .map_err(|e| anyhow::anyhow!("config invalid: {}", e))?;
+    config.validate()?;
+    let servers = config.servers(Some(&file_cfg));
+    if servers.is_empty() {
+        anyhow::bail!("no servers configured");
+    }
+    Ok((config, servers))
+}
+
+async fn public_ip(config: &Config) -> String {
+    match &config.public_ip {
+        Some(ip) => ip.clone(),
+        None if config.disable_ip_detection => String::new(),
+        None => net::detect_public_ip()
+            .await
+            .unwrap_or_else(|_| "0.0.0.0".to_string()),
+    }
+}
+
+/// Register with `server`, then open a tunnel and wait for one Pong.
+async fn check_server(
+    config: &Config,
+    server: &ServerEntry,
+    public_ip: &str,
+    set: &impl Fn(Stage),
+) -> anyhow::Result<()> {
+    let roots = tls::load_root_store(
+        config.tunnel_tls_roots,
+        config.tunnel_extra_ca_file.as_deref(),
+    )?;
+    let shared = Arc::new(client::build_tls_config(roots.clone()));
+    let tls_config = client::server_tls_config(&shared, &roots, server)?;
+
+    let node_name = server
+        .node_name
+        .clone()
+        .unwrap_or_else(|| config.node_name.clone());
+    let node_region = server
+        .node_region
+        .clone()
+        .or_else(|| config.node_region.clone());
+    let aether = AetherClient::new(
+        config,
+        &server.aether_url,
+        &server.management_token,
+        &tls_config,
+        StateFile::client_instance_id(&server.aether_url, &node_name),
+    );
+    let node_id = aether
+        .register(
+            config,
+            &node_name,
+            node_region.as_deref(),
+            &config.node_tags_for(server),
+            public_ip,
+            Some(&hardware::collect()),
+        )
+        .await
+        .map_err(|e| anyhow::anyhow!("registration failed: {:#}", e))?;
+    set(Stage::Registered {
+        node_id: node_id.clone(),
+    });
+
+    let mut rtt = None;
+    client::ping(
+        config,
+        &tls_config,
+        server,
+        &node_id,
+        1,
+        Duration::ZERO,
+        PONG_TIMEOUT,
+        |_, reply| rtt = reply,
+    )
+    .await
+    .map_err(|e| anyhow::anyhow!("tunnel failed: {:#}", e))?;
+    match rtt {
+        Some(rtt) => set(Stage::TunnelUp { node_id, rtt }),
+        None => set(Stage::Failed("tunnel opened but no Pong arrived".into())),
+    }
+    Ok(())
+}
+
+fn status_loop(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    statuses: &Statuses,
+) -> anyhow::Result<()> {
+    let mut done_at: Option<Instant> = None;
+    loop {
+        let all_done = statuses.lock().unwrap().iter().all(|s| s.stage.is_done());
+        if all_done && done_at.is_none() {
+            done_at = Some(Instant::now());
+        }
+        if done_at.is_some_and(|at| at.elapsed() >= HOLD) {
+            return Ok(());
+        }
+
+        terminal.draw(|f| {
+            let statuses = statuses.lock().unwrap();
+            let mut lines = vec![Line::raw("")];
+            for status in statuses.iter() {
+                let (text, color) = status.stage.line();
+                lines.push(Line::from(vec![
+                    Span::styled(
+                        format!("  {}  ", status.url),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ),
+                    Span::styled(text, Style::default().fg(color)),
+                ]));
+            }
+            lines.push(Line::raw(""));
+            lines.push(Line::styled(
+                if all_done {
+                    "  Starting proxy... (any key to continue now)"
+                } else {
+                    "  Checking... (q to skip)"
+                },
+                Style::default().fg(Color::DarkGray),
+            ));
+
+            let panel = Paragraph::new(lines).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(" aether-proxy status "),
+            );
+            f.render_widget(panel, f.area());
+        })?;
+
+        if event::poll(Duration::from_millis(200))? {
+            if let Event::Key(key) = event::read()? {
+                let skip = matches!(key.code, KeyCode::Char('q') | KeyCode::Esc);
+                if key.kind == KeyEventKind::Press && (all_done || skip) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
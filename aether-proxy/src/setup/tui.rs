@@ -5,8 +5,12 @@
 //! save to a TOML config file.  Supports multi-server configuration via
 //! a tabbed interface.
 
+use std::collections::BTreeMap;
 use std::io;
+use std::net::IpAddr;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
@@ -16,11 +20,13 @@ use ratatui::backend::CrosstermBackend;
 use ratatui::layout::{Constraint, Layout, Rect};
 use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Line, Span};
-use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::widgets::{Block, Borders, Clear, Paragraph};
 use ratatui::Frame;
 use ratatui::Terminal;
 
-use crate::config::{ConfigFile, ServerEntry};
+use crate::config::{self, ConfigFile, ServerEntry, ServerRole};
+
+use super::service::{InstallPlan, ServiceConfig};
 
 /// Outcome of the setup wizard, returned to the caller.
 pub enum SetupOutcome {
@@ -35,6 +41,17 @@ pub enum SetupOutcome {
 /// Column width reserved for the field label (chars).
 const LABEL_WIDTH: usize = 22;
 
+/// Connectivity probe result encoding (see `App::connectivity_results`):
+/// `0` while pending, `latency_ms + 1` on success, or `PROBE_FAILED | reason`.
+const PROBE_PENDING: u64 = 0;
+const PROBE_FAILED: u64 = 1 << 63;
+const PROBE_REFUSED: u64 = PROBE_FAILED | 1;
+const PROBE_TIMEOUT: u64 = PROBE_FAILED | 2;
+const PROBE_UNREACHABLE: u64 = PROBE_FAILED | 3;
+const PROBE_ERROR: u64 = PROBE_FAILED | 4;
+/// Per-server timeout for the post-save connectivity probe.
+const PROBE_TIMEOUT_SECS: u64 = 5;
+
 // -- Field types --------------------------------------------------------------
 
 #[derive(Clone, Copy, PartialEq)]
@@ -58,6 +75,14 @@ struct Field {
 /// A single server tab's editable fields.
 struct ServerTab {
     fields: Vec<Field>,
+    /// `node_tags`, `role`, the TLS overrides and `bind_address` from the
+    /// loaded entry, preserved on save.
+    node_tags: BTreeMap<String, String>,
+    role: ServerRole,
+    tls_sni_override: Option<String>,
+    tls_ca_cert: Option<String>,
+    tls_ca_only_custom: bool,
+    bind_address: Option<IpAddr>,
 }
 
 impl ServerTab {
@@ -88,7 +113,21 @@ impl ServerTab {
                     required: true,
                     help: "Node name for identification in Aether dashboard",
                 },
+                Field {
+                    label: "Node Region",
+                    key: "node_region",
+                    value: String::new(),
+                    kind: FieldKind::Text,
+                    required: false,
+                    help: "Region for this server (e.g. ap-northeast-1), blank for global",
+                },
             ],
+            node_tags: BTreeMap::new(),
+            role: ServerRole::Primary,
+            tls_sni_override: None,
+            tls_ca_cert: None,
+            tls_ca_only_custom: false,
+            bind_address: None,
         }
     }
 
@@ -99,6 +138,15 @@ impl ServerTab {
         if let Some(ref name) = entry.node_name {
             tab.fields[2].value = name.clone();
         }
+        if let Some(ref region) = entry.node_region {
+            tab.fields[3].value = region.clone();
+        }
+        tab.node_tags = entry.node_tags.clone();
+        tab.role = entry.role;
+        tab.tls_sni_override = entry.tls_sni_override.clone();
+        tab.tls_ca_cert = entry.tls_ca_cert.clone();
+        tab.tls_ca_only_custom = entry.tls_ca_only_custom;
+        tab.bind_address = entry.bind_address;
         tab
     }
 }
@@ -126,6 +174,12 @@ struct App {
     saved_once: bool,
     pending_quit: bool,
     confirm_delete: bool,
+    /// Duplicate servers were reported on the last ^S; another ^S saves
+    /// them anyway.
+    confirm_duplicates: bool,
+    /// Live results of the post-save connectivity check, one per server tab,
+    /// written by background probe tasks and polled during rendering.
+    connectivity_results: Vec<Arc<AtomicU64>>,
 }
 impl App {
     fn new(config_path: PathBuf) -> Self {
@@ -149,6 +203,14 @@ impl App {
                     required: true,
                     help: "Output logs as JSON -- Enter to toggle",
                 },
+                Field {
+                    label: "Log File",
+                    key: "log_file",
+                    value: String::new(),
+                    kind: FieldKind::Text,
+                    required: false,
+                    help: "Also write logs to this file (rotated at 100M, 5 kept), blank for none",
+                },
                 Field {
                     label: "Install Service",
                     key: "install_service",
@@ -162,6 +224,46 @@ impl App {
                     required: true,
                     help: "Install as systemd service (requires root) -- Enter to toggle",
                 },
+                Field {
+                    label: "Service User",
+                    key: "service_user",
+                    value: String::new(),
+                    kind: FieldKind::Text,
+                    required: false,
+                    help: "Run the service as this user (created if missing), blank for root",
+                },
+                Field {
+                    label: "Service Hardening",
+                    key: "service_hardening",
+                    value: "false".into(),
+                    kind: FieldKind::Bool,
+                    required: true,
+                    help: "NoNewPrivileges, ProtectSystem=strict, PrivateTmp -- Enter to toggle",
+                },
+                Field {
+                    label: "Memory Max",
+                    key: "service_memory_max",
+                    value: String::new(),
+                    kind: FieldKind::Text,
+                    required: false,
+                    help: "systemd MemoryMax for the service (e.g. 512M), blank for no limit",
+                },
+                Field {
+                    label: "CPU Quota",
+                    key: "service_cpu_quota",
+                    value: String::new(),
+                    kind: FieldKind::Text,
+                    required: false,
+                    help: "systemd CPUQuota for the service (e.g. 200%), blank for no limit",
+                },
+                Field {
+                    label: "Tasks Max",
+                    key: "service_tasks_max",
+                    value: String::new(),
+                    kind: FieldKind::Text,
+                    required: false,
+                    help: "systemd TasksMax for the service, blank for the default",
+                },
             ],
             selected: 0,
             mode: Mode::Normal,
@@ -174,6 +276,8 @@ impl App {
             saved_once: false,
             pending_quit: false,
             confirm_delete: false,
+            confirm_duplicates: false,
+            connectivity_results: Vec::new(),
         }
     }
 
@@ -216,17 +320,51 @@ impl App {
     // -- Config <-> fields -----------------------------------------------------
 
     fn load_from_file(&mut self) {
-        if let Ok(cfg) = ConfigFile::load(&self.config_path) {
+        // Edit the base file's own keys; includes and profiles are kept as-is.
+        if let Ok(cfg) = ConfigFile::load_own(&self.config_path) {
             self.apply_config(&cfg);
+            self.warn_duplicates(&cfg);
         }
     }
 
+    /// Show a status line warning if `cfg` has servers that would register
+    /// the same node.  Returns whether it did.
+    fn warn_duplicates(&mut self, cfg: &ConfigFile) -> bool {
+        let Some(&(i, j)) = cfg.duplicate_servers().first() else {
+            return false;
+        };
+        self.message = Some((
+            format!(
+                "servers {} and {} have the same Aether URL and node name",
+                i + 1,
+                j + 1
+            ),
+            Instant::now(),
+            true,
+        ));
+        true
+    }
+
     fn apply_config(&mut self, cfg: &ConfigFile) {
+        // Service settings not in the file yet are taken from the installed
+        // unit, so a reinstall keeps them.
+        let service = if cfg.service.is_empty() {
+            ServiceConfig::installed().unwrap_or_default()
+        } else {
+            cfg.service.clone()
+        };
+
         // Global fields
         for field in &mut self.global_fields {
             let val: Option<String> = match field.key {
                 "log_level" => cfg.log_level.clone(),
                 "log_json" => cfg.log_json.map(|v| v.to_string()),
+                "log_file" => cfg.log_file.clone(),
+                "service_user" => service.user.clone(),
+                "service_hardening" => Some(service.hardening.to_string()),
+                "service_memory_max" => service.memory_max.clone(),
+                "service_cpu_quota" => service.cpu_quota.clone(),
+                "service_tasks_max" => service.tasks_max.map(|v| v.to_string()),
                 _ => None,
             };
             if let Some(v) = val {
@@ -257,7 +395,10 @@ impl App {
         self.scroll_offset = 0;
     }
 
-    fn to_config(&self) -> ConfigFile {
+    /// The edited settings applied over `base`, the config file as last
+    /// read.  Keys the TUI doesn't edit (`include`, profiles, global
+    /// `node_tags`, ...) are kept from `base`.
+    fn to_config(&self, base: ConfigFile) -> ConfigFile {
         let get_global = |key: &str| -> Option<String> {
             self.global_fields
                 .iter()
@@ -277,7 +418,17 @@ impl App {
         let mut cfg = ConfigFile {
             log_level: get_global("log_level"),
             log_json: get_global("log_json").and_then(|v| v.parse().ok()),
-            ..ConfigFile::default()
+            log_file: get_global("log_file"),
+            service: ServiceConfig {
+                user: get_global("service_user"),
+                hardening: get_global("service_hardening").as_deref() == Some("true"),
+                memory_max: get_global("service_memory_max"),
+                cpu_quota: get_global("service_cpu_quota"),
+                tasks_max: get_global("service_tasks_max").and_then(|v| v.parse().ok()),
+            },
+            aether_url: None,
+            management_token: None,
+            ..base
         };
 
         // Always write [[servers]] format; old top-level fields are read-only compat
@@ -288,14 +439,50 @@ impl App {
                 aether_url: get_tab(tab, "aether_url").unwrap_or_default(),
                 management_token: get_tab(tab, "management_token").unwrap_or_default(),
                 node_name: get_tab(tab, "node_name"),
+                node_region: get_tab(tab, "node_region"),
+                node_tags: tab.node_tags.clone(),
+                role: tab.role,
+                tls_sni_override: tab.tls_sni_override.clone(),
+                tls_ca_cert: tab.tls_ca_cert.clone(),
+                tls_ca_only_custom: tab.tls_ca_only_custom,
+                bind_address: tab.bind_address,
             })
             .collect();
         cfg
     }
 
     fn save(&mut self) -> anyhow::Result<()> {
-        let cfg = self.to_config();
-        cfg.save(&self.config_path)?;
+        let cfg = self.to_config(ConfigFile::default());
+        let tasks_max = self
+            .global_fields
+            .iter()
+            .find(|f| f.key == "service_tasks_max")
+            .map_or("", |f| f.value.as_str());
+        if !tasks_max.is_empty() && tasks_max.parse::<u64>().is_err() {
+            anyhow::bail!("tasks max must be a number: {}", tasks_max);
+        }
+        cfg.service.validate()?;
+        if !self.confirm_duplicates && !cfg.duplicate_servers().is_empty() {
+            // Both entries would register the same node and fight over it.
+            self.warn_duplicates(&cfg);
+            if let Some((msg, _, _)) = &mut self.message {
+                msg.push_str(" -- ^S again to save anyway");
+            }
+            self.confirm_duplicates = true;
+            return Ok(());
+        }
+        self.confirm_duplicates = false;
+        {
+            // Re-read under the lock so changes made since the TUI loaded
+            // the file (e.g. `servers add`, persisted remote config) survive.
+            let _lock = config::lock_config(&self.config_path)?;
+            let current = if self.config_path.exists() {
+                ConfigFile::load_own(&self.config_path)?
+            } else {
+                ConfigFile::default()
+            };
+            self.to_config(current).save(&self.config_path)?;
+        }
         // Restrict config file permissions to owner-only (contains management token).
         #[cfg(unix)]
         {
@@ -310,8 +497,64 @@ impl App {
             Instant::now(),
             false,
         ));
+        self.start_connectivity_check();
         Ok(())
     }
+
+    /// Move the active server tab one place left or right, keeping it active.
+    /// Tab order decides the `server-N` labels and which server fills the
+    /// top-level fallback fields.
+    fn move_active_tab(&mut self, forward: bool) {
+        let from = self.active_tab;
+        let to = if forward {
+            from + 1
+        } else {
+            match from.checked_sub(1) {
+                Some(to) => to,
+                None => return,
+            }
+        };
+        if to >= self.server_tabs.len() {
+            return;
+        }
+        self.server_tabs.swap(from, to);
+        if to < self.connectivity_results.len() {
+            self.connectivity_results.swap(from, to);
+        }
+        self.active_tab = to;
+        self.modified = true;
+        self.message = Some((
+            format!("server {} moved to position {}", from + 1, to + 1),
+            Instant::now(),
+            false,
+        ));
+    }
+
+    /// Probe each configured server in the background; results are shown in
+    /// the footer as they arrive.
+    fn start_connectivity_check(&mut self) {
+        let Ok(handle) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        self.connectivity_results = self
+            .server_tabs
+            .iter()
+            .map(|tab| {
+                let slot = Arc::new(AtomicU64::new(PROBE_PENDING));
+                let url = tab
+                    .fields
+                    .iter()
+                    .find(|f| f.key == "aether_url")
+                    .map(|f| f.value.clone())
+                    .unwrap_or_default();
+                let result = Arc::clone(&slot);
+                handle.spawn(async move {
+                    result.store(probe_server(&url).await, Ordering::Release);
+                });
+                slot
+            })
+            .collect();
+    }
     // -- Scrolling ---------------------------------------------------------------
 
     fn ensure_visible(&mut self, visible_rows: usize) {
@@ -337,7 +580,10 @@ impl App {
     fn handle_key(&mut self, key: KeyEvent) -> bool {
         // Expire old messages (but keep quit-confirmation messages alive)
         if let Some((_, when, _)) = &self.message {
-            if !self.pending_quit && !self.confirm_delete && when.elapsed() > Duration::from_secs(4)
+            if !self.pending_quit
+                && !self.confirm_delete
+                && !self.confirm_duplicates
+                && when.elapsed() > Duration::from_secs(4)
             {
                 self.message = None;
             }
@@ -379,12 +625,16 @@ impl App {
             self.confirm_delete = false;
             self.message = None;
         }
+        let is_save_key = key.code == KeyCode::Char('s')
+            && (key.modifiers.contains(KeyModifiers::CONTROL)
+                || key.modifiers.contains(KeyModifiers::SUPER));
+        if self.confirm_duplicates && !is_save_key {
+            self.confirm_duplicates = false;
+            self.message = None;
+        }
 
         match key.code {
-            KeyCode::Char('s')
-                if key.modifiers.contains(KeyModifiers::CONTROL)
-                    || key.modifiers.contains(KeyModifiers::SUPER) =>
-            {
+            KeyCode::Char('s') if is_save_key => {
                 if let Err(e) = self.save() {
                     self.message = Some((format!("error: {}", e), Instant::now(), true));
                 }
@@ -392,10 +642,8 @@ impl App {
             KeyCode::Up | KeyCode::Char('k') => {
                 self.selected = self.selected.saturating_sub(1);
             }
-            KeyCode::Down | KeyCode::Char('j') => {
-                if self.selected + 1 < self.total_field_count() {
-                    self.selected += 1;
-                }
+            KeyCode::Down | KeyCode::Char('j') if self.selected + 1 < self.total_field_count() => {
+                self.selected += 1;
             }
             KeyCode::Home => self.selected = 0,
             KeyCode::End => self.selected = self.total_field_count() - 1,
@@ -434,21 +682,24 @@ impl App {
                 }
             }
             // -- Tab navigation --
-            KeyCode::Tab => {
-                if self.server_tabs.len() > 1 {
-                    self.active_tab = (self.active_tab + 1) % self.server_tabs.len();
-                    self.clamp_selection();
-                }
+            KeyCode::Tab if self.server_tabs.len() > 1 => {
+                self.active_tab = (self.active_tab + 1) % self.server_tabs.len();
+                self.clamp_selection();
             }
-            KeyCode::BackTab => {
-                if self.server_tabs.len() > 1 {
-                    self.active_tab = if self.active_tab == 0 {
-                        self.server_tabs.len() - 1
-                    } else {
-                        self.active_tab - 1
-                    };
-                    self.clamp_selection();
-                }
+            KeyCode::BackTab if self.server_tabs.len() > 1 => {
+                self.active_tab = if self.active_tab == 0 {
+                    self.server_tabs.len() - 1
+                } else {
+                    self.active_tab - 1
+                };
+                self.clamp_selection();
+            }
+            // -- Reorder servers (the first one is the single-server fallback) --
+            KeyCode::Left if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.move_active_tab(false);
+            }
+            KeyCode::Right if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.move_active_tab(true);
             }
             KeyCode::Char(c @ '1'..='9') if !key.modifiers.contains(KeyModifiers::CONTROL) => {
                 let idx = (c as usize) - ('1' as usize);
@@ -506,22 +757,20 @@ impl App {
                     self.selected_field_mut().value = self.edit_buffer.clone();
                     self.modified = true;
                     self.mode = Mode::Normal;
+                    let cfg = self.to_config(ConfigFile::default());
+                    self.warn_duplicates(&cfg);
                 } else {
                     self.message = Some(("invalid format".into(), Instant::now(), true));
                 }
             }
-            KeyCode::Backspace => {
-                if self.edit_cursor > 0 {
-                    self.edit_cursor -= 1;
-                    let byte = self.char_byte_pos(self.edit_cursor);
-                    self.edit_buffer.remove(byte);
-                }
+            KeyCode::Backspace if self.edit_cursor > 0 => {
+                self.edit_cursor -= 1;
+                let byte = self.char_byte_pos(self.edit_cursor);
+                self.edit_buffer.remove(byte);
             }
-            KeyCode::Delete => {
-                if self.edit_cursor < self.edit_buffer.chars().count() {
-                    let byte = self.char_byte_pos(self.edit_cursor);
-                    self.edit_buffer.remove(byte);
-                }
+            KeyCode::Delete if self.edit_cursor < self.edit_buffer.chars().count() => {
+                let byte = self.char_byte_pos(self.edit_cursor);
+                self.edit_buffer.remove(byte);
             }
             KeyCode::Left => {
                 self.edit_cursor = self.edit_cursor.saturating_sub(1);
@@ -761,7 +1010,7 @@ fn render_footer(f: &mut Frame, app: &App, area: Rect) {
     let keybindings = if app.mode == Mode::Editing {
         "Enter confirm  Esc cancel"
     } else if app.server_tabs.len() > 1 {
-        "j/k select  Enter edit  Tab switch  + add  x remove  ^S save  q quit"
+        "j/k select  Enter edit  Tab switch  ^←/^→ move  + add  x remove  ^S save  q quit"
     } else {
         "j/k select  Enter edit  + add server  ^S save  q quit"
     };
@@ -778,7 +1027,7 @@ fn render_footer(f: &mut Frame, app: &App, area: Rect) {
     }
 
     let footer_text = vec![
-        Line::raw(""),
+        connectivity_line(app).unwrap_or_else(|| Line::raw("")),
         Line::from(Span::styled(
             format!(" {}", help),
             Style::default().fg(Color::DarkGray),
@@ -794,6 +1043,77 @@ fn render_footer(f: &mut Frame, app: &App, area: Rect) {
 
     f.render_widget(footer, area);
 }
+/// Status line summarizing the post-save connectivity check, if one ran.
+fn connectivity_line(app: &App) -> Option<Line<'static>> {
+    if app.connectivity_results.is_empty() {
+        return None;
+    }
+
+    let results: Vec<u64> = app
+        .connectivity_results
+        .iter()
+        .map(|r| r.load(Ordering::Acquire))
+        .collect();
+    let prefix = if results.contains(&PROBE_PENDING) {
+        " Testing connectivity..."
+    } else {
+        " Connectivity:"
+    };
+
+    let mut spans = vec![Span::styled(prefix, Style::default().fg(Color::DarkGray))];
+    for (i, result) in results.into_iter().enumerate() {
+        let (text, color) = match result {
+            PROBE_PENDING => (format!("… server-{}", i), Color::Yellow),
+            r if r & PROBE_FAILED != 0 => {
+                let reason = match r {
+                    PROBE_REFUSED => "refused",
+                    PROBE_TIMEOUT => "timeout",
+                    PROBE_UNREACHABLE => "unreachable",
+                    _ => "error",
+                };
+                (format!("✗ server-{} ({})", i, reason), Color::Red)
+            }
+            r => (format!("✓ server-{} ({}ms)", i, r - 1), Color::Green),
+        };
+        spans.push(Span::raw("  "));
+        spans.push(Span::styled(text, Style::default().fg(color)));
+    }
+    Some(Line::from(spans))
+}
+
+/// Issue a single request to the Aether server and encode the outcome.
+///
+/// Any HTTP response counts as reachable -- this only checks the network
+/// path, not the token.
+async fn probe_server(aether_url: &str) -> u64 {
+    let client = match reqwest::Client::builder()
+        .timeout(Duration::from_secs(PROBE_TIMEOUT_SECS))
+        .build()
+    {
+        Ok(c) => c,
+        Err(_) => return PROBE_ERROR,
+    };
+
+    let start = Instant::now();
+    match client.get(aether_url.trim_end_matches('/')).send().await {
+        Ok(_) => (start.elapsed().as_millis() as u64).saturating_add(1) & !PROBE_FAILED,
+        Err(e) if e.is_timeout() => PROBE_TIMEOUT,
+        Err(e) if e.is_connect() => {
+            let mut source = std::error::Error::source(&e);
+            while let Some(err) = source {
+                if let Some(io_err) = err.downcast_ref::<io::Error>() {
+                    if io_err.kind() == io::ErrorKind::ConnectionRefused {
+                        return PROBE_REFUSED;
+                    }
+                }
+                source = err.source();
+            }
+            PROBE_UNREACHABLE
+        }
+        Err(_) => PROBE_ERROR,
+    }
+}
+
 // -- Entry point --------------------------------------------------------------
 
 pub fn run(config_path: PathBuf) -> anyhow::Result<SetupOutcome> {
@@ -806,7 +1126,38 @@ pub fn run(config_path: PathBuf) -> anyhow::Result<SetupOutcome> {
     let mut app = App::new(config_path.clone());
     app.load_from_file();
 
-    let result = event_loop(&mut terminal, &mut app);
+    let mut result = event_loop(&mut terminal, &mut app);
+
+    let wants_service = app
+        .global_fields
+        .iter()
+        .find(|f| f.key == "install_service")
+        .map(|f| f.value == "true")
+        .unwrap_or(false);
+
+    // Writing a system unit is durable: show what will be installed and
+    // require an explicit "yes".  If the plan can't be resolved, let
+    // install_service report why.
+    let mut install = false;
+    if result.is_ok() && app.saved_once && wants_service {
+        install = match super::service::plan_install(&config_path) {
+            Ok(plan) => match confirm_install(&mut terminal, &mut app, &plan) {
+                Ok(confirmed) => confirmed,
+                Err(e) => {
+                    result = Err(e);
+                    false
+                }
+            },
+            Err(_) => true,
+        };
+    }
+
+    // Heading for a direct start: stay up until registration and the
+    // tunnel have been checked.  A running service would have its node
+    // taken over, so leave it alone.
+    if result.is_ok() && app.saved_once && !install && !super::service::is_service_active() {
+        result = super::status::show(&mut terminal, &config_path);
+    }
 
     terminal::disable_raw_mode()?;
     execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
@@ -824,14 +1175,7 @@ pub fn run(config_path: PathBuf) -> anyhow::Result<SetupOutcome> {
     eprintln!("  Config saved to {}", config_path.display());
     eprintln!();
 
-    let wants_service = app
-        .global_fields
-        .iter()
-        .find(|f| f.key == "install_service")
-        .map(|f| f.value == "true")
-        .unwrap_or(false);
-
-    if wants_service {
+    if install {
         match super::service::install_service(&config_path) {
             Ok(()) => return Ok(SetupOutcome::ServiceInstalled),
             Err(e) => {
@@ -839,6 +1183,8 @@ pub fn run(config_path: PathBuf) -> anyhow::Result<SetupOutcome> {
                 eprintln!("  Starting proxy directly instead.\n");
             }
         }
+    } else if wants_service {
+        eprintln!("  Service install not confirmed; starting proxy directly.\n");
     } else if super::service::is_installed() {
         if let Err(e) = super::service::uninstall_service() {
             eprintln!("  Service uninstall failed: {}", e);
@@ -849,6 +1195,113 @@ pub fn run(config_path: PathBuf) -> anyhow::Result<SetupOutcome> {
     Ok(SetupOutcome::ReadyToRun(config_path))
 }
 
+/// Modal over the form listing what installing the service writes.
+/// Returns `true` only once the user types `yes` and presses Enter.
+fn confirm_install(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    app: &mut App,
+    plan: &InstallPlan,
+) -> anyhow::Result<bool> {
+    let mut input = String::new();
+    loop {
+        terminal.draw(|f| {
+            ui(f, app);
+            render_install_confirmation(f, plan, &input);
+        })?;
+
+        if event::poll(Duration::from_millis(200))? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Esc => return Ok(false),
+                    KeyCode::Enter if input.trim().eq_ignore_ascii_case("yes") => return Ok(true),
+                    // Anything but "yes" is a no.
+                    KeyCode::Enter => return Ok(false),
+                    KeyCode::Backspace => {
+                        input.pop();
+                    }
+                    KeyCode::Char(c) if input.len() < 8 => input.push(c),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+fn render_install_confirmation(f: &mut Frame, plan: &InstallPlan, input: &str) {
+    let service = &plan.service;
+    let row = |label: &str, value: &str| {
+        Line::from(vec![
+            Span::styled(
+                format!("  {:<10}", label),
+                Style::default().fg(Color::DarkGray),
+            ),
+            Span::styled(value.to_string(), Style::default().fg(Color::White)),
+        ])
+    };
+    let mut lines = vec![
+        Line::raw(""),
+        Line::raw("  Install and start aether-proxy as a systemd service?"),
+        Line::raw(""),
+        row("Unit", plan.unit_path),
+        row("Binary", &plan.exe),
+        row("Config", &plan.config),
+        row("WorkDir", &plan.working_dir),
+        row("User", service.user.as_deref().unwrap_or("root")),
+    ];
+    if service.hardening {
+        lines.push(row("Hardening", "on"));
+    }
+    for (label, value) in [
+        ("MemoryMax", service.memory_max.clone()),
+        ("CPUQuota", service.cpu_quota.clone()),
+        ("TasksMax", service.tasks_max.map(|n| n.to_string())),
+    ] {
+        if let Some(value) = value {
+            lines.push(row(label, &value));
+        }
+    }
+    lines.push(Line::raw(""));
+    lines.push(Line::from(vec![
+        Span::raw("  Type "),
+        Span::styled("yes", Style::default().add_modifier(Modifier::BOLD)),
+        Span::raw(" to confirm: "),
+        Span::styled(format!("{}_", input), Style::default().fg(Color::Yellow)),
+    ]));
+    lines.push(Line::styled(
+        "  Enter confirm  Esc cancel (starts the proxy directly)",
+        Style::default().fg(Color::DarkGray),
+    ));
+
+    let area = f.area();
+    let width = lines
+        .iter()
+        .map(Line::width)
+        .max()
+        .unwrap_or(0)
+        .saturating_add(4)
+        .min(area.width as usize) as u16;
+    let height = (lines.len() as u16 + 2).min(area.height);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+    f.render_widget(Clear, popup);
+    f.render_widget(
+        Paragraph::new(lines).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(" Install service ")
+                .border_style(Style::default().fg(Color::Yellow)),
+        ),
+        popup,
+    );
+}
+
 fn event_loop(
     terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
     app: &mut App,
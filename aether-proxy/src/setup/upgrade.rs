@@ -1,16 +1,28 @@
 //! Self-upgrade for aether-proxy.
 //!
-//! Downloads a release from GitHub, verifies SHA256 checksum, and atomically
-//! replaces the running binary.  Restarts the systemd service if active.
+//! Downloads a release from GitHub (resuming interrupted transfers),
+//! verifies SHA256 checksum, and atomically replaces the running binary.
+//! Restarts the systemd service if active.
 
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
+use reqwest::StatusCode;
 use sha2::{Digest, Sha256};
 
 const GITHUB_API_BASE: &str = "https://api.github.com";
 const GITHUB_REPO: &str = "wmsyw/Aether";
 const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Attempts per archive download; each one resumes where the last stopped.
+const DOWNLOAD_ATTEMPTS: u32 = 5;
+/// Pause between download attempts.
+const DOWNLOAD_RETRY_DELAY: Duration = Duration::from_secs(2);
+/// Minimum interval between progress line updates.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
 // ── GitHub API types ─────────────────────────────────────────────────────────
 
 #[derive(serde::Deserialize)]
@@ -112,17 +124,21 @@ async fn fetch_release(
 
 // ── Download via GitHub release direct links ─────────────────────────────────
 
-/// Download a release asset via the public direct download URL:
-/// `https://github.com/{repo}/releases/download/{tag}/{filename}`
+/// Public direct download URL of a release asset.
+fn release_file_url(tag: &str, filename: &str) -> String {
+    format!(
+        "https://github.com/{}/releases/download/{}/{}",
+        GITHUB_REPO, tag, filename
+    )
+}
+
+/// Download a small release asset (e.g. `SHA256SUMS.txt`) into memory.
 async fn download_release_file(
     client: &reqwest::Client,
     tag: &str,
     filename: &str,
 ) -> anyhow::Result<Vec<u8>> {
-    let url = format!(
-        "https://github.com/{}/releases/download/{}/{}",
-        GITHUB_REPO, tag, filename
-    );
+    let url = release_file_url(tag, filename);
     let resp = client
         .get(&url)
         .header(reqwest::header::ACCEPT, "application/octet-stream")
@@ -138,6 +154,183 @@ async fn download_release_file(
     Ok(resp.bytes().await?.to_vec())
 }
 
+/// Non-retryable HTTP status (e.g. 404 for a missing asset).
+#[derive(Debug, thiserror::Error)]
+#[error("HTTP {0}")]
+struct FatalStatus(StatusCode);
+
+/// Stream `url` into `path`, resuming from the bytes already in `path`
+/// with a `Range` request after each interruption.  Returns the file size.
+async fn download_to_file(
+    client: &reqwest::Client,
+    url: &str,
+    path: &Path,
+    retry_delay: Duration,
+) -> anyhow::Result<u64> {
+    let mut progress = Progress::default();
+    let mut attempt = 1;
+    loop {
+        match download_attempt(client, url, path, &mut progress).await {
+            Ok(size) => {
+                progress.finish(size);
+                return Ok(size);
+            }
+            Err(e) if e.is::<FatalStatus>() || attempt == DOWNLOAD_ATTEMPTS => {
+                progress.finish(progress.done);
+                return Err(e.context(format!("download failed for '{}'", url)));
+            }
+            Err(e) => {
+                progress.finish(progress.done);
+                eprintln!(
+                    "  WARNING: download interrupted (attempt {}/{}): {:#}; resuming...",
+                    attempt, DOWNLOAD_ATTEMPTS, e
+                );
+                attempt += 1;
+                tokio::time::sleep(retry_delay).await;
+            }
+        }
+    }
+}
+
+async fn download_attempt(
+    client: &reqwest::Client,
+    url: &str,
+    path: &Path,
+    progress: &mut Progress,
+) -> anyhow::Result<u64> {
+    let offset = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let mut request = client
+        .get(url)
+        .header(reqwest::header::ACCEPT, "application/octet-stream");
+    if offset > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", offset));
+    }
+    let mut resp = request.send().await?;
+    let status = resp.status();
+    if status == StatusCode::RANGE_NOT_SATISFIABLE && offset > 0 {
+        // Completed by an earlier attempt; the checksum has the final say.
+        return Ok(offset);
+    }
+    if status.is_client_error() {
+        return Err(FatalStatus(status).into());
+    }
+    if !status.is_success() {
+        anyhow::bail!("HTTP {}", status);
+    }
+
+    let content_range = resp
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned);
+    let start = body_offset(
+        status == StatusCode::PARTIAL_CONTENT,
+        content_range.as_deref(),
+        offset,
+    )?;
+    let total = content_range
+        .as_deref()
+        .and_then(content_range_total)
+        .or_else(|| resp.content_length().map(|len| start + len));
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(path)?;
+    file.set_len(start)?;
+    file.seek(SeekFrom::Start(start))?;
+    progress.total = total;
+    progress.done = start;
+    while let Some(chunk) = resp.chunk().await? {
+        file.write_all(&chunk)?;
+        progress.update(progress.done + chunk.len() as u64);
+    }
+    file.flush()?;
+
+    if let Some(total) = total {
+        if progress.done < total {
+            anyhow::bail!("connection closed at {}/{} bytes", progress.done, total);
+        }
+    }
+    Ok(progress.done)
+}
+
+/// Where in the file the response body starts: `requested` when a 206
+/// answers our range, 0 when the server sent the whole file instead.
+fn body_offset(partial: bool, content_range: Option<&str>, requested: u64) -> anyhow::Result<u64> {
+    if !partial {
+        return Ok(0);
+    }
+    let start = content_range
+        .and_then(|r| r.strip_prefix("bytes "))
+        .and_then(|r| r.split_once('-'))
+        .and_then(|(start, _)| start.trim().parse::<u64>().ok());
+    match start {
+        Some(start) if start == requested => Ok(start),
+        _ => anyhow::bail!(
+            "unexpected Content-Range {:?} when resuming at byte {}",
+            content_range,
+            requested
+        ),
+    }
+}
+
+/// Complete length from `bytes <start>-<end>/<total>` (`None` for `*`).
+fn content_range_total(content_range: &str) -> Option<u64> {
+    content_range.rsplit_once('/')?.1.trim().parse().ok()
+}
+
+/// `bytes/total (percent)` line on stderr, redrawn at most every
+/// [`PROGRESS_INTERVAL`].
+#[derive(Default)]
+struct Progress {
+    done: u64,
+    total: Option<u64>,
+    drawn_at: Option<Instant>,
+}
+
+impl Progress {
+    fn update(&mut self, done: u64) {
+        self.done = done;
+        if self
+            .drawn_at
+            .is_some_and(|at| at.elapsed() < PROGRESS_INTERVAL)
+        {
+            return;
+        }
+        self.drawn_at = Some(Instant::now());
+        self.draw();
+    }
+
+    fn finish(&mut self, done: u64) {
+        if self.drawn_at.take().is_some() {
+            self.done = done;
+            self.draw();
+            eprintln!();
+        }
+    }
+
+    fn draw(&self) {
+        match self.total {
+            Some(total) if total > 0 => eprint!(
+                "\r  {}/{} bytes ({}%)",
+                self.done,
+                total,
+                self.done.min(total) * 100 / total
+            ),
+            _ => eprint!("\r  {} bytes", self.done),
+        }
+    }
+}
+
+/// Hex SHA-256 of a file, read in chunks.
+fn sha256_file(path: &Path) -> anyhow::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
 fn parse_checksum(sums_text: &str, filename: &str) -> anyhow::Result<String> {
     for line in sums_text.lines() {
         // Format: "<hash>  <filename>" (GNU coreutils convention)
@@ -159,27 +352,27 @@ async fn download_and_verify(
     dest: &Path,
 ) -> anyhow::Result<()> {
     let archive_name = format!("aether-proxy-{}.tar.gz", platform);
+    // Kept after a failed download so the next run resumes it; the tag in
+    // the name keeps parts of different releases apart.
+    let archive_path = dest.with_file_name(format!(".{}.{}.part", archive_name, tag));
+
+    let archive_url = release_file_url(tag, &archive_name);
 
     eprintln!("  Downloading {}...", archive_name);
-    let (archive_bytes, checksum_bytes) = tokio::try_join!(
-        download_release_file(client, tag, &archive_name),
+    let (archive_len, checksum_bytes) = tokio::try_join!(
+        download_to_file(client, &archive_url, &archive_path, DOWNLOAD_RETRY_DELAY,),
         download_release_file(client, tag, "SHA256SUMS.txt"),
     )?;
     let checksum_text = String::from_utf8(checksum_bytes)?;
 
-    eprintln!(
-        "  Downloaded {} ({} bytes)",
-        archive_name,
-        archive_bytes.len()
-    );
+    eprintln!("  Downloaded {} ({} bytes)", archive_name, archive_len);
 
     // Verify SHA256
     let expected_hash = parse_checksum(&checksum_text, &archive_name)?;
-    let mut hasher = Sha256::new();
-    hasher.update(&archive_bytes);
-    let actual_hash = hex::encode(hasher.finalize());
+    let actual_hash = sha256_file(&archive_path)?;
 
     if actual_hash != expected_hash {
+        let _ = std::fs::remove_file(&archive_path);
         anyhow::bail!(
             "SHA256 mismatch for {}:\n  expected: {}\n  actual:   {}",
             archive_name,
@@ -189,21 +382,23 @@ async fn download_and_verify(
     }
     eprintln!("  SHA256 verified: {}", &actual_hash[..16]);
 
-    extract_binary(&archive_bytes, dest)?;
-
-    Ok(())
+    let extracted = File::open(&archive_path)
+        .map_err(anyhow::Error::from)
+        .and_then(|archive| extract_binary(archive, dest));
+    let _ = std::fs::remove_file(&archive_path);
+    extracted
 }
 
 // ── Archive extraction ───────────────────────────────────────────────────────
 
-fn extract_binary(archive_bytes: &[u8], dest: &Path) -> anyhow::Result<()> {
+fn extract_binary(archive: impl Read, dest: &Path) -> anyhow::Result<()> {
     use flate2::read::GzDecoder;
     use tar::Archive;
 
     // Guard against decompression bombs
     const MAX_BINARY_SIZE: u64 = 100 * 1024 * 1024; // 100 MB
 
-    let decoder = GzDecoder::new(archive_bytes);
+    let decoder = GzDecoder::new(archive);
     let mut archive = Archive::new(decoder);
 
     let binary_name = if cfg!(target_os = "windows") {
@@ -409,3 +604,100 @@ pub async fn cmd_upgrade(version: Option<String>) -> anyhow::Result<()> {
 pub async fn perform_upgrade(version: &str) -> anyhow::Result<()> {
     execute_upgrade(Some(version), true, RestartMode::Required).await
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn resume_offset_follows_content_range() {
+        assert_eq!(body_offset(false, None, 1024).unwrap(), 0);
+        assert_eq!(
+            body_offset(true, Some("bytes 1024-4095/4096"), 1024).unwrap(),
+            1024
+        );
+        assert!(body_offset(true, Some("bytes 0-4095/4096"), 1024).is_err());
+        assert!(body_offset(true, None, 1024).is_err());
+
+        assert_eq!(content_range_total("bytes 1024-4095/4096"), Some(4096));
+        assert_eq!(content_range_total("bytes 1024-4095/*"), None);
+    }
+
+    /// HTTP/1.1 server for `body` that cuts the first response off halfway
+    /// and honours `Range` afterwards.  Records the range of each request.
+    async fn flaky_server(body: Vec<u8>) -> (String, Arc<Mutex<Vec<Option<u64>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&ranges);
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0; 4096];
+                let n = socket.read(&mut buf).await.unwrap();
+                let request = String::from_utf8_lossy(&buf[..n]).to_ascii_lowercase();
+                let range = request.lines().find_map(|line| {
+                    line.strip_prefix("range: bytes=")?
+                        .trim_end_matches('-')
+                        .parse::<u64>()
+                        .ok()
+                });
+                let first = {
+                    let mut seen = seen.lock().unwrap();
+                    seen.push(range);
+                    seen.len() == 1
+                };
+
+                let start = range.unwrap_or(0) as usize;
+                let head = match range {
+                    Some(_) => format!(
+                        "HTTP/1.1 206 Partial Content\r\ncontent-length: {}\r\ncontent-range: bytes {}-{}/{}\r\nconnection: close\r\n\r\n",
+                        body.len() - start,
+                        start,
+                        body.len() - 1,
+                        body.len()
+                    ),
+                    None => format!(
+                        "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                        body.len()
+                    ),
+                };
+                socket.write_all(head.as_bytes()).await.unwrap();
+                let end = if first { body.len() / 2 } else { body.len() };
+                socket.write_all(&body[start..end]).await.unwrap();
+                let _ = socket.shutdown().await;
+            }
+        });
+        (format!("http://{}/archive.tar.gz", addr), ranges)
+    }
+
+    #[tokio::test]
+    async fn interrupted_download_resumes_and_checksums_the_file() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let body: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let (url, ranges) = flaky_server(body.clone()).await;
+        let dir = std::env::temp_dir().join(format!("aether-upgrade-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("archive.part");
+        let _ = std::fs::remove_file(&path);
+
+        let client = reqwest::Client::new();
+        let size = download_to_file(&client, &url, &path, Duration::ZERO)
+            .await
+            .unwrap();
+
+        assert_eq!(size, body.len() as u64);
+        assert_eq!(*ranges.lock().unwrap(), [None, Some(100_000)]);
+        assert_eq!(std::fs::read(&path).unwrap(), body);
+        assert_eq!(
+            sha256_file(&path).unwrap(),
+            hex::encode(Sha256::digest(&body))
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
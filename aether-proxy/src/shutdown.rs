@@ -0,0 +1,126 @@
+//! Shutdown report: per-server drain/unregister outcome and exit codes.
+//!
+//! The report is logged as structured lines, persisted to the state file
+//! for `aether-proxy status`, and mapped to a process exit code so that
+//! supervisors (systemd `Restart=on-failure`) can tell clean stops apart.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+/// Shutdown was fully clean.
+pub const EXIT_CLEAN: i32 = 0;
+/// Some in-flight streams were aborted at the drain deadline.
+pub const EXIT_PARTIAL_DRAIN: i32 = 10;
+/// At least one server could not be unregistered.
+pub const EXIT_UNREGISTER_FAILED: i32 = 11;
+
+/// Exit code documentation appended to `--help`.
+pub const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0   clean shutdown (all streams drained, all servers unregistered)
+  10  partial drain (in-flight streams aborted at the drain deadline)
+  11  unregister failed for at least one server";
+
+/// Shutdown outcome for a single Aether server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerShutdown {
+    pub server: String,
+    pub streams_drained: u64,
+    pub streams_aborted: u64,
+    pub unregister_ok: bool,
+    pub duration_ms: u64,
+}
+
+/// Process-level shutdown summary.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShutdownReport {
+    /// Unix timestamp (seconds) when shutdown completed.
+    pub stopped_at: u64,
+    pub duration_ms: u64,
+    pub exit_code: i32,
+    pub servers: Vec<ServerShutdown>,
+}
+
+impl ShutdownReport {
+    pub fn new(servers: Vec<ServerShutdown>, duration_ms: u64) -> Self {
+        let exit_code = if servers.iter().any(|s| !s.unregister_ok) {
+            EXIT_UNREGISTER_FAILED
+        } else if servers.iter().any(|s| s.streams_aborted > 0) {
+            EXIT_PARTIAL_DRAIN
+        } else {
+            EXIT_CLEAN
+        };
+        Self {
+            stopped_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            duration_ms,
+            exit_code,
+            servers,
+        }
+    }
+
+    /// Emit one structured line per server plus a process summary.
+    pub fn log(&self) {
+        for s in &self.servers {
+            info!(
+                server = %s.server,
+                streams_drained = s.streams_drained,
+                streams_aborted = s.streams_aborted,
+                unregister_ok = s.unregister_ok,
+                duration_ms = s.duration_ms,
+                "server shutdown summary"
+            );
+        }
+        let drained: u64 = self.servers.iter().map(|s| s.streams_drained).sum();
+        let aborted: u64 = self.servers.iter().map(|s| s.streams_aborted).sum();
+        let unregister_failed = self.servers.iter().filter(|s| !s.unregister_ok).count();
+        if self.exit_code == EXIT_CLEAN {
+            info!(
+                servers = self.servers.len(),
+                streams_drained = drained,
+                duration_ms = self.duration_ms,
+                exit_code = self.exit_code,
+                "shutdown complete"
+            );
+        } else {
+            warn!(
+                servers = self.servers.len(),
+                streams_drained = drained,
+                streams_aborted = aborted,
+                unregister_failed,
+                duration_ms = self.duration_ms,
+                exit_code = self.exit_code,
+                "shutdown completed with errors"
+            );
+        }
+    }
+
+    /// Human-readable summary for `aether-proxy status`.
+    pub fn print(&self) {
+        let outcome = match self.exit_code {
+            EXIT_CLEAN => "clean",
+            EXIT_PARTIAL_DRAIN => "partial drain",
+            EXIT_UNREGISTER_FAILED => "unregister failed",
+            _ => "unknown",
+        };
+        eprintln!(
+            "  Last shutdown: {} (exit {}, {}ms, at unix {})",
+            outcome, self.exit_code, self.duration_ms, self.stopped_at
+        );
+        for s in &self.servers {
+            eprintln!(
+                "    {}: drained {}, aborted {}, unregister {}, {}ms",
+                s.server,
+                s.streams_drained,
+                s.streams_aborted,
+                if s.unregister_ok { "ok" } else { "failed" },
+                s.duration_ms
+            );
+        }
+        eprintln!();
+    }
+}
@@ -1,24 +1,88 @@
 //! Shared application state passed to all subsystems.
 
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, RwLock};
-use std::time::Duration;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
-use crate::config::Config;
+use tokio::sync::{watch, Notify};
+
+use crate::config::{Config, ServerRole};
+use crate::dedup::ExpiringSet;
+use crate::memory::{BufferBudget, MemoryGuard};
+use crate::notification::{Notifier, TunnelEvent};
+use crate::rate_limit::TokenBucket;
 use crate::registration::client::AetherClient;
 use crate::runtime::SharedDynamicConfig;
 use crate::target_filter::DnsCache;
-use crate::upstream_client::UpstreamClient;
+use crate::tunnel::affinity::AffinityRouter;
+use crate::tunnel::routing::ConnectionRegistry;
+use crate::upstream_auth::AuthLoopDetector;
+use crate::upstream_client::{UpstreamClients, UpstreamErrorClass};
 
 /// Central application state shared across all servers/tunnels.
 pub struct AppState {
     pub config: Arc<Config>,
     /// DNS cache for upstream target resolution (shared).
     pub dns_cache: Arc<DnsCache>,
-    /// Hyper client for tunnel upstream requests with validated DNS and connection timing.
-    pub upstream_client: UpstreamClient,
-    /// Shared TLS config for tunnel WebSocket connections (avoids re-parsing root CAs on each reconnect).
-    pub tunnel_tls_config: Arc<rustls::ClientConfig>,
+    /// Hyper clients for tunnel upstream requests with validated DNS and
+    /// connection timing (one pool per `upstream_pool_overrides` host).
+    pub upstream_clients: UpstreamClients,
+    /// The same clients bound to each server's `bind_address`.
+    pub bound_upstream_clients: HashMap<IpAddr, UpstreamClients>,
+    /// TLS config for upstream WebSocket relays (HTTP/1.1 ALPN only).
+    pub upstream_ws_tls_config: Arc<rustls::ClientConfig>,
+    /// Process memory guard (soft/hard RSS limits).
+    pub memory: Arc<MemoryGuard>,
+    /// Bytes buffered by all streams (`global_buffer_budget_mb`).
+    pub buffer_budget: Arc<BufferBudget>,
+    /// Secondary servers are held back until every primary fails.
+    pub failover_enabled: bool,
+    /// Fingerprints of recently accepted requests, to reject replays
+    /// arriving on another tunnel.
+    pub recent_fingerprints: Arc<ExpiringSet<String>>,
+    /// Recent upstream 401/407 challenges, to flag retry loops.
+    pub auth_loops: AuthLoopDetector,
+    /// Proxy-wide upstream request budget (`None` when `global_max_rps` is 0).
+    pub global_rate_limit: Option<TokenBucket>,
+    /// Request body bytes/s budget (`None` when `max_upstream_bps` is 0).
+    pub upstream_bandwidth: Option<Arc<TokenBucket>>,
+    /// Response body bytes/s budget (`None` when `max_downstream_bps` is 0).
+    pub downstream_bandwidth: Option<Arc<TokenBucket>>,
+    /// Per-core runtimes for stream handlers (`enable_stream_affinity`).
+    pub stream_affinity: Option<AffinityRouter>,
+    /// Tunnel state webhook (`notification_webhook_url`).
+    pub notifier: Option<Notifier>,
+}
+
+impl AppState {
+    /// Report a tunnel state change to the webhook, if one is configured.
+    pub fn notify(
+        &self,
+        event: TunnelEvent,
+        server: &ServerContext,
+        conn_idx: usize,
+        reason: Option<&str>,
+    ) {
+        if let Some(notifier) = &self.notifier {
+            notifier.tunnel_event(event, &server.server_label, conn_idx, reason);
+        }
+    }
+
+    /// Upstream clients for streams arriving from `server`: bound to its
+    /// `bind_address` when it has one.
+    pub fn upstream_clients_for(&self, server: &ServerContext) -> &UpstreamClients {
+        server
+            .bind_address
+            .and_then(|addr| self.bound_upstream_clients.get(&addr))
+            .unwrap_or(&self.upstream_clients)
+    }
+
+    /// Whether `server` runs tunnels without waiting for failover.
+    pub fn starts_active(&self, server: &ServerContext) -> bool {
+        !self.failover_enabled || server.role.is_primary()
+    }
 }
 
 /// Per-server state: one instance per Aether server connection.
@@ -33,7 +97,22 @@ pub struct ServerContext {
     /// After startup, the active node_name is read from `dynamic` (may be updated remotely).
     #[allow(dead_code)]
     pub node_name: String,
-    /// Node ID assigned by this Aether server.
+    /// Region reported at registration (per-server override or global fallback).
+    pub node_region: Option<String>,
+    /// Tags reported at registration (global tags overlaid with the server's).
+    pub node_tags: BTreeMap<String, String>,
+    /// Secondary servers only run tunnels during failover.
+    pub role: ServerRole,
+    /// SNI for the tunnel TLS handshake instead of the `aether_url` host.
+    pub tls_sni_override: Option<String>,
+    /// Local address for tunnel connections and upstream requests.
+    pub bind_address: Option<IpAddr>,
+    /// TLS config for this server's tunnel and API connections (the shared
+    /// tunnel config unless the entry sets `tls_ca_cert`), built once so
+    /// reconnects don't re-parse root CAs.
+    pub tls_config: Arc<rustls::ClientConfig>,
+    /// Node ID assigned by this Aether server (empty while registration is
+    /// still pending in lazy registration mode).
     pub node_id: Arc<RwLock<String>>,
     /// API client for this server.
     pub aether_client: Arc<AetherClient>,
@@ -43,6 +122,97 @@ pub struct ServerContext {
     pub active_connections: Arc<AtomicU64>,
     /// Per-server request/latency metrics.
     pub metrics: Arc<ProxyMetrics>,
+    /// Stream drain outcome recorded by tunnels during shutdown.
+    pub drain: Arc<DrainStats>,
+    /// Health score (0-100), refreshed by the tunnel pool scaler.
+    pub health_score: Arc<AtomicU8>,
+    /// Heartbeats still unacknowledged when the next one was due (reset on ACK).
+    pub heartbeat_failures: Arc<AtomicU32>,
+    /// Set while the primary tunnel connection keeps failing to connect.
+    pub circuit_open: Arc<AtomicBool>,
+    /// Wakes the re-registration task early (Aether reported the node missing).
+    pub reregister: Arc<Notify>,
+    /// Signalled when re-registration assigns a different node_id; tunnels
+    /// reconnect so their handshake carries it.
+    pub node_id_changed: watch::Sender<()>,
+    /// Signalled when a remote config update asks for `reconnect`; tunnels
+    /// rotate so their handshake carries the new node_name.
+    pub remote_reconnect: ReconnectTrigger,
+    /// Tunnel connections that accepted cross-connection responses; stream
+    /// handlers reroute their frames between these.
+    pub connections: Arc<ConnectionRegistry>,
+}
+
+impl ServerContext {
+    /// Whether this server has assigned a node ID yet.
+    pub fn is_registered(&self) -> bool {
+        !self.node_id.read().unwrap().is_empty()
+    }
+
+    /// Recompute the health score from current failure signals and store it.
+    pub fn refresh_health_score(&self) -> u8 {
+        let score = health_score(
+            self.heartbeat_failures.load(Ordering::Acquire),
+            self.circuit_open.load(Ordering::Acquire),
+        );
+        self.health_score.store(score, Ordering::Release);
+        score
+    }
+}
+
+/// `100 - 10 * heartbeat_failures`, or 0 while the circuit is open.
+pub fn health_score(heartbeat_failures: u32, circuit_open: bool) -> u8 {
+    if circuit_open {
+        return 0;
+    }
+    100u32.saturating_sub(heartbeat_failures.saturating_mul(10)) as u8
+}
+
+/// Minimum time between two remote-requested reconnects of one server, so a
+/// misbehaving backend cannot keep its tunnels flapping.
+pub const REMOTE_RECONNECT_MIN_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Rate-limited signal asking a server's tunnel connections to rotate.
+pub struct ReconnectTrigger {
+    tx: watch::Sender<()>,
+    last: Mutex<Option<Instant>>,
+    min_interval: Duration,
+}
+
+impl ReconnectTrigger {
+    pub fn new(min_interval: Duration) -> Self {
+        Self {
+            tx: watch::Sender::new(()),
+            last: Mutex::new(None),
+            min_interval,
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.tx.subscribe()
+    }
+
+    /// Signal every current subscriber, unless the last signal was less
+    /// than `min_interval` ago.  Returns whether it was signalled.
+    pub fn request(&self) -> bool {
+        let mut last = self.last.lock().unwrap();
+        let now = Instant::now();
+        if last.is_some_and(|at| now.duration_since(at) < self.min_interval) {
+            return false;
+        }
+        *last = Some(now);
+        self.tx.send_replace(());
+        true
+    }
+}
+
+/// In-flight stream accounting for the shutdown report.
+#[derive(Default)]
+pub struct DrainStats {
+    /// Streams that finished within the drain grace period.
+    pub streams_drained: AtomicU64,
+    /// Streams still running at the deadline and aborted.
+    pub streams_aborted: AtomicU64,
 }
 
 /// Aggregate metrics for reporting to Aether.
@@ -54,6 +224,33 @@ pub struct ProxyMetrics {
     pub failed_requests: AtomicU64,
     pub dns_failures: AtomicU64,
     pub stream_errors: AtomicU64,
+    /// Streams cut off because their body channel stayed full.
+    pub stream_backpressure: AtomicU64,
+    /// Idle connections that answered the staleness probe (reconnect avoided).
+    pub stale_probes_answered: AtomicU64,
+    /// Connections dropped because the staleness probe went unanswered.
+    pub stale_reconnects: AtomicU64,
+    /// Request bodies whose SHA-256 did not match `RequestMeta.body_sha256`.
+    pub body_integrity_failures: AtomicU64,
+    /// Streams that finished within the grace period of a connection rotation.
+    pub rotation_streams_migrated: AtomicU64,
+    /// Streams ended with `connection_rotating` after the rotation grace period.
+    pub rotation_streams_killed: AtomicU64,
+    /// Upstream 401/407 responses flagged with `x-aether-auth-loop`.
+    pub auth_loops: AtomicU64,
+    /// New upstream TLS connections that ran a full handshake.
+    pub tls_handshakes_full: AtomicU64,
+    /// New upstream TLS connections that resumed a cached session.
+    pub tls_handshakes_resumed: AtomicU64,
+    /// Heartbeat ACKs that carried a `remote_config` body (an unchanged
+    /// config is left out once Aether has seen its etag).
+    pub config_updates_received: AtomicU64,
+    /// Response body bytes fed to the frame compressor, and what it sent
+    /// on (compressed, or the input when gzip didn't shrink it).
+    pub compression_bytes_in: AtomicU64,
+    pub compression_bytes_out: AtomicU64,
+    /// Upstream failures per [`UpstreamErrorClass`] (indexed by `as usize`).
+    pub upstream_errors: [AtomicU64; UpstreamErrorClass::ALL.len()],
 }
 
 impl ProxyMetrics {
@@ -64,9 +261,27 @@ impl ProxyMetrics {
             failed_requests: AtomicU64::new(0),
             dns_failures: AtomicU64::new(0),
             stream_errors: AtomicU64::new(0),
+            stream_backpressure: AtomicU64::new(0),
+            stale_probes_answered: AtomicU64::new(0),
+            stale_reconnects: AtomicU64::new(0),
+            body_integrity_failures: AtomicU64::new(0),
+            rotation_streams_migrated: AtomicU64::new(0),
+            rotation_streams_killed: AtomicU64::new(0),
+            auth_loops: AtomicU64::new(0),
+            tls_handshakes_full: AtomicU64::new(0),
+            tls_handshakes_resumed: AtomicU64::new(0),
+            config_updates_received: AtomicU64::new(0),
+            compression_bytes_in: AtomicU64::new(0),
+            compression_bytes_out: AtomicU64::new(0),
+            upstream_errors: Default::default(),
         }
     }
 
+    /// Count an upstream failure under its class.
+    pub fn record_upstream_error(&self, class: UpstreamErrorClass) {
+        self.upstream_errors[class as usize].fetch_add(1, Ordering::Release);
+    }
+
     /// Record a completed request with its connection-establishment latency
     /// (DNS + TCP/TLS + TTFB, excludes response body streaming).
     pub fn record_request(&self, connect_elapsed: Duration) {
@@ -75,3 +290,32 @@ impl ProxyMetrics {
         self.total_latency_ns.fetch_add(nanos, Ordering::Release);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn health_score_drops_with_heartbeat_failures_and_open_circuit() {
+        assert_eq!(health_score(0, false), 100);
+        assert_eq!(health_score(1, false), 90);
+        assert_eq!(health_score(6, false), 40);
+        assert_eq!(health_score(u32::MAX, false), 0);
+        assert_eq!(health_score(0, true), 0);
+    }
+
+    #[test]
+    fn reconnect_requests_are_rate_limited() {
+        let trigger = ReconnectTrigger::new(REMOTE_RECONNECT_MIN_INTERVAL);
+        let mut rx = trigger.subscribe();
+        assert!(trigger.request());
+        assert!(rx.has_changed().unwrap());
+        rx.mark_unchanged();
+        assert!(!trigger.request());
+        assert!(!rx.has_changed().unwrap());
+
+        let unlimited = ReconnectTrigger::new(Duration::ZERO);
+        assert!(unlimited.request());
+        assert!(unlimited.request());
+    }
+}
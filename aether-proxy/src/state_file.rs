@@ -0,0 +1,329 @@
+//! Small JSON state file persisted next to the config file.
+//!
+//! Holds process state that must survive restarts (e.g. the last shutdown
+//! report shown by `aether-proxy status`).  All fields are optional so the
+//! file stays readable across versions.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::shutdown::ShutdownReport;
+
+/// State file name, placed in the same directory as the config file.
+const STATE_FILE_NAME: &str = "aether-proxy.state.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StateFile {
+    /// Summary of the previous instance's shutdown.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_shutdown: Option<ShutdownReport>,
+    /// Highest remote `config_version` applied, keyed by node_id.  Used as a
+    /// floor so a stale snapshot is never re-applied after re-registration.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub config_versions: HashMap<String, u64>,
+    /// Registration idempotency key per server entry, keyed by
+    /// `aether_url|node_name`.  Sent on register and heartbeat so Aether can
+    /// recognise the same node across restarts and IP changes.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub client_instance_ids: HashMap<String, String>,
+    /// node_id last assigned by Aether, keyed by client instance id.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub node_ids: HashMap<String, String>,
+    /// Clean-shutdown marker: the node_ids the last graceful shutdown
+    /// unregistered (emptied at startup).  `None` in state files written
+    /// before the marker existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clean_shutdown: Option<Vec<String>>,
+}
+
+impl StateFile {
+    /// Location of the state file (next to the active config file).
+    pub fn path() -> PathBuf {
+        let config_path = crate::config::config_file_path();
+        match config_path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.join(STATE_FILE_NAME),
+            _ => PathBuf::from(STATE_FILE_NAME),
+        }
+    }
+
+    /// Load the state file, returning defaults if it is missing or unreadable.
+    pub fn load() -> Self {
+        Self::load_from(&Self::path())
+    }
+
+    fn load_from(path: &Path) -> Self {
+        std::fs::read(path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    /// Persisted config_version floor for a node (0 if unknown).
+    pub fn config_version_floor(node_id: &str) -> u64 {
+        Self::load()
+            .config_versions
+            .get(node_id)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Load, modify, and write back the state file, under the same
+    /// cross-process lock as config edits ([`crate::config::lock_config`]).
+    /// Blocks; call it from `spawn_blocking` in async code.
+    pub fn update(f: impl FnOnce(&mut StateFile)) -> anyhow::Result<()> {
+        Self::update_at(&Self::path(), f)
+    }
+
+    fn update_at(path: &Path, f: impl FnOnce(&mut StateFile)) -> anyhow::Result<()> {
+        let _lock = crate::config::lock_config(path)?;
+        let mut state = Self::load_from(path);
+        f(&mut state);
+        crate::config::write_atomic(path, &serde_json::to_vec_pretty(&state)?)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600));
+        }
+        Ok(())
+    }
+
+    /// node_ids (keyed by client instance id) that a previous instance left
+    /// registered because it crashed or was killed: persisted ones missing
+    /// from the clean-shutdown marker.  Resets the marker, so a crash of
+    /// this instance is detected on the next start.
+    pub fn take_stale_node_ids() -> HashMap<String, String> {
+        Self::take_stale_node_ids_at(&Self::path())
+    }
+
+    fn take_stale_node_ids_at(path: &Path) -> HashMap<String, String> {
+        let mut stale = HashMap::new();
+        let result = Self::update_at(path, |state| {
+            if let Some(clean) = state.clean_shutdown.replace(Vec::new()) {
+                stale = state
+                    .node_ids
+                    .iter()
+                    .filter(|(_, node_id)| !clean.contains(node_id))
+                    .map(|(id, node_id)| (id.clone(), node_id.clone()))
+                    .collect();
+            }
+        });
+        if let Err(e) = result {
+            tracing::warn!(error = %e, "failed to reset clean-shutdown marker");
+        }
+        stale
+    }
+
+    /// Stable client instance id for a server entry, generated and persisted
+    /// on first use.  If the state file cannot be written the fresh id is
+    /// still returned (it just won't survive a restart).
+    pub fn client_instance_id(aether_url: &str, node_name: &str) -> String {
+        Self::client_instance_id_at(&Self::path(), aether_url, node_name)
+    }
+
+    fn client_instance_id_at(path: &Path, aether_url: &str, node_name: &str) -> String {
+        let key = instance_key(aether_url, node_name);
+        let mut id = String::new();
+        let result = Self::update_at(path, |state| {
+            id = state
+                .client_instance_ids
+                .entry(key)
+                .or_insert_with(new_instance_id)
+                .clone();
+        });
+        if let Err(e) = result {
+            tracing::warn!(error = %e, "failed to persist client instance id");
+        }
+        id
+    }
+
+    /// node_id last assigned to a server entry, without creating a client
+    /// instance id for it.
+    pub fn node_id_for(aether_url: &str, node_name: &str) -> Option<String> {
+        Self::node_id_for_at(&Self::path(), aether_url, node_name)
+    }
+
+    fn node_id_for_at(path: &Path, aether_url: &str, node_name: &str) -> Option<String> {
+        let mut state = Self::load_from(path);
+        let instance_id = state
+            .client_instance_ids
+            .get(&instance_key(aether_url, node_name))?;
+        state.node_ids.remove(instance_id)
+    }
+
+    /// Record the node_id Aether assigned to `client_instance_id`.  Returns
+    /// the previously persisted node_id when it differs.
+    pub fn record_node_id(client_instance_id: &str, node_id: &str) -> Option<String> {
+        Self::record_node_id_at(&Self::path(), client_instance_id, node_id)
+    }
+
+    fn record_node_id_at(path: &Path, client_instance_id: &str, node_id: &str) -> Option<String> {
+        let previous = Self::load_from(path)
+            .node_ids
+            .get(client_instance_id)
+            .cloned();
+        if previous.as_deref() == Some(node_id) {
+            return None;
+        }
+        let result = Self::update_at(path, |state| {
+            state
+                .node_ids
+                .insert(client_instance_id.to_string(), node_id.to_string());
+        });
+        if let Err(e) = result {
+            tracing::warn!(error = %e, "failed to persist node_id");
+        }
+        previous
+    }
+}
+
+/// `client_instance_ids` key of a server entry.
+fn instance_key(aether_url: &str, node_name: &str) -> String {
+    format!("{}|{}", aether_url.trim_end_matches('/'), node_name)
+}
+
+/// Random UUIDv4.
+fn new_instance_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_state_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "aether-proxy-state-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(STATE_FILE_NAME)
+    }
+
+    #[test]
+    fn instance_id_is_generated_once_and_survives_reload() {
+        let path = temp_state_path("instance-id");
+        let id = StateFile::client_instance_id_at(&path, "https://a.example.com/", "node-1");
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "4");
+
+        // Same entry (trailing slash ignored) -> same id; other entry -> new id.
+        let again = StateFile::client_instance_id_at(&path, "https://a.example.com", "node-1");
+        assert_eq!(again, id);
+        let other = StateFile::client_instance_id_at(&path, "https://a.example.com", "node-2");
+        assert_ne!(other, id);
+
+        let state = StateFile::load_from(&path);
+        assert_eq!(state.client_instance_ids.len(), 2);
+
+        assert_eq!(StateFile::record_node_id_at(&path, &id, "n1"), None);
+        assert_eq!(StateFile::record_node_id_at(&path, &id, "n1"), None);
+        assert_eq!(
+            StateFile::record_node_id_at(&path, &id, "n2").as_deref(),
+            Some("n1")
+        );
+        assert_eq!(StateFile::load_from(&path).node_ids[&id], "n2");
+        assert_eq!(
+            StateFile::node_id_for_at(&path, "https://a.example.com/", "node-1").as_deref(),
+            Some("n2")
+        );
+        assert_eq!(
+            StateFile::node_id_for_at(&path, "https://a.example.com", "node-2"),
+            None
+        );
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn nodes_missing_from_the_clean_shutdown_marker_are_stale() {
+        let path = temp_state_path("stale");
+        StateFile::record_node_id_at(&path, "a", "n1");
+        StateFile::record_node_id_at(&path, "b", "n2");
+        // Written before the marker existed: nothing is known to be stale.
+        assert!(StateFile::take_stale_node_ids_at(&path).is_empty());
+
+        // Started, then killed: every node is stale.
+        let stale = StateFile::take_stale_node_ids_at(&path);
+        assert_eq!(stale.len(), 2);
+        assert_eq!(stale["a"], "n1");
+
+        // A graceful shutdown that could only unregister n1.
+        StateFile::update_at(&path, |s| s.clean_shutdown = Some(vec!["n1".into()])).unwrap();
+        let stale = StateFile::take_stale_node_ids_at(&path);
+        assert_eq!(stale.keys().collect::<Vec<_>>(), ["b"]);
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn concurrent_entries_do_not_lose_updates() {
+        let path = temp_state_path("concurrent");
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    StateFile::client_instance_id_at(
+                        &path,
+                        "https://a.example.com",
+                        &format!("n{i}"),
+                    )
+                })
+            })
+            .collect();
+        let ids: Vec<String> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        let state = StateFile::load_from(&path);
+        assert_eq!(state.client_instance_ids.len(), 8);
+        for (i, id) in ids.iter().enumerate() {
+            assert_eq!(
+                &state.client_instance_ids[&format!("https://a.example.com|n{i}")],
+                id
+            );
+        }
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn updates_wait_for_the_file_lock() {
+        let path = temp_state_path("file-lock");
+        // Another process (e.g. `setup`) holding the lock.
+        let held = crate::config::lock_config(&path).unwrap();
+        let writer = {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                StateFile::update_at(&path, |s| {
+                    s.config_versions.insert("n1".into(), 7);
+                })
+            })
+        };
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!path.exists());
+
+        drop(held);
+        writer.join().unwrap().unwrap();
+        assert_eq!(StateFile::load_from(&path).config_versions["n1"], 7);
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn concurrent_updates_keep_every_write() {
+        let path = temp_state_path("concurrent-updates");
+        let writers: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    StateFile::update_at(&path, |s| {
+                        s.config_versions.insert(format!("n{i}"), i);
+                    })
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap().unwrap();
+        }
+        assert_eq!(StateFile::load_from(&path).config_versions.len(), 8);
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
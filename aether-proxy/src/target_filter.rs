@@ -1,9 +1,11 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-use tokio::sync::RwLock;
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{RwLock, Semaphore};
 
 /// Check if an IP address belongs to a private/reserved network.
 pub fn is_private_ip(ip: &IpAddr) -> bool {
@@ -83,7 +85,7 @@ fn is_private_ipv6(ip: &Ipv6Addr) -> bool {
     false
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum FilterError {
     PrivateIp(IpAddr),
     PortNotAllowed(u16),
@@ -108,19 +110,208 @@ impl std::fmt::Display for FilterError {
     }
 }
 
+/// One `allowed_ports` entry: a single port (`443`) or an inclusive range
+/// (`"8000-8999"`).  Single ports serialize as plain numbers so existing
+/// config files and remote configs stay valid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "PortRangeRepr", into = "PortRangeRepr")]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum PortRangeRepr {
+    Port(i64),
+    Range(String),
+}
+
+impl TryFrom<PortRangeRepr> for PortRange {
+    type Error = String;
+
+    fn try_from(repr: PortRangeRepr) -> Result<Self, Self::Error> {
+        match repr {
+            PortRangeRepr::Port(port) => port.to_string().parse(),
+            PortRangeRepr::Range(s) => s.parse(),
+        }
+    }
+}
+
+impl From<PortRange> for PortRangeRepr {
+    fn from(range: PortRange) -> Self {
+        if range.start == range.end {
+            Self::Port(range.start.into())
+        } else {
+            Self::Range(range.to_string())
+        }
+    }
+}
+
+impl From<u16> for PortRange {
+    fn from(port: u16) -> Self {
+        Self {
+            start: port,
+            end: port,
+        }
+    }
+}
+
+impl std::str::FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let entry = s.trim();
+        if entry.is_empty() {
+            return Err("allowed_ports: empty entry (list at least one port or range)".into());
+        }
+        let parse = |p: &str| {
+            p.trim()
+                .parse::<u16>()
+                .ok()
+                .filter(|&p| p != 0)
+                .ok_or_else(|| format!("allowed_ports: invalid port '{}' in '{}'", p.trim(), entry))
+        };
+        let range = match entry.split_once('-') {
+            Some((start, end)) => Self {
+                start: parse(start)?,
+                end: parse(end)?,
+            },
+            None => parse(entry)?.into(),
+        };
+        if range.start > range.end {
+            return Err(format!(
+                "allowed_ports: range '{}' ends before it starts",
+                entry
+            ));
+        }
+        Ok(range)
+    }
+}
+
+impl std::fmt::Display for PortRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+/// Well-known non-HTTP services; allowing one is almost always a mistake.
+const SERVICE_PORTS: &[(u16, &str)] = &[
+    (22, "ssh"),
+    (23, "telnet"),
+    (25, "smtp"),
+    (53, "dns"),
+    (3306, "mysql"),
+    (5432, "postgresql"),
+    (6379, "redis"),
+    (11211, "memcached"),
+    (27017, "mongodb"),
+];
+
+/// Warnings for `allowed_ports` entries that are likely misconfigured:
+/// well-known non-HTTP service ports, and privileged ports other than 80
+/// and 443.
+pub fn port_warnings(ports: &[PortRange]) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for range in ports {
+        if range.start == range.end {
+            if let Some((_, service)) = SERVICE_PORTS.iter().find(|(p, _)| *p == range.start) {
+                warnings.push(format!(
+                    "allowed_ports: port {} is {}, not an HTTP service",
+                    range.start, service
+                ));
+                continue;
+            }
+        }
+        let privileged = (range.start..=range.end.min(1023)).any(|p| p != 80 && p != 443);
+        if privileged {
+            warnings.push(format!(
+                "allowed_ports: {} includes privileged ports other than 80 and 443",
+                range
+            ));
+        }
+    }
+    warnings
+}
+
+/// Allowed destination ports as sorted, non-overlapping ranges, so a
+/// `1024-65535` entry costs one comparison instead of 64k set entries.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PortSet(Vec<PortRange>);
+
+impl PortSet {
+    pub fn contains(&self, port: u16) -> bool {
+        let idx = self.0.partition_point(|r| r.end < port);
+        self.0.get(idx).is_some_and(|r| r.start <= port)
+    }
+}
+
+impl FromIterator<PortRange> for PortSet {
+    /// Sorts and merges overlapping or adjacent ranges.
+    fn from_iter<I: IntoIterator<Item = PortRange>>(iter: I) -> Self {
+        let mut ranges: Vec<PortRange> = iter.into_iter().collect();
+        ranges.sort_unstable();
+        let mut merged: Vec<PortRange> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if u32::from(range.start) <= u32::from(last.end) + 1 => {
+                    last.end = last.end.max(range.end);
+                }
+                _ => merged.push(range),
+            }
+        }
+        Self(merged)
+    }
+}
+
+impl std::fmt::Display for PortSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, range) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", range)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for FilterError {}
+
 struct DnsCacheEntry {
     addrs: Arc<Vec<SocketAddr>>,
     expires_at: Instant,
     inserted_at: Instant,
 }
 
+/// Resolves `host:port` to socket addresses (`tokio::net::lookup_host`
+/// outside tests).
+type ResolveFn =
+    dyn Fn(String) -> BoxFuture<'static, std::io::Result<Vec<SocketAddr>>> + Send + Sync;
+
+/// A lookup shared by every caller that missed the cache for the same key.
+type InflightLookup = Shared<BoxFuture<'static, Result<Arc<Vec<SocketAddr>>, FilterError>>>;
+
+/// Default cap on concurrent DNS lookups.
+pub const DEFAULT_DNS_MAX_INFLIGHT: usize = 32;
+
 /// Lightweight DNS cache with TTL + capacity bounds.
 /// Stores all public resolved addresses per host (used by SafeDnsResolver
 /// to ensure reqwest connects to the same validated addresses).
+///
+/// Concurrent misses for the same host:port share one lookup, and at most
+/// `max_inflight` lookups run at once.
 pub struct DnsCache {
     ttl: Duration,
     capacity: usize,
     entries: RwLock<HashMap<String, DnsCacheEntry>>,
+    inflight: Mutex<HashMap<String, InflightLookup>>,
+    lookup_permits: Arc<Semaphore>,
+    resolve: Arc<ResolveFn>,
 }
 
 impl DnsCache {
@@ -129,6 +320,78 @@ impl DnsCache {
             ttl,
             capacity,
             entries: RwLock::new(HashMap::new()),
+            inflight: Mutex::new(HashMap::new()),
+            lookup_permits: Arc::new(Semaphore::new(DEFAULT_DNS_MAX_INFLIGHT)),
+            resolve: Arc::new(|addr: String| {
+                async move { Ok(tokio::net::lookup_host(addr).await?.collect()) }.boxed()
+            }),
+        }
+    }
+
+    /// Cap concurrent DNS lookups at `max_inflight` (at least one).
+    pub fn with_max_inflight(mut self, max_inflight: usize) -> Self {
+        self.lookup_permits = Arc::new(Semaphore::new(max_inflight.max(1)));
+        self
+    }
+
+    /// Replace the system resolver.
+    #[cfg(test)]
+    fn with_resolver(
+        mut self,
+        resolve: impl Fn(String) -> BoxFuture<'static, std::io::Result<Vec<SocketAddr>>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.resolve = Arc::new(resolve);
+        self
+    }
+
+    /// Join the in-flight lookup for `host:port`, starting one if needed.
+    fn lookup(&self, host: &str, port: u16) -> InflightLookup {
+        let mut inflight = self.inflight.lock().unwrap();
+        inflight
+            .entry(Self::key(host, port))
+            .or_insert_with(|| {
+                let permits = Arc::clone(&self.lookup_permits);
+                let resolve = Arc::clone(&self.resolve);
+                let host = host.to_string();
+                async move {
+                    let _permit = permits.acquire_owned().await;
+                    let resolved = resolve(format!("{}:{}", host, port))
+                        .await
+                        .map_err(|_| FilterError::DnsResolutionFailed(host.clone()))?;
+                    if resolved.is_empty() {
+                        return Err(FilterError::DnsResolutionFailed(host));
+                    }
+
+                    // Filter out private/reserved addresses
+                    let public: Vec<SocketAddr> = resolved
+                        .into_iter()
+                        .filter(|addr| !is_private_ip(&addr.ip()))
+                        .collect();
+                    if public.is_empty() {
+                        return Err(FilterError::NoPublicAddrs(host));
+                    }
+                    Ok(Arc::new(public))
+                }
+                .boxed()
+                .shared()
+            })
+            .clone()
+    }
+
+    /// Forget a finished lookup.  Returns true for the one caller that
+    /// removed it (and should cache the result).
+    fn finish_lookup(&self, host: &str, port: u16, lookup: &InflightLookup) -> bool {
+        let key = Self::key(host, port);
+        let mut inflight = self.inflight.lock().unwrap();
+        match inflight.get(&key) {
+            Some(current) if current.ptr_eq(lookup) => {
+                inflight.remove(&key);
+                true
+            }
+            _ => false,
         }
     }
 
@@ -224,31 +487,17 @@ pub async fn resolve_public_addrs(
         return Ok((*addrs).clone());
     }
 
-    // Async DNS resolution
-    let addr_str = format!("{}:{}", host, port);
-    let resolved: Vec<SocketAddr> = tokio::net::lookup_host(&addr_str)
-        .await
-        .map_err(|_| FilterError::DnsResolutionFailed(host.to_string()))?
-        .collect();
-
-    if resolved.is_empty() {
-        return Err(FilterError::DnsResolutionFailed(host.to_string()));
-    }
-
-    // Filter out private/reserved addresses
-    let public: Vec<SocketAddr> = resolved
-        .into_iter()
-        .filter(|addr| !is_private_ip(&addr.ip()))
-        .collect();
-
-    if public.is_empty() {
-        return Err(FilterError::NoPublicAddrs(host.to_string()));
-    }
+    // Async DNS resolution, shared with concurrent misses for the same key
+    let lookup = dns_cache.lookup(host, port);
+    let result = lookup.clone().await;
+    let owner = dns_cache.finish_lookup(host, port, &lookup);
+    let addrs = result?;
 
     // Cache the validated public addresses
-    let arc_addrs = Arc::new(public);
-    dns_cache.insert(host, port, Arc::clone(&arc_addrs)).await;
-    Ok((*arc_addrs).clone())
+    if owner {
+        dns_cache.insert(host, port, Arc::clone(&addrs)).await;
+    }
+    Ok((*addrs).clone())
 }
 
 /// Validate that the target host:port is allowed.
@@ -259,11 +508,11 @@ pub async fn resolve_public_addrs(
 pub async fn validate_target(
     host: &str,
     port: u16,
-    allowed_ports: &HashSet<u16>,
+    allowed_ports: &PortSet,
     dns_cache: &DnsCache,
 ) -> Result<Vec<SocketAddr>, FilterError> {
     // Port whitelist check
-    if !allowed_ports.contains(&port) {
+    if !allowed_ports.contains(port) {
         return Err(FilterError::PortNotAllowed(port));
     }
 
@@ -281,10 +530,16 @@ pub async fn validate_target(
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
     use super::*;
 
-    fn ports() -> HashSet<u16> {
-        [80, 443, 8080, 8443].into_iter().collect()
+    fn ports() -> PortSet {
+        [80, 443, 8080, 8443]
+            .into_iter()
+            .map(PortRange::from)
+            .collect()
     }
 
     fn cache() -> DnsCache {
@@ -378,4 +633,172 @@ mod tests {
         let cached = cache.get("example.com", 443).await.unwrap();
         assert_eq!(*cached, addrs);
     }
+
+    /// Cache whose resolver counts lookups and answers after a short delay
+    /// (so concurrent callers overlap), failing when `fail` is set.
+    fn counting_cache(lookups: Arc<AtomicUsize>, fail: bool) -> Arc<DnsCache> {
+        let cache = cache().with_resolver(move |addr| {
+            lookups.fetch_add(1, Ordering::SeqCst);
+            async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                if fail {
+                    return Err(std::io::Error::other("resolver down"));
+                }
+                let port = addr.rsplit(':').next().unwrap().parse().unwrap();
+                Ok(vec![SocketAddr::new(
+                    IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)),
+                    port,
+                )])
+            }
+            .boxed()
+        });
+        Arc::new(cache)
+    }
+
+    #[tokio::test]
+    async fn concurrent_misses_share_one_lookup() {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let cache = counting_cache(Arc::clone(&lookups), false);
+        let tasks: Vec<_> = (0..100)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                tokio::spawn(async move {
+                    validate_target("burst.example.com", 443, &ports(), &cache).await
+                })
+            })
+            .collect();
+        for task in tasks {
+            let addrs = task.await.unwrap().unwrap();
+            assert_eq!(addrs[0].port(), 443);
+        }
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+        assert!(cache.get("burst.example.com", 443).await.is_some());
+        assert!(cache.inflight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn lookup_failure_reaches_every_waiter() {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let cache = counting_cache(Arc::clone(&lookups), true);
+        let tasks: Vec<_> = (0..10)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                tokio::spawn(async move {
+                    validate_target("down.example.com", 443, &ports(), &cache).await
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert!(matches!(
+                task.await.unwrap(),
+                Err(FilterError::DnsResolutionFailed(_))
+            ));
+        }
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn port_ranges_parse_and_serialize_like_plain_ports() {
+        let entries: Vec<PortRange> =
+            serde_json::from_str(r#"[443, "8000-8999", "80", " 22 - 23 "]"#).unwrap();
+        let set: PortSet = entries.iter().copied().collect();
+        assert_eq!(set.to_string(), "22-23,80,443,8000-8999");
+        assert!(set.contains(8000) && set.contains(8999) && set.contains(443));
+        assert!(!set.contains(7999) && !set.contains(9000) && !set.contains(1024));
+        assert_eq!(
+            serde_json::to_string(&entries[..2]).unwrap(),
+            r#"[443,"8000-8999"]"#
+        );
+        let toml_entries: ConfigPorts = toml::from_str("ports = [80, \"1024-2048\"]").unwrap();
+        assert_eq!(
+            toml_entries.ports[1],
+            PortRange {
+                start: 1024,
+                end: 2048
+            }
+        );
+
+        for (bad, named) in [
+            ("0", "'0'"),
+            ("9000-8000", "'9000-8000'"),
+            ("80-", "'80-'"),
+            ("http", "'http'"),
+            ("70000", "'70000'"),
+        ] {
+            let err = bad.parse::<PortRange>().unwrap_err();
+            assert!(err.contains(named), "{err}");
+        }
+        let err = serde_json::from_str::<Vec<PortRange>>("[443, 0]").unwrap_err();
+        assert!(err.to_string().contains("'0'"), "{err}");
+        let err = "".parse::<PortRange>().unwrap_err();
+        assert!(err.contains("empty entry"), "{err}");
+    }
+
+    #[test]
+    fn suspicious_allowed_ports_are_warned_about() {
+        let ports: Vec<PortRange> = ["80", "443", "8000-8999", "6379", "22", "1-1024"]
+            .iter()
+            .map(|p| p.parse().unwrap())
+            .collect();
+        assert_eq!(
+            port_warnings(&ports),
+            [
+                "allowed_ports: port 6379 is redis, not an HTTP service",
+                "allowed_ports: port 22 is ssh, not an HTTP service",
+                "allowed_ports: 1-1024 includes privileged ports other than 80 and 443",
+            ]
+        );
+        assert!(port_warnings(&[80, 443, 8080, 8443].map(PortRange::from)).is_empty());
+    }
+
+    #[derive(serde::Deserialize)]
+    struct ConfigPorts {
+        ports: Vec<PortRange>,
+    }
+
+    /// Deterministic xorshift generator for the property-style test below.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn port(&mut self) -> u16 {
+            // Cluster around a few spots so ranges overlap and touch.
+            let base = [1u16, 80, 8000, 65000][(self.next() % 4) as usize];
+            base.saturating_add((self.next() % 600) as u16)
+        }
+    }
+
+    #[test]
+    fn port_set_membership_matches_expanded_set() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..200 {
+            let ranges: Vec<PortRange> = (0..rng.next() % 12)
+                .map(|_| {
+                    let (a, b) = (rng.port(), rng.port());
+                    PortRange {
+                        start: a.min(b),
+                        end: a.max(b),
+                    }
+                })
+                .collect();
+            let expanded: HashSet<u16> = ranges.iter().flat_map(|r| r.start..=r.end).collect();
+            let set: PortSet = ranges.into_iter().collect();
+            for pair in set.0.windows(2) {
+                assert!(u32::from(pair[0].end) + 1 < u32::from(pair[1].start));
+            }
+            for port in 0..=u16::MAX {
+                assert_eq!(
+                    set.contains(port),
+                    expanded.contains(&port),
+                    "{port} in {set}"
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,187 @@
+//! OpenTelemetry trace export and W3C trace context propagation.
+//!
+//! With `otel_endpoint` set, spans are exported over OTLP/HTTP and a stream
+//! whose request carries a `traceparent` gets a child span of Aether's: the
+//! upstream request is sent with that child's `traceparent` and its span id
+//! in [`PROXY_SPAN_ID_HEADER`], so the Aether backend can stitch the trace.
+//! Without it the tracing spans have no OpenTelemetry context and the
+//! headers pass through unmodified.
+
+use std::collections::HashMap;
+
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::{TraceContextExt, TracerProvider as _};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use tracing::Subscriber;
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::build_info;
+
+/// Span id of the proxy's stream span, sent upstream next to `traceparent`.
+pub const PROXY_SPAN_ID_HEADER: &str = "x-aether-proxy-span-id";
+
+const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Flushes buffered spans and stops the exporter when dropped.
+pub struct TelemetryGuard(SdkTracerProvider);
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        let _ = self.0.shutdown();
+    }
+}
+
+/// Start exporting spans to the OTLP/HTTP traces `endpoint`
+/// (e.g. `http://collector:4318/v1/traces`).
+pub fn init(endpoint: &str) -> anyhow::Result<TelemetryGuard> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| anyhow::anyhow!("failed to build OTLP exporter for {}: {}", endpoint, e))?;
+    let resource = Resource::builder()
+        .with_service_name("aether-proxy")
+        .with_attribute(opentelemetry::KeyValue::new(
+            "service.version",
+            build_info::VERSION,
+        ))
+        .build();
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    Ok(TelemetryGuard(provider))
+}
+
+/// The subscriber layer that turns tracing spans into exported spans.
+pub fn layer<S>(guard: &TelemetryGuard) -> OpenTelemetryLayer<S, SdkTracer>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    tracing_opentelemetry::layer().with_tracer(guard.0.tracer("aether-proxy"))
+}
+
+/// Make `span` a child of the trace in Aether's `traceparent`/`tracestate`
+/// request headers, if any.
+pub fn set_parent(span: &tracing::Span, headers: &HashMap<String, String>) {
+    if !headers
+        .keys()
+        .any(|k| k.eq_ignore_ascii_case(TRACEPARENT_HEADER))
+    {
+        return;
+    }
+    let parent = TraceContextPropagator::new().extract(&RequestHeaders(headers));
+    if parent.span().span_context().is_remote() {
+        let _ = span.set_parent(parent);
+    }
+}
+
+/// Replace the upstream `traceparent`/`tracestate` with `span`'s and add
+/// [`PROXY_SPAN_ID_HEADER`].  No-op unless `span` joined Aether's trace
+/// through [`set_parent`] with export enabled.
+pub fn inject(span: &tracing::Span, headers: &mut hyper::HeaderMap) {
+    let context = span.context();
+    let span_context = context.span().span_context().clone();
+    if !span_context.is_valid() || !headers.contains_key(TRACEPARENT_HEADER) {
+        return;
+    }
+    TraceContextPropagator::new().inject_context(&context, &mut UpstreamHeaders(headers));
+    if let Ok(value) = hyper::header::HeaderValue::from_str(&span_context.span_id().to_string()) {
+        headers.insert(PROXY_SPAN_ID_HEADER, value);
+    }
+}
+
+/// Case-insensitive view of the request headers Aether sent.
+struct RequestHeaders<'a>(&'a HashMap<String, String>);
+
+impl Extractor for RequestHeaders<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(String::as_str).collect()
+    }
+}
+
+struct UpstreamHeaders<'a>(&'a mut hyper::HeaderMap);
+
+impl Injector for UpstreamHeaders<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            hyper::header::HeaderName::from_bytes(key.as_bytes()),
+            hyper::header::HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tracing_subscriber::prelude::*;
+
+    const PARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    fn upstream_headers(request: &HashMap<String, String>) -> hyper::HeaderMap {
+        let mut headers = hyper::HeaderMap::new();
+        for (name, value) in request {
+            headers.insert(
+                hyper::header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn traceparent_is_replaced_by_a_child_span() {
+        let guard = TelemetryGuard(SdkTracerProvider::builder().build());
+        let subscriber = tracing_subscriber::registry().with(layer(&guard));
+        let request = HashMap::from([
+            ("Traceparent".to_string(), PARENT.to_string()),
+            ("tracestate".to_string(), "vendor=1".to_string()),
+        ]);
+
+        let headers = tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("stream");
+            set_parent(&span, &request);
+            let mut headers = upstream_headers(&request);
+            inject(&span, &mut headers);
+            headers
+        });
+
+        let traceparent = headers["traceparent"].to_str().unwrap();
+        let span_id = headers[PROXY_SPAN_ID_HEADER].to_str().unwrap();
+        assert_eq!(
+            traceparent,
+            format!("00-4bf92f3577b34da6a3ce929d0e0e4736-{span_id}-01")
+        );
+        assert_ne!(span_id, "00f067aa0ba902b7");
+        assert_eq!(headers["tracestate"], "vendor=1");
+    }
+
+    #[test]
+    fn headers_pass_through_without_telemetry() {
+        let request = HashMap::from([("traceparent".to_string(), PARENT.to_string())]);
+        let subscriber = tracing_subscriber::registry();
+        let headers = tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("stream");
+            set_parent(&span, &request);
+            let mut headers = upstream_headers(&request);
+            inject(&span, &mut headers);
+            headers
+        });
+        assert_eq!(headers["traceparent"], PARENT);
+        assert!(!headers.contains_key(PROXY_SPAN_ID_HEADER));
+    }
+}
@@ -0,0 +1,133 @@
+//! `x-proxy-timing` response header.
+//!
+//! Every proxy path reports request phases through [`ProxyTiming`] so that
+//! Aether parses one schema regardless of mode.  Phases that a path does not
+//! measure are left as `None` and omitted from the JSON.
+
+use serde::{Deserialize, Serialize};
+
+/// Response header carrying the serialized [`ProxyTiming`].
+pub const TIMING_HEADER: &str = "x-proxy-timing";
+
+/// Per-request timing reported back to Aether.  All durations are in
+/// milliseconds.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProxyTiming {
+    /// Proxy mode that served the request (e.g. `"tunnel"`).
+    pub mode: String,
+    /// Request ID propagated to the upstream.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Resolving and validating the upstream host.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_ms: Option<u64>,
+    /// Time until a pooled or new upstream connection was available.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection_acquire_ms: Option<u64>,
+    /// Whether the upstream connection came from the pool.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection_reused: Option<bool>,
+    /// TCP connect (0 on a reused connection).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_ms: Option<u64>,
+    /// TLS handshake (0 on a reused connection).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_ms: Option<u64>,
+    /// Sending the request until upstream response headers arrived,
+    /// including connection acquisition.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttfb_ms: Option<u64>,
+    /// Same as `ttfb_ms`; the frontend and older backends read it as TTFB.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_ms: Option<u64>,
+    /// Upstream processing: `ttfb_ms` minus `connection_acquire_ms`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_wait_ms: Option<u64>,
+    /// Proxy receipt of the request until upstream response headers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_ms: Option<u64>,
+    /// Request body bytes forwarded upstream.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_body_bytes: Option<u64>,
+    /// Upstream attempts beyond the first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// How connection phases were measured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timing_source: Option<String>,
+}
+
+impl ProxyTiming {
+    pub fn new(mode: &str) -> Self {
+        Self {
+            mode: mode.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Serialize for the response header.
+    ///
+    /// With `legacy_keys`, the pre-`ProxyTiming` key names
+    /// (`upstream_processing_ms`, `body_size`) are
+    /// emitted alongside the current ones for backends that have not yet
+    /// moved over.
+    pub fn header_value(&self, legacy_keys: bool) -> String {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        if legacy_keys {
+            if let Some(obj) = value.as_object_mut() {
+                if let Some(wait_ms) = self.response_wait_ms {
+                    obj.insert("upstream_processing_ms".into(), wait_ms.into());
+                }
+                if let Some(bytes) = self.request_body_bytes {
+                    obj.insert("body_size".into(), bytes.into());
+                }
+            }
+        }
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn absent_phases_are_omitted_and_round_trip() {
+        let timing = ProxyTiming {
+            dns_ms: Some(3),
+            ttfb_ms: Some(120),
+            upstream_ms: Some(120),
+            response_wait_ms: Some(100),
+            request_body_bytes: Some(42),
+            ..ProxyTiming::new("tunnel")
+        };
+        let header = timing.header_value(false);
+        let json: serde_json::Value = serde_json::from_str(&header).unwrap();
+        assert!(json.get("retries").is_none());
+        assert!(json.get("body_size").is_none());
+        assert_eq!(
+            serde_json::from_str::<ProxyTiming>(&header).unwrap(),
+            timing
+        );
+    }
+
+    #[test]
+    fn legacy_keys_mirror_current_fields() {
+        let timing = ProxyTiming {
+            ttfb_ms: Some(120),
+            upstream_ms: Some(120),
+            response_wait_ms: Some(100),
+            request_body_bytes: Some(42),
+            ..ProxyTiming::new("tunnel")
+        };
+        let json: serde_json::Value = serde_json::from_str(&timing.header_value(true)).unwrap();
+        assert_eq!(json["upstream_ms"], 120);
+        assert_eq!(json["response_wait_ms"], 100);
+        assert_eq!(json["upstream_processing_ms"], 100);
+        assert_eq!(json["body_size"], 42);
+        // Unknown legacy keys are ignored when parsing.
+        let parsed: ProxyTiming = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, timing);
+    }
+}
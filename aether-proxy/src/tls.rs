@@ -0,0 +1,120 @@
+//! TLS root certificate store selection.
+//!
+//! Shared by the tunnel/Aether API clients and the upstream client.  The
+//! bundled webpki roots are the default; the OS store can be used instead
+//! (or in addition) for environments that intercept outbound TLS with an
+//! internal CA, and an extra PEM bundle can be appended on top.
+
+use std::fmt;
+use std::path::Path;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use rustls::RootCertStore;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Which root certificates to trust.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsRoots {
+    /// Bundled Mozilla roots (webpki-roots)
+    Webpki,
+    /// Operating system certificate store
+    Native,
+    /// Bundled roots plus the OS store
+    Both,
+}
+
+impl fmt::Display for TlsRoots {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Webpki => "webpki",
+            Self::Native => "native",
+            Self::Both => "both",
+        })
+    }
+}
+
+/// A loaded root store plus a human-readable summary of where it came from.
+#[derive(Clone)]
+pub struct RootStore {
+    pub store: RootCertStore,
+    pub description: String,
+}
+
+impl RootStore {
+    /// These roots plus the certificates in `ca_file`, or only the latter
+    /// with `only_custom` (a server's `tls_ca_cert`).
+    pub fn with_ca_file(&self, ca_file: &str, only_custom: bool) -> anyhow::Result<RootStore> {
+        let mut store = if only_custom {
+            RootCertStore::empty()
+        } else {
+            self.store.clone()
+        };
+        let added = add_pem_file(&mut store, Path::new(ca_file))?;
+        let custom = format!("{} ({} certs)", ca_file, added);
+        let description = if only_custom {
+            custom
+        } else {
+            format!("{} + {}", self.description, custom)
+        };
+        Ok(RootStore { store, description })
+    }
+}
+
+/// Build a root store for `roots`, appending certificates from `extra_ca_file`.
+///
+/// Failure to load the OS store degrades to webpki with a warning; an
+/// unreadable or empty `extra_ca_file` is a hard error since it was
+/// configured explicitly.
+pub fn load_root_store(roots: TlsRoots, extra_ca_file: Option<&str>) -> anyhow::Result<RootStore> {
+    let mut store = RootCertStore::empty();
+    let mut parts = Vec::new();
+    let mut use_webpki = matches!(roots, TlsRoots::Webpki | TlsRoots::Both);
+
+    if matches!(roots, TlsRoots::Native | TlsRoots::Both) {
+        let result = rustls_native_certs::load_native_certs();
+        for e in &result.errors {
+            warn!(error = %e, "error loading native root certificates");
+        }
+        let (added, _ignored) = store.add_parsable_certificates(result.certs);
+        if added > 0 {
+            parts.push(format!("native ({} certs)", added));
+        } else {
+            warn!("no usable native root certificates found, falling back to webpki roots");
+            parts.push("native unavailable".to_string());
+            use_webpki = true;
+        }
+    }
+
+    if use_webpki {
+        store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        parts.push(format!(
+            "webpki ({} certs)",
+            webpki_roots::TLS_SERVER_ROOTS.len()
+        ));
+    }
+
+    if let Some(path) = extra_ca_file.filter(|p| !p.is_empty()) {
+        let added = add_pem_file(&mut store, Path::new(path))?;
+        parts.push(format!("{} ({} certs)", path, added));
+    }
+
+    Ok(RootStore {
+        store,
+        description: parts.join(" + "),
+    })
+}
+
+/// Append all certificates from a PEM bundle, returning how many were added.
+fn add_pem_file(store: &mut RootCertStore, path: &Path) -> anyhow::Result<usize> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| anyhow::anyhow!("failed to read CA file {}: {}", path.display(), e))?;
+    let (added, _ignored) = store.add_parsable_certificates(certs);
+    if added == 0 {
+        anyhow::bail!("no valid certificates found in CA file {}", path.display());
+    }
+    Ok(added)
+}
@@ -0,0 +1,108 @@
+//! Per-stream CPU affinity for stream handlers.
+//!
+//! With `enable_stream_affinity`, stream handlers run on one of N
+//! single-threaded runtimes (one per CPU core, each on a thread pinned to
+//! its core where the OS allows it) instead of the shared work-stealing
+//! pool.  A stream always lands on worker `stream_id % N`, so its body
+//! frames, upstream I/O and timers stay on one core.
+
+use std::future::Future;
+use std::io;
+
+use tokio::runtime::{Builder, Handle};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+/// Routes stream handler tasks to per-core single-threaded runtimes.
+pub struct AffinityRouter {
+    workers: Vec<Handle>,
+    /// Dropping these stops the worker threads (and their runtimes).
+    _stop: Vec<oneshot::Sender<()>>,
+}
+
+impl AffinityRouter {
+    /// Start `workers` runtime threads (at least one).
+    pub fn new(workers: usize) -> io::Result<Self> {
+        let workers = workers.max(1);
+        let mut handles = Vec::with_capacity(workers);
+        let mut stop = Vec::with_capacity(workers);
+        for core in 0..workers {
+            let runtime = Builder::new_current_thread().enable_all().build()?;
+            let (stop_tx, stop_rx) = oneshot::channel::<()>();
+            handles.push(runtime.handle().clone());
+            stop.push(stop_tx);
+            std::thread::Builder::new()
+                .name(format!("aether-stream-{core}"))
+                .spawn(move || {
+                    pin_current_thread(core);
+                    let _ = runtime.block_on(stop_rx);
+                    debug!(core, "stream worker stopped");
+                })?;
+        }
+        Ok(Self {
+            workers: handles,
+            _stop: stop,
+        })
+    }
+
+    /// Number of worker runtimes.
+    pub fn workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Spawn `task` on the worker owning `stream_id`.
+    pub fn spawn<F>(&self, stream_id: u32, task: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.workers[worker_index(stream_id, self.workers.len())].spawn(task)
+    }
+}
+
+fn worker_index(stream_id: u32, workers: usize) -> usize {
+    stream_id as usize % workers
+}
+
+/// Pin the calling thread to `core` (Linux only; best effort).
+fn pin_current_thread(core: usize) {
+    #[cfg(target_os = "linux")]
+    {
+        // SAFETY: `set` is a plain bitmask initialised by CPU_ZERO before use,
+        // and pid 0 targets the calling thread.
+        let ret = unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            libc::CPU_ZERO(&mut set);
+            libc::CPU_SET(core, &mut set);
+            libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+        };
+        if ret != 0 {
+            warn!(
+                core,
+                error = %io::Error::last_os_error(),
+                "failed to pin stream worker to core"
+            );
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = core;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn streams_stay_on_their_worker_thread() {
+        let router = AffinityRouter::new(2).unwrap();
+        let thread_name = || std::thread::current().name().unwrap_or("").to_string();
+
+        let a = router.spawn(4, async move { thread_name() }).await.unwrap();
+        let b = router.spawn(6, async move { thread_name() }).await.unwrap();
+        let c = router.spawn(7, async move { thread_name() }).await.unwrap();
+        assert_eq!(a, "aether-stream-0");
+        assert_eq!(b, "aether-stream-0");
+        assert_eq!(c, "aether-stream-1");
+    }
+}
@@ -1,18 +1,34 @@
 //! WebSocket tunnel client: connect, authenticate, and run the tunnel.
 
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use bytes::Bytes;
+use futures_util::{SinkExt, StreamExt};
+
+use rustls::pki_types::ServerName;
 use tokio::net::TcpStream;
 use tokio::sync::watch;
 use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::client::Response;
 use tokio_tungstenite::tungstenite::http;
 use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
 use tracing::{debug, info, warn};
 
+use crate::config::{Config, ServerEntry};
+use crate::net;
+use crate::notification::TunnelEvent;
 use crate::state::{AppState, ServerContext};
 
-use super::{dispatcher, heartbeat, writer};
+use super::protocol::{error_codes, headers as tunnel_headers, Frame, MsgType};
+use super::{dispatcher, heartbeat, routing, writer};
+
+/// Established tunnel WebSocket.
+pub type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
 /// Outcome of a tunnel session.
 pub enum TunnelOutcome {
@@ -32,17 +48,22 @@ pub async fn connect_and_run(
     conn_idx: usize,
     shutdown: &mut watch::Receiver<bool>,
 ) -> Result<TunnelOutcome, anyhow::Error> {
-    let ws_url = build_tunnel_url(server);
+    let ws_url = tunnel_url(&server.aether_url);
     info!(url = %ws_url, conn = conn_idx, "connecting tunnel");
 
     // Build WebSocket request with auth headers
-    let mut request = ws_url.clone().into_client_request()?;
+    let mut request = ws_url.into_client_request()?;
     let headers = request.headers_mut();
     headers.insert(
         "Authorization",
         http::HeaderValue::from_str(&format!("Bearer {}", server.management_token))?,
     );
+    // Aether rejects handshakes without a node ID; `tunnel::run` holds
+    // connections back until registration has produced one.
     let node_id = server.node_id.read().unwrap().clone();
+    if node_id.is_empty() {
+        anyhow::bail!("node is not registered yet");
+    }
     headers.insert("X-Node-Id", http::HeaderValue::from_str(&node_id)?);
     // Use dynamic node_name (may be updated by remote config) instead of
     // the static server.node_name, so that remote name changes take effect
@@ -55,64 +76,44 @@ pub async fn connect_and_run(
     // Advertise per-connection max concurrent streams so the backend can
     // respect the proxy's capacity limit (backward-compatible: old backends
     // ignore this header).
-    let max_streams = state.config.tunnel_max_streams.unwrap_or(128);
+    let max_streams = server.dynamic.load().max_streams;
     headers.insert("X-Tunnel-Max-Streams", http::HeaderValue::from(max_streams));
+    // Offer cross-connection responses; Aether echoes the header when it
+    // can reassemble a stream from frames arriving on any connection.
+    let conn_id = server.connections.next_id();
+    if state.config.tunnel_response_rerouting {
+        headers.insert(
+            tunnel_headers::CROSS_CONNECTION,
+            http::HeaderValue::from_static("1"),
+        );
+        headers.insert(
+            tunnel_headers::CONNECTION_ID,
+            http::HeaderValue::from(conn_id),
+        );
+    }
 
-    // Parse host:port from URL
-    let uri: http::Uri = ws_url.parse()?;
-    let host = uri
-        .host()
-        .ok_or_else(|| anyhow::anyhow!("missing host in tunnel URL"))?;
-    let is_tls = uri.scheme_str() == Some("wss");
-    let port = uri.port_u16().unwrap_or(if is_tls { 443 } else { 80 });
-
-    // TCP connect with timeout
-    let connect_timeout = Duration::from_secs(state.config.tunnel_connect_timeout_secs);
-    let tcp_stream = tokio::time::timeout(connect_timeout, TcpStream::connect((host, port)))
-        .await
-        .map_err(|_| {
-            anyhow::anyhow!(
-                "tunnel TCP connect timeout ({}s)",
-                connect_timeout.as_secs()
-            )
-        })??;
+    // Offer frame sequencing; only used if Aether echoes it.
+    if state.config.enable_frame_sequencing {
+        headers.insert(
+            tunnel_headers::FRAME_SEQUENCING,
+            http::HeaderValue::from_static("1"),
+        );
+    }
 
-    // Configure TCP parameters via socket2
-    configure_tcp_socket(&tcp_stream, state);
+    // Offer trailer forwarding; only used if Aether echoes it.
+    headers.insert(
+        tunnel_headers::RESPONSE_TRAILERS,
+        http::HeaderValue::from_static("1"),
+    );
 
-    // WebSocket upgrade (with TLS if wss://)
-    let connector = if is_tls {
-        Some(tokio_tungstenite::Connector::Rustls(Arc::clone(
-            &state.tunnel_tls_config,
-        )))
-    } else {
-        None
-    };
-    // Match Python-side _MAX_FRAME_SIZE (64 MiB) to prevent tungstenite's
-    // default 16 MiB limit from rejecting large AI API payloads (multi-image
-    // base64 requests can exceed 16 MiB).
-    let ws_config = WebSocketConfig {
-        max_frame_size: Some(64 << 20),
-        max_message_size: Some(64 << 20),
-        ..Default::default()
-    };
-    let handshake_timeout = Duration::from_secs(state.config.tunnel_connect_timeout_secs);
-    let (ws_stream, _response) = tokio::time::timeout(
-        handshake_timeout,
-        tokio_tungstenite::client_async_tls_with_config(
-            request,
-            tcp_stream,
-            Some(ws_config),
-            connector,
-        ),
+    let (ws_stream, response) = open_websocket(
+        &state.config,
+        &server.tls_config,
+        request,
+        server.tls_sni_override.as_deref(),
+        server.bind_address,
     )
-    .await
-    .map_err(|_| {
-        anyhow::anyhow!(
-            "tunnel WebSocket handshake timeout ({}s)",
-            handshake_timeout.as_secs()
-        )
-    })??;
+    .await?;
     info!(
         conn = conn_idx,
         tcp_keepalive_secs = state.config.tunnel_tcp_keepalive_secs,
@@ -121,16 +122,53 @@ pub async fn connect_and_run(
         stale_timeout_secs = state.config.tunnel_stale_timeout_secs,
         "tunnel connected"
     );
+    let rerouting = state.config.tunnel_response_rerouting
+        && accepted(response.headers(), tunnel_headers::CROSS_CONNECTION);
+    if rerouting {
+        info!(
+            conn = conn_idx,
+            conn_id, "cross-connection responses enabled"
+        );
+    }
+    let sequencing = state.config.enable_frame_sequencing
+        && accepted(response.headers(), tunnel_headers::FRAME_SEQUENCING);
+    if sequencing {
+        info!(conn = conn_idx, "frame sequencing enabled");
+    } else if state.config.enable_frame_sequencing {
+        warn!(
+            conn = conn_idx,
+            "Aether did not accept frame sequencing, frames stay unsequenced"
+        );
+    }
+    let trailers = accepted(response.headers(), tunnel_headers::RESPONSE_TRAILERS);
+    if !trailers {
+        debug!(
+            conn = conn_idx,
+            "Aether did not accept response trailers, upstream trailers are dropped"
+        );
+    }
+    state.notify(TunnelEvent::Connected, server, conn_idx, None);
+
+    if conn_idx == 0 {
+        server.circuit_open.store(false, Ordering::Release);
+    }
 
     // NOTE: reconnect_attempts reset is handled by the caller (mod.rs)
     // based on how long the connection stayed alive.
 
     // Split into read/write halves
-    let (ws_sink, ws_read) = futures_util::StreamExt::split(ws_stream);
+    let (ws_sink, ws_read) = ws_stream.split();
 
     // Spawn writer task (with WebSocket ping keepalive)
     let ping_interval = Duration::from_secs(state.config.tunnel_ping_interval_secs);
-    let (frame_tx, mut writer_handle) = writer::spawn_writer(ws_sink, ping_interval);
+    let (frame_tx, writer_dequeued, mut writer_handle) = writer::spawn_writer(
+        ws_sink,
+        ping_interval,
+        state.config.ws_max_message_size_bytes,
+    );
+    // Registered connections take frames rerouted from their siblings.
+    let registered: Option<routing::RegisteredLink> =
+        rerouting.then(|| server.connections.register(conn_id, frame_tx.clone()));
 
     // Spawn heartbeat task (only for primary connection to avoid
     // resetting shared atomic metrics via swap(0))
@@ -138,6 +176,7 @@ pub async fn connect_and_run(
         heartbeat::spawn(
             Arc::clone(&state.config),
             Arc::clone(server),
+            Arc::clone(&state.memory),
             frame_tx.clone(),
             shutdown.clone(),
         )
@@ -145,7 +184,8 @@ pub async fn connect_and_run(
         heartbeat::spawn_noop()
     };
 
-    // Run dispatcher (blocks until disconnect or shutdown).
+    // Run dispatcher (blocks until disconnect or shutdown; on shutdown it
+    // drains in-flight streams before returning).
     // Also watch for writer exit — if the write half dies (e.g. the peer
     // closed the connection) but the read half stays open, dispatcher would
     // block forever on `ws_stream.next()`.  Monitoring `writer_handle`
@@ -153,10 +193,30 @@ pub async fn connect_and_run(
     let state_clone = Arc::clone(state);
     let server_clone = Arc::clone(server);
     let outcome = tokio::select! {
-        result = dispatcher::run(state_clone, server_clone, ws_read, frame_tx.clone(), hb_handle) => {
+        result = dispatcher::run(
+            state_clone,
+            server_clone,
+            conn_idx,
+            ws_read,
+            frame_tx.clone(),
+            writer_dequeued,
+            registered.as_ref().map(|r| Arc::clone(r.link())),
+            sequencing,
+            trailers,
+            hb_handle,
+            shutdown.clone(),
+        ) => {
             match result {
-                Ok(()) => TunnelOutcome::Disconnected,
-                Err(e) => return Err(e),
+                Ok(()) if *shutdown.borrow() => TunnelOutcome::Shutdown,
+                Ok(()) => {
+                    state.notify(TunnelEvent::Disconnected, server, conn_idx, Some("connection closed"));
+                    TunnelOutcome::Disconnected
+                }
+                Err(e) => {
+                    let reason = format!("{:#}", e);
+                    state.notify(TunnelEvent::Disconnected, server, conn_idx, Some(&reason));
+                    return Err(e);
+                }
             }
         }
         writer_result = &mut writer_handle => {
@@ -170,14 +230,13 @@ pub async fn connect_and_run(
                     }
                 }
             }
+            state.notify(TunnelEvent::Disconnected, server, conn_idx, Some("writer task exited"));
             TunnelOutcome::Disconnected
         }
-        _ = shutdown.changed() => {
-            debug!("shutdown during tunnel dispatch");
-            TunnelOutcome::Shutdown
-        }
     };
 
+    // Unregister first: the registry holds a sender too.
+    drop(registered);
     // Drop our sender; the writer will exit once all stream handler clones
     // are also dropped (i.e. after they finish their in-flight work).
     drop(frame_tx);
@@ -194,13 +253,200 @@ pub async fn connect_and_run(
     Ok(outcome)
 }
 
-/// Configure TCP keepalive and NODELAY on an established socket.
-fn configure_tcp_socket(stream: &TcpStream, state: &Arc<AppState>) {
+/// Open an authenticated WebSocket to the tunnel endpoint: TCP connect,
+/// socket tuning, then the (TLS) WebSocket upgrade, each bounded by
+/// `tunnel_connect_timeout_secs`.  Returns the stream together with the
+/// upgrade response so callers can read negotiated headers.
+///
+/// `sni_override` replaces the URL host as the TLS server name (SNI and
+/// certificate verification); the Host header still follows the URL.
+/// `bind_address` is the local address to connect from.
+pub async fn open_websocket(
+    config: &Config,
+    tls_config: &Arc<rustls::ClientConfig>,
+    request: http::Request<()>,
+    sni_override: Option<&str>,
+    bind_address: Option<IpAddr>,
+) -> anyhow::Result<(WsStream, Response)> {
+    // Parse host:port from URL
+    let uri = request.uri().clone();
+    let host = uri
+        .host()
+        .ok_or_else(|| anyhow::anyhow!("missing host in tunnel URL"))?;
+    let is_tls = uri.scheme_str() == Some("wss");
+    let port = uri.port_u16().unwrap_or(if is_tls { 443 } else { 80 });
+    let sni = match sni_override {
+        Some(name) if is_tls => Some(
+            ServerName::try_from(name.to_string())
+                .map_err(|e| anyhow::anyhow!("invalid tls_sni_override '{}': {}", name, e))?,
+        ),
+        _ => None,
+    };
+
+    // TCP connect with timeout
+    let connect_timeout = Duration::from_secs(config.tunnel_connect_timeout_secs);
+    let connect = async {
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port)).await?.collect();
+        net::connect_tcp(&addrs, bind_address).await
+    };
+    let tcp_stream = tokio::time::timeout(connect_timeout, connect)
+        .await
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "tunnel TCP connect timeout ({}s)",
+                connect_timeout.as_secs()
+            )
+        })??;
+
+    // Configure TCP parameters via socket2
+    configure_tcp_socket(&tcp_stream, config);
+
+    // WebSocket upgrade (with TLS if wss://)
+    let connector = if is_tls {
+        Some(tokio_tungstenite::Connector::Rustls(Arc::clone(tls_config)))
+    } else {
+        None
+    };
+    // Match Python-side _MAX_FRAME_SIZE (64 MiB) to prevent tungstenite's
+    // default 16 MiB limit from rejecting large AI API payloads (multi-image
+    // base64 requests can exceed 16 MiB).
+    let ws_config = WebSocketConfig {
+        max_frame_size: Some(64 << 20),
+        max_message_size: Some(64 << 20),
+        ..Default::default()
+    };
+    let handshake_timeout = Duration::from_secs(config.tunnel_connect_timeout_secs);
+    let handshake = async {
+        match sni {
+            // The default connector derives SNI from the URL, so do the TLS
+            // handshake ourselves and upgrade over the established stream.
+            Some(sni) => {
+                let tls = tokio_rustls::TlsConnector::from(Arc::clone(tls_config))
+                    .connect(sni, tcp_stream)
+                    .await?;
+                tokio_tungstenite::client_async_with_config(
+                    request,
+                    MaybeTlsStream::Rustls(tls),
+                    Some(ws_config),
+                )
+                .await
+            }
+            None => {
+                tokio_tungstenite::client_async_tls_with_config(
+                    request,
+                    tcp_stream,
+                    Some(ws_config),
+                    connector,
+                )
+                .await
+            }
+        }
+    };
+    let handshake = tokio::time::timeout(handshake_timeout, handshake)
+        .await
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "tunnel WebSocket handshake timeout ({}s)",
+                handshake_timeout.as_secs()
+            )
+        })??;
+    Ok(handshake)
+}
+
+/// Open a tunnel WebSocket without registering or running the dispatcher
+/// and measure `count` WebSocket Ping/Pong round trips.
+///
+/// Aether only accepts tunnels for a known node, so the handshake carries
+/// `node_id` and the probe joins that node's connection pool.  It sends
+/// GOAWAY at once and refuses any stream routed to it anyway with a
+/// retry-safe `connection_rotating` error.
+///
+/// `on_reply` is called after each ping with its sequence number (1-based)
+/// and the RTT, or `None` if no Pong arrived within `timeout`.
+#[allow(clippy::too_many_arguments)]
+pub async fn ping(
+    config: &Config,
+    tls_config: &Arc<rustls::ClientConfig>,
+    server: &ServerEntry,
+    node_id: &str,
+    count: u32,
+    interval: Duration,
+    timeout: Duration,
+    mut on_reply: impl FnMut(u32, Option<Duration>),
+) -> anyhow::Result<()> {
+    let mut request = tunnel_url(&server.aether_url).into_client_request()?;
+    let headers = request.headers_mut();
+    headers.insert(
+        "Authorization",
+        http::HeaderValue::from_str(&format!("Bearer {}", server.management_token))?,
+    );
+    headers.insert("X-Node-Id", http::HeaderValue::from_str(node_id)?);
+    let node_name = server.node_name.as_deref().unwrap_or(&config.node_name);
+    headers.insert("X-Node-Name", http::HeaderValue::from_str(node_name)?);
+    let (mut ws, _) = open_websocket(
+        config,
+        tls_config,
+        request,
+        server.tls_sni_override.as_deref(),
+        server.bind_address,
+    )
+    .await?;
+    let goaway = Frame::new(0, MsgType::GoAway, 0, Bytes::new());
+    ws.send(Message::Binary(goaway.encode().to_vec())).await?;
+
+    for seq in 1..=count {
+        if seq > 1 {
+            tokio::time::sleep(interval).await;
+        }
+        let payload = seq.to_be_bytes().to_vec();
+        let sent_at = Instant::now();
+        ws.send(Message::Ping(payload.clone())).await?;
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        let rtt = loop {
+            match tokio::time::timeout_at(deadline, ws.next()).await {
+                Ok(Some(Ok(Message::Pong(p)))) if p == payload => break Some(sent_at.elapsed()),
+                Ok(Some(Ok(Message::Binary(data)))) => {
+                    if let Some(refusal) = refuse_stream(data.into()) {
+                        ws.send(Message::Binary(refusal.encode().to_vec())).await?;
+                    }
+                }
+                Ok(Some(Ok(_))) => continue, // unrelated traffic
+                Ok(Some(Err(e))) => return Err(e.into()),
+                Ok(None) => anyhow::bail!("connection closed by server"),
+                Err(_) => break None,
+            }
+        };
+        on_reply(seq, rtt);
+    }
+
+    let _ = ws.close(None).await;
+    Ok(())
+}
+
+/// Whether Aether's handshake response accepted the capability offered
+/// with `header`.
+fn accepted(response: &http::HeaderMap, header: &str) -> bool {
+    response.get(header).is_some_and(|v| v == "1")
+}
+
+/// StreamError for a stream Aether opened on a probe connection, which
+/// never serves requests.
+fn refuse_stream(data: Bytes) -> Option<Frame> {
+    let frame = Frame::decode(data).ok()?;
+    (frame.msg_type == MsgType::RequestHeaders).then(|| {
+        let msg = format!("{}: retry_safe=true", error_codes::CONNECTION_ROTATING);
+        Frame::new(frame.stream_id, MsgType::StreamError, 0, Bytes::from(msg))
+    })
+}
+
+/// Configure TCP keepalive, NODELAY and low-water marks on an established socket.
+fn configure_tcp_socket(stream: &TcpStream, config: &Config) {
     let sock_ref = socket2::SockRef::from(stream);
 
-    if state.config.tunnel_tcp_keepalive_secs > 0 {
+    if config.tunnel_tcp_keepalive_secs > 0 {
         let keepalive = socket2::TcpKeepalive::new()
-            .with_time(Duration::from_secs(state.config.tunnel_tcp_keepalive_secs))
+            .with_time(Duration::from_secs(config.tunnel_tcp_keepalive_secs))
             .with_interval(Duration::from_secs(5));
         #[cfg(not(target_os = "windows"))]
         let keepalive = keepalive.with_retries(3);
@@ -209,24 +455,114 @@ fn configure_tcp_socket(stream: &TcpStream, state: &Arc<AppState>) {
         }
     }
 
-    if state.config.tunnel_tcp_nodelay {
+    if config.tunnel_tcp_nodelay {
         if let Err(e) = sock_ref.set_nodelay(true) {
             warn!(error = %e, "failed to set TCP_NODELAY on tunnel socket");
         }
     }
+
+    #[cfg(unix)]
+    if config.tunnel_tcp_recv_lowat.is_some() || config.tunnel_tcp_send_lowat.is_some() {
+        for (option, name, value) in [
+            (
+                libc::SO_RCVLOWAT,
+                "SO_RCVLOWAT",
+                config.tunnel_tcp_recv_lowat,
+            ),
+            (
+                libc::SO_SNDLOWAT,
+                "SO_SNDLOWAT",
+                config.tunnel_tcp_send_lowat,
+            ),
+        ] {
+            if let Some(bytes) = value {
+                if let Err(e) = set_socket_int(stream, option, bytes.min(i32::MAX as u32) as i32) {
+                    warn!(error = %e, option = name, "failed to set low-water mark on tunnel socket");
+                }
+            }
+        }
+        tracing::debug!(
+            rcvlowat = get_socket_int(stream, libc::SO_RCVLOWAT).ok(),
+            sndlowat = get_socket_int(stream, libc::SO_SNDLOWAT).ok(),
+            rcvbuf = sock_ref.recv_buffer_size().ok(),
+            sndbuf = sock_ref.send_buffer_size().ok(),
+            "tunnel socket buffer settings"
+        );
+    }
+}
+
+#[cfg(unix)]
+fn set_socket_int(stream: &TcpStream, option: libc::c_int, value: i32) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let value: libc::c_int = value;
+    let ret = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(unix)]
+fn get_socket_int(stream: &TcpStream, option: libc::c_int) -> std::io::Result<i32> {
+    use std::os::unix::io::AsRawFd;
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret == 0 {
+        Ok(value)
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
 }
 
-/// Build rustls ClientConfig with system root certificates.
-pub fn build_tls_config() -> rustls::ClientConfig {
-    let root_store =
-        rustls::RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+/// Build the rustls ClientConfig for the tunnel (and Aether API) from the
+/// configured root store.
+pub fn build_tls_config(roots: crate::tls::RootStore) -> rustls::ClientConfig {
     rustls::ClientConfig::builder()
-        .with_root_certificates(root_store)
+        .with_root_certificates(roots.store)
         .with_no_client_auth()
 }
 
-fn build_tunnel_url(server: &ServerContext) -> String {
-    let base = server.aether_url.trim_end_matches('/');
+/// TLS config for a server's tunnel and API client: `shared` unless the
+/// entry sets `tls_ca_cert`, in which case that CA is added to (or, with
+/// `tls_ca_only_custom`, replaces) `roots`.
+pub fn server_tls_config(
+    shared: &Arc<rustls::ClientConfig>,
+    roots: &crate::tls::RootStore,
+    server: &ServerEntry,
+) -> anyhow::Result<Arc<rustls::ClientConfig>> {
+    match server.tls_ca_cert.as_deref().filter(|p| !p.is_empty()) {
+        None if server.tls_ca_only_custom => {
+            anyhow::bail!("tls_ca_only_custom requires tls_ca_cert")
+        }
+        None => Ok(Arc::clone(shared)),
+        Some(path) => {
+            let roots = roots.with_ca_file(path, server.tls_ca_only_custom)?;
+            Ok(Arc::new(build_tls_config(roots)))
+        }
+    }
+}
+
+/// WebSocket tunnel endpoint for an Aether base URL.
+pub fn tunnel_url(aether_url: &str) -> String {
+    let base = aether_url.trim_end_matches('/');
     let ws_base = if base.starts_with("https://") {
         base.replacen("https://", "wss://", 1)
     } else if base.starts_with("http://") {
@@ -236,3 +572,115 @@ fn build_tunnel_url(server: &ServerContext) -> String {
     };
     format!("{}/api/internal/proxy-tunnel", ws_base)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use aether_proxy_test_utils::MockAetherServer;
+    use clap::Parser;
+
+    fn probe(url: &str) -> (Config, Arc<rustls::ClientConfig>, ServerEntry) {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let config = Config::try_parse_from([
+            "aether-proxy",
+            &format!("--aether-url={url}"),
+            "--management-token=ae_x",
+            "--node-name=jp-01",
+        ])
+        .unwrap();
+        let tls = Arc::new(build_tls_config(crate::tls::RootStore {
+            store: rustls::RootCertStore::empty(),
+            description: String::new(),
+        }));
+        let server = ServerEntry {
+            aether_url: url.to_string(),
+            management_token: "ae_x".to_string(),
+            node_name: None,
+            node_region: None,
+            node_tags: Default::default(),
+            role: Default::default(),
+            tls_sni_override: None,
+            tls_ca_cert: None,
+            tls_ca_only_custom: false,
+            bind_address: None,
+        };
+        (config, tls, server)
+    }
+
+    #[tokio::test]
+    async fn ping_identifies_the_node_and_refuses_streams() {
+        let aether = MockAetherServer::builder().build().await;
+        let (config, tls, server) = probe(&aether.url());
+
+        let pinging = tokio::spawn(async move {
+            let mut replies = Vec::new();
+            ping(
+                &config,
+                &tls,
+                &server,
+                "node-1",
+                3,
+                Duration::from_millis(100),
+                Duration::from_secs(5),
+                |seq, rtt| replies.push((seq, rtt.is_some())),
+            )
+            .await
+            .map(|()| replies)
+        });
+        let mut tunnel = aether.accept_tunnel().await;
+        assert_eq!(tunnel.header("X-Node-Id"), Some("node-1"));
+        assert_eq!(tunnel.header("X-Node-Name"), Some("jp-01"));
+
+        // Aether routed a request to the probe anyway.
+        let meta = crate::tunnel::protocol::RequestMeta {
+            method: "GET".into(),
+            url: "https://example.com/".into(),
+            headers: Default::default(),
+            timeout: 5,
+            request_id: None,
+            request_fingerprint: None,
+            body_sha256: None,
+            websocket: false,
+        };
+        tunnel.send_request(1, &meta, b"");
+        let response = tunnel.expect_response(1).await;
+        assert_eq!(
+            response.error.as_deref(),
+            Some("connection_rotating: retry_safe=true")
+        );
+
+        let replies = pinging.await.unwrap().unwrap();
+        assert_eq!(replies, [(1, true), (2, true), (3, true)]);
+    }
+
+    #[test]
+    fn capabilities_need_aethers_echo() {
+        let mut response = http::HeaderMap::new();
+        assert!(!accepted(&response, tunnel_headers::FRAME_SEQUENCING));
+        response.insert(tunnel_headers::FRAME_SEQUENCING, "0".parse().unwrap());
+        assert!(!accepted(&response, tunnel_headers::FRAME_SEQUENCING));
+        response.insert(tunnel_headers::FRAME_SEQUENCING, "1".parse().unwrap());
+        assert!(accepted(&response, tunnel_headers::FRAME_SEQUENCING));
+        assert!(!accepted(&response, tunnel_headers::CROSS_CONNECTION));
+    }
+
+    #[tokio::test]
+    async fn tunnels_without_a_node_id_are_rejected() {
+        let aether = MockAetherServer::builder().build().await;
+        let (config, tls, server) = probe(&aether.url());
+        let result = ping(
+            &config,
+            &tls,
+            &server,
+            "",
+            1,
+            Duration::ZERO,
+            Duration::from_secs(1),
+            |_, _| {},
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(aether.rejected_tunnels(), 1);
+    }
+}
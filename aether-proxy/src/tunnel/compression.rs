@@ -0,0 +1,279 @@
+//! Per-stream compression decisions for response body frames.
+//!
+//! Tunnel frames are gzipped once they reach `compression_min_size` and
+//! the result is smaller.  SSE streams are many small, latency-sensitive
+//! chunks where that only costs CPU, so each response body gets its own
+//! decision: `text/event-stream` is never compressed, a chunked response
+//! whose first chunks are small stops being compressed, and
+//! `compression_content_types` overrides both per content type.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+
+use bytes::Bytes;
+use hyper::header::{HeaderMap, CONTENT_TYPE, TRANSFER_ENCODING};
+use serde::{Deserialize, Serialize};
+
+use super::protocol::compress_payload;
+use crate::state::ProxyMetrics;
+
+/// Chunks of a chunked response sampled before judging its cadence.
+const CADENCE_SAMPLE_CHUNKS: u64 = 4;
+/// Below this average chunk size a chunked response is streamed as-is.
+const SMALL_CHUNK_AVG: u64 = 2048;
+
+/// How one content type is compressed: `"off"`, `"on"` (the current
+/// `compression_min_size`) or a minimum size in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RuleRepr", into = "RuleRepr")]
+pub enum ContentTypeRule {
+    Off,
+    On,
+    MinSize(usize),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RuleRepr {
+    MinSize(usize),
+    Switch(String),
+}
+
+impl TryFrom<RuleRepr> for ContentTypeRule {
+    type Error = String;
+
+    fn try_from(repr: RuleRepr) -> Result<Self, Self::Error> {
+        match repr {
+            RuleRepr::MinSize(size) => Ok(Self::MinSize(size)),
+            RuleRepr::Switch(s) => match s.to_ascii_lowercase().as_str() {
+                "off" => Ok(Self::Off),
+                "on" => Ok(Self::On),
+                _ => Err(format!(
+                    "expected \"on\", \"off\" or a minimum size, got \"{s}\""
+                )),
+            },
+        }
+    }
+}
+
+impl From<ContentTypeRule> for RuleRepr {
+    fn from(rule: ContentTypeRule) -> Self {
+        match rule {
+            ContentTypeRule::Off => Self::Switch("off".to_string()),
+            ContentTypeRule::On => Self::Switch("on".to_string()),
+            ContentTypeRule::MinSize(size) => Self::MinSize(size),
+        }
+    }
+}
+
+/// Rules by content type (`type/subtype` or `type/*`, case-insensitive).
+/// Parsed from a JSON object on the command line and in the environment;
+/// a `[compression.content_types]` table in the config file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ContentTypeRules(pub BTreeMap<String, ContentTypeRule>);
+
+impl ContentTypeRules {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The rule for a `Content-Type` value: an exact match on its
+    /// `type/subtype`, else a `type/*` entry.
+    fn rule_for(&self, content_type: &str) -> Option<ContentTypeRule> {
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let wildcard = essence.split_once('/').map(|(kind, _)| format!("{kind}/*"));
+        let find = |key: &str| {
+            self.0
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, rule)| *rule)
+        };
+        find(&essence).or_else(|| wildcard.as_deref().and_then(find))
+    }
+}
+
+impl FromStr for ContentTypeRules {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rules: Self = serde_json::from_str(s).map_err(|e| e.to_string())?;
+        if let Some(key) = rules.0.keys().find(|k| !k.contains('/')) {
+            return Err(format!(
+                "compression_content_types: '{key}' is not a type/subtype"
+            ));
+        }
+        Ok(rules)
+    }
+}
+
+impl fmt::Display for ContentTypeRules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&serde_json::to_string(self).map_err(|_| fmt::Error)?)
+    }
+}
+
+/// Compression decision for one response body.
+#[derive(Debug)]
+pub struct ResponseCompression {
+    /// Minimum chunk size to compress, `None` = never.
+    min_size: Option<usize>,
+    /// `(chunks, bytes)` seen so far of a chunked response still being
+    /// sampled.
+    cadence: Option<(u64, u64)>,
+}
+
+impl ResponseCompression {
+    /// Decide from the upstream response `headers`.  `min_size` is the
+    /// tunnel's current setting (`None` when compression is disabled, which
+    /// no rule overrides).
+    pub fn for_response(
+        headers: &HeaderMap,
+        min_size: Option<usize>,
+        rules: &ContentTypeRules,
+    ) -> Self {
+        let content_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        let chunked = headers
+            .get_all(TRANSFER_ENCODING)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .any(|v| v.to_ascii_lowercase().contains("chunked"));
+        let (min_size, cadence) = match (min_size, rules.rule_for(content_type)) {
+            (None, _) | (_, Some(ContentTypeRule::Off)) => (None, false),
+            (base, Some(ContentTypeRule::On)) => (base, false),
+            (_, Some(ContentTypeRule::MinSize(size))) => (Some(size), false),
+            (_, None) if is_event_stream(content_type) => (None, false),
+            (base, None) => (base, chunked),
+        };
+        Self {
+            min_size,
+            cadence: cadence.then_some((0, 0)),
+        }
+    }
+
+    /// Count a body chunk toward the cadence sample; a chunked response
+    /// whose first chunks average under `SMALL_CHUNK_AVG` is not compressed
+    /// from then on.
+    pub fn observe_chunk(&mut self, len: usize) {
+        let Some((chunks, bytes)) = &mut self.cadence else {
+            return;
+        };
+        *chunks += 1;
+        *bytes += len as u64;
+        if *chunks >= CADENCE_SAMPLE_CHUNKS {
+            if *bytes / *chunks < SMALL_CHUNK_AVG {
+                self.min_size = None;
+            }
+            self.cadence = None;
+        }
+    }
+
+    /// Payload and flags for a ResponseBody frame carrying `data`, counting
+    /// what went through the compressor in `metrics`.
+    pub fn compress(&self, data: Bytes, metrics: &ProxyMetrics) -> (Bytes, u8) {
+        match self.min_size {
+            Some(min_size) if data.len() >= min_size => {
+                let len = data.len() as u64;
+                let (payload, flags) = compress_payload(data, Some(min_size));
+                metrics
+                    .compression_bytes_in
+                    .fetch_add(len, Ordering::Relaxed);
+                metrics
+                    .compression_bytes_out
+                    .fetch_add(payload.len() as u64, Ordering::Relaxed);
+                (payload, flags)
+            }
+            _ => (data, 0),
+        }
+    }
+}
+
+fn is_event_stream(content_type: &str) -> bool {
+    content_type
+        .split(';')
+        .next()
+        .is_some_and(|essence| essence.trim().eq_ignore_ascii_case("text/event-stream"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tunnel::protocol::flags;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn content_type_decides_and_rules_override() {
+        let rules: ContentTypeRules =
+            r#"{"application/json": 4096, "image/*": "off", "text/event-stream": "on"}"#
+                .parse()
+                .unwrap();
+        let none = ContentTypeRules::default();
+        let decide = |h: &[(&'static str, &'static str)], rules: &ContentTypeRules| {
+            ResponseCompression::for_response(&headers(h), Some(512), rules).min_size
+        };
+
+        let sse = [("content-type", "text/event-stream; charset=utf-8")];
+        assert_eq!(decide(&sse, &none), None);
+        assert_eq!(decide(&sse, &rules), Some(512));
+        assert_eq!(
+            decide(&[("content-type", "Application/JSON")], &rules),
+            Some(4096)
+        );
+        assert_eq!(decide(&[("content-type", "image/png")], &rules), None);
+        assert_eq!(decide(&[("content-type", "text/plain")], &rules), Some(512));
+        // Disabled compression stays disabled.
+        let off = ResponseCompression::for_response(&headers(&sse), None, &rules);
+        assert_eq!(off.min_size, None);
+
+        assert!("{\"json\": \"on\"}".parse::<ContentTypeRules>().is_err());
+        assert!("{\"text/html\": \"maybe\"}"
+            .parse::<ContentTypeRules>()
+            .is_err());
+        let toml_rules: ContentTypeRules = toml::from_str("\"text/html\" = 1024").unwrap();
+        assert_eq!(toml_rules.to_string(), r#"{"text/html":1024}"#);
+    }
+
+    #[test]
+    fn small_chunk_cadence_stops_compression() {
+        let metrics = ProxyMetrics::new();
+        let chunked = headers(&[("transfer-encoding", "chunked")]);
+        let mut small = ResponseCompression::for_response(&chunked, Some(512), &Default::default());
+        let data = Bytes::from(vec![b'a'; 1024]);
+        for _ in 0..CADENCE_SAMPLE_CHUNKS - 1 {
+            small.observe_chunk(data.len());
+            assert_eq!(
+                small.compress(data.clone(), &metrics).1,
+                flags::GZIP_COMPRESSED
+            );
+        }
+        small.observe_chunk(data.len());
+        assert_eq!(small.compress(data.clone(), &metrics), (data.clone(), 0));
+        assert_eq!(
+            metrics.compression_bytes_in.load(Ordering::Relaxed),
+            3 * 1024
+        );
+        assert!(metrics.compression_bytes_out.load(Ordering::Relaxed) < 3 * 1024);
+
+        let mut large = ResponseCompression::for_response(&chunked, Some(512), &Default::default());
+        for _ in 0..CADENCE_SAMPLE_CHUNKS {
+            large.observe_chunk(8192);
+        }
+        assert_eq!(large.compress(data, &metrics).1, flags::GZIP_COMPRESSED);
+    }
+}
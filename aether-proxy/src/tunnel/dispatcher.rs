@@ -1,30 +1,100 @@
 //! Frame dispatcher: reads incoming WebSocket frames and routes them.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
 use bytes::Bytes;
 use futures_util::StreamExt;
-use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, watch, Notify};
 use tokio::task::JoinHandle;
 use tokio_tungstenite::tungstenite::Message;
 use tracing::{debug, error, info, warn};
 
-use crate::state::{AppState, ServerContext};
+use crate::memory::MemoryPressure;
+use crate::runtime::SharedDynamicConfig;
+use crate::state::{AppState, ProxyMetrics, ServerContext};
 
 use super::heartbeat::HeartbeatHandle;
-use super::protocol::{decompress_if_gzip, Frame, MsgType, RequestMeta};
+use super::protocol::{decompress_if_gzip, error_codes, Frame, MsgType, RequestMeta};
+use super::routing::{self, ConnectionLink, StreamRoute};
 use super::stream_handler;
 use super::writer::FrameSender;
 
+/// Grace period for in-flight stream handlers once the read loop exits
+/// because Aether closed the connection (proxy-initiated rotations use
+/// `connection_rotation_grace_secs` instead).
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long the read loop waits on a full per-stream body channel before
+/// giving up on that stream.  Bounds how long one slow handler can stall
+/// every other stream on the connection.
+const BODY_BACKPRESSURE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Longest the read loop pauses for a congested writer before reading the
+/// next frame anyway (so pings, ACKs and body frames keep flowing).
+const WRITER_PAUSE_MAX: Duration = Duration::from_secs(1);
+
+/// How long an idle connection has to answer the staleness probe.
+const STALE_PROBE_GRACE: Duration = Duration::from_secs(5);
+
+/// Most sequenced body frames held per stream while waiting for a missing one.
+const MAX_OUT_OF_ORDER_FRAMES: usize = 64;
+
+/// A spawned stream handler and how much of its response reached Aether.
+struct StreamTask {
+    stream_id: u32,
+    request_id: String,
+    handle: JoinHandle<()>,
+    response_bytes: Arc<AtomicU64>,
+}
+
+/// Outcome of waiting for stream handlers after the read loop exits.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct DrainOutcome {
+    /// Handlers that finished within the grace period.
+    drained: u64,
+    /// Handlers aborted at the deadline: stream id, request id and whether
+    /// a retry is safe (no response bytes were forwarded).
+    aborted: Vec<(u32, String, bool)>,
+}
+
+/// Result of forwarding a frame to a stream's body channel.
+#[derive(Debug, PartialEq, Eq)]
+enum BodyForward {
+    Sent,
+    /// Channel stayed full past the backpressure timeout.
+    Backpressure,
+    /// Handler already finished and dropped its receiver.
+    Closed,
+}
+
 /// Run the dispatcher loop, reading from the WebSocket stream.
+///
+/// On local shutdown the loop stops accepting frames and drains in-flight
+/// streams, recording the outcome in the server's drain stats.
+///
+/// `conn_idx` is this connection's place in the pool; it staggers rotation
+/// onto a new node_id.  `link` is set when Aether accepted cross-connection
+/// responses: streams may then finish over another connection if this one
+/// is lost.
+/// `sequencing` is set when Aether accepted frame sequencing, `trailers`
+/// when it accepted response trailers.
+#[allow(clippy::too_many_arguments)]
 pub async fn run<S>(
     state: Arc<AppState>,
     server: Arc<ServerContext>,
+    conn_idx: usize,
     mut ws_stream: S,
     frame_tx: FrameSender,
+    writer_dequeued: Arc<Notify>,
+    link: Option<Arc<ConnectionLink>>,
+    sequencing: bool,
+    trailers: bool,
     heartbeat: HeartbeatHandle,
+    mut shutdown: watch::Receiver<bool>,
 ) -> Result<(), anyhow::Error>
 where
     S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>>
@@ -34,28 +104,89 @@ where
 {
     // Active streams: stream_id -> body sender
     let mut streams: HashMap<u32, mpsc::Sender<Frame>> = HashMap::new();
+    // Reordering of sequenced body frames (frame sequencing negotiated)
+    let mut reorder: HashMap<u32, SequenceBuffer> = HashMap::new();
+    let max_control_frame_bytes = state.config.max_control_frame_bytes;
     // Track spawned stream handlers so we can wait for them on shutdown
-    let mut handler_handles: Vec<JoinHandle<()>> = Vec::new();
-    let max_streams = state.config.tunnel_max_streams.unwrap_or(128) as usize;
+    let mut handler_handles: Vec<StreamTask> = Vec::new();
     let mut frames_since_cleanup: u32 = 0;
-    let stale_timeout = Duration::from_secs(state.config.tunnel_stale_timeout_secs);
+    let mut stale = StaleDetector::new(
+        Duration::from_secs(state.config.tunnel_stale_timeout_secs),
+        STALE_PROBE_GRACE,
+    );
+    let body_channel_depth = state.config.stream_body_channel_depth;
+    let writer_high = state.config.writer_backpressure_high_watermark;
+    let writer_low = state.config.writer_backpressure_low_watermark;
+    let mut writer_congested = false;
 
-    // Track last time we received any data to detect stale connections
-    let mut last_data_at = tokio::time::Instant::now();
+    let mut shutting_down = false;
+    // The proxy itself is retiring this connection (shutdown, drain, a
+    // node_id change or a remote reconnect request), as opposed to Aether
+    // closing it.
+    let mut rotating = false;
+    // The connection itself is gone (closed, stale or broken), as opposed
+    // to a GoAway or rotation where its writer still works.
+    let mut lost = false;
+    let mut node_id_changed = server.node_id_changed.subscribe();
+    // Armed on a node_id change; the pool's connections rotate one after
+    // another instead of all dropping at once.
+    let node_id_rotation = tokio::time::sleep(Duration::ZERO);
+    tokio::pin!(node_id_rotation);
+    let mut node_id_rotation_armed = false;
+    let mut remote_reconnect = server.remote_reconnect.subscribe();
 
     let read_err = loop {
+        // While the writer is congested, hold off reading so Aether sees TCP
+        // backpressure instead of us queueing more response work.
+        let queued = writer_queue_depth(&frame_tx);
+        if !writer_congested && writer_backpressure(false, queued, writer_high, writer_low) {
+            warn!(queued, "writer queue congested, pausing intake");
+            writer_congested = true;
+        }
+        if writer_congested {
+            wait_for_writer(&frame_tx, &writer_dequeued, writer_low, &shutdown).await;
+            let queued = writer_queue_depth(&frame_tx);
+            if !writer_backpressure(true, queued, writer_high, writer_low) {
+                info!(queued, "writer queue drained, accepting new streams");
+                writer_congested = false;
+            }
+        }
+
         let msg_result = tokio::select! {
-            msg = ws_stream.next() => {
-                match msg {
-                    Some(r) => r,
-                    None => break None,
+            next = next_live_message(&mut ws_stream, &mut stale, &frame_tx, &server.metrics) => {
+                match next {
+                    Liveness::Message(r) => r,
+                    Liveness::Closed | Liveness::Stale => {
+                        lost = true;
+                        break None;
+                    }
                 }
             }
-            _ = tokio::time::sleep_until(last_data_at + stale_timeout) => {
-                warn!(
-                    stale_secs = stale_timeout.as_secs(),
-                    "tunnel connection stale, no data received"
+            _ = shutdown.changed() => {
+                debug!("shutdown during tunnel dispatch");
+                shutting_down = true;
+                rotating = true;
+                break None;
+            }
+            _ = node_id_changed.changed(), if !node_id_rotation_armed => {
+                let delay = super::rotation_stagger(conn_idx);
+                info!(
+                    delay_ms = delay.as_millis() as u64,
+                    "node_id changed after re-registration, reconnecting tunnel"
                 );
+                node_id_rotation
+                    .as_mut()
+                    .reset(tokio::time::Instant::now() + delay);
+                node_id_rotation_armed = true;
+                continue;
+            }
+            _ = &mut node_id_rotation, if node_id_rotation_armed => {
+                rotating = true;
+                break None;
+            }
+            _ = remote_reconnect.changed() => {
+                info!("remote config requested a reconnect, reconnecting tunnel");
+                rotating = true;
                 break None;
             }
         };
@@ -64,19 +195,23 @@ where
             Ok(m) => m,
             Err(e) => {
                 error!(error = %e, "WebSocket read error");
+                lost = true;
                 break Some(e);
             }
         };
 
-        // Any successfully received message proves the connection is alive
-        last_data_at = tokio::time::Instant::now();
-
         let data = match msg {
             Message::Binary(data) => Bytes::from(data),
             Message::Ping(_) => continue,
-            Message::Pong(_) => continue,
+            Message::Pong(payload) => {
+                if let (Some(link), Some(rtt)) = (&link, routing::rtt_from_pong(&payload)) {
+                    link.record_rtt(rtt);
+                }
+                continue;
+            }
             Message::Close(_) => {
                 info!("received WebSocket close");
+                lost = true;
                 break None;
             }
             _ => continue,
@@ -90,6 +225,16 @@ where
             }
         };
 
+        if is_control_frame(frame.msg_type) && frame.payload.len() > max_control_frame_bytes {
+            warn!(
+                msg_type = ?frame.msg_type,
+                bytes = frame.payload.len(),
+                limit = max_control_frame_bytes,
+                "oversized control frame dropped"
+            );
+            continue;
+        }
+
         match frame.msg_type {
             MsgType::RequestHeaders => {
                 // Decompress if the frame is gzip-compressed, then parse metadata
@@ -100,88 +245,153 @@ where
                         continue;
                     }
                 };
-                let meta: RequestMeta = match serde_json::from_slice(&payload) {
+                let mut meta: RequestMeta = match serde_json::from_slice(&payload) {
                     Ok(m) => m,
                     Err(e) => {
                         warn!(stream_id = frame.stream_id, error = %e, "invalid request metadata");
-                        // Use try_send to avoid blocking the read loop
-                        if frame_tx
-                            .try_send(Frame::new(
-                                frame.stream_id,
-                                MsgType::StreamError,
-                                0,
-                                Bytes::from(format!("invalid request metadata: {e}")),
-                            ))
-                            .is_err()
-                        {
-                            warn!(
-                                stream_id = frame.stream_id,
-                                "writer channel full, StreamError dropped"
-                            );
-                        }
+                        let msg = format!("invalid request metadata: {e}");
+                        let request_id = lenient_request_id(&payload);
+                        reject_stream(&frame_tx, frame.stream_id, &request_id, &msg);
                         continue;
                     }
                 };
+                // Resolved here so rejections carry the same id the
+                // handler would use.
+                let request_id = stream_handler::resolve_request_id(meta.request_id.as_deref());
+                meta.request_id = Some(request_id.clone());
+
+                if server.dynamic.load().paused {
+                    debug!(stream_id = frame.stream_id, "node paused, rejecting stream");
+                    reject_stream(
+                        &frame_tx,
+                        frame.stream_id,
+                        &request_id,
+                        error_codes::NODE_PAUSED,
+                    );
+                    continue;
+                }
+
+                if writer_congested {
+                    warn!(
+                        stream_id = frame.stream_id,
+                        queued = writer_queue_depth(&frame_tx),
+                        "writer queue congested, rejecting stream"
+                    );
+                    reject_stream(
+                        &frame_tx,
+                        frame.stream_id,
+                        &request_id,
+                        error_codes::WRITER_BACKPRESSURE,
+                    );
+                    continue;
+                }
+
+                let pressure = state.memory.pressure();
+                if pressure != MemoryPressure::Normal {
+                    warn!(
+                        stream_id = frame.stream_id,
+                        pressure = pressure.as_str(),
+                        rss_mb = state.memory.rss_bytes() / (1024 * 1024),
+                        "memory limit reached, rejecting stream"
+                    );
+                    reject_stream(
+                        &frame_tx,
+                        frame.stream_id,
+                        &request_id,
+                        error_codes::MEMORY_PRESSURE,
+                    );
+                    continue;
+                }
+
+                if state.buffer_budget.exhausted() {
+                    warn!(
+                        stream_id = frame.stream_id,
+                        buffered_mb = state.buffer_budget.used_bytes() / (1024 * 1024),
+                        "buffer budget exhausted, rejecting stream"
+                    );
+                    reject_stream(
+                        &frame_tx,
+                        frame.stream_id,
+                        &request_id,
+                        error_codes::MEMORY_PRESSURE,
+                    );
+                    continue;
+                }
 
-                if streams.len() >= max_streams {
+                if at_stream_limit(streams.len(), &server.dynamic) {
                     warn!(
                         stream_id = frame.stream_id,
+                        max_streams = server.dynamic.load().max_streams,
                         "max concurrent streams reached"
                     );
-                    if frame_tx
-                        .try_send(Frame::new(
-                            frame.stream_id,
-                            MsgType::StreamError,
-                            0,
-                            Bytes::from("max concurrent streams reached"),
-                        ))
-                        .is_err()
-                    {
-                        warn!(
-                            stream_id = frame.stream_id,
-                            "writer channel full, StreamError dropped"
-                        );
-                    }
+                    reject_stream(
+                        &frame_tx,
+                        frame.stream_id,
+                        &request_id,
+                        error_codes::MAX_CONCURRENT_STREAMS,
+                    );
                     continue;
                 }
 
                 // Create body channel and spawn handler
-                let (body_tx, body_rx) = mpsc::channel::<Frame>(64);
+                let (body_tx, body_rx) = mpsc::channel::<Frame>(body_channel_depth);
                 streams.insert(frame.stream_id, body_tx);
 
-                let state_clone = Arc::clone(&state);
                 let server_clone = Arc::clone(&server);
                 let tx_clone = frame_tx.clone();
                 let sid = frame.stream_id;
-                let handle = tokio::spawn(async move {
-                    stream_handler::handle_stream(
-                        state_clone,
-                        server_clone,
-                        sid,
-                        meta,
-                        body_rx,
-                        tx_clone,
-                    )
-                    .await;
+                let tracked = state.memory.track();
+                let response_bytes = Arc::clone(&tracked.response_bytes);
+                let task = stream_handler::handle_stream(
+                    Arc::clone(&state),
+                    server_clone,
+                    sid,
+                    meta,
+                    body_rx,
+                    tx_clone,
+                    tracked,
+                    sequencing,
+                    trailers,
+                );
+                let route = link.as_ref().map(|link| {
+                    StreamRoute::new(Arc::clone(&server.connections), Arc::clone(link))
+                });
+                let task = async move {
+                    match route {
+                        Some(route) => route.scope(task).await,
+                        None => task.await,
+                    }
+                };
+                let handle = match &state.stream_affinity {
+                    Some(router) => router.spawn(sid, task),
+                    None => tokio::spawn(task),
+                };
+                handler_handles.push(StreamTask {
+                    stream_id: sid,
+                    request_id,
+                    handle,
+                    response_bytes,
                 });
-                handler_handles.push(handle);
 
                 debug!(stream_id = frame.stream_id, "new stream started");
             }
 
             MsgType::RequestBody => {
-                if let Some(tx) = streams.get(&frame.stream_id) {
-                    let is_end = frame.is_end_stream();
-                    let sid = frame.stream_id;
-                    let _ = tx.send(frame).await;
-                    if is_end {
-                        streams.remove(&sid);
-                    }
-                }
+                forward_request_body(
+                    frame,
+                    &mut streams,
+                    &mut reorder,
+                    Some(&mut handler_handles),
+                    sequencing,
+                    &frame_tx,
+                    &server,
+                )
+                .await;
             }
 
             MsgType::StreamEnd | MsgType::StreamError => {
                 // Client-side cancellation or end
+                reorder.remove(&frame.stream_id);
                 if let Some(tx) = streams.remove(&frame.stream_id) {
                     let _ = tx.send(frame).await;
                 }
@@ -197,6 +407,10 @@ where
                 }
             }
 
+            MsgType::Pong => {
+                // Answer to a staleness probe; receiving it was the point.
+            }
+
             MsgType::HeartbeatAck => {
                 heartbeat.on_ack(frame.payload).await;
             }
@@ -214,18 +428,141 @@ where
         // Periodically clean up finished handles to avoid unbounded growth.
         // Trigger every 64 frames OR when the count exceeds max_streams.
         frames_since_cleanup += 1;
-        if frames_since_cleanup >= 64 || handler_handles.len() > max_streams {
-            handler_handles.retain(|h| !h.is_finished());
+        if frames_since_cleanup >= 64 || handler_handles.len() > server.dynamic.load().max_streams {
+            handler_handles.retain(|t| !t.handle.is_finished());
             frames_since_cleanup = 0;
         }
     };
 
-    // Drop body senders so stream handlers waiting on body_rx will unblock
-    streams.clear();
+    // In-flight streams send what's left over the other connections.
+    if lost {
+        if let Some(link) = &link {
+            link.mark_dead();
+        }
+        // Drop body senders so stream handlers waiting on body_rx will unblock
+        streams.clear();
+    }
 
     // Wait for active stream handlers to finish so their frame_tx clones
-    // are dropped before the writer closes the sink.
-    drain_handlers(handler_handles).await;
+    // are dropped before the writer closes the sink.  On a proxy-initiated
+    // rotation, ask Aether to stop opening streams here and tell it which
+    // streams outlived the grace period (and whether retrying them is safe).
+    let grace = if rotating {
+        if frame_tx
+            .try_send(Frame::new(0, MsgType::GoAway, 0, Bytes::new()))
+            .is_err()
+        {
+            warn!("writer channel full, GOAWAY dropped");
+        }
+        Duration::from_secs(state.config.connection_rotation_grace_secs)
+    } else {
+        DRAIN_TIMEOUT
+    };
+    let drain = drain_handlers(handler_handles, grace);
+    tokio::pin!(drain);
+    // While the connection is still up, keep reading: in-flight streams
+    // still get their request bodies, and streams Aether opens meanwhile
+    // are refused with a retryable error.
+    let outcome = loop {
+        tokio::select! {
+            outcome = &mut drain => break outcome,
+            next = next_live_message(&mut ws_stream, &mut stale, &frame_tx, &server.metrics), if !lost => {
+                let data = match next {
+                    Liveness::Message(Ok(Message::Binary(data))) => Bytes::from(data),
+                    Liveness::Message(Ok(Message::Close(_)) | Err(_))
+                    | Liveness::Closed
+                    | Liveness::Stale => {
+                        debug!("tunnel connection closed while draining streams");
+                        if let Some(link) = &link {
+                            link.mark_dead();
+                        }
+                        lost = true;
+                        streams.clear();
+                        continue;
+                    }
+                    Liveness::Message(Ok(_)) => continue,
+                };
+                let Ok(frame) = Frame::decode(data) else {
+                    continue;
+                };
+                match frame.msg_type {
+                    MsgType::RequestHeaders => {
+                        debug!(stream_id = frame.stream_id, "draining, refusing new stream");
+                        let request_id = decompress_if_gzip(&frame)
+                            .map(|payload| lenient_request_id(&payload))
+                            .unwrap_or_else(|_| lenient_request_id(&[]));
+                        reject_stream(
+                            &frame_tx,
+                            frame.stream_id,
+                            &request_id,
+                            &rotating_error(true),
+                        );
+                    }
+                    MsgType::RequestBody => {
+                        forward_request_body(
+                            frame,
+                            &mut streams,
+                            &mut reorder,
+                            None,
+                            sequencing,
+                            &frame_tx,
+                            &server,
+                        )
+                        .await;
+                    }
+                    MsgType::StreamEnd | MsgType::StreamError => {
+                        reorder.remove(&frame.stream_id);
+                        if let Some(tx) = streams.remove(&frame.stream_id) {
+                            let _ = tx.send(frame).await;
+                        }
+                    }
+                    MsgType::Ping => {
+                        let _ = frame_tx.try_send(Frame::control(MsgType::Pong, frame.payload));
+                    }
+                    MsgType::HeartbeatAck => heartbeat.on_ack(frame.payload).await,
+                    _ => {}
+                }
+            }
+        }
+    };
+    streams.clear();
+    let (drained, aborted) = (outcome.drained, outcome.aborted.len() as u64);
+    if rotating {
+        for (stream_id, request_id, retry_safe) in &outcome.aborted {
+            reject_stream(
+                &frame_tx,
+                *stream_id,
+                request_id,
+                &rotating_error(*retry_safe),
+            );
+        }
+        server
+            .metrics
+            .rotation_streams_migrated
+            .fetch_add(drained, Ordering::Relaxed);
+        server
+            .metrics
+            .rotation_streams_killed
+            .fetch_add(aborted, Ordering::Relaxed);
+        if drained > 0 || aborted > 0 {
+            info!(
+                migrated = drained,
+                killed = aborted,
+                grace_secs = grace.as_secs(),
+                "connection rotation finished"
+            );
+        }
+    }
+    if shutting_down {
+        server
+            .drain
+            .streams_drained
+            .fetch_add(drained, Ordering::Relaxed);
+        server
+            .drain
+            .streams_aborted
+            .fetch_add(aborted, Ordering::Relaxed);
+    }
 
     match read_err {
         Some(e) => Err(e.into()),
@@ -233,17 +570,794 @@ where
     }
 }
 
-/// Wait for all active stream handlers to finish (with a timeout).
-async fn drain_handlers(handles: Vec<JoinHandle<()>>) {
-    if handles.is_empty() {
+/// StreamError message for a stream whose connection is being retired.
+fn rotating_error(retry_safe: bool) -> String {
+    format!(
+        "{}: retry_safe={retry_safe}",
+        error_codes::CONNECTION_ROTATING
+    )
+}
+
+/// Forward a RequestBody frame to its stream's handler, putting sequenced
+/// frames back in order first.  A stream that can't take the frame is
+/// dropped (see [`fail_stream`]).
+async fn forward_request_body(
+    frame: Frame,
+    streams: &mut HashMap<u32, mpsc::Sender<Frame>>,
+    reorder: &mut HashMap<u32, SequenceBuffer>,
+    handlers: Option<&mut Vec<StreamTask>>,
+    sequencing: bool,
+    frame_tx: &FrameSender,
+    server: &ServerContext,
+) {
+    let sid = frame.stream_id;
+    if !streams.contains_key(&sid) {
         return;
     }
-    let count = handles.len();
+    let mut frame = frame;
+    let seq = match frame.take_sequence() {
+        Ok(seq) => seq,
+        Err(e) => {
+            warn!(stream_id = sid, error = %e, "invalid sequenced body frame");
+            return;
+        }
+    };
+    let ready = match seq {
+        Some(seq) if sequencing => match reorder.entry(sid).or_default().push(seq, frame) {
+            Some(ready) => ready,
+            None => {
+                streams.remove(&sid);
+                reorder.remove(&sid);
+                warn!(stream_id = sid, "sequence gap not filled, dropping stream");
+                fail_stream(sid, error_codes::SEQUENCE_GAP, handlers, frame_tx);
+                return;
+            }
+        },
+        _ => vec![frame],
+    };
+    for frame in ready {
+        let Some(tx) = streams.get(&sid) else {
+            break;
+        };
+        let is_end = frame.is_end_stream();
+        let queued = tx.max_capacity() - tx.capacity();
+        match forward_body(tx, frame, BODY_BACKPRESSURE_TIMEOUT).await {
+            BodyForward::Sent if !is_end => {}
+            BodyForward::Sent | BodyForward::Closed => {
+                streams.remove(&sid);
+            }
+            BodyForward::Backpressure => {
+                streams.remove(&sid);
+                server
+                    .metrics
+                    .stream_backpressure
+                    .fetch_add(1, Ordering::Release);
+                warn!(
+                    stream_id = sid,
+                    queued, "stream body channel full, dropping stream"
+                );
+                fail_stream(sid, error_codes::STREAM_BACKPRESSURE, handlers, frame_tx);
+                break;
+            }
+        }
+    }
+    if !streams.contains_key(&sid) {
+        reorder.remove(&sid);
+    }
+}
+
+/// End a stream the dispatcher gave up on (its body channel is already
+/// dropped) with `code`.
+///
+/// The handler is stopped first and the error sent once it is gone, so it
+/// cannot add a StreamError of its own (e.g. for the truncated body).
+/// While draining, the handlers are not at hand (`None`): the handler then
+/// reports the truncated body itself and nothing is sent here.
+fn fail_stream(
+    stream_id: u32,
+    code: &'static str,
+    handlers: Option<&mut Vec<StreamTask>>,
+    frame_tx: &FrameSender,
+) {
+    let Some(handlers) = handlers else {
+        return;
+    };
+    // A handler that already finished has ended the stream itself.
+    let Some(index) = handlers.iter().position(|t| t.stream_id == stream_id) else {
+        return;
+    };
+    let task = handlers.swap_remove(index);
+    task.handle.abort();
+    let frame_tx = frame_tx.clone();
+    tokio::spawn(async move {
+        let _ = task.handle.await;
+        reject_stream(&frame_tx, stream_id, &task.request_id, code);
+    });
+}
+
+/// Send a StreamError with `code` (one of [`error_codes`], or a message),
+/// tagged with the request id like the handler's own errors.  Best effort:
+/// the read loop must not block on a congested writer.
+fn reject_stream(frame_tx: &FrameSender, stream_id: u32, request_id: &str, code: &str) {
+    let payload = stream_handler::error_payload(code, request_id);
+    let error = Frame::new(stream_id, MsgType::StreamError, 0, payload);
+    if frame_tx.try_send(error).is_err() {
+        warn!(stream_id, "writer channel full, StreamError dropped");
+    }
+}
+
+/// Request id of a RequestHeaders payload that is not (or not yet) parsed
+/// as [`RequestMeta`], resolved like the handler does.
+fn lenient_request_id(payload: &[u8]) -> String {
+    #[derive(serde::Deserialize)]
+    struct RequestId {
+        request_id: Option<String>,
+    }
+    let provided = serde_json::from_slice::<RequestId>(payload)
+        .ok()
+        .and_then(|meta| meta.request_id);
+    stream_handler::resolve_request_id(provided.as_deref())
+}
+
+/// Idle-connection liveness.
+///
+/// No data for `timeout` doesn't by itself mean the connection is broken --
+/// a node without traffic is simply idle.  The detector asks for a probe at
+/// that point and only declares the connection stale if nothing at all
+/// arrives within `grace` of it.
+struct StaleDetector {
+    timeout: Duration,
+    grace: Duration,
+    last_data_at: tokio::time::Instant,
+    probe_sent_at: Option<tokio::time::Instant>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum StaleAction {
+    Probe,
+    Stale,
+}
+
+impl StaleDetector {
+    fn new(timeout: Duration, grace: Duration) -> Self {
+        Self {
+            timeout,
+            grace,
+            last_data_at: tokio::time::Instant::now(),
+            probe_sent_at: None,
+        }
+    }
+
+    /// When to call [`Self::on_deadline`] if nothing arrives first.
+    fn deadline(&self) -> tokio::time::Instant {
+        match self.probe_sent_at {
+            Some(sent) => sent + self.grace,
+            None => self.last_data_at + self.timeout,
+        }
+    }
+
+    /// Record received data.  Returns `true` if it answered a probe.
+    fn on_data(&mut self) -> bool {
+        self.last_data_at = tokio::time::Instant::now();
+        self.probe_sent_at.take().is_some()
+    }
+
+    fn on_deadline(&mut self) -> StaleAction {
+        if self.probe_sent_at.is_some() {
+            return StaleAction::Stale;
+        }
+        self.probe_sent_at = Some(tokio::time::Instant::now());
+        StaleAction::Probe
+    }
+}
+
+/// Result of waiting for the next WebSocket message.
+enum Liveness {
+    Message(Result<Message, tokio_tungstenite::tungstenite::Error>),
+    /// The peer ended the stream.
+    Closed,
+    /// Idle past the stale timeout and the probe went unanswered.
+    Stale,
+}
+
+/// Read the next message, probing the connection with a protocol Ping when
+/// it has been idle for the stale timeout.  Any message -- data, Pong or a
+/// WebSocket-level frame -- counts as an answer.
+async fn next_live_message<S>(
+    ws_stream: &mut S,
+    stale: &mut StaleDetector,
+    frame_tx: &FrameSender,
+    metrics: &ProxyMetrics,
+) -> Liveness
+where
+    S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+{
+    loop {
+        tokio::select! {
+            msg = ws_stream.next() => {
+                let Some(msg) = msg else {
+                    return Liveness::Closed;
+                };
+                if msg.is_ok() && stale.on_data() {
+                    metrics.stale_probes_answered.fetch_add(1, Ordering::Release);
+                    debug!("idle tunnel answered staleness probe");
+                }
+                return Liveness::Message(msg);
+            }
+            _ = tokio::time::sleep_until(stale.deadline()) => {
+                match stale.on_deadline() {
+                    StaleAction::Probe => {
+                        debug!(
+                            idle_secs = stale.timeout.as_secs(),
+                            "tunnel idle, sending staleness probe"
+                        );
+                        // Best effort: with a full writer queue the writer's own
+                        // WebSocket pings still draw a Pong.
+                        let _ = frame_tx.try_send(Frame::control(MsgType::Ping, Bytes::new()));
+                    }
+                    StaleAction::Stale => {
+                        metrics.stale_reconnects.fetch_add(1, Ordering::Release);
+                        warn!(
+                            stale_secs = (stale.timeout + stale.grace).as_secs(),
+                            "tunnel connection stale, probe unanswered"
+                        );
+                        return Liveness::Stale;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Stream admission check.  The limit is read from the dynamic config on
+/// every call so remote throttling applies to live connections, not just
+/// after the next reconnect.
+fn at_stream_limit(active_streams: usize, dynamic: &SharedDynamicConfig) -> bool {
+    active_streams >= dynamic.load().max_streams
+}
+
+/// Frames waiting in the writer queue.
+fn writer_queue_depth(frame_tx: &FrameSender) -> usize {
+    frame_tx.max_capacity() - frame_tx.capacity()
+}
+
+/// Wait until the writer queue is below `low`, for at most
+/// [`WRITER_PAUSE_MAX`] or until shutdown.  Woken by the writer each time it
+/// dequeues a frame.
+async fn wait_for_writer(
+    frame_tx: &FrameSender,
+    dequeued: &Notify,
+    low: usize,
+    shutdown: &watch::Receiver<bool>,
+) {
+    let mut shutdown = shutdown.clone();
+    let paused_until = tokio::time::sleep(WRITER_PAUSE_MAX);
+    tokio::pin!(paused_until);
+    loop {
+        // Register before checking the depth so a dequeue in between
+        // isn't missed.
+        let drained = dequeued.notified();
+        tokio::pin!(drained);
+        drained.as_mut().enable();
+        if writer_queue_depth(frame_tx) < low || *shutdown.borrow() {
+            return;
+        }
+        tokio::select! {
+            _ = &mut drained => {}
+            _ = &mut paused_until => return,
+            _ = shutdown.changed() => return,
+        }
+    }
+}
+
+/// Writer backpressure with hysteresis: engages at `high` queued frames and
+/// releases only once the queue is below `low`.
+fn writer_backpressure(active: bool, queued: usize, high: usize, low: usize) -> bool {
+    if active {
+        queued >= low
+    } else {
+        queued >= high
+    }
+}
+
+/// Control frames subject to `max_control_frame_bytes`.
+fn is_control_frame(msg_type: MsgType) -> bool {
+    matches!(
+        msg_type,
+        MsgType::Ping | MsgType::Pong | MsgType::HeartbeatData | MsgType::HeartbeatAck
+    )
+}
+
+/// Puts one stream's sequenced body frames back in order.
+#[derive(Default)]
+struct SequenceBuffer {
+    next: u32,
+    pending: BTreeMap<u32, Frame>,
+}
+
+impl SequenceBuffer {
+    /// Accept frame `seq` and return the frames now deliverable in order
+    /// (duplicates are dropped), or `None` once more than
+    /// `MAX_OUT_OF_ORDER_FRAMES` are waiting on a missing frame.
+    fn push(&mut self, seq: u32, frame: Frame) -> Option<Vec<Frame>> {
+        if seq < self.next {
+            return Some(Vec::new());
+        }
+        self.pending.entry(seq).or_insert(frame);
+        let mut ready = Vec::new();
+        while let Some(frame) = self.pending.remove(&self.next) {
+            ready.push(frame);
+            self.next = self.next.wrapping_add(1);
+        }
+        (self.pending.len() <= MAX_OUT_OF_ORDER_FRAMES).then_some(ready)
+    }
+}
+
+/// Forward a frame to a stream's body channel without blocking the read loop
+/// for longer than `max_wait`.
+async fn forward_body(tx: &mpsc::Sender<Frame>, frame: Frame, max_wait: Duration) -> BodyForward {
+    match tx.try_send(frame) {
+        Ok(()) => BodyForward::Sent,
+        Err(TrySendError::Closed(_)) => BodyForward::Closed,
+        Err(TrySendError::Full(frame)) => {
+            match tokio::time::timeout(max_wait, tx.send(frame)).await {
+                Ok(Ok(())) => BodyForward::Sent,
+                Ok(Err(_)) => BodyForward::Closed,
+                Err(_) => BodyForward::Backpressure,
+            }
+        }
+    }
+}
+
+/// Wait for all active stream handlers to finish (within `grace`).
+///
+/// Handlers still running at the deadline are aborted; once this returns
+/// none of them will send another frame.
+async fn drain_handlers(tasks: Vec<StreamTask>, grace: Duration) -> DrainOutcome {
+    let pending: Vec<StreamTask> = tasks
+        .into_iter()
+        .filter(|t| !t.handle.is_finished())
+        .collect();
+    let mut outcome = DrainOutcome::default();
+    if pending.is_empty() {
+        return outcome;
+    }
+    let count = pending.len();
     debug!(count, "waiting for active stream handlers to finish");
-    let _ = tokio::time::timeout(Duration::from_secs(30), async {
-        for h in handles {
-            let _ = h.await;
+    let deadline = tokio::time::Instant::now() + grace;
+    for mut task in pending {
+        if tokio::time::timeout_at(deadline, &mut task.handle)
+            .await
+            .is_ok()
+        {
+            outcome.drained += 1;
+        } else {
+            task.handle.abort();
+            let _ = task.handle.await;
+            let retry_safe = task.response_bytes.load(Ordering::Relaxed) == 0;
+            outcome
+                .aborted
+                .push((task.stream_id, task.request_id, retry_safe));
         }
-    })
-    .await;
+    }
+    outcome
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::DynamicConfig;
+    use crate::tunnel::heartbeat;
+    use crate::tunnel::protocol::flags;
+    use sha2::{Digest, Sha256};
+
+    fn stream_task(stream_id: u32, run_for: Duration, forwarded: u64) -> StreamTask {
+        StreamTask {
+            stream_id,
+            request_id: format!("req-{stream_id}"),
+            handle: tokio::spawn(tokio::time::sleep(run_for)),
+            response_bytes: Arc::new(AtomicU64::new(forwarded)),
+        }
+    }
+
+    #[tokio::test]
+    async fn drain_reports_streams_outliving_the_grace_period() {
+        let tasks = vec![
+            stream_task(1, Duration::from_millis(10), 128),
+            stream_task(3, Duration::from_secs(60), 0),
+            stream_task(5, Duration::from_secs(60), 4096),
+        ];
+        let outcome = drain_handlers(tasks, Duration::from_millis(100)).await;
+        assert_eq!(
+            outcome,
+            DrainOutcome {
+                drained: 1,
+                aborted: vec![(3, "req-3".into(), true), (5, "req-5".into(), false)],
+            }
+        );
+    }
+
+    fn body_frame(stream_id: u32) -> Frame {
+        Frame::new(stream_id, MsgType::RequestBody, 0, Bytes::from_static(b"x"))
+    }
+
+    #[tokio::test]
+    async fn slow_stream_does_not_delay_other_streams() {
+        let max_wait = Duration::from_millis(100);
+        // Stream 1's handler never reads its body channel.
+        let (slow_tx, _slow_rx) = mpsc::channel::<Frame>(2);
+        let (fast_tx, mut fast_rx) = mpsc::channel::<Frame>(2);
+
+        assert_eq!(
+            forward_body(&slow_tx, body_frame(1), max_wait).await,
+            BodyForward::Sent
+        );
+        assert_eq!(
+            forward_body(&slow_tx, body_frame(1), max_wait).await,
+            BodyForward::Sent
+        );
+
+        let start = tokio::time::Instant::now();
+        assert_eq!(
+            forward_body(&slow_tx, body_frame(1), max_wait).await,
+            BodyForward::Backpressure
+        );
+        assert!(start.elapsed() >= max_wait);
+
+        // The read loop moves on: stream 3 is forwarded immediately.
+        let start = tokio::time::Instant::now();
+        for _ in 0..4 {
+            assert_eq!(
+                forward_body(&fast_tx, body_frame(3), max_wait).await,
+                BodyForward::Sent
+            );
+            assert_eq!(fast_rx.recv().await.expect("frame").stream_id, 3);
+        }
+        assert!(start.elapsed() < max_wait);
+    }
+
+    #[test]
+    fn sequence_buffer_reorders_and_bounds_gaps() {
+        let order = |frames: Option<Vec<Frame>>| -> Vec<u8> {
+            frames
+                .expect("within bound")
+                .iter()
+                .map(|f| f.payload[0])
+                .collect()
+        };
+        let frame = |n: u8| Frame::new(1, MsgType::RequestBody, 0, vec![n]);
+
+        let mut buf = SequenceBuffer::default();
+        assert_eq!(order(buf.push(1, frame(1))), Vec::<u8>::new());
+        assert_eq!(order(buf.push(2, frame(2))), Vec::<u8>::new());
+        assert_eq!(order(buf.push(0, frame(0))), vec![0, 1, 2]);
+        // Replayed frames are dropped.
+        assert_eq!(order(buf.push(1, frame(1))), Vec::<u8>::new());
+        assert_eq!(order(buf.push(3, frame(3))), vec![3]);
+
+        let mut buf = SequenceBuffer::default();
+        for seq in 1..=MAX_OUT_OF_ORDER_FRAMES as u32 {
+            assert!(buf.push(seq, frame(0)).is_some());
+        }
+        assert!(buf
+            .push(MAX_OUT_OF_ORDER_FRAMES as u32 + 1, frame(0))
+            .is_none());
+    }
+
+    #[test]
+    fn writer_backpressure_uses_watermarks() {
+        assert!(!writer_backpressure(false, 150, 200, 100));
+        assert!(writer_backpressure(false, 200, 200, 100));
+        // Stays engaged between the watermarks.
+        assert!(writer_backpressure(true, 150, 200, 100));
+        assert!(!writer_backpressure(true, 99, 200, 100));
+    }
+
+    #[tokio::test]
+    async fn writer_pause_ends_once_the_queue_drains() {
+        let (frame_tx, mut frame_rx) = mpsc::channel::<Frame>(4);
+        for _ in 0..4 {
+            frame_tx
+                .try_send(Frame::control(MsgType::Ping, Bytes::new()))
+                .unwrap();
+        }
+        let dequeued = Arc::new(Notify::new());
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        let waiter = tokio::spawn({
+            let (frame_tx, dequeued) = (frame_tx.clone(), Arc::clone(&dequeued));
+            async move { wait_for_writer(&frame_tx, &dequeued, 2, &shutdown_rx).await }
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiter.is_finished());
+        for _ in 0..3 {
+            frame_rx.recv().await.unwrap();
+            dequeued.notify_waiters();
+        }
+        tokio::time::timeout(WRITER_PAUSE_MAX / 2, waiter)
+            .await
+            .expect("woken by the writer")
+            .unwrap();
+    }
+
+    fn dynamic_config() -> SharedDynamicConfig {
+        let config = crate::config::test_config(&[]);
+        Arc::new(arc_swap::ArcSwap::from_pointee(DynamicConfig::from_config(
+            &config,
+        )))
+    }
+
+    #[test]
+    fn remote_max_streams_applies_without_reconnect() {
+        use crate::registration::client::RemoteConfig;
+        use crate::runtime::apply_remote_config;
+
+        let dynamic = dynamic_config();
+        assert!(!at_stream_limit(10, &dynamic));
+
+        let throttle = RemoteConfig {
+            tunnel_max_streams: Some(10),
+            ..Default::default()
+        };
+        assert!(apply_remote_config(&dynamic, &throttle, 1));
+        assert!(at_stream_limit(10, &dynamic));
+        assert!(!at_stream_limit(9, &dynamic));
+    }
+
+    #[tokio::test]
+    async fn pause_rejects_new_streams_while_in_flight_bodies_flow() {
+        use crate::registration::client::RemoteConfig;
+        use crate::runtime::apply_remote_config;
+
+        let dynamic = dynamic_config();
+        let (in_flight_tx, mut in_flight_rx) = mpsc::channel::<Frame>(2);
+
+        let pause = RemoteConfig {
+            paused: Some(true),
+            ..Default::default()
+        };
+        assert!(apply_remote_config(&dynamic, &pause, 1));
+        assert!(dynamic.load().paused);
+        assert!(error_codes::is_retryable(error_codes::NODE_PAUSED));
+
+        // Body frames for a stream admitted before the pause are still routed.
+        assert_eq!(
+            forward_body(&in_flight_tx, body_frame(1), Duration::from_millis(100)).await,
+            BodyForward::Sent
+        );
+        assert_eq!(in_flight_rx.recv().await.expect("frame").stream_id, 1);
+
+        let resume = RemoteConfig {
+            paused: Some(false),
+            ..pause
+        };
+        assert!(apply_remote_config(&dynamic, &resume, 2));
+        assert!(!dynamic.load().paused);
+    }
+
+    /// Mock tunnel peer: a read stream fed by the test, and the writer queue
+    /// the dispatcher sends probes to.
+    fn mock_tunnel() -> (
+        mpsc::UnboundedSender<Message>,
+        impl StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+        FrameSender,
+        mpsc::Receiver<Frame>,
+    ) {
+        let (peer_tx, peer_rx) = mpsc::unbounded_channel::<Message>();
+        let ws = futures_util::stream::unfold(peer_rx, |mut rx| async move {
+            rx.recv().await.map(|m| (Ok(m), rx))
+        })
+        .boxed();
+        let (frame_tx, frame_rx) = mpsc::channel(16);
+        (peer_tx, ws, frame_tx, frame_rx)
+    }
+
+    #[tokio::test]
+    async fn idle_connection_answering_probes_stays_up() {
+        let (peer_tx, mut ws, frame_tx, mut frame_rx) = mock_tunnel();
+        // The peer answers every protocol Ping with a Pong.
+        tokio::spawn(async move {
+            while let Some(frame) = frame_rx.recv().await {
+                if frame.msg_type == MsgType::Ping {
+                    let pong = Frame::control(MsgType::Pong, Bytes::new());
+                    let _ = peer_tx.send(Message::Binary(pong.encode().to_vec()));
+                }
+            }
+        });
+
+        let metrics = ProxyMetrics::new();
+        let mut stale = StaleDetector::new(Duration::from_millis(40), Duration::from_millis(40));
+        // Idle for many stale timeouts: only probe answers arrive.
+        let idle = tokio::time::sleep(Duration::from_millis(500));
+        tokio::pin!(idle);
+        loop {
+            tokio::select! {
+                next = next_live_message(&mut ws, &mut stale, &frame_tx, &metrics) => {
+                    assert!(matches!(next, Liveness::Message(Ok(_))), "connection declared stale");
+                }
+                _ = &mut idle => break,
+            }
+        }
+        assert!(metrics.stale_probes_answered.load(Ordering::Acquire) >= 5);
+        assert_eq!(metrics.stale_reconnects.load(Ordering::Acquire), 0);
+    }
+
+    #[tokio::test]
+    async fn unanswered_probe_marks_connection_stale() {
+        let (_peer_tx, mut ws, frame_tx, mut frame_rx) = mock_tunnel();
+        let metrics = ProxyMetrics::new();
+        let (timeout, grace) = (Duration::from_millis(40), Duration::from_millis(60));
+        let mut stale = StaleDetector::new(timeout, grace);
+
+        let start = tokio::time::Instant::now();
+        let next = next_live_message(&mut ws, &mut stale, &frame_tx, &metrics).await;
+        assert!(matches!(next, Liveness::Stale));
+        assert!(start.elapsed() >= timeout + grace);
+        assert_eq!(frame_rx.try_recv().unwrap().msg_type, MsgType::Ping);
+        assert!(frame_rx.try_recv().is_err());
+        assert_eq!(metrics.stale_reconnects.load(Ordering::Acquire), 1);
+        assert_eq!(metrics.stale_probes_answered.load(Ordering::Acquire), 0);
+    }
+
+    /// RequestHeaders for a POST whose handler buffers the body to check it
+    /// against `body_sha256` (of "expected") before going upstream.
+    fn integrity_checked_post(stream_id: u32) -> Frame {
+        let meta = RequestMeta {
+            method: "POST".into(),
+            url: "https://203.0.113.10/".into(),
+            headers: HashMap::new(),
+            timeout: 5,
+            request_id: Some(format!("req-{stream_id}")),
+            request_fingerprint: None,
+            body_sha256: Some(hex::encode(Sha256::digest(b"expected"))),
+            websocket: false,
+        };
+        let payload = serde_json::to_vec(&meta).unwrap();
+        Frame::new(stream_id, MsgType::RequestHeaders, 0, payload)
+    }
+
+    /// Next frame the dispatcher queued, skipping error reports.
+    async fn next_frame(frame_rx: &mut mpsc::Receiver<Frame>) -> Frame {
+        loop {
+            let frame = tokio::time::timeout(Duration::from_secs(5), frame_rx.recv())
+                .await
+                .expect("frame in time")
+                .expect("writer open");
+            if frame.msg_type != MsgType::ErrorReport {
+                return frame;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn rotation_keeps_reading_while_streams_drain() {
+        let (state, server) = crate::app::test_context(&[]);
+        let (peer_tx, ws, frame_tx, mut frame_rx) = mock_tunnel();
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        let dispatcher = tokio::spawn(run(
+            state,
+            Arc::clone(&server),
+            0,
+            ws,
+            frame_tx,
+            Arc::new(Notify::new()),
+            None,
+            false,
+            false,
+            heartbeat::spawn_noop(),
+            shutdown_rx,
+        ));
+        let send = |frame: Frame| {
+            peer_tx
+                .send(Message::Binary(frame.encode().to_vec()))
+                .unwrap()
+        };
+        // Stream 1 is waiting for its body when the node_id changes.
+        send(integrity_checked_post(1));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        server.node_id_changed.send_replace(());
+        assert_eq!(next_frame(&mut frame_rx).await.msg_type, MsgType::GoAway);
+
+        // A stream opened during the grace period is refused, retryably.
+        send(integrity_checked_post(3));
+        let refused = next_frame(&mut frame_rx).await;
+        assert_eq!(
+            (refused.stream_id, refused.msg_type),
+            (3, MsgType::StreamError)
+        );
+        assert_eq!(
+            refused.payload,
+            Bytes::from_static(b"connection_rotating: retry_safe=true (request_id: req-3)")
+        );
+
+        // Stream 1's body still reaches its handler, which checks it.
+        send(Frame::new(
+            1,
+            MsgType::RequestBody,
+            flags::END_STREAM,
+            Bytes::from_static(b"actual"),
+        ));
+        let checked = next_frame(&mut frame_rx).await;
+        assert_eq!(
+            (checked.stream_id, checked.msg_type),
+            (1, MsgType::StreamError)
+        );
+        assert!(String::from_utf8_lossy(&checked.payload).contains("body integrity check failed"));
+
+        tokio::time::timeout(Duration::from_secs(5), dispatcher)
+            .await
+            .expect("drained in time")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn dropped_stream_gets_a_single_stream_error() {
+        let (state, server) = crate::app::test_context(&[]);
+        let (peer_tx, ws, frame_tx, mut frame_rx) = mock_tunnel();
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        let dispatcher = tokio::spawn(run(
+            state,
+            server,
+            0,
+            ws,
+            frame_tx,
+            Arc::new(Notify::new()),
+            None,
+            true,
+            false,
+            heartbeat::spawn_noop(),
+            shutdown_rx,
+        ));
+        let send = |frame: Frame| {
+            peer_tx
+                .send(Message::Binary(frame.encode().to_vec()))
+                .unwrap()
+        };
+
+        // The handler is buffering stream 1's body when frame 0 never
+        // arrives and the dispatcher gives up on the stream.
+        send(integrity_checked_post(1));
+        for seq in 1..=MAX_OUT_OF_ORDER_FRAMES as u32 + 1 {
+            send(
+                Frame::new(1, MsgType::RequestBody, 0, Bytes::from_static(b"x")).with_sequence(seq),
+            );
+        }
+        let error = next_frame(&mut frame_rx).await;
+        assert_eq!((error.stream_id, error.msg_type), (1, MsgType::StreamError));
+        assert_eq!(
+            error.payload,
+            Bytes::from_static(b"sequence_gap (request_id: req-1)")
+        );
+
+        // The handler doesn't follow up with its truncated body.
+        drop(peer_tx);
+        tokio::time::timeout(Duration::from_secs(5), dispatcher)
+            .await
+            .expect("stopped in time")
+            .unwrap()
+            .unwrap();
+        while let Ok(frame) = frame_rx.try_recv() {
+            assert_ne!(frame.msg_type, MsgType::StreamError, "second StreamError");
+        }
+    }
+
+    #[test]
+    fn unparseable_metadata_keeps_its_request_id() {
+        assert_eq!(
+            lenient_request_id(br#"{"request_id": "req-9", "method": 5}"#),
+            "req-9"
+        );
+        assert!(lenient_request_id(b"not json").starts_with("px-"));
+    }
+
+    #[tokio::test]
+    async fn finished_handler_reports_closed() {
+        let (tx, rx) = mpsc::channel::<Frame>(1);
+        drop(rx);
+        assert_eq!(
+            forward_body(&tx, body_frame(1), Duration::from_millis(10)).await,
+            BodyForward::Closed
+        );
+    }
 }
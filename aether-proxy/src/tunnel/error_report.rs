@@ -0,0 +1,159 @@
+//! Per-stream error reports for the Aether dashboard.
+//!
+//! With `error_reporting_enabled`, every StreamError sent by a stream
+//! handler is accompanied by an ErrorReport control frame describing the
+//! failure (error kind, URL without query string, duration).  The stream's
+//! details live in a task-local set up by `handle_stream`, so error paths
+//! keep calling `send_error` unchanged.
+
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use tracing::debug;
+
+use super::protocol::{Frame as TunnelFrame, MsgType};
+use super::writer::FrameSender;
+
+tokio::task_local! {
+    static STREAM: StreamReport;
+}
+
+/// What a report needs to know about the stream being handled.
+pub(super) struct StreamReport {
+    url: String,
+    started: Instant,
+    sample_rate: f64,
+}
+
+impl StreamReport {
+    pub(super) fn new(url: &str, sample_rate: f64) -> Self {
+        Self {
+            url: sanitize_url(url),
+            started: Instant::now(),
+            sample_rate,
+        }
+    }
+
+    /// Run `fut` with this stream's report context.
+    pub(super) async fn scope<F: Future>(self, fut: F) -> F::Output {
+        STREAM.scope(self, fut).await
+    }
+}
+
+/// Queue an ErrorReport for the current stream's error `msg`.  No-op
+/// outside a reporting scope, when not sampled, or when the writer queue
+/// is full (reports must never hold up the stream).
+pub(super) fn report(tx: &FrameSender, stream_id: u32, msg: &str) {
+    let payload = STREAM.try_with(|stream| {
+        if !sampled(stream.sample_rate) {
+            return None;
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let report = serde_json::json!({
+            "stream_id": stream_id,
+            "error_kind": error_kind(msg),
+            "url": stream.url,
+            "timestamp": timestamp,
+            "duration_ms": stream.started.elapsed().as_millis() as u64,
+        });
+        serde_json::to_vec(&report).ok()
+    });
+    if let Ok(Some(payload)) = payload {
+        if tx
+            .try_send(TunnelFrame::control(
+                MsgType::ErrorReport,
+                Bytes::from(payload),
+            ))
+            .is_err()
+        {
+            debug!(stream_id, "writer channel full, ErrorReport dropped");
+        }
+    }
+}
+
+fn sampled(rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    (hasher.finish() as f64 / u64::MAX as f64) < rate
+}
+
+/// The `<code>` of a `<code>: <detail>` error, or `proxy_error` for
+/// free-form messages.
+fn error_kind(msg: &str) -> &str {
+    let code = msg.split_once(':').map_or(msg, |(code, _)| code);
+    if !code.is_empty() && code.bytes().all(|b| b.is_ascii_lowercase() || b == b'_') {
+        code
+    } else {
+        "proxy_error"
+    }
+}
+
+/// Scheme, host, port and path only: credentials, query and fragment may
+/// carry secrets.
+pub(super) fn sanitize_url(url: &str) -> String {
+    match url::Url::parse(url) {
+        Ok(mut url) => {
+            let _ = url.set_username("");
+            let _ = url.set_password(None);
+            url.set_query(None);
+            url.set_fragment(None);
+            url.to_string()
+        }
+        Err(_) => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_are_sanitized_and_kinds_extracted() {
+        assert_eq!(
+            sanitize_url("https://user:pw@api.example.com:8443/v1/chat?key=secret#frag"),
+            "https://api.example.com:8443/v1/chat"
+        );
+        assert_eq!(sanitize_url("not a url"), "");
+
+        assert_eq!(
+            error_kind("connect_timeout: upstream connect error"),
+            "connect_timeout"
+        );
+        assert_eq!(error_kind("rate_limited: retry after 5ms"), "rate_limited");
+        assert_eq!(error_kind("memory_pressure"), "memory_pressure");
+        assert_eq!(error_kind("invalid URL: empty host"), "proxy_error");
+        assert_eq!(error_kind("request body timeout"), "proxy_error");
+    }
+
+    #[tokio::test]
+    async fn reports_are_only_sent_inside_a_scope() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+        report(&tx, 1, "dns_error: lookup failed");
+        assert!(rx.try_recv().is_err());
+
+        StreamReport::new("https://api.example.com/v1?token=x", 1.0)
+            .scope(async { report(&tx, 7, "dns_error: lookup failed") })
+            .await;
+        let frame = rx.try_recv().unwrap();
+        assert_eq!(frame.stream_id, 0);
+        assert_eq!(frame.msg_type, MsgType::ErrorReport);
+        let json: serde_json::Value = serde_json::from_slice(&frame.payload).unwrap();
+        assert_eq!(json["stream_id"], 7);
+        assert_eq!(json["error_kind"], "dns_error");
+        assert_eq!(json["url"], "https://api.example.com/v1");
+
+        StreamReport::new("https://api.example.com/", 0.0)
+            .scope(async { report(&tx, 8, "dns_error: lookup failed") })
+            .await;
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -7,13 +7,16 @@ use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
 use bytes::Bytes;
+use serde::Deserialize;
 use tokio::sync::watch;
 use tracing::{debug, info, warn};
 
 use crate::config::Config;
+use crate::memory::MemoryGuard;
 use crate::registration::client::RemoteConfig;
 use crate::runtime;
-use crate::state::ServerContext;
+use crate::state::{ServerContext, REMOTE_RECONNECT_MIN_INTERVAL};
+use crate::upstream_client::UpstreamErrorClass;
 
 use super::protocol::{Frame, MsgType};
 use super::writer::FrameSender;
@@ -22,10 +25,43 @@ const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
 static UPGRADE_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
 static NON_ROOT_UPGRADE_WARNED: AtomicBool = AtomicBool::new(false);
 
+/// Heartbeat fields that `heartbeat_report_fields` can select.  Identity
+/// fields (`node_id`, `client_instance_id`, `heartbeat_session_id`,
+/// `heartbeat_id`) and `config_etag` are always sent.
+pub const REPORTABLE_FIELDS: &[&str] = &[
+    "active_connections",
+    "total_requests",
+    "avg_latency_ms",
+    "failed_requests",
+    "dns_failures",
+    "stream_errors",
+    "stream_backpressure",
+    "stale_probes_answered",
+    "stale_reconnects",
+    "body_integrity_failures",
+    "rotation_streams_migrated",
+    "rotation_streams_killed",
+    "auth_loops",
+    "tls_handshakes_full",
+    "tls_handshakes_resumed",
+    "config_updates_received",
+    "compression",
+    "upstream_errors",
+    "proxy_metadata",
+    "memory_pressure",
+    "rss_mb",
+];
+
+/// ACK `action` asking the proxy to register again (its node_id is unknown).
+const ACK_ACTION_REREGISTER: &str = "reregister";
+
 enum AckDecision {
     Accept {
         heartbeat_id: Option<u64>,
         upgrade_to: Option<String>,
+        /// Aether no longer knows this node_id (`node_not_found` or
+        /// `action: "reregister"`).
+        node_not_found: bool,
     },
     Ignore,
 }
@@ -58,12 +94,26 @@ struct HeartbeatSnapshot {
     failed: u64,
     dns_failures: u64,
     stream_errors: u64,
+    stream_backpressure: u64,
+    stale_probes_answered: u64,
+    stale_reconnects: u64,
+    body_integrity_failures: u64,
+    rotation_streams_migrated: u64,
+    rotation_streams_killed: u64,
+    auth_loops: u64,
+    tls_handshakes_full: u64,
+    tls_handshakes_resumed: u64,
+    config_updates_received: u64,
+    compression_bytes_in: u64,
+    compression_bytes_out: u64,
+    upstream_errors: [u64; UpstreamErrorClass::ALL.len()],
 }
 
 /// Spawn the heartbeat task. Returns a handle for forwarding ACKs.
 pub fn spawn(
-    _config: Arc<Config>,
+    config: Arc<Config>,
     server: Arc<ServerContext>,
+    memory: Arc<MemoryGuard>,
     frame_tx: FrameSender,
     mut shutdown: watch::Receiver<bool>,
 ) -> HeartbeatHandle {
@@ -93,7 +143,13 @@ pub fn spawn(
         loop {
             tokio::select! {
                 _ = tokio::time::sleep(current_interval) => {
+                    // Nothing to report against until registration completes.
+                    if !server.is_registered() {
+                        continue;
+                    }
                     let (heartbeat_id, snapshot) = if let Some((id, snap)) = pending {
+                        // Previous heartbeat was never acknowledged.
+                        server.heartbeat_failures.fetch_add(1, Ordering::AcqRel);
                         (id, snap)
                     } else {
                         let snap = collect_snapshot(&server);
@@ -108,6 +164,8 @@ pub fn spawn(
 
                     let payload = build_heartbeat_payload(
                         &server,
+                        &memory,
+                        &config.heartbeat_report_fields,
                         &heartbeat_session_id,
                         heartbeat_id,
                         snapshot
@@ -135,12 +193,27 @@ pub fn spawn(
                         current_interval = new_interval;
                     }
                 }
-                Some(ack_payload) = ack_rx.recv() => {
-                    match handle_ack(&server, &ack_payload) {
+                ack_payload = ack_rx.recv() => {
+                    // The dispatcher dropped its handle: the tunnel is gone,
+                    // and our frame_tx clone would keep the writer alive.
+                    let Some(ack_payload) = ack_payload else {
+                        debug!("tunnel closed, heartbeat task exiting");
+                        if let Some((_, snap)) = pending.take() {
+                            restore_snapshot(&server, snap);
+                        }
+                        break;
+                    };
+                    match handle_ack(&config, &server, &ack_payload) {
                         AckDecision::Accept {
                             heartbeat_id: ack_id,
                             upgrade_to,
+                            node_not_found,
                         } => {
+                            if node_not_found {
+                                warn!("Aether reports this node missing, requesting re-registration");
+                                server.reregister.notify_one();
+                            }
+                            server.heartbeat_failures.store(0, Ordering::Release);
                             if let Some((pending_id, _)) = pending {
                                 match ack_id {
                                     Some(id) if id == pending_id => {
@@ -180,6 +253,45 @@ fn collect_snapshot(server: &ServerContext) -> HeartbeatSnapshot {
         failed: server.metrics.failed_requests.swap(0, Ordering::AcqRel),
         dns_failures: server.metrics.dns_failures.swap(0, Ordering::AcqRel),
         stream_errors: server.metrics.stream_errors.swap(0, Ordering::AcqRel),
+        stream_backpressure: server.metrics.stream_backpressure.swap(0, Ordering::AcqRel),
+        stale_probes_answered: server
+            .metrics
+            .stale_probes_answered
+            .swap(0, Ordering::AcqRel),
+        stale_reconnects: server.metrics.stale_reconnects.swap(0, Ordering::AcqRel),
+        body_integrity_failures: server
+            .metrics
+            .body_integrity_failures
+            .swap(0, Ordering::AcqRel),
+        rotation_streams_migrated: server
+            .metrics
+            .rotation_streams_migrated
+            .swap(0, Ordering::AcqRel),
+        rotation_streams_killed: server
+            .metrics
+            .rotation_streams_killed
+            .swap(0, Ordering::AcqRel),
+        auth_loops: server.metrics.auth_loops.swap(0, Ordering::AcqRel),
+        tls_handshakes_full: server.metrics.tls_handshakes_full.swap(0, Ordering::AcqRel),
+        tls_handshakes_resumed: server
+            .metrics
+            .tls_handshakes_resumed
+            .swap(0, Ordering::AcqRel),
+        config_updates_received: server
+            .metrics
+            .config_updates_received
+            .swap(0, Ordering::AcqRel),
+        compression_bytes_in: server
+            .metrics
+            .compression_bytes_in
+            .swap(0, Ordering::AcqRel),
+        compression_bytes_out: server
+            .metrics
+            .compression_bytes_out
+            .swap(0, Ordering::AcqRel),
+        upstream_errors: std::array::from_fn(|i| {
+            server.metrics.upstream_errors[i].swap(0, Ordering::AcqRel)
+        }),
     }
 }
 
@@ -214,10 +326,92 @@ fn restore_snapshot(server: &ServerContext, snap: HeartbeatSnapshot) {
             .stream_errors
             .fetch_add(snap.stream_errors, Ordering::Release);
     }
+    if snap.stream_backpressure > 0 {
+        server
+            .metrics
+            .stream_backpressure
+            .fetch_add(snap.stream_backpressure, Ordering::Release);
+    }
+    if snap.stale_probes_answered > 0 {
+        server
+            .metrics
+            .stale_probes_answered
+            .fetch_add(snap.stale_probes_answered, Ordering::Release);
+    }
+    if snap.stale_reconnects > 0 {
+        server
+            .metrics
+            .stale_reconnects
+            .fetch_add(snap.stale_reconnects, Ordering::Release);
+    }
+    if snap.body_integrity_failures > 0 {
+        server
+            .metrics
+            .body_integrity_failures
+            .fetch_add(snap.body_integrity_failures, Ordering::Release);
+    }
+    if snap.rotation_streams_migrated > 0 {
+        server
+            .metrics
+            .rotation_streams_migrated
+            .fetch_add(snap.rotation_streams_migrated, Ordering::Release);
+    }
+    if snap.rotation_streams_killed > 0 {
+        server
+            .metrics
+            .rotation_streams_killed
+            .fetch_add(snap.rotation_streams_killed, Ordering::Release);
+    }
+    if snap.auth_loops > 0 {
+        server
+            .metrics
+            .auth_loops
+            .fetch_add(snap.auth_loops, Ordering::Release);
+    }
+    if snap.tls_handshakes_full > 0 {
+        server
+            .metrics
+            .tls_handshakes_full
+            .fetch_add(snap.tls_handshakes_full, Ordering::Release);
+    }
+    if snap.tls_handshakes_resumed > 0 {
+        server
+            .metrics
+            .tls_handshakes_resumed
+            .fetch_add(snap.tls_handshakes_resumed, Ordering::Release);
+    }
+    if snap.config_updates_received > 0 {
+        server
+            .metrics
+            .config_updates_received
+            .fetch_add(snap.config_updates_received, Ordering::Release);
+    }
+    if snap.compression_bytes_in > 0 {
+        server
+            .metrics
+            .compression_bytes_in
+            .fetch_add(snap.compression_bytes_in, Ordering::Release);
+        server
+            .metrics
+            .compression_bytes_out
+            .fetch_add(snap.compression_bytes_out, Ordering::Release);
+    }
+    for (counter, &count) in server
+        .metrics
+        .upstream_errors
+        .iter()
+        .zip(&snap.upstream_errors)
+    {
+        if count > 0 {
+            counter.fetch_add(count, Ordering::Release);
+        }
+    }
 }
 
 fn build_heartbeat_payload(
     server: &ServerContext,
+    memory: &MemoryGuard,
+    report_fields: &[String],
     heartbeat_session_id: &str,
     heartbeat_id: u64,
     snapshot: HeartbeatSnapshot,
@@ -230,52 +424,155 @@ fn build_heartbeat_payload(
         None
     };
 
-    let payload = serde_json::json!({
+    let mut payload = serde_json::json!({
         "node_id": node_id,
+        "client_instance_id": server.aether_client.client_instance_id(),
         "heartbeat_session_id": heartbeat_session_id,
         "heartbeat_id": heartbeat_id,
+        "config_etag": server.dynamic.load().config_etag,
         "active_connections": server.active_connections.load(Ordering::Acquire),
         "total_requests": snapshot.requests,
         "avg_latency_ms": avg_latency_ms,
         "failed_requests": snapshot.failed,
         "dns_failures": snapshot.dns_failures,
         "stream_errors": snapshot.stream_errors,
+        "stream_backpressure": snapshot.stream_backpressure,
+        "stale_probes_answered": snapshot.stale_probes_answered,
+        "stale_reconnects": snapshot.stale_reconnects,
+        "body_integrity_failures": snapshot.body_integrity_failures,
+        "rotation_streams_migrated": snapshot.rotation_streams_migrated,
+        "rotation_streams_killed": snapshot.rotation_streams_killed,
+        "auth_loops": snapshot.auth_loops,
+        "tls_handshakes_full": snapshot.tls_handshakes_full,
+        "tls_handshakes_resumed": snapshot.tls_handshakes_resumed,
+        "config_updates_received": snapshot.config_updates_received,
+        "compression": {
+            "bytes_in": snapshot.compression_bytes_in,
+            "bytes_out": snapshot.compression_bytes_out,
+            "ratio": (snapshot.compression_bytes_in > 0).then(|| {
+                snapshot.compression_bytes_out as f64 / snapshot.compression_bytes_in as f64
+            }),
+        },
+        "upstream_errors": upstream_error_breakdown(&snapshot.upstream_errors),
         "proxy_metadata": {
             "version": CURRENT_VERSION,
         },
     });
+    if memory.enabled() {
+        payload["memory_pressure"] = memory.pressure().as_str().into();
+        payload["rss_mb"] = (memory.rss_bytes() / (1024 * 1024)).into();
+    }
+    if server.dynamic.load().paused {
+        payload["paused"] = true.into();
+    }
+    select_report_fields(&mut payload, report_fields);
 
     Bytes::from(serde_json::to_vec(&payload).unwrap_or_default())
 }
 
-fn handle_ack(server: &ServerContext, payload: &[u8]) -> AckDecision {
+/// Non-zero upstream failure counts keyed by class code.
+fn upstream_error_breakdown(counts: &[u64]) -> serde_json::Map<String, serde_json::Value> {
+    UpstreamErrorClass::ALL
+        .iter()
+        .zip(counts)
+        .filter(|(_, &count)| count > 0)
+        .map(|(class, &count)| (class.code().to_string(), count.into()))
+        .collect()
+}
+
+/// Drop reportable fields not listed in `report_fields` (empty keeps all).
+fn select_report_fields(payload: &mut serde_json::Value, report_fields: &[String]) {
+    if report_fields.is_empty() {
+        return;
+    }
+    if let Some(fields) = payload.as_object_mut() {
+        fields.retain(|key, _| {
+            !REPORTABLE_FIELDS.contains(&key.as_str()) || report_fields.iter().any(|f| f == key)
+        });
+    }
+}
+
+fn handle_ack(config: &Config, server: &ServerContext, payload: &[u8]) -> AckDecision {
     if payload.is_empty() {
         return AckDecision::Accept {
             heartbeat_id: None,
             upgrade_to: None,
+            node_not_found: false,
         };
     }
 
     #[derive(serde::Deserialize)]
     struct AckPayload {
+        /// Left out by Aether when `config_etag` in the heartbeat matched.
         #[serde(default)]
-        remote_config: Option<RemoteConfig>,
+        remote_config: Option<serde_json::Value>,
         #[serde(default)]
         config_version: u64,
         #[serde(default)]
+        config_etag: Option<String>,
+        #[serde(default)]
         heartbeat_id: Option<u64>,
         #[serde(default)]
         upgrade_to: Option<String>,
+        #[serde(default)]
+        node_not_found: bool,
+        #[serde(default)]
+        action: Option<String>,
     }
 
     match serde_json::from_slice::<AckPayload>(payload) {
         Ok(ack) => {
-            if let Some(ref rc) = ack.remote_config {
-                runtime::apply_remote_config(&server.dynamic, rc, ack.config_version);
+            if let Some(body) = ack.remote_config {
+                let rc = match RemoteConfig::deserialize(&body) {
+                    Ok(rc) => rc,
+                    Err(e) => {
+                        warn!(error = %e, "failed to parse heartbeat ACK remote_config");
+                        return AckDecision::Ignore;
+                    }
+                };
+                server
+                    .metrics
+                    .config_updates_received
+                    .fetch_add(1, Ordering::Release);
+                let changed =
+                    runtime::apply_remote_config(&server.dynamic, &rc, ack.config_version);
+                if changed {
+                    record_config_version(server, ack.config_version);
+                    if rc.reconnect {
+                        if server.remote_reconnect.request() {
+                            info!(server = %server.server_label, "reconnecting tunnels as requested by remote config");
+                        } else {
+                            warn!(
+                                server = %server.server_label,
+                                "remote config reconnect request ignored: last one was under {}s ago",
+                                REMOTE_RECONNECT_MIN_INTERVAL.as_secs()
+                            );
+                        }
+                    }
+                    if config.persist_remote_config {
+                        persist_remote_config(server, &rc);
+                    }
+                }
+                // Prefer Aether's own etag: it is what the next heartbeat
+                // is compared against.
+                let etag = ack
+                    .config_etag
+                    .filter(|etag| !etag.is_empty())
+                    .unwrap_or_else(|| runtime::config_etag(&body));
+                runtime::record_config_etag(&server.dynamic, etag, ack.config_version);
             }
+            let reregister = match ack.action.as_deref() {
+                Some(ACK_ACTION_REREGISTER) => true,
+                Some(other) => {
+                    debug!(action = other, "ignoring unknown heartbeat ACK action");
+                    false
+                }
+                None => false,
+            };
             AckDecision::Accept {
                 heartbeat_id: ack.heartbeat_id,
                 upgrade_to: ack.upgrade_to.and_then(normalize_upgrade_target),
+                node_not_found: ack.node_not_found || reregister,
             }
         }
         Err(e) => {
@@ -285,6 +582,39 @@ fn handle_ack(server: &ServerContext, payload: &[u8]) -> AckDecision {
     }
 }
 
+/// Persist the applied config_version as this node's floor, off the
+/// runtime: the state file update may wait on another process's lock.
+fn record_config_version(server: &ServerContext, version: u64) {
+    let node_id = server.node_id.read().unwrap().clone();
+    tokio::task::spawn_blocking(move || {
+        let result = crate::state_file::StateFile::update(|s| {
+            let floor = s.config_versions.entry(node_id).or_insert(0);
+            *floor = (*floor).max(version);
+        });
+        if let Err(e) = result {
+            warn!(error = %e, "failed to persist config_version floor");
+        }
+    });
+}
+
+/// Write the update into the config file, off the runtime: the edit may
+/// wait on the config lock held by `setup`.
+fn persist_remote_config(server: &ServerContext, remote: &RemoteConfig) {
+    let path = crate::config::config_file_path();
+    let aether_url = server.aether_url.clone();
+    let remote = remote.clone();
+    tokio::task::spawn_blocking(move || {
+        if !path.exists() {
+            debug!(path = %path.display(), "no config file, remote config not persisted");
+            return;
+        }
+        match runtime::persist_remote_config(&path, &aether_url, &remote) {
+            Ok(()) => info!(path = %path.display(), "remote config persisted to file"),
+            Err(e) => warn!(path = %path.display(), error = %e, "failed to persist remote config"),
+        }
+    });
+}
+
 fn normalize_upgrade_target(raw: String) -> Option<String> {
     let trimmed = raw.trim();
     if trimmed.is_empty() {
@@ -338,3 +668,29 @@ fn maybe_trigger_upgrade(version: Option<String>) {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_fields_filter_metrics_but_keep_identity() {
+        let full = serde_json::json!({
+            "node_id": "n1",
+            "heartbeat_id": 7,
+            "active_connections": 3,
+            "avg_latency_ms": 12.5,
+            "total_requests": 10,
+        });
+
+        let mut payload = full.clone();
+        select_report_fields(&mut payload, &[]);
+        assert_eq!(payload, full);
+
+        select_report_fields(&mut payload, &["total_requests".to_string()]);
+        assert_eq!(
+            payload,
+            serde_json::json!({"node_id": "n1", "heartbeat_id": 7, "total_requests": 10})
+        );
+    }
+}
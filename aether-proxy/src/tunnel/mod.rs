@@ -1,16 +1,23 @@
+pub mod affinity;
 pub mod client;
+pub mod compression;
 pub mod dispatcher;
+mod error_report;
 pub mod heartbeat;
 pub mod protocol;
+pub mod routing;
 pub mod stream_handler;
 pub mod writer;
+mod ws_relay;
 
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use tokio::sync::watch;
 use tracing::{error, info};
 
+use crate::notification::TunnelEvent;
 use crate::state::{AppState, ServerContext};
 
 /// If a tunnel stays connected at least this long, treat the next disconnect
@@ -21,11 +28,15 @@ const STABLE_SESSION_RESET_AFTER: Duration = Duration::from_secs(30);
 const STARTUP_STAGGER_STEP_MS: u64 = 150;
 /// Upper bound for startup staggering.
 const MAX_STARTUP_STAGGER_MS: u64 = 1_500;
-/// Keep a tiny floor for repeated reconnects; first retry is still immediate.
+/// Spacing between pool connections rotating onto a new node_id, long
+/// enough for the previous one to have reconnected.
+const ROTATION_STAGGER_STEP_MS: u64 = 2_000;
+/// Floor for the reconnect base delay.
 const MIN_RECONNECT_DELAY_MS: u64 = 50;
-/// Even under sustained failures, keep probing frequently so recovery is fast
-/// once cross-border network quality improves.
-const RECONNECT_PROBE_MAX_DELAY_MS: u64 = 3_000;
+
+/// Consecutive connect failures on the primary connection after which the
+/// server's circuit is considered open (health score drops to 0).
+const CIRCUIT_OPEN_AFTER_FAILURES: u32 = 5;
 
 /// Run the tunnel mode main loop (connect, dispatch, reconnect).
 ///
@@ -38,6 +49,9 @@ pub async fn run(
     mut shutdown: watch::Receiver<bool>,
 ) {
     info!(server = %server.server_label, conn = conn_idx, "starting tunnel");
+    if !wait_for_node_id(server, conn_idx, &mut shutdown).await {
+        return;
+    }
     let reconnect_salt = compute_connection_salt(server, conn_idx);
 
     let startup_delay = compute_startup_stagger(conn_idx, reconnect_salt);
@@ -61,18 +75,20 @@ pub async fn run(
 
     loop {
         let started_at = Instant::now();
-        match client::connect_and_run(state, server, conn_idx, &mut shutdown).await {
+        let reason = match client::connect_and_run(state, server, conn_idx, &mut shutdown).await {
             Ok(client::TunnelOutcome::Shutdown) => {
                 info!(server = %server.server_label, conn = conn_idx, "tunnel shut down gracefully");
                 return;
             }
             Ok(client::TunnelOutcome::Disconnected) => {
                 info!(server = %server.server_label, conn = conn_idx, "tunnel disconnected, reconnecting");
+                "connection lost".to_string()
             }
             Err(e) => {
                 error!(server = %server.server_label, conn = conn_idx, error = %e, "tunnel connection error, reconnecting");
+                format!("{:#}", e)
             }
-        }
+        };
 
         if *shutdown.borrow() {
             info!(server = %server.server_label, conn = conn_idx, "shutdown requested, not reconnecting");
@@ -87,6 +103,12 @@ pub async fn run(
         } else {
             consecutive_failures = consecutive_failures.saturating_add(1);
         }
+        if conn_idx == 0 {
+            server.circuit_open.store(
+                consecutive_failures >= CIRCUIT_OPEN_AFTER_FAILURES,
+                Ordering::Release,
+            );
+        }
 
         let reconnect_delay = compute_reconnect_delay(
             state.config.tunnel_reconnect_base_ms,
@@ -101,6 +123,7 @@ pub async fn run(
             delay_ms = reconnect_delay.as_millis(),
             "waiting before reconnect"
         );
+        state.notify(TunnelEvent::Reconnecting, server, conn_idx, Some(&reason));
 
         tokio::select! {
             _ = tokio::time::sleep(reconnect_delay) => {}
@@ -112,6 +135,37 @@ pub async fn run(
     }
 }
 
+/// Hold a connection back until its server has a node_id.
+///
+/// Aether rejects tunnel handshakes without `X-Node-Id`, so under lazy
+/// registration connecting early would only fail and trip the circuit
+/// breaker.  Returns `false` if shutdown was requested while waiting.
+async fn wait_for_node_id(
+    server: &ServerContext,
+    conn_idx: usize,
+    shutdown: &mut watch::Receiver<bool>,
+) -> bool {
+    let mut node_id_changed = server.node_id_changed.subscribe();
+    if server.is_registered() {
+        return true;
+    }
+    info!(server = %server.server_label, conn = conn_idx, "waiting for registration before connecting");
+    while !server.is_registered() {
+        tokio::select! {
+            changed = node_id_changed.changed() => {
+                if changed.is_err() {
+                    return false;
+                }
+            }
+            _ = shutdown.changed() => {
+                info!(server = %server.server_label, conn = conn_idx, "shutdown requested while waiting for registration");
+                return false;
+            }
+        }
+    }
+    true
+}
+
 fn compute_connection_salt(server: &ServerContext, conn_idx: usize) -> u64 {
     // FNV-1a style hash over server label + connection index.
     let mut h: u64 = 0xcbf29ce484222325;
@@ -132,39 +186,40 @@ fn compute_startup_stagger(conn_idx: usize, salt: u64) -> Duration {
     Duration::from_millis((base + jitter).min(MAX_STARTUP_STAGGER_MS))
 }
 
+/// How long connection `conn_idx` keeps serving after a node_id change
+/// before it rotates: connection 0 goes first and each later one a step
+/// after the previous, plus up to a quarter step of jitter so the pools of
+/// several servers do not line up either.
+pub(crate) fn rotation_stagger(conn_idx: usize) -> Duration {
+    let now_nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or(0);
+    let base = (conn_idx as u64).saturating_mul(ROTATION_STAGGER_STEP_MS);
+    let jitter = mix_u64(now_nanos ^ conn_idx as u64) % (ROTATION_STAGGER_STEP_MS / 4 + 1);
+    Duration::from_millis(base + jitter)
+}
+
+/// Full-jitter backoff: a uniform delay in `[0, min(max, base * 2^(n-1))]`
+/// for the n-th consecutive failure.  Spreading every retry over the whole
+/// window (rather than its upper half) de-synchronizes tunnels that dropped
+/// together, and the low end keeps recovery fast after transient blips.
 fn compute_reconnect_delay(
     base_ms: u64,
     max_ms: u64,
     consecutive_failures: u32,
     salt: u64,
 ) -> Duration {
-    // First retry should be immediate to maximize recovery speed on transient
-    // blips (the user's primary expectation in poor networks).
-    if consecutive_failures <= 1 {
-        return Duration::ZERO;
-    }
-
-    // Keep a sane minimum for repeated failures.
     let base_ms = base_ms.max(MIN_RECONNECT_DELAY_MS);
     let max_ms = max_ms.max(base_ms);
-    let cap_ms = compute_reconnect_cap_ms(base_ms, max_ms, consecutive_failures)
-        .min(RECONNECT_PROBE_MAX_DELAY_MS.max(base_ms));
-
-    // Equal-jitter: randomize in [cap/2, cap], preventing synchronized reconnect
-    // storms while keeping reconnect latency bounded.
-    if cap_ms <= 1 {
-        return Duration::from_millis(cap_ms);
-    }
+    let cap_ms = compute_reconnect_cap_ms(base_ms, max_ms, consecutive_failures);
 
-    let half = cap_ms / 2;
-    let span = cap_ms - half;
     let now_nanos = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .map(|d| d.subsec_nanos() as u64)
         .unwrap_or(0);
     let mixed = mix_u64(now_nanos ^ salt);
-    let jitter = if span == 0 { 0 } else { mixed % (span + 1) };
-    Duration::from_millis(half + jitter)
+    Duration::from_millis(mixed % (cap_ms + 1))
 }
 
 fn compute_reconnect_cap_ms(base_ms: u64, max_ms: u64, consecutive_failures: u32) -> u64 {
@@ -191,8 +246,9 @@ mod tests {
     use std::time::Duration;
 
     use super::{
-        compute_reconnect_cap_ms, compute_reconnect_delay, compute_startup_stagger,
-        MAX_STARTUP_STAGGER_MS, RECONNECT_PROBE_MAX_DELAY_MS, STARTUP_STAGGER_STEP_MS,
+        compute_reconnect_cap_ms, compute_reconnect_delay, compute_startup_stagger, mix_u64,
+        rotation_stagger, MAX_STARTUP_STAGGER_MS, ROTATION_STAGGER_STEP_MS,
+        STARTUP_STAGGER_STEP_MS,
     };
 
     #[test]
@@ -224,13 +280,40 @@ mod tests {
     }
 
     #[test]
-    fn reconnect_delay_is_immediate_on_first_failure() {
-        assert_eq!(compute_reconnect_delay(700, 45_000, 1, 123), Duration::ZERO);
+    fn reconnect_delay_is_at_most_base_on_first_failure() {
+        for salt in 0..100 {
+            assert!(compute_reconnect_delay(700, 45_000, 1, salt) <= Duration::from_millis(700));
+        }
+    }
+
+    #[test]
+    fn reconnect_delay_uses_full_jitter_within_cap() {
+        let cap = Duration::from_millis(8_000);
+        let samples: Vec<Duration> = (0..1000u64)
+            .map(|i| compute_reconnect_delay(500, 30_000, 5, mix_u64(i)))
+            .collect();
+        assert!(samples.iter().all(|d| *d <= cap));
+        // Spread over the whole window, not just its upper half.
+        assert!(samples.iter().any(|d| *d < cap / 4));
+        assert!(samples.iter().any(|d| *d > cap * 3 / 4));
+
+        // Many failures are capped by the configured maximum.
+        for salt in 0..1000 {
+            assert!(
+                compute_reconnect_delay(500, 45_000, 100, salt) <= Duration::from_millis(45_000)
+            );
+        }
     }
 
     #[test]
-    fn reconnect_delay_stays_within_probe_ceiling_after_many_failures() {
-        let d = compute_reconnect_delay(500, 45_000, 100, 12345);
-        assert!(d <= Duration::from_millis(RECONNECT_PROBE_MAX_DELAY_MS));
+    fn rotation_stagger_orders_pool_connections() {
+        let step = Duration::from_millis(ROTATION_STAGGER_STEP_MS);
+        for conn_idx in 0..4u32 {
+            for _ in 0..100 {
+                let delay = rotation_stagger(conn_idx as usize);
+                assert!(delay >= step * conn_idx);
+                assert!(delay <= step * conn_idx + step / 4);
+            }
+        }
     }
 }
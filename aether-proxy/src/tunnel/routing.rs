@@ -0,0 +1,386 @@
+//! Cross-connection response routing.
+//!
+//! A stream's response normally goes back over the tunnel connection its
+//! RequestHeaders arrived on.  When Aether accepts the cross-connection
+//! capability in the handshake, the connection is registered with its
+//! server's [`ConnectionRegistry`] along with live health inputs (writer
+//! queue depth, recent send timeouts, WebSocket ping RTT).  A stream whose
+//! connection died or is badly congested then sends its remaining frames
+//! over the healthiest other connection, tagged `REROUTED` with the id of
+//! the connection the stream arrived on so Aether can reassemble it by
+//! stream_id.  Without the capability streams keep strict affinity.
+//!
+//! The stream's route lives in a task-local set up by the dispatcher, so
+//! stream handlers keep calling `send_frame` unchanged.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, info};
+
+use super::protocol::Frame;
+use super::writer::FrameSender;
+
+/// Below this score a stream looks for a better connection.
+pub const CONGESTED_SCORE: u8 = 50;
+/// How much healthier another connection must be to move a stream there.
+pub const REROUTE_MARGIN: u8 = 25;
+/// How long a frame send timeout counts against a connection.
+const SEND_TIMEOUT_WINDOW: Duration = Duration::from_secs(60);
+
+tokio::task_local! {
+    static ROUTE: StreamRoute;
+}
+
+/// Inputs to [`health_score`] for one connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealthInputs {
+    /// Still reading frames from Aether.
+    pub alive: bool,
+    /// Frames waiting in the writer queue, out of `capacity`.
+    pub queued: usize,
+    pub capacity: usize,
+    /// Frame sends that timed out within the last minute.
+    pub recent_send_timeouts: u32,
+    /// Latest WebSocket ping round trip, if measured yet.
+    pub rtt: Option<Duration>,
+}
+
+/// Connection health from 0 (dead) to 100 (idle): a full writer queue
+/// costs up to 60 points, each recent send timeout 15 (at most 30), and
+/// every 50ms of RTT 1 (at most 10).  A live connection scores at least 1.
+pub fn health_score(inputs: &HealthInputs) -> u8 {
+    if !inputs.alive {
+        return 0;
+    }
+    let fill = inputs.queued.min(inputs.capacity) as f64 / inputs.capacity.max(1) as f64;
+    let queue_penalty = (fill * 60.0).round() as u32;
+    let timeout_penalty = inputs.recent_send_timeouts.min(2) * 15;
+    let rtt_penalty = inputs
+        .rtt
+        .map_or(0, |rtt| (rtt.as_millis() / 50).min(10) as u32);
+    100u32
+        .saturating_sub(queue_penalty + timeout_penalty + rtt_penalty)
+        .max(1) as u8
+}
+
+/// Whether a stream on a connection scoring `current` should move to one
+/// scoring `best`: always off a dead connection, otherwise only off a
+/// congested one and to a clearly healthier one.
+pub fn should_reroute(current: u8, best: u8) -> bool {
+    if best == 0 {
+        return false;
+    }
+    current == 0 || (current < CONGESTED_SCORE && best >= current.saturating_add(REROUTE_MARGIN))
+}
+
+/// One registered tunnel connection.
+pub struct ConnectionLink {
+    pub id: u32,
+    tx: FrameSender,
+    alive: AtomicBool,
+    /// Latest ping RTT in microseconds (0 = not measured).
+    rtt_us: AtomicU64,
+    send_timeouts: Mutex<Vec<Instant>>,
+}
+
+impl ConnectionLink {
+    fn new(id: u32, tx: FrameSender) -> Self {
+        Self {
+            id,
+            tx,
+            alive: AtomicBool::new(true),
+            rtt_us: AtomicU64::new(0),
+            send_timeouts: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The connection was lost; streams move off it with their next frame.
+    pub fn mark_dead(&self) {
+        self.alive.store(false, Ordering::Release);
+    }
+
+    pub fn record_rtt(&self, rtt: Duration) {
+        let us = u64::try_from(rtt.as_micros()).unwrap_or(u64::MAX).max(1);
+        self.rtt_us.store(us, Ordering::Release);
+    }
+
+    pub fn record_send_timeout(&self) {
+        let now = Instant::now();
+        let mut timeouts = self.send_timeouts.lock().unwrap();
+        timeouts.retain(|t| now.duration_since(*t) < SEND_TIMEOUT_WINDOW);
+        timeouts.push(now);
+    }
+
+    pub fn inputs(&self) -> HealthInputs {
+        let now = Instant::now();
+        let recent_send_timeouts = self
+            .send_timeouts
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|t| now.duration_since(**t) < SEND_TIMEOUT_WINDOW)
+            .count() as u32;
+        let rtt_us = self.rtt_us.load(Ordering::Acquire);
+        HealthInputs {
+            alive: self.alive.load(Ordering::Acquire) && !self.tx.is_closed(),
+            queued: self.tx.max_capacity() - self.tx.capacity(),
+            capacity: self.tx.max_capacity(),
+            recent_send_timeouts,
+            rtt: (rtt_us > 0).then(|| Duration::from_micros(rtt_us)),
+        }
+    }
+
+    pub fn score(&self) -> u8 {
+        health_score(&self.inputs())
+    }
+}
+
+/// A server's tunnel connections that accepted cross-connection responses.
+#[derive(Default)]
+pub struct ConnectionRegistry {
+    next_id: AtomicU32,
+    links: Mutex<Vec<Arc<ConnectionLink>>>,
+}
+
+impl ConnectionRegistry {
+    /// Id for a new connection's handshake (unique within the process).
+    pub fn next_id(&self) -> u32 {
+        self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Register connection `id` writing to `tx`.  It stays registered (and
+    /// `tx` open) until the returned guard is dropped.
+    pub fn register(self: &Arc<Self>, id: u32, tx: FrameSender) -> RegisteredLink {
+        let link = Arc::new(ConnectionLink::new(id, tx));
+        self.links.lock().unwrap().push(Arc::clone(&link));
+        RegisteredLink {
+            registry: Arc::clone(self),
+            link,
+        }
+    }
+
+    /// The best-scoring live connection.
+    fn healthiest(&self) -> Option<(Arc<ConnectionLink>, u8)> {
+        self.links
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|link| (Arc::clone(link), link.score()))
+            .filter(|(_, score)| *score > 0)
+            .max_by_key(|(_, score)| *score)
+    }
+}
+
+/// Registration of one connection; dropping it marks the connection dead
+/// and removes it from the registry.
+pub struct RegisteredLink {
+    registry: Arc<ConnectionRegistry>,
+    link: Arc<ConnectionLink>,
+}
+
+impl RegisteredLink {
+    pub fn link(&self) -> &Arc<ConnectionLink> {
+        &self.link
+    }
+}
+
+impl Drop for RegisteredLink {
+    fn drop(&mut self) {
+        self.link.mark_dead();
+        self.registry
+            .links
+            .lock()
+            .unwrap()
+            .retain(|link| !Arc::ptr_eq(link, &self.link));
+    }
+}
+
+/// Where one stream's response frames go.
+pub(super) struct StreamRoute {
+    registry: Arc<ConnectionRegistry>,
+    origin: Arc<ConnectionLink>,
+    current: Mutex<Arc<ConnectionLink>>,
+}
+
+impl StreamRoute {
+    pub(super) fn new(registry: Arc<ConnectionRegistry>, origin: Arc<ConnectionLink>) -> Self {
+        Self {
+            registry,
+            current: Mutex::new(Arc::clone(&origin)),
+            origin,
+        }
+    }
+
+    /// Run `fut` with this stream's route.
+    pub(super) async fn scope<F: Future>(self, fut: F) -> F::Output {
+        ROUTE.scope(self, fut).await
+    }
+
+    /// Pick the connection for the next frame: the current one unless
+    /// [`should_reroute`] says otherwise.  Streams stick to a connection
+    /// once moved, so their frames don't alternate between sockets.
+    fn pick(&self, stream_id: u32) -> Arc<ConnectionLink> {
+        let mut current = self.current.lock().unwrap();
+        let score = current.score();
+        if score >= CONGESTED_SCORE {
+            return Arc::clone(&current);
+        }
+        if let Some((best, best_score)) = self.registry.healthiest() {
+            if best.id != current.id && should_reroute(score, best_score) {
+                if score == 0 {
+                    info!(
+                        stream_id,
+                        from = current.id,
+                        to = best.id,
+                        "tunnel connection lost, rerouting stream responses"
+                    );
+                } else {
+                    debug!(
+                        stream_id,
+                        from = current.id,
+                        to = best.id,
+                        score,
+                        best_score,
+                        "tunnel connection congested, rerouting stream responses"
+                    );
+                }
+                *current = best;
+            }
+        }
+        Arc::clone(&current)
+    }
+}
+
+/// Sender and frame for `frame`, sent on the stream's own connection `tx`
+/// unless the stream's route moved it, in which case the frame is tagged
+/// with its origin.  Also returns the chosen connection, for health
+/// accounting, when a route is in scope.
+pub(super) fn route(
+    tx: &FrameSender,
+    frame: Frame,
+) -> (FrameSender, Frame, Option<Arc<ConnectionLink>>) {
+    let picked = ROUTE
+        .try_with(|route| {
+            let link = route.pick(frame.stream_id);
+            (link, route.origin.id)
+        })
+        .ok();
+    match picked {
+        Some((link, origin)) => {
+            let frame = if link.id == origin {
+                frame
+            } else {
+                frame.with_origin(origin)
+            };
+            (link.tx.clone(), frame, Some(link))
+        }
+        None => (tx.clone(), frame, None),
+    }
+}
+
+/// Reference point for ping timestamps.
+fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(Instant::now)
+}
+
+/// Payload for the writer's WebSocket Pings: the send time, echoed back in
+/// the Pong so the dispatcher can measure the round trip.
+pub fn ping_payload() -> Vec<u8> {
+    let micros = u64::try_from(epoch().elapsed().as_micros()).unwrap_or(u64::MAX);
+    micros.to_be_bytes().to_vec()
+}
+
+/// Round trip of the Ping whose Pong carried `payload`.
+pub fn rtt_from_pong(payload: &[u8]) -> Option<Duration> {
+    let sent = u64::from_be_bytes(payload.try_into().ok()?);
+    let now = u64::try_from(epoch().elapsed().as_micros()).ok()?;
+    now.checked_sub(sent).map(Duration::from_micros)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tunnel::protocol::MsgType;
+
+    fn inputs(queued: usize, timeouts: u32, rtt_ms: Option<u64>) -> HealthInputs {
+        HealthInputs {
+            alive: true,
+            queued,
+            capacity: 256,
+            recent_send_timeouts: timeouts,
+            rtt: rtt_ms.map(Duration::from_millis),
+        }
+    }
+
+    #[test]
+    fn health_score_weighs_queue_timeouts_and_rtt() {
+        assert_eq!(health_score(&inputs(0, 0, None)), 100);
+        assert_eq!(health_score(&inputs(128, 0, None)), 70);
+        assert_eq!(health_score(&inputs(256, 0, None)), 40);
+        assert_eq!(health_score(&inputs(0, 1, None)), 85);
+        assert_eq!(health_score(&inputs(0, 5, None)), 70);
+        assert_eq!(health_score(&inputs(0, 0, Some(20))), 100);
+        assert_eq!(health_score(&inputs(0, 0, Some(250))), 95);
+        assert_eq!(health_score(&inputs(0, 0, Some(5_000))), 90);
+        // Everything at once still beats a dead connection.
+        assert_eq!(health_score(&inputs(300, 9, Some(5_000))), 1);
+        let dead = HealthInputs {
+            alive: false,
+            ..inputs(0, 0, None)
+        };
+        assert_eq!(health_score(&dead), 0);
+    }
+
+    #[test]
+    fn streams_move_only_off_dead_or_congested_connections() {
+        assert!(should_reroute(0, 1));
+        assert!(!should_reroute(0, 0));
+        assert!(!should_reroute(CONGESTED_SCORE, 100));
+        assert!(should_reroute(40, 65));
+        assert!(!should_reroute(40, 64));
+    }
+
+    #[tokio::test]
+    async fn frames_follow_the_healthiest_connection_once_the_origin_dies() {
+        let registry = Arc::new(ConnectionRegistry::default());
+        let (tx_a, mut rx_a) = tokio::sync::mpsc::channel(8);
+        let (tx_b, mut rx_b) = tokio::sync::mpsc::channel(8);
+        let a = registry.register(registry.next_id(), tx_a.clone());
+        let b = registry.register(registry.next_id(), tx_b);
+        let origin = Arc::clone(a.link());
+
+        // Outside a route scope frames stay on the given sender.
+        let (tx, _, link) = route(&tx_a, Frame::new(1, MsgType::StreamEnd, 0, Vec::new()));
+        assert!(tx.same_channel(&tx_a) && link.is_none());
+
+        StreamRoute::new(Arc::clone(&registry), origin)
+            .scope(async {
+                let (tx, frame, _) = route(&tx_a, Frame::new(1, MsgType::ResponseBody, 0, "x"));
+                tx.send(frame).await.unwrap();
+                a.link().mark_dead();
+                let (tx, frame, _) = route(&tx_a, Frame::new(1, MsgType::StreamEnd, 0, ""));
+                tx.send(frame).await.unwrap();
+            })
+            .await;
+
+        assert!(!rx_a.try_recv().unwrap().is_rerouted());
+        let mut moved = rx_b.try_recv().unwrap();
+        assert_eq!(moved.take_origin().unwrap(), Some(a.link().id));
+        assert_eq!(moved.msg_type, MsgType::StreamEnd);
+
+        drop(b);
+        assert!(registry.healthiest().is_none());
+    }
+
+    #[test]
+    fn pong_payload_yields_the_round_trip() {
+        let payload = ping_payload();
+        let rtt = rtt_from_pong(&payload).unwrap();
+        assert!(rtt < Duration::from_secs(1));
+        assert_eq!(rtt_from_pong(&[]), None);
+    }
+}
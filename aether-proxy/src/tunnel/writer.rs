@@ -4,29 +4,49 @@
 //! avoiding contention on the WebSocket sink.  The writer also sends
 //! periodic WebSocket Ping frames to keep the connection alive through
 //! intermediary proxies (Nginx, Cloudflare, etc.).
+//!
+//! Optionally, large messages are split into WebSocket continuation frames
+//! so intermediaries that buffer whole messages don't hold them in memory.
 
 use std::time::Duration;
 
+use std::sync::Arc;
+
 use futures_util::SinkExt;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, Notify};
 use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::{Data, OpCode};
+use tokio_tungstenite::tungstenite::protocol::frame::Frame as WsFrame;
 use tokio_tungstenite::tungstenite::Message;
 use tracing::{debug, error, trace};
 
 use super::protocol::Frame;
+use super::routing;
 
 /// Sender half — cloned by stream handlers and heartbeat.
 pub type FrameSender = mpsc::Sender<Frame>;
 
-/// Spawn the writer task. Returns the sender and a JoinHandle for cleanup.
+/// Capacity of the writer's frame queue.
+pub const WRITER_CHANNEL_CAPACITY: usize = 256;
+
+/// Spawn the writer task. Returns the sender, a `Notify` signalled each time
+/// the writer takes a frame off the queue, and a JoinHandle for cleanup.
 ///
 /// `ping_interval` controls WebSocket-level Ping frequency (typically 15s).
 /// This keeps the connection alive through intermediary proxies/load-balancers.
-pub fn spawn_writer<S>(mut sink: S, ping_interval: Duration) -> (FrameSender, JoinHandle<()>)
+/// `max_message_size` fragments larger messages into continuation frames
+/// (0 disables fragmentation).
+pub fn spawn_writer<S>(
+    mut sink: S,
+    ping_interval: Duration,
+    max_message_size: usize,
+) -> (FrameSender, Arc<Notify>, JoinHandle<()>)
 where
     S: SinkExt<Message, Error = tokio_tungstenite::tungstenite::Error> + Unpin + Send + 'static,
 {
-    let (tx, mut rx) = mpsc::channel::<Frame>(256);
+    let (tx, mut rx) = mpsc::channel::<Frame>(WRITER_CHANNEL_CAPACITY);
+    let dequeued = Arc::new(Notify::new());
+    let writer_dequeued = Arc::clone(&dequeued);
 
     let handle = tokio::spawn(async move {
         let mut ping_ticker = tokio::time::interval(ping_interval);
@@ -37,8 +57,9 @@ where
                 frame = rx.recv() => {
                     match frame {
                         Some(frame) => {
+                            writer_dequeued.notify_waiters();
                             let data = frame.encode();
-                            if let Err(e) = sink.send(Message::Binary(data.into())).await {
+                            if let Err(e) = send_message(&mut sink, data.into(), max_message_size).await {
                                 error!(error = %e, "failed to write frame to WebSocket");
                                 break;
                             }
@@ -47,7 +68,7 @@ where
                     }
                 }
                 _ = ping_ticker.tick() => {
-                    if let Err(e) = sink.send(Message::Ping(vec![])).await {
+                    if let Err(e) = sink.send(Message::Ping(routing::ping_payload())).await {
                         error!(error = %e, "failed to send WebSocket ping");
                         break;
                     }
@@ -59,5 +80,64 @@ where
         let _ = sink.close().await;
     });
 
-    (tx, handle)
+    (tx, dequeued, handle)
+}
+
+/// Send one binary message, fragmenting it if it exceeds `max_message_size`.
+async fn send_message<S>(
+    sink: &mut S,
+    data: Vec<u8>,
+    max_message_size: usize,
+) -> Result<(), tokio_tungstenite::tungstenite::Error>
+where
+    S: SinkExt<Message, Error = tokio_tungstenite::tungstenite::Error> + Unpin,
+{
+    if max_message_size == 0 || data.len() <= max_message_size {
+        return sink.send(Message::Binary(data)).await;
+    }
+    for fragment in fragment_message(&data, max_message_size) {
+        sink.feed(fragment).await?;
+    }
+    sink.flush().await
+}
+
+/// Split `data` into a Binary frame followed by Continuation frames of at
+/// most `max_size` bytes each; only the last fragment has FIN set.
+fn fragment_message(data: &[u8], max_size: usize) -> Vec<Message> {
+    let count = data.len().div_ceil(max_size);
+    data.chunks(max_size)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let opcode = if i == 0 { Data::Binary } else { Data::Continue };
+            Message::Frame(WsFrame::message(
+                chunk.to_vec(),
+                OpCode::Data(opcode),
+                i + 1 == count,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fragments_large_message_into_continuation_frames() {
+        let data: Vec<u8> = (0..10u8).collect();
+        let fragments = fragment_message(&data, 4);
+        assert_eq!(fragments.len(), 3);
+
+        let mut reassembled = Vec::new();
+        for (i, message) in fragments.into_iter().enumerate() {
+            let Message::Frame(frame) = message else {
+                panic!("expected raw frame");
+            };
+            let expected = if i == 0 { Data::Binary } else { Data::Continue };
+            assert_eq!(frame.header().opcode, OpCode::Data(expected));
+            assert_eq!(frame.header().is_final, i == 2);
+            reassembled.extend_from_slice(frame.payload());
+        }
+        assert_eq!(reassembled, data);
+    }
 }
@@ -0,0 +1,551 @@
+//! WebSocket upstream relay.
+//!
+//! A stream whose `RequestMeta.websocket` is set opens a WebSocket to the
+//! upstream instead of making an HTTP request.  After a `ResponseHeaders`
+//! frame with status 101, messages flow as body frames in both directions:
+//! `RequestBody` (Aether -> upstream) and `ResponseBody` (upstream ->
+//! Aether), one message per frame, with [`flags::WS_TEXT`] marking text.
+//!
+//! `END_STREAM` on a request body frame or a `StreamEnd` frame closes the
+//! upstream socket; the upstream closing ends the stream with `StreamEnd`.
+//! A `StreamEnd` payload, if any, is a WebSocket close payload (big-endian
+//! close code followed by the UTF-8 reason).  An upgrade the upstream
+//! refuses is relayed as a plain HTTP response.
+
+use std::borrow::Cow;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, WebSocketConfig};
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::WebSocketStream;
+use tracing::{debug, warn};
+
+use crate::net;
+use crate::rate_limit::{self, TokenBucket};
+use crate::state::{AppState, ServerContext};
+use crate::telemetry;
+use crate::upstream_client::UpstreamErrorClass;
+
+use super::protocol::{
+    compress_payload, decompress_if_gzip, flags, Frame as TunnelFrame, MsgType, RequestMeta,
+    ResponseMeta,
+};
+use super::stream_handler::{
+    apply_upstream_identity, inject_request_id, response_header_pairs, send_error, send_frame,
+    send_response_frame, sequence, BLOCKED_HEADERS, MAX_TIMEOUT_SECS, MIN_TIMEOUT_SECS,
+};
+use super::writer::FrameSender;
+
+/// Handshake headers generated by the WebSocket client itself.
+const HANDSHAKE_HEADERS: &[&str] = &[
+    "sec-websocket-accept",
+    "sec-websocket-extensions",
+    "sec-websocket-key",
+    "sec-websocket-version",
+];
+
+/// Largest WebSocket message relayed in either direction (matches the
+/// tunnel's own limit).
+const MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+/// How long to wait for the upstream's close reply after Aether closed.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Open the upstream WebSocket and relay until either side closes.
+///
+/// Returns the connection-establishment duration once the upgrade succeeded
+/// (or the upstream answered with a plain HTTP response).
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn relay(
+    state: &AppState,
+    server: &ServerContext,
+    stream_id: u32,
+    request_id: &str,
+    meta: &RequestMeta,
+    mut target_url: url::Url,
+    target_addrs: Vec<SocketAddr>,
+    body_rx: mpsc::Receiver<TunnelFrame>,
+    frame_tx: &FrameSender,
+    response_bytes: &AtomicU64,
+    connect_start: Instant,
+    sequencing: bool,
+) -> Option<Duration> {
+    let is_tls = matches!(target_url.scheme(), "https" | "wss");
+    let _ = target_url.set_scheme(if is_tls { "wss" } else { "ws" });
+
+    let mut request = match target_url.as_str().into_client_request() {
+        Ok(request) => request,
+        Err(e) => {
+            let msg = format!("invalid upstream websocket request: {e}");
+            send_error(frame_tx, stream_id, request_id, &msg).await;
+            return None;
+        }
+    };
+    let headers = request.headers_mut();
+    for (k, v) in &meta.headers {
+        let k_lower = k.to_ascii_lowercase();
+        if BLOCKED_HEADERS.contains(&k_lower.as_str())
+            || HANDSHAKE_HEADERS.contains(&k_lower.as_str())
+        {
+            continue;
+        }
+        if let (Ok(name), Ok(value)) = (
+            hyper::header::HeaderName::from_bytes(k.as_bytes()),
+            hyper::header::HeaderValue::from_str(v),
+        ) {
+            headers.insert(name, value);
+        }
+    }
+    inject_request_id(
+        headers,
+        &state.config.upstream_request_id_header,
+        request_id,
+    );
+    apply_upstream_identity(
+        headers,
+        &server.dynamic.load(),
+        &server.node_id.read().unwrap(),
+    );
+    telemetry::inject(&tracing::Span::current(), headers);
+    let host = target_url.host_str().unwrap_or_default();
+    state.config.upstream_auth.apply(host, headers);
+    state.config.upstream_header_rules.apply(host, headers);
+
+    let ws = match connect(state, server, meta, request, &target_addrs, is_tls).await {
+        Ok(ws) => ws,
+        Err(Rejected(response)) => {
+            let host = target_url.host_str().unwrap_or_default();
+            relay_rejection(state, host, frame_tx, stream_id, response, response_bytes).await;
+            return Some(connect_start.elapsed());
+        }
+        Err(Failed(class, detail)) => {
+            server
+                .metrics
+                .failed_requests
+                .fetch_add(1, Ordering::Release);
+            server.metrics.record_upstream_error(class);
+            let msg = format!("{}: upstream websocket error: {detail}", class.code());
+            send_error(frame_tx, stream_id, request_id, &msg).await;
+            return None;
+        }
+    };
+    let (ws, response) = ws;
+    let connect_elapsed = connect_start.elapsed();
+
+    let resp_meta = ResponseMeta {
+        status: response.status().as_u16(),
+        headers: response_header_pairs(
+            state,
+            target_url.host_str().unwrap_or_default(),
+            response.headers(),
+        ),
+        trailers: Vec::new(),
+    };
+    let compression = server.dynamic.load().compression_min_size();
+    let meta_json: Bytes = serde_json::to_vec(&resp_meta).unwrap_or_default().into();
+    let (meta_payload, meta_flags) = compress_payload(meta_json, compression);
+    if !send_response_frame(
+        frame_tx,
+        TunnelFrame::new(
+            stream_id,
+            MsgType::ResponseHeaders,
+            meta_flags,
+            meta_payload,
+        ),
+        response_bytes,
+    )
+    .await
+    {
+        return Some(connect_elapsed);
+    }
+
+    debug!(stream_id, "websocket relay established");
+    bridge(
+        ws,
+        stream_id,
+        request_id,
+        body_rx,
+        frame_tx,
+        response_bytes,
+        compression,
+        state.downstream_bandwidth.as_deref(),
+        sequencing.then_some(0),
+    )
+    .await;
+    debug!(stream_id, "websocket relay closed");
+    Some(connect_elapsed)
+}
+
+enum ConnectError {
+    /// The upstream answered the upgrade with a plain HTTP response.
+    Rejected(tungstenite::http::Response<Option<Vec<u8>>>),
+    Failed(UpstreamErrorClass, String),
+}
+use ConnectError::{Failed, Rejected};
+
+type UpstreamWebSocket = WebSocketStream<tokio_tungstenite::MaybeTlsStream<TcpStream>>;
+
+async fn connect(
+    state: &AppState,
+    server: &ServerContext,
+    meta: &RequestMeta,
+    request: tungstenite::handshake::client::Request,
+    target_addrs: &[SocketAddr],
+    is_tls: bool,
+) -> Result<(UpstreamWebSocket, tungstenite::handshake::client::Response), ConnectError> {
+    let connect_timeout = Duration::from_secs(state.config.upstream_connect_timeout_secs);
+    let tcp = match tokio::time::timeout(
+        connect_timeout,
+        net::connect_tcp(target_addrs, server.bind_address),
+    )
+    .await
+    {
+        Ok(Ok(tcp)) => tcp,
+        Ok(Err(e)) => {
+            let class = if e.kind() == std::io::ErrorKind::ConnectionRefused {
+                UpstreamErrorClass::ConnectRefused
+            } else {
+                UpstreamErrorClass::Other
+            };
+            return Err(Failed(class, format!("connect error: {e}")));
+        }
+        Err(_) => {
+            return Err(Failed(
+                UpstreamErrorClass::ConnectTimeout,
+                "connect timeout".to_string(),
+            ))
+        }
+    };
+    let _ = tcp.set_nodelay(state.config.upstream_tcp_nodelay);
+
+    let connector = is_tls.then(|| {
+        tokio_tungstenite::Connector::Rustls(std::sync::Arc::clone(&state.upstream_ws_tls_config))
+    });
+    let ws_config = WebSocketConfig {
+        max_frame_size: Some(MAX_MESSAGE_SIZE),
+        max_message_size: Some(MAX_MESSAGE_SIZE),
+        ..Default::default()
+    };
+    let handshake_timeout =
+        Duration::from_secs(meta.timeout.clamp(MIN_TIMEOUT_SECS, MAX_TIMEOUT_SECS));
+    let handshake =
+        tokio_tungstenite::client_async_tls_with_config(request, tcp, Some(ws_config), connector);
+    match tokio::time::timeout(handshake_timeout, handshake).await {
+        Ok(Ok(connected)) => Ok(connected),
+        Ok(Err(tungstenite::Error::Http(response))) => Err(Rejected(response)),
+        Ok(Err(e)) => {
+            let class = match e {
+                tungstenite::Error::Tls(_) => UpstreamErrorClass::TlsHandshake,
+                tungstenite::Error::Io(_) => UpstreamErrorClass::ResponseRead,
+                _ => UpstreamErrorClass::Other,
+            };
+            Err(Failed(class, e.to_string()))
+        }
+        Err(_) => Err(Failed(
+            UpstreamErrorClass::ResponseTimeout,
+            "handshake timeout".to_string(),
+        )),
+    }
+}
+
+/// Forward a refused upgrade (e.g. 401, 404) as an ordinary response.
+async fn relay_rejection(
+    state: &AppState,
+    host: &str,
+    frame_tx: &FrameSender,
+    stream_id: u32,
+    response: tungstenite::http::Response<Option<Vec<u8>>>,
+    forwarded: &AtomicU64,
+) {
+    let resp_meta = ResponseMeta {
+        status: response.status().as_u16(),
+        headers: response_header_pairs(state, host, response.headers()),
+        trailers: Vec::new(),
+    };
+    let meta_json: Bytes = serde_json::to_vec(&resp_meta).unwrap_or_default().into();
+    if !send_response_frame(
+        frame_tx,
+        TunnelFrame::new(stream_id, MsgType::ResponseHeaders, 0, meta_json),
+        forwarded,
+    )
+    .await
+    {
+        return;
+    }
+    if let Some(body) = response.into_body().filter(|b| !b.is_empty()) {
+        if !send_response_frame(
+            frame_tx,
+            TunnelFrame::new(stream_id, MsgType::ResponseBody, 0, body),
+            forwarded,
+        )
+        .await
+        {
+            return;
+        }
+    }
+    let _ = send_frame(
+        frame_tx,
+        TunnelFrame::new(
+            stream_id,
+            MsgType::StreamEnd,
+            flags::END_STREAM,
+            Bytes::new(),
+        ),
+    )
+    .await;
+}
+
+/// Pump messages between the upstream socket and the tunnel stream until
+/// either side closes.
+#[allow(clippy::too_many_arguments)]
+async fn bridge<S>(
+    ws: WebSocketStream<S>,
+    stream_id: u32,
+    request_id: &str,
+    mut body_rx: mpsc::Receiver<TunnelFrame>,
+    frame_tx: &FrameSender,
+    forwarded: &AtomicU64,
+    compression: Option<usize>,
+    bandwidth: Option<&TokenBucket>,
+    mut next_seq: Option<u32>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut sink, mut source) = ws.split();
+    loop {
+        tokio::select! {
+            incoming = source.next() => {
+                let (frame_flags, payload) = match incoming {
+                    Some(Ok(Message::Text(text))) => (flags::WS_TEXT, Bytes::from(text)),
+                    Some(Ok(Message::Binary(data))) => (0, Bytes::from(data)),
+                    Some(Ok(Message::Close(close))) => {
+                        let _ = sink.close().await;
+                        send_end(frame_tx, stream_id, close_payload(close)).await;
+                        return;
+                    }
+                    // Pings are answered by tungstenite itself.
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => {
+                        warn!(stream_id, error = %e, "upstream websocket error");
+                        let msg = format!(
+                            "{}: upstream websocket error: {e}",
+                            UpstreamErrorClass::ResponseRead.code()
+                        );
+                        send_error(frame_tx, stream_id, request_id, &msg).await;
+                        return;
+                    }
+                    None => {
+                        send_end(frame_tx, stream_id, Bytes::new()).await;
+                        return;
+                    }
+                };
+                rate_limit::throttle(bandwidth, payload.len()).await;
+                let (payload, extra_flags) = compress_payload(payload, compression);
+                let frame = TunnelFrame::new(
+                    stream_id,
+                    MsgType::ResponseBody,
+                    frame_flags | extra_flags,
+                    payload,
+                );
+                if !send_response_frame(frame_tx, sequence(frame, &mut next_seq), forwarded).await {
+                    let _ = sink.close().await;
+                    return;
+                }
+            }
+            frame = body_rx.recv() => {
+                let close = match frame {
+                    Some(frame) if frame.msg_type == MsgType::RequestBody => {
+                        let end = frame.is_end_stream();
+                        let message = match client_message(&frame) {
+                            Ok(message) => message,
+                            Err(e) => {
+                                let _ = sink.close().await;
+                                send_error(frame_tx, stream_id, request_id, &e).await;
+                                return;
+                            }
+                        };
+                        if let Some(message) = message.filter(|m| !end || !m.is_empty()) {
+                            if let Err(e) = sink.send(message).await {
+                                warn!(stream_id, error = %e, "upstream websocket send failed");
+                                let msg = format!(
+                                    "{}: upstream websocket error: {e}",
+                                    UpstreamErrorClass::RequestWrite.code()
+                                );
+                                send_error(frame_tx, stream_id, request_id, &msg).await;
+                                return;
+                            }
+                        }
+                        if !end {
+                            continue;
+                        }
+                        None
+                    }
+                    Some(frame) if frame.msg_type == MsgType::StreamEnd => {
+                        close_frame(&frame.payload)
+                    }
+                    Some(frame) if frame.msg_type != MsgType::StreamError => continue,
+                    // Cancelled by Aether, or the tunnel is gone.
+                    _ => {
+                        let _ = sink.close().await;
+                        return;
+                    }
+                };
+
+                // Aether closed: close upstream and wait briefly for its reply.
+                let _ = sink.send(Message::Close(close)).await;
+                let reply = tokio::time::timeout(CLOSE_TIMEOUT, async {
+                    while let Some(Ok(message)) = source.next().await {
+                        if let Message::Close(close) = message {
+                            return close_payload(close);
+                        }
+                    }
+                    Bytes::new()
+                })
+                .await
+                .unwrap_or_default();
+                send_end(frame_tx, stream_id, reply).await;
+                return;
+            }
+        }
+    }
+}
+
+/// Message carried by a request body frame (`None` for an empty final frame
+/// is decided by the caller).
+fn client_message(frame: &TunnelFrame) -> Result<Option<Message>, String> {
+    let payload = decompress_if_gzip(frame).map_err(|e| format!("gzip decompress failed: {e}"))?;
+    if frame.flags & flags::WS_TEXT != 0 {
+        let text = String::from_utf8(payload.to_vec())
+            .map_err(|_| "websocket text message is not valid UTF-8".to_string())?;
+        Ok(Some(Message::Text(text)))
+    } else {
+        Ok(Some(Message::Binary(payload.to_vec())))
+    }
+}
+
+/// Parse a `StreamEnd` payload as a WebSocket close payload.
+fn close_frame(payload: &[u8]) -> Option<CloseFrame<'static>> {
+    let code = u16::from_be_bytes(payload.get(..2)?.try_into().ok()?);
+    Some(CloseFrame {
+        code: CloseCode::from(code),
+        reason: Cow::Owned(String::from_utf8_lossy(&payload[2..]).into_owned()),
+    })
+}
+
+/// Encode a close frame as a `StreamEnd` payload (empty without one).
+fn close_payload(close: Option<CloseFrame<'_>>) -> Bytes {
+    let Some(close) = close else {
+        return Bytes::new();
+    };
+    let mut payload = u16::from(close.code).to_be_bytes().to_vec();
+    payload.extend_from_slice(close.reason.as_bytes());
+    payload.into()
+}
+
+async fn send_end(frame_tx: &FrameSender, stream_id: u32, payload: Bytes) {
+    let _ = send_frame(
+        frame_tx,
+        TunnelFrame::new(stream_id, MsgType::StreamEnd, flags::END_STREAM, payload),
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Local WebSocket server echoing every data message.
+    async fn echo_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(tcp).await.unwrap();
+            while let Some(Ok(message)) = ws.next().await {
+                if message.is_text() || message.is_binary() {
+                    ws.send(message).await.unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn messages_are_relayed_both_ways_and_close_ends_the_stream() {
+        let addr = echo_server().await;
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let (ws, _) = tokio_tungstenite::client_async(format!("ws://{addr}/"), tcp)
+            .await
+            .unwrap();
+        let (body_tx, body_rx) = mpsc::channel(4);
+        let (frame_tx, mut frame_rx) = mpsc::channel(4);
+        let relay = tokio::spawn(async move {
+            let forwarded = AtomicU64::new(0);
+            bridge(
+                ws, 7, "req-1", body_rx, &frame_tx, &forwarded, None, None, None,
+            )
+            .await;
+        });
+
+        body_tx
+            .send(TunnelFrame::new(
+                7,
+                MsgType::RequestBody,
+                flags::WS_TEXT,
+                Bytes::from_static(b"hello"),
+            ))
+            .await
+            .unwrap();
+        let echoed = frame_rx.recv().await.unwrap();
+        assert_eq!(echoed.msg_type, MsgType::ResponseBody);
+        assert_eq!(echoed.flags, flags::WS_TEXT);
+        assert_eq!(&echoed.payload[..], b"hello");
+
+        body_tx
+            .send(TunnelFrame::new(
+                7,
+                MsgType::RequestBody,
+                0,
+                Bytes::from_static(&[0, 1, 2]),
+            ))
+            .await
+            .unwrap();
+        let echoed = frame_rx.recv().await.unwrap();
+        assert_eq!(echoed.flags, 0);
+        assert_eq!(&echoed.payload[..], [0, 1, 2]);
+
+        // Aether closes with 1000 "bye"; the upstream's close reply ends
+        // the stream.
+        let mut close = 1000u16.to_be_bytes().to_vec();
+        close.extend_from_slice(b"bye");
+        body_tx
+            .send(TunnelFrame::new(7, MsgType::StreamEnd, 0, close.clone()))
+            .await
+            .unwrap();
+        let end = frame_rx.recv().await.unwrap();
+        assert_eq!(end.msg_type, MsgType::StreamEnd);
+        assert_eq!(&end.payload[..], &close[..]);
+        relay.await.unwrap();
+    }
+
+    #[test]
+    fn close_payload_round_trips() {
+        let frame = close_frame(b"\x03\xe8done").unwrap();
+        assert_eq!(u16::from(frame.code), 1000);
+        assert_eq!(frame.reason, "done");
+        assert_eq!(&close_payload(Some(frame))[..], b"\x03\xe8done");
+        assert!(close_frame(b"x").is_none());
+        assert!(close_payload(None).is_empty());
+    }
+}
@@ -0,0 +1,293 @@
+//! Upstream authentication: static per-host credentials and detection of
+//! 401/407 challenge loops.
+//!
+//! `upstream_auth` maps a target host to a header injected into requests
+//! that carry no `Authorization` of their own (for upstreams behind an
+//! authenticating proxy).  The auth-loop detector remembers the last
+//! challenge per request; when the same request is challenged identically
+//! again within `AUTH_LOOP_WINDOW`, the response is flagged with
+//! `x-aether-auth-loop: true` so Aether stops retrying it.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::config::redact_secret;
+
+/// Response header marking a repeated identical auth challenge.
+pub const AUTH_LOOP_HEADER: &str = "x-aether-auth-loop";
+/// How long a challenge is remembered for loop detection.
+pub const AUTH_LOOP_WINDOW: Duration = Duration::from_secs(60);
+/// Maximum number of challenged requests remembered at once.
+pub const AUTH_LOOP_CAPACITY: usize = 1024;
+
+/// Header injected for one host.  `Debug` redacts the value.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpstreamCredential {
+    pub header: String,
+    pub value: String,
+}
+
+impl fmt::Debug for UpstreamCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UpstreamCredential")
+            .field("header", &self.header)
+            .field("value", &redact_secret(&self.value))
+            .finish()
+    }
+}
+
+/// Configured credentials by host (case-insensitive).  Parsed from a JSON
+/// object on the command line and in the environment; an `[upstream_auth]`
+/// table in the config file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct UpstreamAuth(pub BTreeMap<String, UpstreamCredential>);
+
+impl UpstreamAuth {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Copy with every credential value masked, for display.
+    pub fn redacted(&self) -> Self {
+        Self(
+            self.0
+                .iter()
+                .map(|(host, cred)| {
+                    let cred = UpstreamCredential {
+                        header: cred.header.clone(),
+                        value: redact_secret(&cred.value),
+                    };
+                    (host.clone(), cred)
+                })
+                .collect(),
+        )
+    }
+
+    /// Inject the credential for `host` unless the request already has an
+    /// `Authorization` header or the configured header itself.
+    pub fn apply(&self, host: &str, headers: &mut HeaderMap) {
+        let Some(cred) = self
+            .0
+            .iter()
+            .find(|(h, _)| h.eq_ignore_ascii_case(host))
+            .map(|(_, cred)| cred)
+        else {
+            return;
+        };
+        // Checked when the config was parsed.
+        let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(cred.header.as_bytes()),
+            HeaderValue::from_str(&cred.value),
+        ) else {
+            return;
+        };
+        if headers.contains_key(AUTHORIZATION) || headers.contains_key(&name) {
+            return;
+        }
+        let mut value = value;
+        value.set_sensitive(true);
+        headers.insert(name, value);
+    }
+}
+
+impl FromStr for UpstreamAuth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let auth: Self = serde_json::from_str(s).map_err(|e| e.to_string())?;
+        for (host, cred) in &auth.0 {
+            HeaderName::from_bytes(cred.header.as_bytes())
+                .map_err(|_| format!("upstream_auth.{host}: invalid header name"))?;
+            HeaderValue::from_str(&cred.value)
+                .map_err(|_| format!("upstream_auth.{host}: invalid header value"))?;
+        }
+        Ok(auth)
+    }
+}
+
+impl fmt::Display for UpstreamAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&serde_json::to_string(self).map_err(|_| fmt::Error)?)
+    }
+}
+
+/// Remembers the last 401/407 challenge per request (host, method, URL),
+/// bounded by capacity (the oldest entry is evicted first).
+pub struct AuthLoopDetector {
+    window: Duration,
+    capacity: usize,
+    entries: Mutex<HashMap<u64, (u64, Instant)>>,
+}
+
+impl AuthLoopDetector {
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        Self {
+            window,
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record an upstream response; returns `true` when it repeats the
+    /// previous challenge for the same request within the window.
+    pub fn observe(
+        &self,
+        host: &str,
+        method: &str,
+        url: &str,
+        status: StatusCode,
+        headers: &HeaderMap,
+    ) -> bool {
+        self.observe_at(host, method, url, status, headers, Instant::now())
+    }
+
+    fn observe_at(
+        &self,
+        host: &str,
+        method: &str,
+        url: &str,
+        status: StatusCode,
+        headers: &HeaderMap,
+        now: Instant,
+    ) -> bool {
+        if self.capacity == 0 {
+            return false;
+        }
+        let mut hasher = DefaultHasher::new();
+        host.to_ascii_lowercase().hash(&mut hasher);
+        method.hash(&mut hasher);
+        url.hash(&mut hasher);
+        let key = hasher.finish();
+
+        let challenge_header = match status {
+            StatusCode::UNAUTHORIZED => hyper::header::WWW_AUTHENTICATE,
+            StatusCode::PROXY_AUTHENTICATION_REQUIRED => hyper::header::PROXY_AUTHENTICATE,
+            _ => {
+                self.entries.lock().unwrap().remove(&key);
+                return false;
+            }
+        };
+        let mut hasher = DefaultHasher::new();
+        status.as_u16().hash(&mut hasher);
+        for value in headers.get_all(challenge_header) {
+            value.as_bytes().hash(&mut hasher);
+        }
+        let challenge = hasher.finish();
+
+        let mut entries = self.entries.lock().unwrap();
+        let repeated = entries.get(&key).is_some_and(|(previous, seen)| {
+            *previous == challenge && now.duration_since(*seen) < self.window
+        });
+        entries.retain(|_, (_, seen)| now.duration_since(*seen) < self.window);
+        while entries.len() >= self.capacity && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (_, seen))| *seen)
+                .map(|(key, _)| *key);
+            match oldest {
+                Some(oldest) => entries.remove(&oldest),
+                None => break,
+            };
+        }
+        entries.insert(key, (challenge, now));
+        repeated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            hyper::header::PROXY_AUTHENTICATE,
+            HeaderValue::from_static(value),
+        );
+        headers
+    }
+
+    #[test]
+    fn repeated_identical_challenges_are_a_loop() {
+        let detector = AuthLoopDetector::new(AUTH_LOOP_WINDOW, 16);
+        let start = Instant::now();
+        let status = StatusCode::PROXY_AUTHENTICATION_REQUIRED;
+        let basic = challenge("Basic realm=\"corp\"");
+        let observe = |url, headers: &HeaderMap, at| {
+            detector.observe_at("api.example.com", "GET", url, status, headers, at)
+        };
+
+        assert!(!observe("https://api.example.com/a", &basic, start));
+        assert!(observe(
+            "https://api.example.com/a",
+            &basic,
+            start + Duration::from_secs(5)
+        ));
+        // Another request, a changed challenge or an expired entry is not.
+        assert!(!observe("https://api.example.com/b", &basic, start));
+        let digest = challenge("Digest realm=\"corp\"");
+        assert!(!observe(
+            "https://api.example.com/a",
+            &digest,
+            start + Duration::from_secs(6)
+        ));
+        assert!(!observe(
+            "https://api.example.com/a",
+            &digest,
+            start + Duration::from_secs(6) + AUTH_LOOP_WINDOW
+        ));
+
+        // A successful response resets the request.
+        let url = "https://api.example.com/c";
+        assert!(!observe(url, &basic, start));
+        assert!(!detector.observe_at(
+            "api.example.com",
+            "GET",
+            url,
+            StatusCode::OK,
+            &HeaderMap::new(),
+            start
+        ));
+        assert!(!observe(url, &basic, start));
+    }
+
+    #[test]
+    fn credentials_only_fill_in_missing_auth() {
+        let auth: UpstreamAuth = r#"{
+            "api.example.com": {"header": "proxy-authorization", "value": "Basic c2VjcmV0"}
+        }"#
+        .parse()
+        .unwrap();
+
+        let mut headers = HeaderMap::new();
+        auth.apply("API.example.com", &mut headers);
+        assert_eq!(headers["proxy-authorization"], "Basic c2VjcmV0");
+
+        // The request's own Authorization or header wins.
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer client"));
+        auth.apply("api.example.com", &mut headers);
+        assert!(!headers.contains_key("proxy-authorization"));
+        let mut headers = HeaderMap::new();
+        headers.insert("proxy-authorization", HeaderValue::from_static("Basic own"));
+        auth.apply("api.example.com", &mut headers);
+        assert_eq!(headers["proxy-authorization"], "Basic own");
+
+        let mut headers = HeaderMap::new();
+        auth.apply("other.example.com", &mut headers);
+        assert!(headers.is_empty());
+
+        assert!(!format!("{auth:?}").contains("c2VjcmV0"));
+        assert!(r#"{"h": {"header": "bad name", "value": "x"}}"#.parse::<UpstreamAuth>().is_err());
+    }
+}
@@ -0,0 +1,649 @@
+//! Mock Aether server for aether-proxy integration tests.
+//!
+//! [`MockAetherServer`] listens on a random local port and plays Aether's
+//! side of a proxy's lifecycle: the registration API
+//! (`/api/admin/proxy-nodes/*`) and the WebSocket tunnel
+//! (`/api/internal/proxy-tunnel`).  Every tunnel the proxy opens is handed
+//! to the test as a [`MockTunnel`], which sends synthetic requests and
+//! collects the proxy's responses.  Heartbeats are acknowledged
+//! automatically.  Like aether-hub, the tunnel endpoint answers a handshake
+//! without an `X-Node-Id` header with 400.
+//!
+//! ```no_run
+//! # async fn demo() {
+//! use aether_proxy_test_utils::MockAetherServer;
+//!
+//! let server = MockAetherServer::builder().node_id("node-1").build().await;
+//! // Start aether-proxy with `--aether-url <server.url()>`, then:
+//! let mut tunnel = server.accept_tunnel().await;
+//! let heartbeat = tunnel.expect_heartbeat().await;
+//! # }
+//! ```
+//!
+//! Waits panic after the builder's `timeout`, so a hung proxy fails the
+//! test instead of stalling it.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use aether_tunnel_protocol::{
+    decompress_if_gzip, flags, headers as tunnel_headers, Frame, MsgType, RequestMeta, ResponseMeta,
+};
+use bytes::Bytes;
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/// Default for [`MockAetherServerBuilder::timeout`].
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const REGISTER_PATH: &str = "/api/admin/proxy-nodes/register";
+const UNREGISTER_PATH: &str = "/api/admin/proxy-nodes/unregister";
+const TUNNEL_PATH: &str = "/api/internal/proxy-tunnel";
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// Configures a [`MockAetherServer`].
+#[derive(Debug, Clone)]
+pub struct MockAetherServerBuilder {
+    node_id: String,
+    register_delay: Duration,
+    register_failures: u32,
+    heartbeat_ack: serde_json::Value,
+    heartbeat_ack_delay: Duration,
+    cross_connection: bool,
+    frame_sequencing: bool,
+    response_trailers: bool,
+    timeout: Duration,
+}
+
+impl Default for MockAetherServerBuilder {
+    fn default() -> Self {
+        Self {
+            node_id: "mock-node".to_string(),
+            register_delay: Duration::ZERO,
+            register_failures: 0,
+            heartbeat_ack: serde_json::json!({}),
+            heartbeat_ack_delay: Duration::ZERO,
+            cross_connection: false,
+            frame_sequencing: false,
+            response_trailers: true,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl MockAetherServerBuilder {
+    /// node_id returned by registration.
+    pub fn node_id(mut self, node_id: impl Into<String>) -> Self {
+        self.node_id = node_id.into();
+        self
+    }
+
+    /// Delay before answering each registration.
+    pub fn register_delay(mut self, delay: Duration) -> Self {
+        self.register_delay = delay;
+        self
+    }
+
+    /// Answer the first `count` registrations with 503.
+    pub fn register_failures(mut self, count: u32) -> Self {
+        self.register_failures = count;
+        self
+    }
+
+    /// JSON payload of every HeartbeatAck (`{}` by default), e.g. a
+    /// `remote_config` push or `{"action": "reregister"}`.
+    pub fn heartbeat_ack(mut self, payload: serde_json::Value) -> Self {
+        self.heartbeat_ack = payload;
+        self
+    }
+
+    /// Delay before acknowledging each heartbeat.
+    pub fn heartbeat_ack_delay(mut self, delay: Duration) -> Self {
+        self.heartbeat_ack_delay = delay;
+        self
+    }
+
+    /// Accept cross-connection responses from proxies that offer them, so
+    /// a stream's frames may arrive on any of its tunnels.
+    pub fn cross_connection(mut self, accept: bool) -> Self {
+        self.cross_connection = accept;
+        self
+    }
+
+    /// Accept frame sequencing from proxies that offer it.  Sequence
+    /// prefixes are stripped from collected frames either way.
+    pub fn frame_sequencing(mut self, accept: bool) -> Self {
+        self.frame_sequencing = accept;
+        self
+    }
+
+    /// Accept response trailers from proxies that offer them (the default).
+    pub fn response_trailers(mut self, accept: bool) -> Self {
+        self.response_trailers = accept;
+        self
+    }
+
+    /// How long waits on the proxy last before panicking.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Bind `127.0.0.1:0` and start serving.
+    pub async fn build(self) -> MockAetherServer {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind mock Aether server");
+        let addr = listener.local_addr().expect("mock Aether server address");
+        let timeout = self.timeout;
+        let shared = Arc::new(Shared {
+            remaining_failures: AtomicU32::new(self.register_failures),
+            unregister_failures: AtomicU32::new(0),
+            rejected_tunnels: AtomicU32::new(0),
+            config: self,
+            registrations: Mutex::new(Vec::new()),
+            unregistrations: Mutex::new(Vec::new()),
+        });
+        let (tunnel_tx, tunnel_rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(accept_loop(listener, Arc::clone(&shared), tunnel_tx));
+        MockAetherServer {
+            addr,
+            shared,
+            tunnels: tokio::sync::Mutex::new(tunnel_rx),
+            timeout,
+            task,
+        }
+    }
+}
+
+struct Shared {
+    config: MockAetherServerBuilder,
+    remaining_failures: AtomicU32,
+    unregister_failures: AtomicU32,
+    rejected_tunnels: AtomicU32,
+    registrations: Mutex<Vec<serde_json::Value>>,
+    unregistrations: Mutex<Vec<serde_json::Value>>,
+}
+
+/// Local stand-in for an Aether server.  Stops accepting connections when
+/// dropped.
+pub struct MockAetherServer {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    tunnels: tokio::sync::Mutex<mpsc::UnboundedReceiver<MockTunnel>>,
+    timeout: Duration,
+    task: JoinHandle<()>,
+}
+
+impl MockAetherServer {
+    pub fn builder() -> MockAetherServerBuilder {
+        MockAetherServerBuilder::default()
+    }
+
+    /// Base URL to pass as `--aether-url`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Bodies of the successful registrations so far.
+    pub fn registrations(&self) -> Vec<serde_json::Value> {
+        self.shared.registrations.lock().unwrap().clone()
+    }
+
+    /// Bodies of the unregistrations so far.
+    pub fn unregistrations(&self) -> Vec<serde_json::Value> {
+        self.shared.unregistrations.lock().unwrap().clone()
+    }
+
+    /// Answer the next `count` registrations with a 503 (0 stops injecting).
+    pub fn fail_registrations(&self, count: u32) {
+        self.shared
+            .remaining_failures
+            .store(count, Ordering::Release);
+    }
+
+    /// Answer the next `count` unregistrations with a 503 (0 stops injecting).
+    pub fn fail_unregistrations(&self, count: u32) {
+        self.shared
+            .unregister_failures
+            .store(count, Ordering::Release);
+    }
+
+    /// Tunnel handshakes refused for lacking an `X-Node-Id` header.
+    pub fn rejected_tunnels(&self) -> u32 {
+        self.shared.rejected_tunnels.load(Ordering::Acquire)
+    }
+
+    /// Wait for the proxy's next tunnel connection.
+    pub async fn accept_tunnel(&self) -> MockTunnel {
+        let mut tunnels = self.tunnels.lock().await;
+        match tokio::time::timeout(self.timeout, tunnels.recv()).await {
+            Ok(Some(tunnel)) => tunnel,
+            Ok(None) => panic!("mock Aether server stopped"),
+            Err(_) => panic!("no tunnel connection within {:?}", self.timeout),
+        }
+    }
+}
+
+impl Drop for MockAetherServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// One tunnel connection from the proxy.
+pub struct MockTunnel {
+    headers: HashMap<String, String>,
+    outgoing: mpsc::UnboundedSender<Frame>,
+    frames: mpsc::UnboundedReceiver<Frame>,
+    heartbeats: mpsc::UnboundedReceiver<serde_json::Value>,
+    /// Frames received while waiting for another stream.
+    pending: VecDeque<Frame>,
+    timeout: Duration,
+    pump: JoinHandle<()>,
+}
+
+/// What the proxy sent back for one stream.
+#[derive(Debug, Clone, Default)]
+pub struct MockResponse {
+    /// ResponseHeaders, if the proxy got that far.
+    pub meta: Option<ResponseMeta>,
+    pub body: Vec<u8>,
+    /// ResponseTrailers, if any.
+    pub trailers: Vec<(String, String)>,
+    /// StreamError payload, when the stream failed.
+    pub error: Option<String>,
+    /// Connection id from REROUTED frames: the stream started on that
+    /// connection and finished on this one.
+    pub origin_connection: Option<u32>,
+}
+
+impl MockResponse {
+    /// Response status; panics if the stream failed before its headers.
+    pub fn status(&self) -> u16 {
+        match &self.meta {
+            Some(meta) => meta.status,
+            None => panic!("no response headers (error: {:?})", self.error),
+        }
+    }
+
+    /// First response header named `name` (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.meta.as_ref().and_then(|meta| {
+            meta.headers
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.as_str())
+        })
+    }
+}
+
+impl MockTunnel {
+    /// Handshake request header `name` (case-insensitive), e.g. `X-Node-Id`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    /// Send a raw frame to the proxy.
+    pub fn send_frame(&self, frame: Frame) {
+        let _ = self.outgoing.send(frame);
+    }
+
+    /// Open stream `stream_id`: RequestHeaders, then `body` in one
+    /// RequestBody frame marked END_STREAM.
+    pub fn send_request(&self, stream_id: u32, meta: &RequestMeta, body: &[u8]) {
+        let meta = serde_json::to_vec(meta).expect("serialize RequestMeta");
+        self.send_frame(Frame::new(stream_id, MsgType::RequestHeaders, 0, meta));
+        self.send_frame(Frame::new(
+            stream_id,
+            MsgType::RequestBody,
+            flags::END_STREAM,
+            Bytes::copy_from_slice(body),
+        ));
+    }
+
+    /// Ask the proxy to drop this connection and reconnect.
+    pub fn send_goaway(&self) {
+        self.send_frame(Frame::control(MsgType::GoAway, Bytes::new()));
+    }
+
+    /// Wait for the next heartbeat and return its JSON payload.
+    pub async fn expect_heartbeat(&mut self) -> serde_json::Value {
+        match tokio::time::timeout(self.timeout, self.heartbeats.recv()).await {
+            Ok(Some(heartbeat)) => heartbeat,
+            Ok(None) => panic!("tunnel closed while waiting for a heartbeat"),
+            Err(_) => panic!("no heartbeat within {:?}", self.timeout),
+        }
+    }
+
+    /// Collect stream `stream_id` until StreamEnd or StreamError.
+    pub async fn expect_response(&mut self, stream_id: u32) -> MockResponse {
+        let mut response = MockResponse::default();
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        loop {
+            let frame = match self.pending.iter().position(|f| f.stream_id == stream_id) {
+                Some(i) => self.pending.remove(i).unwrap(),
+                None => match tokio::time::timeout_at(deadline, self.frames.recv()).await {
+                    Ok(Some(frame)) if frame.stream_id == stream_id => frame,
+                    Ok(Some(frame)) => {
+                        self.pending.push_back(frame);
+                        continue;
+                    }
+                    Ok(None) => panic!("tunnel closed before stream {stream_id} finished"),
+                    Err(_) => panic!("stream {stream_id} unfinished after {:?}", self.timeout),
+                },
+            };
+            let (msg_type, data, origin) = payload(frame);
+            response.origin_connection = origin.or(response.origin_connection);
+            record_frame(&mut response, msg_type, data);
+            if response.error.is_some() || msg_type == MsgType::StreamEnd {
+                return response;
+            }
+        }
+    }
+
+    /// Drop the connection without a close handshake, as a network failure
+    /// would.
+    pub fn close(self) {
+        self.pump.abort();
+    }
+
+    /// Wait until the proxy closes this tunnel.
+    pub async fn expect_closed(&mut self) {
+        let closed = async {
+            while let Some(frame) = self.frames.recv().await {
+                self.pending.push_back(frame);
+            }
+        };
+        if tokio::time::timeout(self.timeout, closed).await.is_err() {
+            panic!("tunnel still open after {:?}", self.timeout);
+        }
+    }
+}
+
+/// Message type, decoded payload (origin, sequence number and gzip
+/// removed) and origin connection id.
+fn payload(mut frame: Frame) -> (MsgType, Bytes, Option<u32>) {
+    let origin = frame.take_origin().expect("valid origin prefix");
+    frame.take_sequence().expect("valid sequence prefix");
+    let data = decompress_if_gzip(&frame).expect("valid gzip payload");
+    (frame.msg_type, data, origin)
+}
+
+fn record_frame(response: &mut MockResponse, msg_type: MsgType, data: Bytes) {
+    match msg_type {
+        MsgType::ResponseHeaders => {
+            response.meta = Some(serde_json::from_slice(&data).expect("ResponseMeta JSON"));
+        }
+        MsgType::ResponseBody => response.body.extend_from_slice(&data),
+        MsgType::ResponseTrailers => {
+            let meta: ResponseMeta = serde_json::from_slice(&data).expect("ResponseMeta JSON");
+            response.trailers = meta.trailers;
+        }
+        MsgType::StreamError => {
+            response.error = Some(String::from_utf8_lossy(&data).into_owned());
+        }
+        _ => {}
+    }
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    shared: Arc<Shared>,
+    tunnels: mpsc::UnboundedSender<MockTunnel>,
+) {
+    while let Ok((tcp, _)) = listener.accept().await {
+        let shared = Arc::clone(&shared);
+        let tunnels = tunnels.clone();
+        tokio::spawn(async move {
+            let _ = handle_connection(tcp, shared, tunnels).await;
+        });
+    }
+}
+
+/// Parsed request line and headers (names lowercased).
+struct RequestHead {
+    len: usize,
+    method: String,
+    path: String,
+    headers: HashMap<String, String>,
+}
+
+/// Peek at the request head without consuming it, so WebSocket upgrades
+/// can be handed to tungstenite untouched.
+async fn peek_head(tcp: &TcpStream) -> std::io::Result<Option<RequestHead>> {
+    let mut buf = vec![0u8; MAX_HEAD_SIZE];
+    loop {
+        let n = tcp.peek(&mut buf).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        if let Some(end) = buf[..n].windows(4).position(|w| w == b"\r\n\r\n") {
+            let text = String::from_utf8_lossy(&buf[..end]);
+            let mut lines = text.split("\r\n");
+            let mut request_line = lines.next().unwrap_or_default().split(' ');
+            let method = request_line.next().unwrap_or_default().to_string();
+            let path = request_line.next().unwrap_or_default().to_string();
+            let headers = lines
+                .filter_map(|line| line.split_once(':'))
+                .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+                .collect();
+            return Ok(Some(RequestHead {
+                len: end + 4,
+                method,
+                path,
+                headers,
+            }));
+        }
+        if n == buf.len() {
+            return Err(std::io::Error::other("request head too large"));
+        }
+        // peek() returns at once while data is buffered; wait for more.
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+}
+
+async fn handle_connection(
+    mut tcp: TcpStream,
+    shared: Arc<Shared>,
+    tunnels: mpsc::UnboundedSender<MockTunnel>,
+) -> std::io::Result<()> {
+    let Some(head) = peek_head(&tcp).await? else {
+        return Ok(());
+    };
+    let path = head.path.split('?').next().unwrap_or_default();
+    let upgrade = head
+        .headers
+        .get("upgrade")
+        .is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    if upgrade && path == TUNNEL_PATH {
+        let has_node_id = head
+            .headers
+            .get("x-node-id")
+            .is_some_and(|v| !v.trim().is_empty());
+        let cross_connection = shared.config.cross_connection
+            && head
+                .headers
+                .get(tunnel_headers::CROSS_CONNECTION)
+                .is_some_and(|v| v == "1");
+        let frame_sequencing = shared.config.frame_sequencing
+            && head
+                .headers
+                .get(tunnel_headers::FRAME_SEQUENCING)
+                .is_some_and(|v| v == "1");
+        let response_trailers = shared.config.response_trailers
+            && head
+                .headers
+                .get(tunnel_headers::RESPONSE_TRAILERS)
+                .is_some_and(|v| v == "1");
+        // The error type is fixed by tungstenite's callback signature.
+        #[allow(clippy::result_large_err)]
+        let accept = move |_: &Request, mut response: Response| {
+            if !has_node_id {
+                let mut reject = ErrorResponse::new(Some("missing X-Node-ID header".into()));
+                *reject.status_mut() = StatusCode::BAD_REQUEST;
+                return Err(reject);
+            }
+            if cross_connection {
+                response.headers_mut().insert(
+                    tunnel_headers::CROSS_CONNECTION,
+                    "1".parse().expect("header value"),
+                );
+            }
+            if frame_sequencing {
+                response.headers_mut().insert(
+                    tunnel_headers::FRAME_SEQUENCING,
+                    "1".parse().expect("header value"),
+                );
+            }
+            if response_trailers {
+                response.headers_mut().insert(
+                    tunnel_headers::RESPONSE_TRAILERS,
+                    "1".parse().expect("header value"),
+                );
+            }
+            Ok(response)
+        };
+        let ws = match tokio_tungstenite::accept_hdr_async(tcp, accept).await {
+            Ok(ws) => ws,
+            Err(e) => {
+                if !has_node_id {
+                    shared.rejected_tunnels.fetch_add(1, Ordering::AcqRel);
+                }
+                return Err(std::io::Error::other(e));
+            }
+        };
+        let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+        let (frames_tx, frames_rx) = mpsc::unbounded_channel();
+        let (heartbeats_tx, heartbeats_rx) = mpsc::unbounded_channel();
+        let pump = tokio::spawn(run_tunnel(
+            ws,
+            Arc::clone(&shared),
+            outgoing_tx.clone(),
+            outgoing_rx,
+            frames_tx,
+            heartbeats_tx,
+        ));
+        let _ = tunnels.send(MockTunnel {
+            headers: head.headers,
+            outgoing: outgoing_tx,
+            frames: frames_rx,
+            heartbeats: heartbeats_rx,
+            pending: VecDeque::new(),
+            timeout: shared.config.timeout,
+            pump,
+        });
+        return Ok(());
+    }
+
+    let mut discard = vec![0u8; head.len];
+    tcp.read_exact(&mut discard).await?;
+    let content_length = head
+        .headers
+        .get("content-length")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0usize);
+    let mut body = vec![0u8; content_length];
+    tcp.read_exact(&mut body).await?;
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+
+    let (status, reply) = match (head.method.as_str(), path) {
+        ("POST", REGISTER_PATH) => {
+            tokio::time::sleep(shared.config.register_delay).await;
+            let injected = shared
+                .remaining_failures
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+                .is_ok();
+            if injected {
+                (503, serde_json::json!({"detail": "injected failure"}))
+            } else {
+                shared.registrations.lock().unwrap().push(body);
+                (200, serde_json::json!({"node_id": shared.config.node_id}))
+            }
+        }
+        ("POST", UNREGISTER_PATH) => {
+            let injected = shared
+                .unregister_failures
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+                .is_ok();
+            if injected {
+                (503, serde_json::json!({"detail": "injected failure"}))
+            } else {
+                shared.unregistrations.lock().unwrap().push(body);
+                (200, serde_json::json!({}))
+            }
+        }
+        _ => (404, serde_json::json!({"detail": "not found"})),
+    };
+    let reply = reply.to_string();
+    let response = format!(
+        "HTTP/1.1 {status} {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{reply}",
+        match status {
+            200 => "OK",
+            404 => "Not Found",
+            _ => "Service Unavailable",
+        },
+        reply.len(),
+    );
+    tcp.write_all(response.as_bytes()).await?;
+    tcp.shutdown().await
+}
+
+/// Pump one tunnel: frames queued by the test go out, heartbeats are
+/// acknowledged, everything else is handed to the [`MockTunnel`].
+async fn run_tunnel(
+    ws: WebSocketStream<TcpStream>,
+    shared: Arc<Shared>,
+    outgoing_tx: mpsc::UnboundedSender<Frame>,
+    mut outgoing_rx: mpsc::UnboundedReceiver<Frame>,
+    frames: mpsc::UnboundedSender<Frame>,
+    heartbeats: mpsc::UnboundedSender<serde_json::Value>,
+) {
+    let (mut sink, mut stream) = ws.split();
+    loop {
+        tokio::select! {
+            Some(frame) = outgoing_rx.recv() => {
+                if sink.send(Message::Binary(frame.encode().to_vec())).await.is_err() {
+                    break;
+                }
+            }
+            msg = stream.next() => {
+                let data = match msg {
+                    Some(Ok(Message::Binary(data))) => data,
+                    Some(Ok(_)) => continue,
+                    _ => break,
+                };
+                let Ok(frame) = Frame::decode(data.into()) else {
+                    continue;
+                };
+                if frame.msg_type == MsgType::HeartbeatData {
+                    let data = decompress_if_gzip(&frame).unwrap_or_default();
+                    let _ = heartbeats.send(serde_json::from_slice(&data).unwrap_or_default());
+                    let ack = shared.config.heartbeat_ack.to_string();
+                    let delay = shared.config.heartbeat_ack_delay;
+                    let outgoing = outgoing_tx.clone();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(delay).await;
+                        let _ = outgoing.send(Frame::control(MsgType::HeartbeatAck, ack));
+                    });
+                } else if frames.send(frame).is_err() {
+                    // The MockTunnel was dropped.
+                    break;
+                }
+            }
+        }
+    }
+    let _ = sink.close().await;
+}
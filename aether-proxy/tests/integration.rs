@@ -0,0 +1,420 @@
+//! End-to-end tests: the aether-proxy binary against a `MockAetherServer`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use aether_proxy_test_utils::MockAetherServer;
+use aether_tunnel_protocol::{headers, Frame, MsgType, RequestMeta};
+use tokio::process::{Child, Command};
+
+/// Per-test scratch directory for the config and state files.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("aether-proxy-it-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Start the proxy against `server` with a single tunnel connection and a
+/// one-second heartbeat.  Killed when the handle is dropped.
+fn spawn_proxy(server: &MockAetherServer, name: &str) -> Child {
+    spawn_proxy_with(server, name, &["--tunnel-connections", "1"])
+}
+
+/// Like [`spawn_proxy`], with `args` instead of the single connection.
+fn spawn_proxy_with(server: &MockAetherServer, name: &str, args: &[&str]) -> Child {
+    spawn_proxy_in(server, &scratch_dir(name), args)
+}
+
+/// Start the proxy with its config and state files in `dir`, which may
+/// hold a previous run's state.
+fn spawn_proxy_in(server: &MockAetherServer, dir: &Path, args: &[&str]) -> Child {
+    let args = [&["--public-ip", "203.0.113.10"], args].concat();
+    spawn_proxy_without_public_ip(server, dir, &args)
+}
+
+/// Like [`spawn_proxy_in`], leaving the public IP to the proxy (or `args`).
+fn spawn_proxy_without_public_ip(server: &MockAetherServer, dir: &Path, args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_aether-proxy"))
+        .env_clear()
+        .env("AETHER_PROXY_CONFIG", dir.join("aether-proxy.toml"))
+        .args(["--aether-url", &server.url()])
+        .args(["--management-token", "test-token"])
+        .args(["--node-name", "it-node"])
+        .args(["--heartbeat-interval", "1"])
+        .args(args)
+        .args(["--aether-retry-base-delay-ms", "10"])
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("start aether-proxy")
+}
+
+fn get(url: &str, fingerprint: Option<&str>) -> RequestMeta {
+    RequestMeta {
+        method: "GET".to_string(),
+        url: url.to_string(),
+        headers: HashMap::from([("accept".to_string(), "*/*".to_string())]),
+        timeout: 5,
+        request_id: Some("it-request".to_string()),
+        request_fingerprint: fingerprint.map(str::to_string),
+        body_sha256: None,
+        websocket: false,
+    }
+}
+
+#[tokio::test]
+async fn registers_then_heartbeats_over_the_tunnel() {
+    let server = MockAetherServer::builder()
+        .node_id("node-it-1")
+        .register_failures(1)
+        .build()
+        .await;
+    let _proxy = spawn_proxy(&server, "register");
+
+    let mut tunnel = server.accept_tunnel().await;
+    // The injected 503 was retried.
+    let registrations = server.registrations();
+    assert_eq!(registrations.len(), 1);
+    assert_eq!(registrations[0]["name"], "it-node");
+    assert_eq!(registrations[0]["ip"], "203.0.113.10");
+    assert_eq!(registrations[0]["tunnel_mode"], true);
+    assert_eq!(tunnel.header("authorization"), Some("Bearer test-token"));
+    assert_eq!(tunnel.header("x-node-id"), Some("node-it-1"));
+    assert_eq!(tunnel.header("x-node-name"), Some("it-node"));
+
+    let heartbeat = tunnel.expect_heartbeat().await;
+    assert!(heartbeat.get("total_requests").is_some());
+    assert_eq!(
+        heartbeat["client_instance_id"],
+        registrations[0]["client_instance_id"]
+    );
+}
+
+#[tokio::test]
+async fn disabled_ip_detection_asks_aether_for_the_source_address() {
+    let server = MockAetherServer::builder().build().await;
+    let _proxy = spawn_proxy_without_public_ip(
+        &server,
+        &scratch_dir("no-ip-detection"),
+        &["--tunnel-connections", "1", "--disable-ip-detection"],
+    );
+
+    server.accept_tunnel().await;
+    let registrations = server.registrations();
+    assert_eq!(registrations[0]["ip"], "0.0.0.0");
+    assert_eq!(registrations[0]["detect_ip_server_side"], true);
+}
+
+#[tokio::test]
+async fn lazy_registration_connects_once_a_node_id_is_assigned() {
+    let server = MockAetherServer::builder()
+        .node_id("node-lazy")
+        .register_failures(1)
+        .build()
+        .await;
+    let _proxy = spawn_proxy_with(
+        &server,
+        "lazy",
+        &[
+            "--tunnel-connections",
+            "2",
+            "--lazy-registration",
+            "--aether-retry-max-attempts",
+            "1",
+        ],
+    );
+
+    // The startup registration fails; the background retry succeeds and
+    // only then do the connections open, each identified.
+    for _ in 0..2 {
+        let tunnel = server.accept_tunnel().await;
+        assert_eq!(tunnel.header("x-node-id"), Some("node-lazy"));
+    }
+    assert_eq!(server.registrations().len(), 1);
+    assert_eq!(server.rejected_tunnels(), 0);
+}
+
+#[tokio::test]
+async fn streams_end_in_a_response_or_an_error() {
+    let server = MockAetherServer::builder().build().await;
+    let _proxy = spawn_proxy(&server, "streams");
+    let mut tunnel = server.accept_tunnel().await;
+
+    // Loopback targets are refused before any upstream connection.
+    tunnel.send_request(1, &get("http://127.0.0.1:9/", Some("fp-1")), b"");
+    let blocked = tunnel.expect_response(1).await;
+    assert!(blocked.meta.is_none());
+    let error = blocked.error.unwrap();
+    assert!(error.contains("target blocked"), "{error}");
+
+    // Nothing was answered, so a resend is served, not refused.
+    tunnel.send_request(3, &get("http://127.0.0.1:9/", Some("fp-1")), b"");
+    let resent = tunnel.expect_response(3).await;
+    assert!(resent.error.unwrap().contains("target blocked"));
+
+    // A replay of a stream still in flight (waiting for its integrity
+    // checked body) is answered by the proxy itself.
+    let mut meta = get("https://203.0.113.10/", Some("fp-2"));
+    meta.method = "POST".to_string();
+    meta.body_sha256 = Some("0".repeat(64));
+    tunnel.send_frame(Frame::new(
+        5,
+        MsgType::RequestHeaders,
+        0,
+        serde_json::to_vec(&meta).unwrap(),
+    ));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    tunnel.send_request(7, &get("https://203.0.113.10/", Some("fp-2")), b"");
+    let duplicate = tunnel.expect_response(7).await;
+    assert_eq!(duplicate.status(), 409);
+    assert_eq!(duplicate.header("x-duplicate"), Some("true"));
+    assert!(duplicate.error.is_none());
+}
+
+#[tokio::test]
+async fn remote_pause_rejects_new_streams() {
+    let server = MockAetherServer::builder()
+        .heartbeat_ack(serde_json::json!({
+            "remote_config": {"paused": true},
+            "config_version": 1,
+        }))
+        .build()
+        .await;
+    let _proxy = spawn_proxy(&server, "pause");
+    let mut tunnel = server.accept_tunnel().await;
+
+    // The first ACK pauses the node; the next heartbeat reports it.
+    tunnel.expect_heartbeat().await;
+    let heartbeat = tunnel.expect_heartbeat().await;
+    assert_eq!(heartbeat["paused"], true);
+    // ...and echoes the config's etag so Aether can skip resending it.
+    assert_eq!(heartbeat["config_etag"].as_str().map(str::len), Some(64));
+
+    tunnel.send_request(1, &get("https://example.com/", None), b"");
+    let response = tunnel.expect_response(1).await;
+    assert!(response.error.unwrap().starts_with("node_paused"));
+}
+
+#[tokio::test]
+async fn remote_reconnect_rotates_the_tunnel_once_with_the_new_name() {
+    let server = MockAetherServer::builder()
+        .heartbeat_ack(serde_json::json!({
+            "remote_config": {"node_name": "it-node-renamed", "reconnect": true},
+            "config_version": 1,
+        }))
+        .build()
+        .await;
+    let _proxy = spawn_proxy(&server, "remote-reconnect");
+
+    let mut tunnel = server.accept_tunnel().await;
+    assert_eq!(tunnel.header("x-node-name"), Some("it-node"));
+    tunnel.expect_heartbeat().await;
+    tunnel.expect_closed().await;
+
+    let mut renamed = server.accept_tunnel().await;
+    assert_eq!(renamed.header("x-node-name"), Some("it-node-renamed"));
+    // Every ACK repeats version 1, which is applied only once: the new
+    // connection stays up.
+    for _ in 0..3 {
+        renamed.expect_heartbeat().await;
+    }
+}
+
+#[tokio::test]
+async fn goaway_makes_the_proxy_reconnect() {
+    let server = MockAetherServer::builder()
+        .node_id("node-it-2")
+        .build()
+        .await;
+    let _proxy = spawn_proxy(&server, "goaway");
+
+    let mut tunnel = server.accept_tunnel().await;
+    tunnel.send_goaway();
+    tunnel.expect_closed().await;
+
+    let reconnected = server.accept_tunnel().await;
+    assert_eq!(reconnected.header("x-node-id"), Some("node-it-2"));
+    // Reconnecting reuses the registration.
+    assert_eq!(server.registrations().len(), 1);
+}
+
+#[tokio::test]
+async fn streams_finish_on_a_sibling_when_their_connection_drops() {
+    let server = MockAetherServer::builder()
+        .cross_connection(true)
+        .build()
+        .await;
+    let _proxy = spawn_proxy_with(&server, "reroute", &["--tunnel-connections", "2"]);
+    let first = server.accept_tunnel().await;
+    let mut second = server.accept_tunnel().await;
+    assert_eq!(first.header(headers::CROSS_CONNECTION), Some("1"));
+    let first_id: u32 = first
+        .header(headers::CONNECTION_ID)
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    // An integrity-checked body is buffered before any upstream connection,
+    // so the stream is still waiting for it when its connection drops.
+    let mut meta = get("https://203.0.113.10/", None);
+    meta.method = "POST".to_string();
+    meta.body_sha256 = Some("0".repeat(64));
+    first.send_frame(Frame::new(
+        1,
+        MsgType::RequestHeaders,
+        0,
+        serde_json::to_vec(&meta).unwrap(),
+    ));
+    first.send_frame(Frame::new(1, MsgType::RequestBody, 0, &b"partial"[..]));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    first.close();
+
+    let response = second.expect_response(1).await;
+    assert_eq!(response.origin_connection, Some(first_id));
+    let error = response.error.unwrap();
+    assert!(error.contains("request body truncated"), "{error}");
+}
+
+#[tokio::test]
+async fn frame_sequencing_is_offered_in_the_handshake() {
+    let server = MockAetherServer::builder()
+        .frame_sequencing(true)
+        .build()
+        .await;
+    let _proxy = spawn_proxy_with(
+        &server,
+        "sequencing",
+        &["--tunnel-connections", "1", "--enable-frame-sequencing"],
+    );
+    let tunnel = server.accept_tunnel().await;
+    assert_eq!(tunnel.header(headers::FRAME_SEQUENCING), Some("1"));
+
+    let server = MockAetherServer::builder().build().await;
+    let _proxy = spawn_proxy(&server, "no-sequencing");
+    let tunnel = server.accept_tunnel().await;
+    assert_eq!(tunnel.header(headers::FRAME_SEQUENCING), None);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn restart_after_a_crash_unregisters_the_stale_node() {
+    let server = MockAetherServer::builder()
+        .node_id("node-it-3")
+        .build()
+        .await;
+    let dir = scratch_dir("crash");
+    let args = ["--tunnel-connections", "1"];
+
+    // A graceful run leaves the clean-shutdown marker...
+    let mut proxy = spawn_proxy_in(&server, &dir, &args);
+    server.accept_tunnel().await;
+    let pid = proxy.id().unwrap() as libc::pid_t;
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGTERM) }, 0);
+    proxy.wait().await.unwrap();
+    assert_eq!(server.unregistrations().len(), 1);
+
+    // ...so the next start has nothing to clean up.  SIGKILL skips the
+    // unregister.
+    let mut proxy = spawn_proxy_in(&server, &dir, &args);
+    server.accept_tunnel().await;
+    assert_eq!(server.unregistrations().len(), 1);
+    proxy.kill().await.unwrap();
+
+    // The restart unregisters the killed instance's node before
+    // registering again.
+    let _proxy = spawn_proxy_in(&server, &dir, &args);
+    server.accept_tunnel().await;
+    let unregistrations = server.unregistrations();
+    assert_eq!(unregistrations.len(), 2);
+    assert_eq!(unregistrations[1]["node_id"], "node-it-3");
+    assert_eq!(server.registrations().len(), 3);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn restart_after_a_crash_with_lazy_registration_unregisters_the_stale_node() {
+    let server = MockAetherServer::builder()
+        .node_id("node-it-lazy")
+        .build()
+        .await;
+    let dir = scratch_dir("crash-lazy");
+    let args = [
+        "--tunnel-connections",
+        "1",
+        "--lazy-registration",
+        "--aether-retry-max-attempts",
+        "1",
+    ];
+
+    let mut proxy = spawn_proxy_in(&server, &dir, &args);
+    server.accept_tunnel().await;
+    proxy.kill().await.unwrap();
+
+    // Neither the stale node's unregister nor the registration gets through
+    // at startup, so the server starts with registration pending.
+    server.fail_unregistrations(1);
+    server.fail_registrations(1);
+    let _proxy = spawn_proxy_in(&server, &dir, &args);
+
+    // The pending registration clears the stale node before registering.
+    let tunnel = server.accept_tunnel().await;
+    assert_eq!(tunnel.header("x-node-id"), Some("node-it-lazy"));
+    let unregistrations = server.unregistrations();
+    assert_eq!(unregistrations.len(), 1);
+    assert_eq!(unregistrations[0]["node_id"], "node-it-lazy");
+    assert_eq!(server.registrations().len(), 2);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn registration_retry_unregisters_the_stale_node() {
+    let first = MockAetherServer::builder()
+        .node_id("node-it-a")
+        .build()
+        .await;
+    let second = MockAetherServer::builder()
+        .node_id("node-it-b")
+        .timeout(Duration::from_secs(30))
+        .build()
+        .await;
+    let dir = scratch_dir("crash-retry");
+    std::fs::write(
+        dir.join("aether-proxy.toml"),
+        format!(
+            "[[servers]]\naether_url = \"{}\"\nmanagement_token = \"test-token\"\n\n\
+             [[servers]]\naether_url = \"{}\"\nmanagement_token = \"test-token\"\n",
+            first.url(),
+            second.url()
+        ),
+    )
+    .unwrap();
+    let args = ["--tunnel-connections", "1"];
+
+    let mut proxy = spawn_proxy_in(&first, &dir, &args);
+    first.accept_tunnel().await;
+    second.accept_tunnel().await;
+    proxy.kill().await.unwrap();
+
+    // The second server is down when the proxy restarts: neither the stale
+    // node's unregister nor the registration gets through at startup.
+    second.fail_registrations(u32::MAX);
+    second.fail_unregistrations(u32::MAX);
+    let _proxy = spawn_proxy_in(&first, &dir, &args);
+    first.accept_tunnel().await;
+    assert!(second.unregistrations().is_empty());
+
+    // The background retry clears the stale node before registering.
+    second.fail_registrations(0);
+    second.fail_unregistrations(0);
+    second.accept_tunnel().await;
+    let unregistrations = second.unregistrations();
+    assert_eq!(unregistrations.len(), 1);
+    assert_eq!(unregistrations[0]["node_id"], "node-it-b");
+    assert_eq!(second.registrations().len(), 2);
+}
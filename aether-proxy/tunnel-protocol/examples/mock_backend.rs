@@ -0,0 +1,178 @@
+//! Minimal stand-in for the Aether side of the tunnel.
+//!
+//! Listens for a proxy's WebSocket connection, sends one scripted request
+//! through it and prints the response, answering heartbeats meanwhile.
+//!
+//! ```text
+//! cargo run -p aether-tunnel-protocol --example mock_backend -- [LISTEN] [URL]
+//! aether-proxy --aether-url http://127.0.0.1:8765 --management-token x
+//! ```
+//!
+//! Plain HTTP requests (registration, unregistration) are all answered with
+//! a fixed node_id, so the proxy registers and identifies its tunnel with it.
+
+use std::collections::HashMap;
+
+use aether_tunnel_protocol::{
+    decompress_if_gzip, flags, headers, Frame, MsgType, RequestMeta, ResponseMeta,
+};
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+
+const STREAM_ID: u32 = 1;
+const NODE_ID: &str = "mock-node";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let listen = args.next().unwrap_or_else(|| "127.0.0.1:8765".to_string());
+    let url = args
+        .next()
+        .unwrap_or_else(|| "https://example.com/".to_string());
+
+    let listener = TcpListener::bind(&listen).await?;
+    eprintln!("mock backend listening on {listen}");
+    loop {
+        let (mut tcp, peer) = listener.accept().await?;
+        if !is_upgrade(&tcp).await? {
+            if let Err(e) = answer_http(&mut tcp).await {
+                eprintln!("{peer}: {e}");
+            }
+            continue;
+        }
+        match drive(tcp, &url).await {
+            Ok(()) => return Ok(()),
+            Err(e) => eprintln!("{peer}: {e}"),
+        }
+    }
+}
+
+/// Whether the request waiting on `tcp` is a WebSocket upgrade.
+async fn is_upgrade(tcp: &TcpStream) -> std::io::Result<bool> {
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = tcp.peek(&mut buf).await?;
+        let head = String::from_utf8_lossy(&buf[..n]).to_ascii_lowercase();
+        if head.contains("\r\n\r\n") || n == buf.len() || n == 0 {
+            return Ok(head.contains("upgrade: websocket"));
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    }
+}
+
+/// Answer a plain HTTP request (e.g. registration) with [`NODE_ID`].
+async fn answer_http(tcp: &mut TcpStream) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 4096];
+    let body_start = loop {
+        let n = tcp.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+        if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+    };
+    let head = String::from_utf8_lossy(&request[..body_start]).to_ascii_lowercase();
+    let content_length = head
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length:"))
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    while request.len() < body_start + content_length {
+        let n = tcp.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let reply = format!("{{\"node_id\":\"{NODE_ID}\"}}");
+    let response = format!(
+        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{reply}",
+        reply.len()
+    );
+    tcp.write_all(response.as_bytes()).await
+}
+
+/// Accept a tunnel, send the scripted request and print the response.
+async fn drive(tcp: TcpStream, url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    // Accept trailer forwarding, so gRPC status trailers are printed too.
+    #[allow(clippy::result_large_err)]
+    let accept_trailers = |_: &Request, mut response: Response| {
+        response
+            .headers_mut()
+            .insert(headers::RESPONSE_TRAILERS, HeaderValue::from_static("1"));
+        Ok(response)
+    };
+    let mut ws = tokio_tungstenite::accept_hdr_async(tcp, accept_trailers).await?;
+    eprintln!("proxy connected, requesting GET {url}");
+
+    let meta = RequestMeta {
+        method: "GET".to_string(),
+        url: url.to_string(),
+        headers: HashMap::from([("accept".to_string(), "*/*".to_string())]),
+        timeout: 30,
+        request_id: Some("mock-1".to_string()),
+        request_fingerprint: None,
+        body_sha256: None,
+        websocket: false,
+    };
+    let headers = Frame::new(
+        STREAM_ID,
+        MsgType::RequestHeaders,
+        0,
+        serde_json::to_vec(&meta)?,
+    );
+    let body = Frame::new(
+        STREAM_ID,
+        MsgType::RequestBody,
+        flags::END_STREAM,
+        Vec::new(),
+    );
+    ws.send(Message::Binary(headers.encode().to_vec())).await?;
+    ws.send(Message::Binary(body.encode().to_vec())).await?;
+
+    let mut received = 0usize;
+    while let Some(msg) = ws.next().await {
+        let Message::Binary(data) = msg? else {
+            continue;
+        };
+        let frame = Frame::decode(data.into())?;
+        match frame.msg_type {
+            MsgType::HeartbeatData => {
+                let ack = Frame::control(MsgType::HeartbeatAck, Vec::new());
+                ws.send(Message::Binary(ack.encode().to_vec())).await?;
+            }
+            MsgType::ResponseHeaders => {
+                let meta: ResponseMeta = serde_json::from_slice(&decompress_if_gzip(&frame)?)?;
+                println!("HTTP {}", meta.status);
+                for (name, value) in meta.headers {
+                    println!("{name}: {value}");
+                }
+            }
+            MsgType::ResponseBody => received += decompress_if_gzip(&frame)?.len(),
+            MsgType::ResponseTrailers => {
+                let meta: ResponseMeta = serde_json::from_slice(&decompress_if_gzip(&frame)?)?;
+                for (name, value) in meta.trailers {
+                    println!("trailer {name}: {value}");
+                }
+            }
+            MsgType::StreamEnd => {
+                println!("\n{received} body bytes");
+                break;
+            }
+            MsgType::StreamError => {
+                println!("stream error: {}", String::from_utf8_lossy(&frame.payload));
+                break;
+            }
+            _ => {}
+        }
+    }
+    ws.close(None).await?;
+    Ok(())
+}
@@ -0,0 +1,11 @@
+//! Arbitrary WebSocket message bytes through frame decoding, prefix
+//! stripping, gzip decompression and `RequestMeta` parsing.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../harness.rs"]
+mod harness;
+
+fuzz_target!(|data: &[u8]| harness::run(data));
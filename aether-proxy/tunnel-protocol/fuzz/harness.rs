@@ -0,0 +1,27 @@
+//! Shared by the libFuzzer target and the `fuzz` corpus replay test.
+
+use aether_tunnel_protocol::{decompress_if_gzip, Frame, MsgType, RequestMeta, HEADER_SIZE};
+use bytes::Bytes;
+
+/// Everything the proxy does with an untrusted tunnel message.  Errors are
+/// fine; panics and broken round trips are not.
+pub fn run(data: &[u8]) {
+    let _ = serde_json::from_slice::<RequestMeta>(data);
+
+    let Ok(mut frame) = Frame::decode(Bytes::from(data.to_vec())) else {
+        return;
+    };
+    assert_eq!(
+        frame.encode(),
+        data[..HEADER_SIZE + frame.payload.len()],
+        "decode/encode round trip"
+    );
+    if frame.take_origin().is_err() || frame.take_sequence().is_err() {
+        return;
+    }
+    if let Ok(payload) = decompress_if_gzip(&frame) {
+        if frame.msg_type == MsgType::RequestHeaders {
+            let _ = serde_json::from_slice::<RequestMeta>(&payload);
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Replays the `fuzz_target_1` seed corpus, every truncation of it and
+//! deterministic mutations through the fuzz harness on stable Rust, so CI
+//! covers the decode paths without cargo-fuzz.
+
+mod harness;
+
+use std::path::Path;
+
+/// Deterministic xorshift generator for the mutations below.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn seeds() -> Vec<Vec<u8>> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus/fuzz_target_1");
+    let mut seeds: Vec<Vec<u8>> = std::fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("{}: {e}", dir.display()))
+        .map(|entry| std::fs::read(entry.unwrap().path()).unwrap())
+        .collect();
+    seeds.sort();
+    seeds
+}
+
+#[test]
+fn corpus_seeds_and_truncations() {
+    let seeds = seeds();
+    assert!(seeds.len() >= 5, "seed corpus missing");
+    for seed in &seeds {
+        for len in 0..=seed.len() {
+            harness::run(&seed[..len]);
+        }
+    }
+}
+
+#[test]
+fn mutated_seeds() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for seed in seeds().iter().filter(|s| !s.is_empty()) {
+        for _ in 0..500 {
+            let mut data = seed.clone();
+            for _ in 0..1 + rng.below(4) {
+                let i = rng.below(data.len());
+                data[i] ^= 1 << rng.below(8);
+            }
+            harness::run(&data);
+        }
+    }
+}
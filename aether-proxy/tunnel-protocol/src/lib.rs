@@ -0,0 +1,689 @@
+//! Binary frame protocol for WebSocket tunnel multiplexing.
+//!
+//! Shared by `aether-proxy` and anything that speaks to it over the tunnel
+//! (see `examples/mock_backend.rs`).  Deliberately free of any async
+//! runtime or HTTP client dependency.
+//!
+//! Frame layout (10-byte header + variable payload):
+//! ```text
+//! | stream_id (4B) | msg_type (1B) | flags (1B) | payload_len (4B) | payload (NB) |
+//! ```
+//!
+//! With [`flags::SEQUENCED`] the payload starts with a 4-byte big-endian
+//! per-stream sequence number (outside any gzip compression).  Only used
+//! once negotiated (see [`headers::FRAME_SEQUENCING`]).
+//!
+//! With [`flags::REROUTED`] the payload starts with the 4-byte big-endian
+//! id of the connection the stream arrived on, ahead of any sequence
+//! number (see [`headers::CROSS_CONNECTION`]).
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+pub const HEADER_SIZE: usize = 10;
+
+/// Frame flags.
+pub mod flags {
+    pub const END_STREAM: u8 = 0x01;
+    pub const GZIP_COMPRESSED: u8 = 0x02;
+    /// Payload is prefixed with a 4-byte sequence number.
+    pub const SEQUENCED: u8 = 0x04;
+    /// WebSocket relay: the body frame carries a text message (binary
+    /// otherwise).
+    pub const WS_TEXT: u8 = 0x08;
+    /// Sent over a connection other than the stream's own; the payload is
+    /// prefixed with that connection's 4-byte id.
+    pub const REROUTED: u8 = 0x10;
+}
+
+/// Size of the sequence number prefix on [`flags::SEQUENCED`] frames.
+pub const SEQUENCE_SIZE: usize = 4;
+/// Size of the origin connection id prefix on [`flags::REROUTED`] frames.
+pub const ORIGIN_SIZE: usize = 4;
+
+/// Tunnel WebSocket handshake headers.
+pub mod headers {
+    /// Request: the proxy can send a stream's response frames over any of
+    /// the node's connections (`1`).  Response: Aether accepts such frames
+    /// (`1`); without it every stream stays on its own connection.
+    pub const CROSS_CONNECTION: &str = "x-tunnel-cross-connection";
+    /// Request: this connection's id within the proxy process, as carried
+    /// by [`flags::REROUTED`](super::flags::REROUTED) frames.
+    pub const CONNECTION_ID: &str = "x-tunnel-connection-id";
+    /// Request: the proxy can number response body frames and reorder
+    /// numbered request body frames (`1`).  Response: Aether handles
+    /// [`flags::SEQUENCED`](super::flags::SEQUENCED) frames (`1`); without
+    /// it no frame is sequenced in either direction.
+    pub const FRAME_SEQUENCING: &str = "x-tunnel-frame-sequencing";
+    /// Request: the proxy can forward upstream trailers (`1`).  Response:
+    /// Aether handles [`MsgType::ResponseTrailers`](super::MsgType) frames
+    /// (`1`); without it trailers are dropped.
+    pub const RESPONSE_TRAILERS: &str = "x-tunnel-response-trailers";
+}
+
+/// Message types for the tunnel protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MsgType {
+    RequestHeaders = 0x01,
+    RequestBody = 0x02,
+    ResponseHeaders = 0x03,
+    ResponseBody = 0x04,
+    StreamEnd = 0x05,
+    StreamError = 0x06,
+    /// HTTP trailers, sent after the last body frame and before StreamEnd.
+    /// Only sent when Aether accepted
+    /// [`headers::RESPONSE_TRAILERS`](headers::RESPONSE_TRAILERS).
+    ResponseTrailers = 0x07,
+    Ping = 0x10,
+    Pong = 0x11,
+    GoAway = 0x12,
+    HeartbeatData = 0x13,
+    HeartbeatAck = 0x14,
+    /// Proxy -> Aether: JSON summary of a failed stream (stream_id = 0),
+    /// sent alongside its StreamError for dashboard aggregation.
+    ErrorReport = 0x19,
+}
+
+impl MsgType {
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0x01 => Some(Self::RequestHeaders),
+            0x02 => Some(Self::RequestBody),
+            0x03 => Some(Self::ResponseHeaders),
+            0x04 => Some(Self::ResponseBody),
+            0x05 => Some(Self::StreamEnd),
+            0x06 => Some(Self::StreamError),
+            0x07 => Some(Self::ResponseTrailers),
+            0x10 => Some(Self::Ping),
+            0x11 => Some(Self::Pong),
+            0x12 => Some(Self::GoAway),
+            0x13 => Some(Self::HeartbeatData),
+            0x14 => Some(Self::HeartbeatAck),
+            0x19 => Some(Self::ErrorReport),
+            _ => None,
+        }
+    }
+}
+
+/// A single multiplexed frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub stream_id: u32,
+    pub msg_type: MsgType,
+    pub flags: u8,
+    pub payload: Bytes,
+}
+
+impl Frame {
+    pub fn new(stream_id: u32, msg_type: MsgType, flags: u8, payload: impl Into<Bytes>) -> Self {
+        Self {
+            stream_id,
+            msg_type,
+            flags,
+            payload: payload.into(),
+        }
+    }
+
+    /// Control frame (stream_id = 0).
+    pub fn control(msg_type: MsgType, payload: impl Into<Bytes>) -> Self {
+        Self::new(0, msg_type, 0, payload)
+    }
+
+    pub fn is_end_stream(&self) -> bool {
+        self.flags & flags::END_STREAM != 0
+    }
+
+    pub fn is_gzip(&self) -> bool {
+        self.flags & flags::GZIP_COMPRESSED != 0
+    }
+
+    pub fn is_sequenced(&self) -> bool {
+        self.flags & flags::SEQUENCED != 0
+    }
+
+    /// Prefix the payload with sequence number `seq` and set `SEQUENCED`.
+    /// Apply after compression.
+    pub fn with_sequence(mut self, seq: u32) -> Self {
+        let mut buf = BytesMut::with_capacity(SEQUENCE_SIZE + self.payload.len());
+        buf.put_u32(seq);
+        buf.put(self.payload);
+        self.payload = buf.freeze();
+        self.flags |= flags::SEQUENCED;
+        self
+    }
+
+    /// Strip the sequence number prefix, if the frame carries one.
+    pub fn take_sequence(&mut self) -> Result<Option<u32>, ProtocolError> {
+        if !self.is_sequenced() {
+            return Ok(None);
+        }
+        if self.payload.len() < SEQUENCE_SIZE {
+            return Err(ProtocolError::TooShort {
+                expected: SEQUENCE_SIZE,
+                actual: self.payload.len(),
+            });
+        }
+        let seq = self.payload.get_u32();
+        self.flags &= !flags::SEQUENCED;
+        Ok(Some(seq))
+    }
+
+    pub fn is_rerouted(&self) -> bool {
+        self.flags & flags::REROUTED != 0
+    }
+
+    /// Prefix the payload with origin connection id `connection_id` and set
+    /// `REROUTED`.  Apply last, after sequencing.
+    pub fn with_origin(mut self, connection_id: u32) -> Self {
+        let mut buf = BytesMut::with_capacity(ORIGIN_SIZE + self.payload.len());
+        buf.put_u32(connection_id);
+        buf.put(self.payload);
+        self.payload = buf.freeze();
+        self.flags |= flags::REROUTED;
+        self
+    }
+
+    /// Strip the origin connection id prefix, if the frame carries one.
+    /// Call before [`take_sequence`](Self::take_sequence).
+    pub fn take_origin(&mut self) -> Result<Option<u32>, ProtocolError> {
+        if !self.is_rerouted() {
+            return Ok(None);
+        }
+        if self.payload.len() < ORIGIN_SIZE {
+            return Err(ProtocolError::TooShort {
+                expected: ORIGIN_SIZE,
+                actual: self.payload.len(),
+            });
+        }
+        let connection_id = self.payload.get_u32();
+        self.flags &= !flags::REROUTED;
+        Ok(Some(connection_id))
+    }
+
+    /// Encode into a binary buffer.
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(HEADER_SIZE + self.payload.len());
+        buf.put_u32(self.stream_id);
+        buf.put_u8(self.msg_type as u8);
+        buf.put_u8(self.flags);
+        buf.put_u32(self.payload.len() as u32);
+        buf.put(self.payload.clone());
+        buf.freeze()
+    }
+
+    /// Decode from a binary buffer.
+    pub fn decode(mut data: Bytes) -> Result<Self, ProtocolError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ProtocolError::TooShort {
+                expected: HEADER_SIZE,
+                actual: data.len(),
+            });
+        }
+        let stream_id = data.get_u32();
+        let msg_type_raw = data.get_u8();
+        let frame_flags = data.get_u8();
+        let payload_len = data.get_u32() as usize;
+
+        if data.remaining() < payload_len {
+            return Err(ProtocolError::Incomplete {
+                expected: HEADER_SIZE + payload_len,
+                actual: HEADER_SIZE + data.remaining(),
+            });
+        }
+
+        let msg_type =
+            MsgType::from_u8(msg_type_raw).ok_or(ProtocolError::UnknownMsgType(msg_type_raw))?;
+        let payload = data.split_to(payload_len);
+
+        Ok(Self {
+            stream_id,
+            msg_type,
+            flags: frame_flags,
+            payload,
+        })
+    }
+}
+
+/// Protocol errors.
+#[derive(Debug, thiserror::Error)]
+pub enum ProtocolError {
+    #[error("frame too short: expected {expected} bytes, got {actual}")]
+    TooShort { expected: usize, actual: usize },
+    #[error("frame incomplete: expected {expected} bytes, got {actual}")]
+    Incomplete { expected: usize, actual: usize },
+    #[error("unknown message type: 0x{0:02x}")]
+    UnknownMsgType(u8),
+}
+
+/// JSON payload for REQUEST_HEADERS frames.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RequestMeta {
+    pub method: String,
+    pub url: String,
+    pub headers: std::collections::HashMap<String, String>,
+    #[serde(default = "default_timeout", deserialize_with = "deserialize_timeout")]
+    pub timeout: u64,
+    /// Aether-side request id, used to correlate proxy logs with the backend.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// SHA-256 of method, URL, sorted headers and body hash, set by Aether so
+    /// a request replayed on another tunnel can be recognised.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_fingerprint: Option<String>,
+    /// Hex SHA-256 of the (decompressed) request body; when set, the proxy
+    /// buffers the body and rejects it on mismatch instead of forwarding.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_sha256: Option<String>,
+    /// Open a WebSocket to `url` (`ws`/`wss`, or `http`/`https`) and relay
+    /// messages as body frames instead of making an HTTP request.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub websocket: bool,
+}
+
+fn default_timeout() -> u64 {
+    60
+}
+
+fn deserialize_timeout<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum TimeoutValue {
+        Int(u64),
+        Float(f64),
+    }
+
+    match <TimeoutValue as serde::Deserialize>::deserialize(deserializer)? {
+        TimeoutValue::Int(v) => Ok(v),
+        TimeoutValue::Float(v) => {
+            if !v.is_finite() || v < 0.0 {
+                return Err(serde::de::Error::custom(
+                    "timeout must be a non-negative finite number",
+                ));
+            }
+            if v.fract() != 0.0 {
+                return Err(serde::de::Error::custom("timeout must be integer seconds"));
+            }
+            if v > (u64::MAX as f64) {
+                return Err(serde::de::Error::custom("timeout is too large"));
+            }
+            Ok(v as u64)
+        }
+    }
+}
+
+/// JSON payload for RESPONSE_HEADERS and RESPONSE_TRAILERS frames.
+///
+/// A trailers frame repeats the status, leaves `headers` empty and carries
+/// the upstream's trailers (e.g. gRPC's `grpc-status`).
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ResponseMeta {
+    pub status: u16,
+    /// Header list preserving duplicates (e.g. multiple Set-Cookie).
+    pub headers: Vec<(String, String)>,
+    /// Trailer list preserving duplicates.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trailers: Vec<(String, String)>,
+}
+
+/// Error codes carried in STREAM_ERROR frames.
+///
+/// Today the payload is the code (or a free-form message) as plain text,
+/// optionally followed by ` (request_id: ...)`.
+pub mod error_codes {
+    /// Per-stream body buffer is full.
+    pub const STREAM_BACKPRESSURE: &str = "stream_backpressure";
+    /// The proxy's outgoing frame queue is congested.
+    pub const WRITER_BACKPRESSURE: &str = "writer_backpressure";
+    /// The proxy is over its memory limit.
+    pub const MEMORY_PRESSURE: &str = "memory_pressure";
+    /// The node is paused for maintenance.
+    pub const NODE_PAUSED: &str = "node_paused";
+    /// The connection is at its concurrent stream limit.
+    pub const MAX_CONCURRENT_STREAMS: &str = "max_concurrent_streams";
+    /// Too many sequenced body frames arrived ahead of a missing one.
+    pub const SEQUENCE_GAP: &str = "sequence_gap";
+    /// The proxy-wide request rate limit is exhausted.
+    pub const RATE_LIMITED: &str = "rate_limited";
+    /// The proxy rotated the stream's tunnel connection and the stream
+    /// outlived the grace period.  Sent as
+    /// `connection_rotating: retry_safe=<bool>`; retrying is only safe when
+    /// no response bytes were forwarded yet.
+    pub const CONNECTION_ROTATING: &str = "connection_rotating";
+    /// The target URL's scheme is not allowed (only http/https, ws/wss for
+    /// WebSocket relays, or https/wss with `upstream_require_https`).
+    pub const SCHEME_NOT_ALLOWED: &str = "scheme_not_allowed";
+    /// The target URL carries `user:pass@` credentials.
+    pub const URL_CREDENTIALS: &str = "url_credentials";
+
+    // Upstream failure classes, sent as `<code>: <detail>`.
+    /// The upstream host could not be resolved.
+    pub const DNS_ERROR: &str = "dns_error";
+    /// The upstream refused the TCP connection.
+    pub const CONNECT_REFUSED: &str = "connect_refused";
+    /// The TCP connection to the upstream timed out.
+    pub const CONNECT_TIMEOUT: &str = "connect_timeout";
+    /// The TLS handshake with the upstream failed.
+    pub const TLS_HANDSHAKE: &str = "tls_handshake";
+    /// Sending the request (or its body) to the upstream failed.
+    pub const REQUEST_WRITE: &str = "request_write";
+    /// Reading the upstream response failed.
+    pub const RESPONSE_READ: &str = "response_read";
+    /// The upstream sent no response headers within the request timeout.
+    pub const RESPONSE_TIMEOUT: &str = "response_timeout";
+    /// The upstream connection timed out while streaming the response body.
+    pub const BODY_TIMEOUT: &str = "body_timeout";
+    /// Any other upstream failure.
+    pub const UPSTREAM_ERROR: &str = "upstream_error";
+
+    /// Whether a stream rejected with `code` never reached upstream and can
+    /// be retried on another node.
+    pub fn is_retryable(code: &str) -> bool {
+        matches!(
+            code,
+            WRITER_BACKPRESSURE
+                | MEMORY_PRESSURE
+                | NODE_PAUSED
+                | MAX_CONCURRENT_STREAMS
+                | RATE_LIMITED
+        )
+    }
+}
+
+/// Structured STREAM_ERROR payload.
+///
+/// Planned replacement for the plain-text payload; not yet sent by the
+/// proxy, so backends must keep accepting text.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ErrorPayload {
+    /// One of [`error_codes`], or another short machine-readable code.
+    pub code: String,
+    /// Human-readable detail.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+// ---------------------------------------------------------------------------
+// Tunnel frame compression helpers
+// ---------------------------------------------------------------------------
+
+/// Default minimum payload size to attempt gzip compression (bytes).
+pub const COMPRESS_MIN_SIZE: usize = 512;
+
+/// If the frame has the GZIP_COMPRESSED flag, decompress the payload; otherwise
+/// return a clone of the raw payload bytes.
+pub fn decompress_if_gzip(frame: &Frame) -> Result<Bytes, std::io::Error> {
+    if frame.is_gzip() {
+        decompress_gzip(&frame.payload)
+    } else {
+        Ok(frame.payload.clone())
+    }
+}
+
+/// Gzip-compress `data` if it is at least `min_size` bytes and compression
+/// actually shrinks the payload. `None` disables compression. Returns
+/// `(payload, extra_flags)` where `extra_flags` contains `GZIP_COMPRESSED`
+/// when compression was applied.
+pub fn compress_payload(data: Bytes, min_size: Option<usize>) -> (Bytes, u8) {
+    let Some(min_size) = min_size else {
+        return (data, 0);
+    };
+    if data.len() >= min_size {
+        if let Ok(compressed) = compress_gzip(&data) {
+            if compressed.len() < data.len() {
+                return (compressed, flags::GZIP_COMPRESSED);
+            }
+        }
+    }
+    (data, 0)
+}
+
+fn decompress_gzip(data: &[u8]) -> Result<Bytes, std::io::Error> {
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    let mut decoder = GzDecoder::new(data);
+    let mut buf = Vec::new();
+    decoder.read_to_end(&mut buf)?;
+    Ok(Bytes::from(buf))
+}
+
+fn compress_gzip(data: &[u8]) -> Result<Bytes, std::io::Error> {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(data)?;
+    let compressed = encoder.finish()?;
+    Ok(Bytes::from(compressed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic xorshift generator for the property-style tests below.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+
+        fn bytes(&mut self, max_len: u64) -> Vec<u8> {
+            // Mix random and repetitive data so some payloads compress.
+            let len = self.below(max_len + 1) as usize;
+            let fill = self.below(4) == 0;
+            (0..len)
+                .map(|i| {
+                    if fill {
+                        (i % 7) as u8
+                    } else {
+                        self.next() as u8
+                    }
+                })
+                .collect()
+        }
+
+        fn string(&mut self, max_len: u64) -> String {
+            let len = self.below(max_len + 1) as usize;
+            (0..len)
+                .map(|_| match self.below(10) {
+                    0 => '"',
+                    1 => '\\',
+                    2 => 'é',
+                    3 => '\n',
+                    _ => (b'a' + self.below(26) as u8) as char,
+                })
+                .collect()
+        }
+    }
+
+    const MSG_TYPES: [MsgType; 13] = [
+        MsgType::RequestHeaders,
+        MsgType::RequestBody,
+        MsgType::ResponseHeaders,
+        MsgType::ResponseBody,
+        MsgType::StreamEnd,
+        MsgType::StreamError,
+        MsgType::ResponseTrailers,
+        MsgType::Ping,
+        MsgType::Pong,
+        MsgType::GoAway,
+        MsgType::HeartbeatData,
+        MsgType::HeartbeatAck,
+        MsgType::ErrorReport,
+    ];
+
+    #[test]
+    fn msg_type_round_trips_through_u8() {
+        for t in MSG_TYPES {
+            assert_eq!(MsgType::from_u8(t as u8), Some(t));
+        }
+        assert_eq!(
+            (0..=u8::MAX).filter_map(MsgType::from_u8).count(),
+            MSG_TYPES.len()
+        );
+    }
+
+    #[test]
+    fn frames_round_trip_through_encode_decode() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..500 {
+            let frame = Frame::new(
+                rng.next() as u32,
+                MSG_TYPES[rng.below(MSG_TYPES.len() as u64) as usize],
+                rng.next() as u8,
+                rng.bytes(2048),
+            );
+            let encoded = frame.encode();
+            assert_eq!(encoded.len(), HEADER_SIZE + frame.payload.len());
+            assert_eq!(Frame::decode(encoded.clone()).unwrap(), frame);
+
+            // Any strict prefix is rejected rather than misparsed.
+            let cut = rng.below(encoded.len() as u64) as usize;
+            assert!(Frame::decode(encoded.slice(..cut)).is_err());
+        }
+    }
+
+    #[test]
+    fn decode_never_panics_on_arbitrary_input() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..2000 {
+            let data = Bytes::from(rng.bytes(64));
+            if let Ok(frame) = Frame::decode(data.clone()) {
+                assert!(HEADER_SIZE + frame.payload.len() <= data.len());
+            }
+        }
+    }
+
+    #[test]
+    fn compression_round_trips() {
+        let mut rng = Rng(0xdead_beef_cafe_f00d);
+        for _ in 0..200 {
+            let data = Bytes::from(rng.bytes(4096));
+            let min_size = [None, Some(0), Some(COMPRESS_MIN_SIZE)][rng.below(3) as usize];
+            let (payload, extra) = compress_payload(data.clone(), min_size);
+            if extra & flags::GZIP_COMPRESSED != 0 {
+                assert!(payload.len() < data.len());
+            } else {
+                assert_eq!(payload, data);
+            }
+            let frame = Frame::new(1, MsgType::ResponseBody, extra, payload);
+            assert_eq!(decompress_if_gzip(&frame).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn sequence_numbers_round_trip_outside_compression() {
+        let mut rng = Rng(0x5851_f42d_4c95_7f2d);
+        for _ in 0..200 {
+            let data = Bytes::from(rng.bytes(2048));
+            let (payload, extra) = compress_payload(data.clone(), Some(0));
+            let seq = rng.next() as u32;
+            let sent = Frame::new(7, MsgType::ResponseBody, extra, payload).with_sequence(seq);
+            assert!(sent.is_sequenced());
+
+            let mut received = Frame::decode(sent.encode()).unwrap();
+            assert_eq!(received.take_sequence().unwrap(), Some(seq));
+            assert!(!received.is_sequenced());
+            assert_eq!(decompress_if_gzip(&received).unwrap(), data);
+            assert_eq!(received.take_sequence().unwrap(), None);
+        }
+
+        let mut short = Frame::new(7, MsgType::RequestBody, flags::SEQUENCED, vec![0u8; 3]);
+        assert!(short.take_sequence().is_err());
+    }
+
+    #[test]
+    fn origin_prefix_wraps_the_sequence_number() {
+        let sent = Frame::new(7, MsgType::ResponseBody, 0, &b"chunk"[..])
+            .with_sequence(3)
+            .with_origin(42);
+        assert!(sent.is_rerouted() && sent.is_sequenced());
+
+        let mut received = Frame::decode(sent.encode()).unwrap();
+        assert_eq!(received.take_origin().unwrap(), Some(42));
+        assert!(!received.is_rerouted());
+        assert_eq!(received.take_sequence().unwrap(), Some(3));
+        assert_eq!(received.payload, Bytes::from_static(b"chunk"));
+        assert_eq!(received.take_origin().unwrap(), None);
+
+        let mut short = Frame::new(7, MsgType::StreamError, flags::REROUTED, vec![0u8; 2]);
+        assert!(short.take_origin().is_err());
+    }
+
+    #[test]
+    fn metas_round_trip_through_json() {
+        let mut rng = Rng(0x0123_4567_89ab_cdef);
+        for _ in 0..200 {
+            let request = RequestMeta {
+                method: rng.string(8),
+                url: rng.string(64),
+                headers: (0..rng.below(5))
+                    .map(|_| (rng.string(12), rng.string(24)))
+                    .collect(),
+                timeout: rng.next(),
+                request_id: (rng.below(2) == 0).then(|| rng.string(16)),
+                request_fingerprint: (rng.below(2) == 0).then(|| rng.string(64)),
+                body_sha256: (rng.below(2) == 0).then(|| rng.string(64)),
+                websocket: rng.below(2) == 0,
+            };
+            let json = serde_json::to_vec(&request).unwrap();
+            assert_eq!(
+                serde_json::from_slice::<RequestMeta>(&json).unwrap(),
+                request
+            );
+
+            let response = ResponseMeta {
+                status: rng.next() as u16,
+                headers: (0..rng.below(5))
+                    .map(|_| (rng.string(12), rng.string(24)))
+                    .collect(),
+                trailers: (0..rng.below(3))
+                    .map(|_| (rng.string(12), rng.string(24)))
+                    .collect(),
+            };
+            let json = serde_json::to_vec(&response).unwrap();
+            assert_eq!(
+                serde_json::from_slice::<ResponseMeta>(&json).unwrap(),
+                response
+            );
+
+            let error = ErrorPayload {
+                code: rng.string(20),
+                message: (rng.below(2) == 0).then(|| rng.string(40)),
+                request_id: (rng.below(2) == 0).then(|| rng.string(16)),
+            };
+            let json = serde_json::to_vec(&error).unwrap();
+            assert_eq!(
+                serde_json::from_slice::<ErrorPayload>(&json).unwrap(),
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn request_meta_accepts_integer_timeout() {
+        let raw = br#"{"method":"GET","url":"https://example.com","headers":{},"timeout":15}"#;
+        let meta: RequestMeta = serde_json::from_slice(raw).expect("parse request meta");
+        assert_eq!(meta.timeout, 15);
+    }
+
+    #[test]
+    fn request_meta_accepts_integer_like_float_timeout() {
+        let raw = br#"{"method":"GET","url":"https://example.com","headers":{},"timeout":15.0}"#;
+        let meta: RequestMeta = serde_json::from_slice(raw).expect("parse request meta");
+        assert_eq!(meta.timeout, 15);
+    }
+}
@@ -48,6 +48,9 @@ class ProxyNodeRegisterRequest(BaseModel):
     proxy_version: str | None = Field(
         None, max_length=20, description="兼容字段：aether-proxy 软件版本"
     )
+    detect_ip_server_side: bool = Field(
+        False, description="由服务端使用注册请求的来源地址作为节点 IP"
+    )
 
     @field_validator("ip")
     @classmethod
@@ -283,6 +286,14 @@ def _format_validation_error(exc: ValidationError) -> str:
     return "; ".join(parts) or "输入验证失败"
 
 
+def _observed_ip(client_ip: str) -> str | None:
+    """注册请求的来源地址；无法识别（如 "unknown"）时返回 None"""
+    try:
+        return str(ipaddress.ip_address(client_ip.strip()))
+    except ValueError:
+        return None
+
+
 # ---------------------------------------------------------------------------
 # Adapter 实现
 # ---------------------------------------------------------------------------
@@ -299,10 +310,14 @@ class AdminRegisterProxyNodeAdapter(AdminApiAdapter):
         except ValidationError as exc:
             raise InvalidRequestException("输入验证失败: " + _format_validation_error(exc))
 
+        ip = req.ip
+        if req.detect_ip_server_side:
+            ip = _observed_ip(context.client_ip) or req.ip
+
         node = ProxyNodeService.register_node(
             context.db,
             name=req.name,
-            ip=req.ip,
+            ip=ip,
             port=req.port,
             region=req.region,
             heartbeat_interval=req.heartbeat_interval,
@@ -118,6 +118,7 @@ class FailoverEngine:
             "max outbound streams" in lowered
             or "too many concurrent streams" in lowered
             or "max concurrent streams" in lowered
+            or "max_concurrent_streams" in lowered
         )
 
     @classmethod
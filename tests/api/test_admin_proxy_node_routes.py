@@ -0,0 +1,66 @@
+from __future__ import annotations
+
+from types import SimpleNamespace
+from typing import Any
+from unittest.mock import MagicMock
+
+import pytest
+
+from src.api.admin.proxy_nodes.routes import AdminRegisterProxyNodeAdapter
+
+
+def _build_context(payload: dict[str, Any], client_ip: str) -> SimpleNamespace:
+    return SimpleNamespace(
+        db=MagicMock(),
+        user=None,
+        client_ip=client_ip,
+        ensure_json_body=lambda: payload,
+        add_audit_metadata=lambda **_: None,
+    )
+
+
+def _patch_register_node(monkeypatch: pytest.MonkeyPatch) -> dict[str, Any]:
+    captured: dict[str, Any] = {}
+
+    def _fake_register_node(_db: Any, **kwargs: Any) -> SimpleNamespace:
+        captured.update(kwargs)
+        return SimpleNamespace(id="node-1", ip=kwargs["ip"], port=kwargs["port"])
+
+    monkeypatch.setattr(
+        "src.api.admin.proxy_nodes.routes.ProxyNodeService.register_node",
+        _fake_register_node,
+    )
+    monkeypatch.setattr(
+        "src.api.admin.proxy_nodes.routes.node_to_dict", lambda node: {"id": node.id}
+    )
+    return captured
+
+
+@pytest.mark.asyncio
+async def test_register_uses_source_address_when_ip_detection_is_disabled(
+    monkeypatch: pytest.MonkeyPatch,
+) -> None:
+    captured = _patch_register_node(monkeypatch)
+    payload = {"name": "edge-1", "ip": "0.0.0.0", "detect_ip_server_side": True}
+
+    result = await AdminRegisterProxyNodeAdapter().handle(
+        _build_context(payload, "198.51.100.7")
+    )
+
+    assert result["node_id"] == "node-1"
+    assert captured["ip"] == "198.51.100.7"
+
+
+@pytest.mark.asyncio
+async def test_register_keeps_reported_ip_without_the_hint_or_a_source_address(
+    monkeypatch: pytest.MonkeyPatch,
+) -> None:
+    captured = _patch_register_node(monkeypatch)
+
+    payload = {"name": "edge-1", "ip": "203.0.113.10"}
+    await AdminRegisterProxyNodeAdapter().handle(_build_context(payload, "198.51.100.7"))
+    assert captured["ip"] == "203.0.113.10"
+
+    payload = {"name": "edge-1", "ip": "0.0.0.0", "detect_ip_server_side": True}
+    await AdminRegisterProxyNodeAdapter().handle(_build_context(payload, "unknown"))
+    assert captured["ip"] == "0.0.0.0"